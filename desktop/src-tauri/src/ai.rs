@@ -0,0 +1,492 @@
+use crate::*;
+
+#[derive(Clone, Serialize)]
+pub(crate) struct StationStatus {
+  pub(crate) endpoint: String,
+  pub(crate) host: String,
+  pub(crate) port: u16,
+  pub(crate) reachable: bool,
+  pub(crate) installed: bool,
+  pub(crate) started_by_app: bool,
+  pub(crate) log_path: String,
+}
+
+pub(crate) fn parse_host_port(endpoint: &str) -> Option<(String, u16)> {
+  let mut s = endpoint.trim().to_string();
+  if s.is_empty() {
+    return None;
+  }
+  if let Some(idx) = s.find("://") {
+    s = s[(idx + 3)..].to_string();
+  }
+  // Strip credentials if present.
+  if let Some(at) = s.find('@') {
+    s = s[(at + 1)..].to_string();
+  }
+  // Cut path/query/fragment.
+  for sep in ['/', '?', '#'] {
+    if let Some(i) = s.find(sep) {
+      s = s[..i].to_string();
+      break;
+    }
+  }
+  if s.is_empty() {
+    return None;
+  }
+  // host[:port]
+  if let Some(colon) = s.rfind(':') {
+    let host = s[..colon].to_string();
+    let port_s = s[(colon + 1)..].to_string();
+    if let Ok(p) = port_s.parse::<u16>() {
+      return Some((host, p));
+    }
+  }
+  // Default (Station default varies by install; we standardize our local default).
+  Some((s, 2023))
+}
+
+pub(crate) fn tcp_reachable(host: &str, port: u16) -> bool {
+  TcpStream::connect(format!("{}:{}", host, port)).is_ok()
+}
+
+pub(crate) fn station_bin() -> String {
+  let env = std::env::var("MOONDREAM_STATION_BIN").unwrap_or_else(|_| "".to_string());
+  let env = env.trim().to_string();
+  if !env.is_empty() {
+    return env;
+  }
+  "moondream-station".to_string()
+}
+
+#[tauri::command]
+pub(crate) fn station_status(
+  app: tauri::AppHandle,
+  state: tauri::State<ServerState>,
+  endpoint: Option<String>,
+) -> StationStatus {
+  let endpoint = endpoint
+    .unwrap_or_else(|| DEFAULT_AI_ENDPOINT.to_string())
+    .trim()
+    .to_string();
+  let (host, port) = parse_host_port(&endpoint).unwrap_or_else(|| ("localhost".to_string(), 2023));
+  let reachable = tcp_reachable(&host, port);
+  let started_by_app = state.station.lock().unwrap().is_some();
+  let installed = Command::new(station_bin())
+    .arg("--help")
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .spawn()
+    .is_ok();
+
+  let config_root = app_config_root(&app)
+    .map(|p| p.to_string_lossy().to_string())
+    .unwrap_or_default();
+  let log_path = if config_root.is_empty() {
+    "".to_string()
+  } else {
+    format!("{}/logs/moondream-station.log", config_root)
+  };
+
+  StationStatus {
+    endpoint,
+    host,
+    port,
+    reachable,
+    installed,
+    started_by_app,
+    log_path,
+  }
+}
+
+#[tauri::command]
+pub(crate) fn station_start(
+  app: tauri::AppHandle,
+  state: tauri::State<ServerState>,
+  endpoint: Option<String>,
+) -> Result<StationStatus, String> {
+  let endpoint = endpoint
+    .unwrap_or_else(|| DEFAULT_AI_ENDPOINT.to_string())
+    .trim()
+    .to_string();
+  let (host, port) = parse_host_port(&endpoint).unwrap_or_else(|| ("localhost".to_string(), 2023));
+
+  // Only support local station auto-start.
+  let host_lc = host.to_lowercase();
+  if host_lc != "localhost" && host_lc != "127.0.0.1" && host_lc != "::1" {
+    return Err("Auto-start only supports localhost endpoints. Please start Station manually.".to_string());
+  }
+
+  if tcp_reachable(&host, port) {
+    return Ok(station_status(app, state, Some(endpoint)));
+  }
+
+  // Ensure log dir exists
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  std::fs::create_dir_all(&config_root).map_err(|e| e.to_string())?;
+  let log_dir = logs_dir(&config_root);
+  std::fs::create_dir_all(&log_dir).map_err(|e| e.to_string())?;
+  let log_path = log_dir.join("moondream-station.log");
+  rotate_log(&log_path, compress_rotated_logs(&read_settings(&config_root)));
+  let out = OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(&log_path)
+    .map_err(|e| e.to_string())?;
+  let err = out.try_clone().map_err(|e| e.to_string())?;
+
+  // Kill previous station child if we started one.
+  if let Some(mut prev) = state.station.lock().unwrap().take() {
+    let _ = prev.kill();
+  }
+
+  let bin = station_bin();
+  let mut cmd = Command::new(bin);
+  cmd
+    .arg("start")
+    .arg(port.to_string())
+    .stdin(Stdio::null())
+    .stdout(Stdio::from(out))
+    .stderr(Stdio::from(err));
+
+  let child = cmd.spawn().map_err(|e| {
+    format!(
+      "Failed to start Moondream Station. Is it installed? Try: python3 -m pip install --user moondream-station. ({})",
+      e
+    )
+  })?;
+  *state.station.lock().unwrap() = Some(child);
+
+  // Wait briefly for port to open.
+  let ok = http_get_200(&host, port, "/", Duration::from_secs(6)) || tcp_reachable(&host, port);
+  if !ok {
+    // Keep it running (it might still be starting), but let the UI show current status.
+  }
+
+  Ok(station_status(app, state, Some(endpoint)))
+}
+
+#[tauri::command]
+pub(crate) fn station_stop(app: tauri::AppHandle, state: tauri::State<ServerState>) -> StationStatus {
+  if let Some(mut c) = state.station.lock().unwrap().take() {
+    let _ = c.kill();
+  }
+  station_status(app, state, None)
+}
+
+// Station answers 200 here (see README); other services on its port generally don't.
+pub(crate) const STATION_PROBE_PATH: &str = "/health";
+
+pub(crate) const STATION_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Serialize)]
+pub(crate) struct EndpointConflict {
+  pub(crate) endpoint: String,
+  pub(crate) port: PortDiagnosis,
+  pub(crate) message: String,
+}
+
+// Some(..) when the configured local Station port is held by something that isn't Station.
+// Nothing listening isn't a conflict (Station is just not running).
+pub(crate) fn ai_endpoint_conflict(state: &ServerState, settings: &AppSettings) -> Option<EndpointConflict> {
+  let ai = ai_config(settings);
+  if !ai.is_local() {
+    return None;
+  }
+  let (host, port) = parse_host_port(&ai.endpoint)?;
+  if !["localhost", "127.0.0.1", "::1", "[::1]"].contains(&host.to_lowercase().as_str()) {
+    return None;
+  }
+  let diagnosis = port_diagnosis(port);
+  if !diagnosis.in_use {
+    return None;
+  }
+  // A Station we started may still be loading its model; it's ours either way.
+  let ours = state.station.lock().unwrap().as_ref().map(|c| c.id());
+  if ours.is_some() && diagnosis.holder_pid == ours {
+    return None;
+  }
+  if http_status(&host, port, STATION_PROBE_PATH, STATION_PROBE_TIMEOUT) == Some(200) {
+    return None;
+  }
+  let message = format!(
+    "{} It doesn't answer like Moondream Station (no {} route), so AI jobs will fail. Quit that app or point ai.endpoint at Station's port.",
+    diagnosis.describe(),
+    STATION_PROBE_PATH
+  );
+  Some(EndpointConflict { endpoint: ai.endpoint, port: diagnosis, message })
+}
+
+#[tauri::command(async)]
+pub(crate) fn check_ai_endpoint(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<Option<EndpointConflict>, String> {
+  let config_root = managed_config_root(&app, &state)?;
+  Ok(ai_endpoint_conflict(&state, &effective_settings(&state, &config_root)))
+}
+
+// Runs off-thread at worker start; the UI hears about it via `moondream:ai:endpoint-conflict`.
+pub(crate) fn warn_ai_endpoint_conflict(app: &tauri::AppHandle, settings: &AppSettings) {
+  let app = app.clone();
+  let settings = settings.clone();
+  std::thread::spawn(move || {
+    let Some(conflict) = ai_endpoint_conflict(&app.state::<ServerState>(), &settings) else {
+      return;
+    };
+    host_log(LogLevel::Warn, &conflict.message);
+    if let Some(window) = app.get_window("main") {
+      dispatch_web_event(
+        &window,
+        "moondream:ai:endpoint-conflict",
+        serde_json::to_value(&conflict).ok(),
+      );
+    }
+  });
+}
+
+pub(crate) fn ai_target(provider: &str, endpoint: &str) -> Option<(String, u16)> {
+  let (host, port) = parse_host_port(endpoint)?;
+  // parse_host_port falls back to the Station port; hosted endpoints use the scheme default.
+  let port = if provider != DEFAULT_AI_PROVIDER && !endpoint.contains(&format!(":{}", port)) {
+    if endpoint.starts_with("https://") { 443 } else { 80 }
+  } else {
+    port
+  };
+  Some((host, port))
+}
+
+// `timeout` covers the DNS lookup and the connect together.
+pub(crate) fn ai_reachable(provider: &str, endpoint: &str, timeout: Duration) -> bool {
+  let Some((host, port)) = ai_target(provider, endpoint) else {
+    return false;
+  };
+  let started = Instant::now();
+  let Some(sock) = resolve_with_timeout(format!("{}:{}", host, port), timeout) else {
+    return false;
+  };
+  let left = timeout.saturating_sub(started.elapsed());
+  !left.is_zero() && TcpStream::connect_timeout(&sock, left).is_ok()
+}
+
+// The system resolver has no timeout of its own; a lookup that outlives `timeout` is left to
+// finish on its thread.
+pub(crate) fn resolve_with_timeout(host_port: String, timeout: Duration) -> Option<std::net::SocketAddr> {
+  let (tx, rx) = std::sync::mpsc::channel();
+  std::thread::spawn(move || {
+    let first = std::net::ToSocketAddrs::to_socket_addrs(host_port.as_str()).ok().and_then(|mut a| a.next());
+    let _ = tx.send(first);
+  });
+  rx.recv_timeout(timeout).ok().flatten()
+}
+
+// Public anycast addresses, dialled by IP so "no internet" can be told apart from "no DNS".
+pub(crate) const INTERNET_PROBES: [&str; 2] = ["1.1.1.1:443", "8.8.8.8:443"];
+
+pub(crate) const NETWORK_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Serialize)]
+pub(crate) struct NetworkStatus {
+  pub(crate) online: bool,
+  // The endpoint's host resolved (trivially true for IP literals and localhost).
+  pub(crate) dns_ok: bool,
+  pub(crate) endpoint_reachable: bool,
+  pub(crate) provider: String,
+  // User-facing explanation when a hosted provider can't be reached; None otherwise.
+  pub(crate) message: Option<String>,
+}
+
+pub(crate) fn check_network(settings: &AppSettings) -> NetworkStatus {
+  let AiConfig { provider, endpoint, .. } = ai_config(settings);
+  let target = ai_target(&provider, &endpoint);
+
+  let (online, resolved) = std::thread::scope(|s| {
+    let probes: Vec<_> = INTERNET_PROBES
+      .iter()
+      .filter_map(|a| a.parse::<std::net::SocketAddr>().ok())
+      .map(|sock| s.spawn(move || TcpStream::connect_timeout(&sock, NETWORK_PROBE_TIMEOUT).is_ok()))
+      .collect();
+    let dns = s.spawn(|| {
+      target
+        .as_ref()
+        .and_then(|(host, port)| std::net::ToSocketAddrs::to_socket_addrs(format!("{}:{}", host, port).as_str()).ok())
+        .and_then(|mut addrs| addrs.next())
+    });
+    (
+      probes.into_iter().any(|p| p.join().unwrap_or(false)),
+      dns.join().ok().flatten(),
+    )
+  });
+  let endpoint_reachable = resolved
+    .map(|sock| TcpStream::connect_timeout(&sock, NETWORK_PROBE_TIMEOUT).is_ok())
+    .unwrap_or(false);
+  let host = target.map(|(h, _)| h).unwrap_or_else(|| endpoint.clone());
+  let message = if provider == DEFAULT_AI_PROVIDER || endpoint_reachable {
+    None
+  } else if !online {
+    Some("You're offline. AI processing resumes once you're back online.".to_string())
+  } else if resolved.is_none() {
+    Some(format!("Can't look up {}. Check the endpoint URL or your DNS settings.", host))
+  } else {
+    Some(format!("Can't reach {}. The endpoint may be down or blocked by a firewall.", host))
+  };
+  NetworkStatus {
+    online,
+    dns_ok: resolved.is_some(),
+    endpoint_reachable,
+    provider,
+    message,
+  }
+}
+
+// Fast-fails remote AI operations with an explanation instead of a worker timeout.
+pub(crate) fn remote_provider_unreachable(settings: &AppSettings) -> Option<String> {
+  if ai_config(settings).is_local() {
+    return None;
+  }
+  check_network(settings).message
+}
+
+#[tauri::command(async)]
+pub(crate) fn network_status(app: tauri::AppHandle) -> Result<NetworkStatus, String> {
+  let state = app.state::<ServerState>();
+  let config_root = managed_config_root(&app, &state)?;
+  Ok(check_network(&effective_settings(&state, &config_root)))
+}
+
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Milliseconds since the start of the probe, so each phase can be read as "by then".
+#[derive(Clone, Serialize)]
+pub(crate) struct ProbeTiming {
+  pub(crate) url: String,
+  pub(crate) status: Option<u16>,
+  pub(crate) dns_ms: Option<u64>,
+  pub(crate) connect_ms: Option<u64>,
+  // https only.
+  pub(crate) tls_ms: Option<u64>,
+  pub(crate) first_byte_ms: Option<u64>,
+  pub(crate) total_ms: Option<u64>,
+  // "native" for plain http, "curl" for https (std has no TLS).
+  pub(crate) via: String,
+  pub(crate) error: Option<String>,
+}
+
+pub(crate) fn endpoint_path(endpoint: &str) -> String {
+  let rest = endpoint.split_once("://").map(|(_, r)| r).unwrap_or(endpoint);
+  match rest.find('/') {
+    Some(i) => rest[i..].to_string(),
+    None => "/".to_string(),
+  }
+}
+
+pub(crate) fn probe_http(provider: &str, url: &str) -> ProbeTiming {
+  let ms = |d: Duration| d.as_millis() as u64;
+  let mut t = ProbeTiming {
+    url: url.to_string(),
+    status: None,
+    dns_ms: None,
+    connect_ms: None,
+    tls_ms: None,
+    first_byte_ms: None,
+    total_ms: None,
+    via: "native".to_string(),
+    error: None,
+  };
+  let Some((host, port)) = ai_target(provider, url) else {
+    t.error = Some("The endpoint URL has no host".to_string());
+    return t;
+  };
+  let start = Instant::now();
+  let sock = match std::net::ToSocketAddrs::to_socket_addrs(format!("{}:{}", host, port).as_str()).map(|mut a| a.next()) {
+    Ok(Some(sock)) => sock,
+    _ => {
+      t.error = Some(format!("Couldn't resolve {}", host));
+      return t;
+    }
+  };
+  t.dns_ms = Some(ms(start.elapsed()));
+  let mut stream = match TcpStream::connect_timeout(&sock, PROBE_TIMEOUT) {
+    Ok(stream) => stream,
+    Err(e) => {
+      t.error = Some(format!("Couldn't connect to {}: {}", sock, e));
+      return t;
+    }
+  };
+  t.connect_ms = Some(ms(start.elapsed()));
+  let _ = stream.set_read_timeout(Some(PROBE_TIMEOUT));
+  let _ = stream.set_write_timeout(Some(PROBE_TIMEOUT));
+  let offset = start.elapsed();
+  let mut exchange = ExchangeTiming::default();
+  t.status = http_exchange_timed(&mut stream, &format!("{}:{}", host, port), "GET", &endpoint_path(url), None, None, &mut exchange)
+    .map(|(status, _)| status);
+  t.first_byte_ms = exchange.first_byte.map(|d| ms(offset + d));
+  t.total_ms = exchange.total.map(|d| ms(offset + d));
+  if t.status.is_none() {
+    t.error = Some("Connected, but got no HTTP response".to_string());
+  }
+  t
+}
+
+// curl's -w timers are cumulative seconds, like ours.
+pub(crate) fn probe_https(url: &str) -> ProbeTiming {
+  let mut t = ProbeTiming {
+    url: url.to_string(),
+    status: None,
+    dns_ms: None,
+    connect_ms: None,
+    tls_ms: None,
+    first_byte_ms: None,
+    total_ms: None,
+    via: "curl".to_string(),
+    error: None,
+  };
+  let out = Command::new("curl")
+    .args(["-s", "-o"])
+    .arg(if cfg!(windows) { "NUL" } else { "/dev/null" })
+    .args(["--max-time", &PROBE_TIMEOUT.as_secs().to_string()])
+    .args(["-w", "%{http_code} %{time_namelookup} %{time_connect} %{time_appconnect} %{time_starttransfer} %{time_total}"])
+    .arg(url)
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output();
+  let out = match out {
+    Ok(out) => out,
+    Err(e) => {
+      t.error = Some(format!("curl isn't available: {}", e));
+      return t;
+    }
+  };
+  let text = String::from_utf8_lossy(&out.stdout);
+  let fields: Vec<&str> = text.split_whitespace().collect();
+  let secs = |i: usize| {
+    fields
+      .get(i)
+      .and_then(|v| v.parse::<f64>().ok())
+      .filter(|v| *v > 0.0)
+      .map(|v| (v * 1000.0).round() as u64)
+  };
+  t.status = fields.first().and_then(|c| c.parse::<u16>().ok()).filter(|c| *c != 0);
+  t.dns_ms = secs(1);
+  t.connect_ms = secs(2);
+  t.tls_ms = secs(3);
+  t.first_byte_ms = secs(4);
+  t.total_ms = secs(5);
+  if !out.status.success() {
+    t.error = Some(format!("curl exited with {}", out.status.code().unwrap_or(-1)));
+  }
+  t
+}
+
+// Times one GET of the configured AI endpoint, split into DNS / connect / TLS / first byte / total,
+// so a slow connection can be told apart from a slow server.
+#[tauri::command(async)]
+pub(crate) fn probe_endpoint(app: tauri::AppHandle) -> Result<ProbeTiming, String> {
+  let state = app.state::<ServerState>();
+  let config_root = managed_config_root(&app, &state)?;
+  let AiConfig { provider, endpoint, .. } = ai_config(&effective_settings(&state, &config_root));
+  let url = endpoint.trim();
+  Ok(if url.starts_with("https://") {
+    probe_https(url)
+  } else {
+    probe_http(&provider, url)
+  })
+}
@@ -0,0 +1,533 @@
+use crate::*;
+
+// Retention is enforced by the web app, which owns the schema and the files on disk:
+// - POST /api/maintenance/retention {"olderThanDays": n, "dryRun": bool} -> {"count": n}
+//   deletes assets (and their files/metadata) created before now - n days.
+// - POST /api/maintenance/vacuum -> 2xx once `VACUUM` has finished.
+pub(crate) const RETENTION_TIMEOUT: Duration = Duration::from_secs(120);
+
+pub(crate) const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub(crate) const RETENTION_RUN_EVERY_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Serialize)]
+pub(crate) struct RetentionRequest {
+  #[serde(rename = "olderThanDays")]
+  pub(crate) older_than_days: u32,
+  #[serde(rename = "dryRun")]
+  pub(crate) dry_run: bool,
+}
+
+#[derive(Clone, Deserialize)]
+pub(crate) struct RetentionResponse {
+  pub(crate) count: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct RetentionResult {
+  pub(crate) count: u64,
+  pub(crate) dry_run: bool,
+  pub(crate) backup_path: Option<String>,
+  pub(crate) compacted: bool,
+}
+
+pub(crate) const WAL_CHECKPOINT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Folds the WAL back into the DB file and truncates it, so a copy of the file alone is complete
+// and minimal. Server contract: POST /api/maintenance/checkpoint-wal runs
+// `PRAGMA wal_checkpoint(TRUNCATE)` on its connection (2xx when done). Without a server (or an
+// older one that 404s) the sqlite3 CLI does it directly.
+pub(crate) fn checkpoint_wal_now(state: &ServerState, db_path: &PathBuf) -> Result<(), String> {
+  if let Some(addr) = current_server_addr(state) {
+    let token = state.session_token.lock().unwrap().clone();
+    match server_request(&addr, "POST", "/api/maintenance/checkpoint-wal", None, token.as_deref(), WAL_CHECKPOINT_TIMEOUT) {
+      Some((status, _)) if (200..300).contains(&status) => return Ok(()),
+      Some((404, _)) | None => {}
+      Some((status, body)) => return Err(format!("Checkpoint failed ({}): {}", status, body.trim())),
+    }
+  }
+  if !db_path.exists() {
+    return Ok(());
+  }
+  let out = Command::new("sqlite3")
+    .arg("-cmd")
+    .arg(".timeout 5000")
+    .arg(db_path)
+    .arg("PRAGMA wal_checkpoint(TRUNCATE);")
+    .stdin(Stdio::null())
+    .output()
+    .map_err(|e| format!("sqlite3 isn't available: {}", e))?;
+  if !out.status.success() {
+    return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+  }
+  // "busy|log frames|checkpointed frames"; busy = 1 means a reader kept it from finishing.
+  let text = String::from_utf8_lossy(&out.stdout);
+  if text.trim().starts_with('1') {
+    return Err("The database is busy; the checkpoint didn't complete.".to_string());
+  }
+  Ok(())
+}
+
+pub(crate) fn current_db_path(state: &ServerState) -> Option<PathBuf> {
+  state
+    .db_path
+    .lock()
+    .unwrap()
+    .clone()
+    .or_else(|| state.data_dir.lock().unwrap().as_ref().map(db_path_for))
+}
+
+#[tauri::command(async)]
+pub(crate) fn checkpoint_wal(state: tauri::State<ServerState>) -> Result<(), String> {
+  let db_path = current_db_path(&state).ok_or_else(|| "No library is open".to_string())?;
+  checkpoint_wal_now(&state, &db_path)
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct SchemaCompat {
+  pub(crate) bundle_version: Option<u32>,
+  pub(crate) db_version: Option<u32>,
+  // False only when the DB is known to be newer than this bundle understands.
+  pub(crate) compatible: bool,
+  pub(crate) action: String, // "none" | "migrate" | "update_app" | "new_library" | "unknown"
+}
+
+// The prepare-next script stamps `"schemaVersion": N` into the bundled package.json: the
+// `user_version` the server's migrations leave the DB at.
+pub(crate) fn bundle_schema_version(app: &tauri::AppHandle) -> Option<u32> {
+  let text = std::fs::read_to_string(resource_path(app, "next/package.json")?).ok()?;
+  let v = serde_json::from_str::<serde_json::Value>(&text).ok()?;
+  v.get("schemaVersion")?.as_u64().and_then(|n| u32::try_from(n).ok())
+}
+
+pub(crate) fn db_schema_version(db_path: &PathBuf) -> Option<u32> {
+  let out = Command::new("sqlite3")
+    .arg("-readonly")
+    .arg("-cmd")
+    .arg(".timeout 2000")
+    .arg(db_path)
+    .arg("PRAGMA user_version;")
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .ok()?;
+  if !out.status.success() {
+    return None;
+  }
+  String::from_utf8_lossy(&out.stdout).trim().parse().ok()
+}
+
+pub(crate) fn check_schema_compat(app: &tauri::AppHandle, db_path: &PathBuf) -> SchemaCompat {
+  let bundle_version = bundle_schema_version(app);
+  let db_exists = db_path.is_file();
+  let db_version = if db_exists { db_schema_version(db_path) } else { None };
+  let action = match (bundle_version, db_version) {
+    _ if !db_exists => "new_library",
+    (Some(bundle), Some(db)) if db > bundle => "update_app",
+    (Some(bundle), Some(db)) if db < bundle => "migrate",
+    (Some(_), Some(_)) => "none",
+    // No stamp in the bundle, or no sqlite3 CLI (Windows): leave it to the server.
+    _ => "unknown",
+  };
+  SchemaCompat {
+    bundle_version,
+    db_version,
+    compatible: action != "update_app",
+    action: action.to_string(),
+  }
+}
+
+#[tauri::command(async)]
+pub(crate) fn schema_compat(app: tauri::AppHandle) -> Result<SchemaCompat, String> {
+  let state = app.state::<ServerState>();
+  // Before the server starts there's no db_path yet; resolve it the way the server will.
+  let running = state.db_path.lock().unwrap().clone();
+  let db_path = match running {
+    Some(p) => p,
+    None => {
+      let config_root = managed_config_root(&app, &state)?;
+      let data_dir = state.data_dir.lock().unwrap().clone().ok_or_else(|| "No library is open".to_string())?;
+      resolve_db_path(&config_root, &data_dir, &effective_settings(&state, &config_root))
+    }
+  };
+  Ok(check_schema_compat(&app, &db_path))
+}
+
+// A standalone copy of the library database (checkpointed first, so no -wal is needed).
+#[tauri::command(async)]
+pub(crate) fn export_database(state: tauri::State<ServerState>, dest: String) -> Result<String, String> {
+  let db_path = current_db_path(&state).ok_or_else(|| "No library is open".to_string())?;
+  let dest = PathBuf::from(dest.trim());
+  if !dest.is_absolute() {
+    return Err("Choose an absolute destination path.".to_string());
+  }
+  checkpoint_wal_now(&state, &db_path)?;
+  std::fs::copy(&db_path, &dest).map_err(|e| format!("Failed to copy the database: {}", e))?;
+  Ok(dest.to_string_lossy().to_string())
+}
+
+// Backups kept in `backups/`; older ones are deleted after each successful backup.
+pub(crate) const BACKUPS_KEEP: usize = 5;
+
+// Backups go under the config root (not the library) so they don't sync to iCloud.
+pub(crate) fn backup_db(state: &ServerState, config_root: &PathBuf, db: &PathBuf) -> Result<PathBuf, String> {
+  let dir = config_root.join("backups");
+  std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+  let dest = dir.join(format!("moondream-{}.sqlite3", unix_now_secs()));
+  match sqlite_backup(db, &dest) {
+    Ok(()) => {}
+    // No sqlite3 CLI (Windows): copy the files instead, with the worker held so they can't change.
+    Err(e) if e.kind() == ErrorKind::NotFound => {
+      with_worker_held(state, config_root, || copy_db_files(db, &dest).map_err(|e| e.to_string()))?
+    }
+    Err(e) => return Err(e.to_string()),
+  }
+  for old in prune_backups(&dir, BACKUPS_KEEP) {
+    host_log(LogLevel::Info, &format!("Removed old backup {}", old));
+  }
+  Ok(dest)
+}
+
+// `VACUUM INTO` reads a single consistent snapshot (WAL included) while the server and worker keep
+// writing, and the result is one self-contained file.
+pub(crate) fn sqlite_backup(db: &PathBuf, dest: &PathBuf) -> io::Result<()> {
+  let out = Command::new("sqlite3")
+    .arg("-readonly")
+    .arg("-cmd")
+    .arg(".timeout 5000")
+    .arg(db)
+    .arg(format!("VACUUM INTO '{}';", dest.to_string_lossy().replace('\'', "''")))
+    .stdin(Stdio::null())
+    .output()?;
+  if !out.status.success() {
+    let _ = std::fs::remove_file(dest);
+    return Err(io::Error::other(String::from_utf8_lossy(&out.stderr).trim().to_string()));
+  }
+  Ok(())
+}
+
+pub(crate) fn copy_db_files(db: &PathBuf, dest: &PathBuf) -> io::Result<()> {
+  std::fs::copy(db, dest)?;
+  // Un-checkpointed pages live in the WAL; without it the copy can be missing recent writes.
+  for suffix in ["-wal", "-shm"] {
+    let side = PathBuf::from(format!("{}{}", db.display(), suffix));
+    if side.exists() {
+      std::fs::copy(&side, PathBuf::from(format!("{}{}", dest.display(), suffix)))?;
+    }
+  }
+  Ok(())
+}
+
+// Deletes all but the newest `keep` backups (with their -wal/-shm copies); returns the names removed.
+pub(crate) fn prune_backups(dir: &PathBuf, keep: usize) -> Vec<String> {
+  let mut backups: Vec<(u64, String)> = std::fs::read_dir(dir)
+    .map(|entries| {
+      entries
+        .flatten()
+        .filter_map(|e| {
+          let name = e.file_name().to_string_lossy().to_string();
+          let ts = name.strip_prefix("moondream-")?.strip_suffix(".sqlite3")?.parse().ok()?;
+          Some((ts, name))
+        })
+        .collect()
+    })
+    .unwrap_or_default();
+  backups.sort_by_key(|b| std::cmp::Reverse(b.0));
+  let mut removed = Vec::new();
+  for (_, name) in backups.into_iter().skip(keep) {
+    if std::fs::remove_file(dir.join(&name)).is_ok() {
+      for suffix in ["-wal", "-shm"] {
+        let _ = std::fs::remove_file(dir.join(format!("{}{}", name, suffix)));
+      }
+      removed.push(name);
+    }
+  }
+  removed
+}
+
+pub(crate) fn request_retention(addr: &ServerAddr, token: Option<&str>, days: u32, dry_run: bool) -> Result<u64, String> {
+  let body = serde_json::to_string(&RetentionRequest {
+    older_than_days: days,
+    dry_run,
+  })
+  .map_err(|e| e.to_string())?;
+  match server_request(addr, "POST", "/api/maintenance/retention", Some(&body), token, RETENTION_TIMEOUT) {
+    Some((200, body)) => serde_json::from_str::<RetentionResponse>(&body)
+      .map(|r| r.count)
+      .map_err(|e| format!("Unexpected retention response: {}", e)),
+    Some((status, body)) if route_missing(status, &body) => Err(
+      "This server build has no /api/maintenance/retention, so retention isn't available.".to_string(),
+    ),
+    Some((status, body)) => Err(format!("Retention request failed ({}): {}", status, body.trim())),
+    None => Err("The local server did not respond".to_string()),
+  }
+}
+
+// Servers without the vacuum route get it from the sqlite3 CLI on the file instead.
+pub(crate) fn vacuum_db(addr: &ServerAddr, token: Option<&str>, db_path: &PathBuf) -> Result<(), String> {
+  match server_request(addr, "POST", "/api/maintenance/vacuum", None, token, RETENTION_TIMEOUT) {
+    Some((status, _)) if (200..300).contains(&status) => Ok(()),
+    Some((status, body)) if route_missing(status, &body) => {
+      let out = Command::new("sqlite3")
+        .arg("-cmd")
+        .arg(".timeout 30000")
+        .arg(db_path)
+        .arg("VACUUM;")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("The server can't compact and sqlite3 isn't available: {}", e))?;
+      if !out.status.success() {
+        return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+      }
+      Ok(())
+    }
+    Some((status, body)) => Err(format!("Compaction failed ({}): {}", status, body.trim())),
+    None => Err("The local server did not respond".to_string()),
+  }
+}
+
+pub(crate) fn run_retention(app: &tauri::AppHandle, days: u32, dry_run: bool, compact: bool) -> Result<RetentionResult, String> {
+  if days == 0 {
+    return Err("Retention must be at least 1 day".to_string());
+  }
+  let state = app.state::<ServerState>();
+  let addr = current_server_addr(&state).ok_or_else(|| "The local server is not running".to_string())?;
+  let token = state.session_token.lock().unwrap().clone();
+  let config_root = require_config_root(&state)?;
+  let data_dir = require_data_dir(&state)?;
+
+  let count = request_retention(&addr, token.as_deref(), days, true)?;
+  if dry_run || count == 0 {
+    return Ok(RetentionResult {
+      count,
+      dry_run,
+      backup_path: None,
+      compacted: false,
+    });
+  }
+
+  let db_path = state.db_path.lock().unwrap().clone().unwrap_or_else(|| db_path_for(&data_dir));
+  let backup = backup_db(&state, &config_root, &db_path).map_err(|e| format!("Backup failed, nothing was deleted: {}", e))?;
+  let count = request_retention(&addr, token.as_deref(), days, false)?;
+  record_deletes(&config_root, count);
+  let compacted = compact
+    && match vacuum_db(&addr, token.as_deref(), &db_path) {
+      Ok(()) => true,
+      Err(e) => {
+        host_log(LogLevel::Warn, &format!("Compaction after retention failed: {}", e));
+        false
+      }
+    };
+
+  if compacted {
+    reset_delete_count(&config_root);
+  }
+  Ok(RetentionResult {
+    count,
+    dry_run,
+    backup_path: Some(backup.to_string_lossy().to_string()),
+    compacted,
+  })
+}
+
+#[tauri::command(async)]
+pub(crate) fn apply_retention(
+  app: tauri::AppHandle,
+  days: u32,
+  dry_run: bool,
+  compact: Option<bool>,
+) -> Result<RetentionResult, String> {
+  run_retention(&app, days, dry_run, compact.unwrap_or(false))
+}
+
+// `days` of None/0 turns the scheduled purge off.
+#[tauri::command]
+pub(crate) fn set_retention_policy(app: tauri::AppHandle, days: Option<u32>, compact: Option<bool>) -> Result<(), String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let mut settings = try_read_settings(&config_root)?;
+  settings.retention = Some(RetentionSettings {
+    days: days.filter(|d| *d > 0),
+    compact,
+  });
+  write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+// Enforces the persisted policy at most once a day (re-read each tick so changes apply without a restart).
+pub(crate) fn schedule_retention(app: tauri::AppHandle, config_root: PathBuf) {
+  std::thread::spawn(move || loop {
+    std::thread::sleep(RETENTION_CHECK_INTERVAL);
+    let policy = read_settings(&config_root).retention.unwrap_or_default();
+    let days = match policy.days {
+      Some(d) if d > 0 => d,
+      _ => continue,
+    };
+    if read_runtime(&config_root)
+      .last_retention_at
+      .map(|t| unix_now_secs().saturating_sub(t) < RETENTION_RUN_EVERY_SECS)
+      .unwrap_or(false)
+    {
+      continue;
+    }
+    if run_retention(&app, days, false, policy.compact.unwrap_or(false)).is_ok() {
+      let mut rt = read_runtime(&config_root);
+      rt.last_retention_at = Some(unix_now_secs());
+      write_runtime(&config_root, &rt);
+    }
+  });
+}
+
+pub(crate) const AUTO_COMPACT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// No deletes for this long counts as idle (bulk deletes tend to come in bursts).
+pub(crate) const AUTO_COMPACT_QUIET_SECS: u64 = 5 * 60;
+
+// How long to wait for in-flight jobs to finish once the worker is paused.
+pub(crate) const COMPACT_DRAIN_TIMEOUT: Duration = Duration::from_secs(120);
+
+pub(crate) fn record_deletes(config_root: &PathBuf, count: u64) {
+  if count == 0 {
+    return;
+  }
+  let mut rt = read_runtime(config_root);
+  rt.deletes_since_compact = Some(rt.deletes_since_compact.unwrap_or(0).saturating_add(count));
+  rt.last_delete_at = Some(unix_now_secs());
+  write_runtime(config_root, &rt);
+}
+
+pub(crate) fn reset_delete_count(config_root: &PathBuf) {
+  let mut rt = read_runtime(config_root);
+  rt.deletes_since_compact = None;
+  write_runtime(config_root, &rt);
+}
+
+// Called by the UI after it deletes assets (`count` = how many).
+#[tauri::command]
+pub(crate) fn report_deletes(app: tauri::AppHandle, state: tauri::State<ServerState>, count: u64) -> Result<(), String> {
+  record_deletes(&managed_config_root(&app, &state)?, count);
+  Ok(())
+}
+
+pub(crate) fn jobs_in_flight(state: &ServerState) -> Option<u64> {
+  let db_path = state.db_path.lock().unwrap().clone();
+  db_path
+    .as_ref()
+    .and_then(queue_from_sqlite)
+    .or_else(|| queue_from_server(state))
+    .map(|q| q.processing)
+}
+
+// Pauses the worker ("compacting") and waits out its in-flight jobs, so nothing it does touches
+// the DB file while `f` rewrites or copies it.
+pub(crate) fn with_worker_held<T>(
+  state: &ServerState,
+  config_root: &PathBuf,
+  f: impl FnOnce() -> Result<T, String>,
+) -> Result<T, String> {
+  let pause_when_offline = pause_worker_when_offline(&effective_settings(state, config_root));
+  if std::mem::replace(&mut *state.db_compacting.lock().unwrap(), true) {
+    return Err("The database is already being compacted or backed up".to_string());
+  }
+  sync_worker_pause(state, config_root, pause_when_offline);
+  let result = drain_worker(state).and_then(|_| f());
+  *state.db_compacting.lock().unwrap() = false;
+  sync_worker_pause(state, config_root, pause_when_offline);
+  result
+}
+
+pub(crate) fn drain_worker(state: &ServerState) -> Result<(), String> {
+  if worker_running(state) {
+    let start = Instant::now();
+    while jobs_in_flight(state).unwrap_or(0) > 0 {
+      if start.elapsed() >= COMPACT_DRAIN_TIMEOUT {
+        return Err("The worker is still processing; try again later".to_string());
+      }
+      std::thread::sleep(Duration::from_secs(1));
+    }
+  }
+  Ok(())
+}
+
+pub(crate) fn vacuum_now(app: &tauri::AppHandle, state: &ServerState, addr: &ServerAddr, db_path: &PathBuf) -> Result<(), String> {
+  if let Some(window) = app.get_window("main") {
+    dispatch_web_event(&window, "moondream:db:compacting", None);
+  }
+  let token = state.session_token.lock().unwrap().clone();
+  vacuum_db(addr, token.as_deref(), db_path)?;
+  checkpoint_wal_now(state, db_path)
+}
+
+// VACUUM (see `vacuum_db`) then a WAL checkpoint. The worker is paused
+// ("compacting") and its in-flight jobs drained first so it never writes during the rewrite.
+pub(crate) fn compact_database_now(app: &tauri::AppHandle) -> Result<(), String> {
+  let state = app.state::<ServerState>();
+  let addr = current_server_addr(&state).ok_or_else(|| "The local server is not running".to_string())?;
+  let config_root = managed_config_root(app, &state)?;
+  let db_path = current_db_path(&state).ok_or_else(|| "No library is open".to_string())?;
+  let result = with_worker_held(&state, &config_root, || vacuum_now(app, &state, &addr, &db_path));
+  if result.is_ok() {
+    reset_delete_count(&config_root);
+  }
+  if let Some(window) = app.get_window("main") {
+    dispatch_web_event(
+      &window,
+      "moondream:db:compacted",
+      Some(serde_json::json!({ "ok": result.is_ok(), "error": result.as_ref().err() })),
+    );
+  }
+  result
+}
+
+#[tauri::command(async)]
+pub(crate) fn compact_database(app: tauri::AppHandle) -> Result<(), String> {
+  compact_database_now(&app)
+}
+
+// Compacts once `maintenance.auto_compact_after_deletes` is reached and things are quiet: no recent
+// deletes and no jobs being processed (re-read each tick so setting changes apply without a restart).
+pub(crate) fn schedule_auto_compact(app: tauri::AppHandle, config_root: PathBuf) {
+  std::thread::spawn(move || loop {
+    std::thread::sleep(AUTO_COMPACT_CHECK_INTERVAL);
+    let threshold = match read_settings(&config_root).maintenance.and_then(|m| m.auto_compact_after_deletes) {
+      Some(t) if t > 0 => t,
+      _ => continue,
+    };
+    let rt = read_runtime(&config_root);
+    if rt.deletes_since_compact.unwrap_or(0) < threshold {
+      continue;
+    }
+    if rt.last_delete_at.map(|t| unix_now_secs().saturating_sub(t) < AUTO_COMPACT_QUIET_SECS).unwrap_or(false) {
+      continue;
+    }
+    let state = app.state::<ServerState>();
+    if jobs_in_flight(&state).unwrap_or(0) > 0 {
+      continue;
+    }
+    match compact_database_now(&app) {
+      Ok(()) => host_log(LogLevel::Info, "Compacted the database after bulk deletes"),
+      Err(e) => host_log(LogLevel::Warn, &format!("Automatic compaction skipped: {}", e)),
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn backups_keep_the_newest() {
+    let dir = scratch_dir("backups");
+    for ts in [100, 300, 200, 400] {
+      std::fs::write(dir.join(format!("moondream-{}.sqlite3", ts)), b"").unwrap();
+    }
+    std::fs::write(dir.join("moondream-100.sqlite3-wal"), b"").unwrap();
+    std::fs::write(dir.join("notes.txt"), b"").unwrap();
+    let mut removed = prune_backups(&dir, 2);
+    removed.sort();
+    assert_eq!(removed, vec!["moondream-100.sqlite3", "moondream-200.sqlite3"]);
+    assert!(!dir.join("moondream-100.sqlite3-wal").exists());
+    assert!(dir.join("moondream-400.sqlite3").exists() && dir.join("notes.txt").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+}
@@ -0,0 +1,747 @@
+use crate::*;
+
+#[derive(Clone, Serialize)]
+pub(crate) struct AboutInfo {
+  pub(crate) name: String,
+  pub(crate) version: String,
+  pub(crate) identifier: String,
+  pub(crate) node: Option<NodeInfo>,
+  // Cached from the first worker start (None until then); `worker_runtime_info` probes on demand.
+  pub(crate) worker: Option<WorkerRuntimeInfo>,
+  pub(crate) hardware_acceleration: String, // "default" | "on" | "off" | "unsupported" (see `apply_hardware_acceleration`)
+  pub(crate) arch: ArchInfo,
+}
+
+#[tauri::command]
+pub(crate) fn about_info(app: tauri::AppHandle, state: tauri::State<ServerState>) -> AboutInfo {
+  let pkg = app.package_info();
+  let worker = state.worker_runtime.lock().unwrap().clone();
+  AboutInfo {
+    name: pkg.name.clone(),
+    version: pkg.version.to_string(),
+    identifier: app.config().tauri.bundle.identifier.clone(),
+    hardware_acceleration: state.hardware_acceleration.clone(),
+    node: check_node(app.clone(), state).ok(),
+    worker,
+    arch: arch_info_for(&app),
+  }
+}
+
+pub(crate) const SELF_TEST_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub(crate) const SELF_TEST_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Clone, Serialize)]
+pub(crate) struct SelfTestStep {
+  pub(crate) name: String,
+  pub(crate) ok: bool,
+  pub(crate) ms: u64,
+  pub(crate) detail: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct SelfTestReport {
+  pub(crate) ok: bool,
+  pub(crate) steps: Vec<SelfTestStep>,
+}
+
+// Last non-empty line of a log, for a step's `detail` (the temp config root is deleted).
+pub(crate) fn last_log_line(path: &PathBuf) -> Option<String> {
+  let text = std::fs::read_to_string(path).ok()?;
+  text.lines().rev().find(|l| !l.trim().is_empty()).map(|l| l.trim().to_string())
+}
+
+// Runs the launch sequence against a throwaway config root and library: its own port, DB and
+// worker control dir, so the running app is untouched. Steps after a failure are skipped.
+#[tauri::command(async)]
+pub(crate) fn self_test(app: tauri::AppHandle, state: tauri::State<ServerState>) -> SelfTestReport {
+  let mut steps: Vec<SelfTestStep> = Vec::new();
+  let mut step = |name: &str, started: Instant, result: Result<Option<String>, String>| {
+    let ok = result.is_ok();
+    steps.push(SelfTestStep {
+      name: name.to_string(),
+      ok,
+      ms: started.elapsed().as_millis() as u64,
+      detail: result.unwrap_or_else(Some),
+    });
+    ok
+  };
+
+  let root = std::env::temp_dir().join(format!("reference-self-test-{}-{}", std::process::id(), unix_now_secs()));
+  let data_dir = root.join("data");
+  let db_path = db_path_for(&data_dir);
+  // Defaults plus the user's Node/AI/security choices; nothing else (library, ports, worker
+  // limits, hooks) can leak into the scratch run.
+  let user = managed_config_root(&app, &state)
+    .map(|c| effective_settings(&state, &c))
+    .unwrap_or_default();
+  let user_server = user.server.unwrap_or_default();
+  let settings = AppSettings {
+    ai: user.ai,
+    security: user.security,
+    server: Some(ServerSettings {
+      node_source: user_server.node_source,
+      ready_path: user_server.ready_path,
+      transport: Some("tcp".to_string()),
+      ..Default::default()
+    }),
+    ..Default::default()
+  };
+
+  let mut server_child = None;
+  let mut worker_child = None;
+  'run: {
+    let t = Instant::now();
+    let prepared = std::fs::create_dir_all(&data_dir)
+      .and_then(|_| write_session_token(&root, &generate_token()))
+      .map(|_| Some(root.to_string_lossy().to_string()))
+      .map_err(|e| e.to_string());
+    if !step("prepare", t, prepared) {
+      break 'run;
+    }
+
+    let t = Instant::now();
+    let port = pick_free_port();
+    let addr = ServerAddr::Tcp(port);
+    if !step("pick_port", t, Ok(Some(port.to_string()))) {
+      break 'run;
+    }
+
+    let t = Instant::now();
+    let spawned = spawn_next_server_process(&app, &addr, &root, &data_dir, &db_path, &settings)
+      .map(|(child, node)| {
+        server_child = Some(child);
+        Some(format!("node {} ({})", node.version.unwrap_or_default(), node.source))
+      })
+      .map_err(|e| e.to_string());
+    if !step("spawn_server", t, spawned) {
+      break 'run;
+    }
+
+    let t = Instant::now();
+    let ready_path = server_ready_path(&settings);
+    let ready = if server_get_200(&addr, &ready_path, SELF_TEST_READY_TIMEOUT) {
+      Ok(Some(format!("{}{}", addr.base_url(), ready_path)))
+    } else {
+      Err(last_log_line(&logs_dir(&root).join("next-server.log"))
+        .unwrap_or_else(|| format!("No 200 from {} within {:?}", ready_path, SELF_TEST_READY_TIMEOUT)))
+    };
+    if !step("server_ready", t, ready) {
+      break 'run;
+    }
+
+    // The worker expects the server to have created the schema.
+    let t = Instant::now();
+    let created = if db_path.is_file() {
+      Ok(Some(db_path.to_string_lossy().to_string()))
+    } else {
+      Err(format!("The server didn't create {}", db_path.display()))
+    };
+    if !step("db_created", t, created) {
+      break 'run;
+    }
+
+    let t = Instant::now();
+    let spawned = spawn_worker_process(&app, &db_path, &root, &settings)
+      .map(|(child, _, _)| {
+        worker_child = Some(child);
+        None
+      })
+      .map_err(|e| e.to_string());
+    if !step("spawn_worker", t, spawned) {
+      break 'run;
+    }
+
+    let t = Instant::now();
+    let heartbeat = worker_control_dir(&root).join("heartbeat");
+    while !heartbeat.exists() && t.elapsed() < SELF_TEST_HEARTBEAT_TIMEOUT {
+      if let Some(Ok(Some(status))) = worker_child.as_mut().map(|w| w.try_wait()) {
+        let detail = last_log_line(&logs_dir(&root).join("moondream-worker.log"));
+        step("worker_heartbeat", t, Err(detail.unwrap_or_else(|| format!("The worker exited ({})", status))));
+        break 'run;
+      }
+      std::thread::sleep(Duration::from_millis(250));
+    }
+    let beat = if heartbeat.exists() {
+      Ok(None)
+    } else {
+      Err(format!("No heartbeat within {:?}", SELF_TEST_HEARTBEAT_TIMEOUT))
+    };
+    step("worker_heartbeat", t, beat);
+  }
+
+  // Always runs: the same graceful path as quitting.
+  if server_child.is_some() || worker_child.is_some() {
+    let t = Instant::now();
+    let grace = shutdown_grace(&settings);
+    let worker_clean = worker_child.map(|w| stop_child_gracefully("self-test worker", w, grace)).unwrap_or(true);
+    let server_clean = server_child.map(|c| stop_child_gracefully("self-test server", c, grace)).unwrap_or(true);
+    let teardown = if worker_clean && server_clean {
+      Ok(None)
+    } else {
+      Err(format!("Had to kill: {}{}", if worker_clean { "" } else { "worker " }, if server_clean { "" } else { "server" }))
+    };
+    step("teardown", t, teardown);
+  }
+  let _ = std::fs::remove_dir_all(&root);
+
+  let ok = steps.iter().all(|s| s.ok);
+  host_log(
+    if ok { LogLevel::Info } else { LogLevel::Warn },
+    &format!(
+      "self-test: {}",
+      steps.iter().map(|s| format!("{}={}({}ms)", s.name, if s.ok { "ok" } else { "FAIL" }, s.ms)).collect::<Vec<_>>().join(" ")
+    ),
+  );
+  SelfTestReport { ok, steps }
+}
+
+// Budget for each network probe in `full_status` (they run in parallel). The storage probe is a
+// filesystem call with no timeout, so the command itself runs off the UI thread.
+pub(crate) const STATUS_PROBE_TIMEOUT: Duration = Duration::from_millis(1500);
+
+#[derive(Clone, Serialize)]
+pub(crate) struct AiStatus {
+  pub(crate) provider: String,
+  pub(crate) endpoint: String,
+  pub(crate) reachable: bool,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct StorageReport {
+  pub(crate) mode: String,
+  pub(crate) path: Option<String>,
+  pub(crate) online: bool,
+  pub(crate) free_bytes: Option<u64>,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct FullStatus {
+  pub(crate) ok: bool,
+  pub(crate) port: Option<u16>,
+  pub(crate) server_ok: bool,
+  pub(crate) worker_running: bool,
+  // Set when the worker writes to a different DB than the server (fix with `repair_worker`).
+  pub(crate) worker_db_mismatch: Option<WorkerDbMismatch>,
+  pub(crate) ai: AiStatus,
+  pub(crate) storage: StorageReport,
+}
+
+// Minimal HTTP server in the host process for external monitoring: `GET /status` returns the
+// `full_status` JSON. Independent of the Next server, so it still answers when that one is down.
+// Requests are handled one at a time on a single thread: monitors poll, they don't fan out.
+pub(crate) fn serve_status(app: tauri::AppHandle, port: u16) {
+  let listener = match TcpListener::bind(("127.0.0.1", port)) {
+    Ok(l) => l,
+    Err(e) => {
+      host_log(LogLevel::Warn, &format!("status server: can't bind 127.0.0.1:{}: {}", port, e));
+      return;
+    }
+  };
+  host_log(LogLevel::Info, &format!("status server listening on 127.0.0.1:{}", port));
+  std::thread::spawn(move || {
+    for stream in listener.incoming().flatten() {
+      handle_status_request(&app, stream, port);
+    }
+  });
+}
+
+// Only our own origin: a web page can't read the answer cross-origin, but DNS rebinding would
+// let it reach 127.0.0.1 under its own host name.
+pub(crate) fn status_host_allowed(head: &str, port: u16) -> bool {
+  let host = head
+    .lines()
+    .skip(1)
+    .filter_map(|l| l.split_once(':'))
+    .find(|(k, _)| k.trim().eq_ignore_ascii_case("host"))
+    .map(|(_, v)| v.trim().to_lowercase());
+  host.is_some_and(|h| h == format!("127.0.0.1:{}", port) || h == format!("localhost:{}", port))
+}
+
+pub(crate) fn handle_status_request(app: &tauri::AppHandle, mut stream: TcpStream, port: u16) {
+  let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+  let _ = stream.set_write_timeout(Some(Duration::from_secs(5)));
+  let mut head = Vec::new();
+  let mut buf = [0u8; 1024];
+  while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < 8192 {
+    match stream.read(&mut buf) {
+      Ok(0) | Err(_) => break,
+      Ok(n) => head.extend_from_slice(&buf[..n]),
+    }
+  }
+  let head = String::from_utf8_lossy(&head);
+  let mut parts = head.lines().next().unwrap_or("").split_whitespace();
+  let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+  let (status, body) = match (method, path.split('?').next().unwrap_or("")) {
+    _ if !status_host_allowed(&head, port) => ("403 Forbidden", "{\"error\":\"forbidden host\"}".to_string()),
+    ("GET", "/status") => {
+      let status = full_status(app.clone(), app.state::<ServerState>());
+      ("200 OK", serde_json::to_string(&status).unwrap_or_else(|_| "{}".to_string()))
+    }
+    ("GET", _) => ("404 Not Found", "{\"error\":\"not found\"}".to_string()),
+    _ => ("405 Method Not Allowed", "{\"error\":\"method not allowed\"}".to_string()),
+  };
+  let _ = write!(
+    stream,
+    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+    status,
+    body.len(),
+    body
+  );
+}
+
+#[tauri::command(async)]
+pub(crate) fn full_status(app: tauri::AppHandle, state: tauri::State<ServerState>) -> FullStatus {
+  let config_root = state
+    .config_root
+    .lock()
+    .unwrap()
+    .clone()
+    .or_else(|| app_config_root(&app));
+  let settings = config_root.as_ref().map(|c| effective_settings(&state, c)).unwrap_or_default();
+  let addr = current_server_addr(&state);
+  let data_dir = state.data_dir.lock().unwrap().clone();
+  let worker_running = worker_running(&state);
+
+  let AiConfig { provider, endpoint, .. } = ai_config(&settings);
+
+  let (server_ok, ai_ok, (online, free)) = std::thread::scope(|s| {
+    let server = s.spawn(|| {
+      addr
+        .as_ref()
+        .map(|a| server_status(a, "/api/health", STATUS_PROBE_TIMEOUT) == Some(200))
+        .unwrap_or(false)
+    });
+    let ai = s.spawn(|| ai_reachable(&provider, &endpoint, STATUS_PROBE_TIMEOUT));
+    let storage = s.spawn(|| match data_dir.as_ref() {
+      Some(d) => (storage_online(d), free_bytes(d)),
+      None => (false, None),
+    });
+    (
+      server.join().unwrap_or(false),
+      ai.join().unwrap_or(false),
+      storage.join().unwrap_or((false, None)),
+    )
+  });
+
+  let worker_db_mismatch = config_root.as_ref().and_then(|c| worker_db_mismatch(&state, c));
+  FullStatus {
+    // AI is excluded: the app stays usable (uploads, browsing) without a model.
+    ok: server_ok && worker_running && worker_db_mismatch.is_none() && online,
+    port: match addr {
+      Some(ServerAddr::Tcp(port)) => Some(port),
+      _ => None,
+    },
+    server_ok,
+    worker_running,
+    worker_db_mismatch,
+    ai: AiStatus {
+      provider,
+      endpoint,
+      reachable: ai_ok,
+    },
+    storage: StorageReport {
+      mode: storage_mode(&settings),
+      path: data_dir.map(|p| p.to_string_lossy().to_string()),
+      online,
+      free_bytes: free,
+    },
+  }
+}
+
+pub(crate) const DIAGNOSTICS_LOG_LINES: usize = 20;
+
+// Last `n` lines of a log; only the final 64 KiB is read so big logs stay cheap.
+pub(crate) fn tail_lines(path: &PathBuf, n: usize) -> Vec<String> {
+  use std::io::{Seek, SeekFrom};
+
+  let mut file = match std::fs::File::open(path) {
+    Ok(f) => f,
+    Err(_) => return Vec::new(),
+  };
+  let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+  let start = len.saturating_sub(64 * 1024);
+  let mut buf = Vec::new();
+  if file.seek(SeekFrom::Start(start)).is_err() || file.read_to_end(&mut buf).is_err() {
+    return Vec::new();
+  }
+  let text = String::from_utf8_lossy(&buf);
+  let lines: Vec<&str> = text.lines().collect();
+  lines[lines.len().saturating_sub(n)..].iter().map(|l| l.to_string()).collect()
+}
+
+// Keeps scheme + host so support can tell local Station from a hosted endpoint; drops path/query/credentials.
+pub(crate) fn mask_endpoint(endpoint: &str) -> String {
+  match parse_host_port(endpoint) {
+    Some((host, _)) => {
+      let scheme = endpoint.split_once("://").map(|(s, _)| s).unwrap_or("http");
+      format!("{}://{}/***", scheme, host)
+    }
+    None => "(unset)".to_string(),
+  }
+}
+
+#[tauri::command(async)]
+pub(crate) fn diagnostics_text(app: tauri::AppHandle, state: tauri::State<ServerState>) -> String {
+  let text = build_diagnostics(&app, &state);
+  let mut clipboard = app.clipboard_manager();
+  let _ = clipboard.write_text(text.clone());
+  text
+}
+
+pub(crate) fn build_diagnostics(app: &tauri::AppHandle, state: &ServerState) -> String {
+  let pkg = app.package_info();
+  let config_root = managed_config_root(app, state).ok();
+  let settings = config_root
+    .as_ref()
+    .map(|c| effective_settings(state, c))
+    .unwrap_or_default();
+  let data_dir = state.data_dir.lock().unwrap().clone();
+  let node = state.node.lock().unwrap().clone();
+  let addr = current_server_addr(state);
+
+  let mut out = Vec::new();
+  out.push(format!("{} {} ({})", pkg.name, pkg.version, app.config().tauri.bundle.identifier));
+  out.push(format!("os: {} {}", std::env::consts::OS, std::env::consts::ARCH));
+  out.push(format!("safe mode: {}", *state.safe_mode.lock().unwrap()));
+  out.push(format!(
+    "node: {}",
+    node
+      .map(|n| format!("{} {} ({})", n.source, n.version.unwrap_or_default(), n.path))
+      .unwrap_or_else(|| "(not started)".to_string())
+  ));
+  out.push(format!(
+    "config root: {}",
+    config_root.as_ref().map(|p| p.display().to_string()).unwrap_or_default()
+  ));
+  out.push(format!(
+    "data dir: {}",
+    data_dir.as_ref().map(|p| p.display().to_string()).unwrap_or_default()
+  ));
+  out.push(format!("storage mode: {}", storage_mode(&settings)));
+  out.push(format!(
+    "storage online: {}",
+    !*state.storage_offline.lock().unwrap()
+  ));
+  out.push(format!(
+    "server: {}",
+    match addr.as_ref() {
+      Some(a) => format!(
+        "{} (health: {})",
+        a.base_url(),
+        server_status(a, "/api/health", STATUS_PROBE_TIMEOUT)
+          .map(|s| s.to_string())
+          .unwrap_or_else(|| "no response".to_string())
+      ),
+      None => "(not started)".to_string(),
+    }
+  ));
+  if let Some(config_root) = config_root.as_ref() {
+    let w = worker_status_for(state, config_root);
+    out.push(format!(
+      "worker: running={} paused={} reason={} heartbeat_age={} concurrency={}",
+      w.running,
+      w.paused,
+      w.pause_reason.unwrap_or_else(|| "-".to_string()),
+      w.heartbeat_age_secs.map(|s| format!("{}s", s)).unwrap_or_else(|| "-".to_string()),
+      w.concurrency.map(|c| c.to_string()).unwrap_or_else(|| "-".to_string())
+    ));
+  }
+  let ai = ai_config(&settings);
+  out.push(format!(
+    "ai: provider={} endpoint={} hf_token={}",
+    ai.provider,
+    mask_endpoint(&ai.endpoint),
+    if ai.hf_token.is_empty() { "unset" } else { "set" }
+  ));
+
+  if let Some(config_root) = config_root.as_ref() {
+    for which in ["host", "server", "worker", "station"] {
+      let name = log_file_name(which).unwrap_or_default();
+      out.push(String::new());
+      out.push(format!("--- {} (last {} lines) ---", name, DIAGNOSTICS_LOG_LINES));
+      out.extend(tail_lines(&logs_dir(config_root).join(name), DIAGNOSTICS_LOG_LINES));
+    }
+  }
+
+  // Logs can echo secrets verbatim; scrub every one we know about.
+  let mut text = out.join("\n");
+  let secrets = [
+    state.session_token.lock().unwrap().clone(),
+    // As configured: the default endpoint isn't a secret.
+    settings.ai.as_ref().and_then(|a| a.hf_token.clone()),
+    settings.ai.as_ref().and_then(|a| a.endpoint.clone()),
+  ];
+  for secret in secrets.iter().flatten().filter(|s| s.len() >= 8) {
+    text = text.replace(secret.as_str(), "[redacted]");
+  }
+  text
+}
+
+// Tauri 1 has no webview capture API, so grab the window's content area off the screen with
+// the platform tool (screencapture / PowerShell System.Drawing / ImageMagick `import` on X11).
+pub(crate) fn capture_window_png(window: &tauri::Window, dest: &PathBuf) -> Result<(), String> {
+  if window.is_minimized().unwrap_or(false) {
+    return Err("The window is minimized.".to_string());
+  }
+  let pos = window.inner_position().map_err(|e| e.to_string())?;
+  let size = window.inner_size().map_err(|e| e.to_string())?;
+  let (x, y, w, h) = (pos.x, pos.y, size.width, size.height);
+  let mut cmd = if cfg!(target_os = "macos") {
+    // screencapture works in points, not physical pixels.
+    let scale = window.scale_factor().unwrap_or(1.0);
+    let pt = |v: f64| (v / scale).round() as i64;
+    let mut c = Command::new("screencapture");
+    c.arg("-x")
+      .arg(format!("-R{},{},{},{}", pt(x as f64), pt(y as f64), pt(w as f64), pt(h as f64)))
+      .arg(dest);
+    c
+  } else if cfg!(target_os = "windows") {
+    let script = format!(
+      "Add-Type -AssemblyName System.Drawing; $b = New-Object System.Drawing.Bitmap {w}, {h}; \
+       [System.Drawing.Graphics]::FromImage($b).CopyFromScreen({x}, {y}, 0, 0, $b.Size); \
+       $b.Save('{p}', [System.Drawing.Imaging.ImageFormat]::Png)",
+      p = dest.to_string_lossy().replace('\'', "''")
+    );
+    let mut c = Command::new("powershell");
+    c.args(["-NoProfile", "-NonInteractive", "-Command", &script]);
+    c
+  } else {
+    let mut c = Command::new("import");
+    c.args(["-window", "root", "-crop", &format!("{}x{}+{}+{}", w, h, x, y)]).arg(dest);
+    c
+  };
+  let out = cmd
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::piped())
+    .output()
+    .map_err(|e| format!("Screenshot tool unavailable: {}", e))?;
+  if !out.status.success() || !dest.is_file() {
+    return Err(format!(
+      "Screenshot failed: {}",
+      String::from_utf8_lossy(&out.stderr).trim()
+    ));
+  }
+  Ok(())
+}
+
+// `dest` may be a folder (a timestamped file is created in it) or a .png path; default is the temp dir.
+pub(crate) fn screenshot_path(dest: Option<String>) -> Result<PathBuf, String> {
+  let name = format!("moondream-screenshot-{}.png", unix_now_secs());
+  let Some(dest) = dest.map(|d| d.trim().to_string()).filter(|d| !d.is_empty()) else {
+    return Ok(std::env::temp_dir().join(name));
+  };
+  let p = PathBuf::from(dest);
+  if !p.is_absolute() {
+    return Err(format!("Expected an absolute path, got {}", p.display()));
+  }
+  Ok(if p.is_dir() { p.join(name) } else { p.with_extension("png") })
+}
+
+#[tauri::command(async)]
+pub(crate) fn capture_screenshot(app: tauri::AppHandle, dest: Option<String>) -> Result<String, String> {
+  let window = app.get_window("main").ok_or_else(|| "No main window".to_string())?;
+  let path = screenshot_path(dest)?;
+  capture_window_png(&window, &path)?;
+  Ok(path.to_string_lossy().to_string())
+}
+
+// Writes a `moondream-diagnostics-<ts>` folder with diagnostics.txt (same redacted text as
+// `diagnostics_text`) and, optionally, a screenshot of the window. Returns the folder path.
+#[tauri::command(async)]
+pub(crate) fn export_diagnostics(app: tauri::AppHandle, dest: Option<String>, include_screenshot: Option<bool>) -> Result<String, String> {
+  let state = app.state::<ServerState>();
+  let parent = match dest.map(|d| PathBuf::from(d.trim())) {
+    Some(p) if p.is_absolute() => p,
+    Some(p) => return Err(format!("Expected an absolute path, got {}", p.display())),
+    None => std::env::temp_dir(),
+  };
+  let dir = parent.join(format!("moondream-diagnostics-{}", unix_now_secs()));
+  std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+  let mut text = build_diagnostics(&app, &state);
+  if include_screenshot.unwrap_or(true) {
+    let shot = dir.join("screenshot.png");
+    let captured = app
+      .get_window("main")
+      .ok_or_else(|| "No main window".to_string())
+      .and_then(|w| capture_window_png(&w, &shot));
+    // A missing screenshot shouldn't cost the user the rest of the report.
+    if let Err(e) = captured {
+      text.push_str(&format!("\n\nscreenshot: {}", e));
+    }
+  }
+  std::fs::write(dir.join("diagnostics.txt"), text).map_err(|e| e.to_string())?;
+  Ok(dir.to_string_lossy().to_string())
+}
+
+// Baked in at build time; without it the mail client asks for a recipient.
+pub(crate) const SUPPORT_EMAIL: Option<&str> = option_env!("MOONDREAM_SUPPORT_EMAIL");
+
+// Windows' mailto handler and several mail clients cut URLs off around 2 KB.
+pub(crate) const MAILTO_MAX_LEN: usize = 1900;
+
+pub(crate) fn mailto_encode(s: &str) -> String {
+  s.bytes()
+    .map(|b| match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+      _ => format!("%{:02X}", b),
+    })
+    .collect()
+}
+
+// Drops whole lines from the end until the URL fits, noting that the rest is in the attachment.
+pub(crate) fn support_mailto(subject: &str, body: &str) -> String {
+  let prefix = format!("mailto:{}?subject={}&body=", SUPPORT_EMAIL.unwrap_or(""), mailto_encode(subject));
+  let note = "\n[truncated; the full report is in the attached zip]";
+  let mut lines: Vec<&str> = body.lines().collect();
+  let mut truncated = false;
+  loop {
+    let text = if truncated { format!("{}{}", lines.join("\n"), note) } else { lines.join("\n") };
+    let url = format!("{}{}", prefix, mailto_encode(&text));
+    if url.len() <= MAILTO_MAX_LEN || lines.is_empty() {
+      return url;
+    }
+    lines.pop();
+    truncated = true;
+  }
+}
+
+pub(crate) fn zip_dir(src: &PathBuf, dest: &PathBuf) -> Result<(), String> {
+  let mut cmd = if cfg!(target_os = "macos") {
+    let mut c = Command::new("ditto");
+    c.args(["-c", "-k", "--keepParent"]).arg(src).arg(dest);
+    c
+  } else if cfg!(target_os = "windows") {
+    let script = format!(
+      "Compress-Archive -Path '{}' -DestinationPath '{}' -Force",
+      src.to_string_lossy().replace('\'', "''"),
+      dest.to_string_lossy().replace('\'', "''")
+    );
+    let mut c = Command::new("powershell");
+    c.args(["-NoProfile", "-NonInteractive", "-Command", &script]);
+    c
+  } else {
+    let mut c = Command::new("zip");
+    c.arg("-qr").arg(dest).arg(src.file_name().unwrap_or_default());
+    if let Some(parent) = src.parent() {
+      c.current_dir(parent);
+    }
+    c
+  };
+  let out = cmd
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::piped())
+    .output()
+    .map_err(|e| format!("No zip tool available: {}", e))?;
+  if !out.status.success() || !dest.is_file() {
+    return Err(format!("Zipping failed: {}", String::from_utf8_lossy(&out.stderr).trim()));
+  }
+  Ok(())
+}
+
+// Shows `path` selected in Finder / Explorer; elsewhere opens its folder.
+pub(crate) fn reveal_in_file_manager(path: &PathBuf) -> Result<(), String> {
+  let mut cmd = if cfg!(target_os = "macos") {
+    let mut c = Command::new("open");
+    c.arg("-R").arg(path);
+    c
+  } else if cfg!(target_os = "windows") {
+    let mut c = Command::new("explorer");
+    c.arg(format!("/select,{}", path.display()));
+    c
+  } else {
+    let mut c = Command::new("xdg-open");
+    c.arg(path.parent().unwrap_or(path));
+    c
+  };
+  cmd
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .spawn()
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+pub(crate) fn open_mailto(url: &str) -> Result<(), String> {
+  let mut cmd = if cfg!(target_os = "macos") {
+    Command::new("open")
+  } else if cfg!(target_os = "windows") {
+    // `start` would need the &s escaped for cmd.exe.
+    let mut c = Command::new("rundll32");
+    c.arg("url.dll,FileProtocolHandler");
+    c
+  } else {
+    Command::new("xdg-open")
+  };
+  cmd
+    .arg(url)
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .spawn()
+    .map(|_| ())
+    .map_err(|e| format!("Couldn't open the mail client: {}", e))
+}
+
+// Exports diagnostics (zipped when a zip tool is around), reveals the file and opens a prefilled
+// email in the default mail client. Never sends anything itself. Returns the attachment path.
+#[tauri::command(async)]
+pub(crate) fn contact_support(app: tauri::AppHandle) -> Result<String, String> {
+  let dir = PathBuf::from(export_diagnostics(app.clone(), None, Some(true))?);
+  // Summary first, logs last, so truncation only ever eats log lines.
+  let report = std::fs::read_to_string(dir.join("diagnostics.txt")).unwrap_or_default();
+  let zip = dir.with_extension("zip");
+  let attachment = match zip_dir(&dir, &zip) {
+    Ok(()) => zip,
+    Err(e) => {
+      host_log(LogLevel::Warn, &format!("{}; attaching the folder instead", e));
+      dir
+    }
+  };
+  let pkg = app.package_info();
+  let body = format!(
+    "What happened, and what did you expect?\n\n\n\nPlease attach this file before sending:\n{}\n\n{}",
+    attachment.display(),
+    report
+  );
+  let url = support_mailto(&format!("{} {} support request", pkg.name, pkg.version), &body);
+  let _ = reveal_in_file_manager(&attachment);
+  open_mailto(&url)?;
+  Ok(attachment.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn status_server_host_check() {
+    assert!(status_host_allowed("GET /status HTTP/1.1\r\nHost: 127.0.0.1:7070\r\n", 7070));
+    assert!(status_host_allowed("GET /status HTTP/1.1\r\nUser-Agent: curl\r\nhost: LocalHost:7070\r\n", 7070));
+    assert!(!status_host_allowed("GET /status HTTP/1.1\r\nHost: evil.example:7070\r\n", 7070));
+    assert!(!status_host_allowed("GET /status HTTP/1.1\r\nHost: 127.0.0.1:8080\r\n", 7070));
+    assert!(!status_host_allowed("GET /status HTTP/1.0\r\n", 7070));
+  }
+
+  #[test]
+  fn mailto_encoding() {
+    assert_eq!(mailto_encode("a-b_c.d~"), "a-b_c.d~");
+    assert_eq!(mailto_encode("a b&c=d\n"), "a%20b%26c%3Dd%0A");
+    assert_eq!(mailto_encode("é"), "%C3%A9");
+  }
+
+  #[test]
+  fn mailto_keeps_whole_lines_under_the_limit() {
+    assert!(support_mailto("Help", "line one\nline two").ends_with("?subject=Help&body=line%20one%0Aline%20two"));
+    let body: Vec<String> = (0..200).map(|i| format!("line {:03} of the report", i)).collect();
+    let url = support_mailto("Help", &body.join("\n"));
+    assert!(url.len() <= MAILTO_MAX_LEN);
+    let kept = url.split("&body=").nth(1).unwrap();
+    assert!(kept.starts_with("line%20000%20of%20the%20report%0A"));
+    assert!(kept.contains("%20of%20the%20report%0A%5Btruncated%3B"));
+  }
+}
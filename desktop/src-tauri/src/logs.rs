@@ -0,0 +1,514 @@
+use crate::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub(crate) enum LogLevel {
+  Error,
+  Warn,
+  Info,
+  Debug,
+}
+
+impl LogLevel {
+  pub(crate) fn parse(s: &str) -> Option<LogLevel> {
+    match s.trim().to_lowercase().as_str() {
+      "error" => Some(LogLevel::Error),
+      "warn" | "warning" => Some(LogLevel::Warn),
+      "info" => Some(LogLevel::Info),
+      "debug" => Some(LogLevel::Debug),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn as_str(&self) -> &'static str {
+    match self {
+      LogLevel::Error => "error",
+      LogLevel::Warn => "warn",
+      LogLevel::Info => "info",
+      LogLevel::Debug => "debug",
+    }
+  }
+}
+
+// The app's own log (`logs/host.log`), separate from the server/worker output it captures.
+pub(crate) struct HostLogger {
+  pub(crate) file: Mutex<std::fs::File>,
+  pub(crate) level: LogLevel,
+  pub(crate) json: bool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct HostLogLine {
+  pub(crate) ts: u64,
+  pub(crate) level: String,
+  pub(crate) msg: String,
+}
+
+// Set once at startup; format/level changes apply on the next launch.
+pub(crate) static HOST_LOGGER: std::sync::OnceLock<HostLogger> = std::sync::OnceLock::new();
+
+// Lines logged before `init_host_logger` (e.g. settings read in main()); written out once it runs.
+pub(crate) static EARLY_HOST_LOG: Mutex<Vec<(LogLevel, String)>> = Mutex::new(Vec::new());
+
+// Dev builds never initialize the logger; don't let the buffer grow without bound there.
+pub(crate) const EARLY_HOST_LOG_MAX: usize = 200;
+
+pub(crate) fn init_host_logger(config_root: &PathBuf, settings: &AppSettings) -> io::Result<()> {
+  let logging = settings.logging.clone().unwrap_or_default();
+  let level = logging
+    .level
+    .as_deref()
+    .and_then(LogLevel::parse)
+    .unwrap_or(LogLevel::Info);
+  let json = logging.format.as_deref().map(|f| f.trim().eq_ignore_ascii_case("json")).unwrap_or(false);
+  let log_dir = logs_dir(config_root);
+  std::fs::create_dir_all(&log_dir)?;
+  let log_path = log_dir.join("host.log");
+  rotate_log(&log_path, compress_rotated_logs(settings));
+  let file = OpenOptions::new().create(true).append(true).open(&log_path)?;
+  let _ = HOST_LOGGER.set(HostLogger {
+    file: Mutex::new(file),
+    level,
+    json,
+  });
+  for (level, msg) in std::mem::take(&mut *EARLY_HOST_LOG.lock().unwrap()) {
+    host_log(level, &msg);
+  }
+  Ok(())
+}
+
+pub(crate) fn host_log(level: LogLevel, msg: &str) {
+  let Some(logger) = HOST_LOGGER.get() else {
+    // Not initialized yet (or a dev build, where stderr is visible anyway).
+    eprintln!("[{}] {}", level.as_str(), msg);
+    let mut early = EARLY_HOST_LOG.lock().unwrap();
+    if early.len() < EARLY_HOST_LOG_MAX {
+      early.push((level, msg.to_string()));
+    }
+    return;
+  };
+  if level > logger.level {
+    return;
+  }
+  let ts = std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs();
+  let line = if logger.json {
+    serde_json::to_string(&HostLogLine {
+      ts,
+      level: level.as_str().to_string(),
+      msg: msg.to_string(),
+    })
+    .unwrap_or_default()
+  } else {
+    format!("{} [{}] {}", ts, level.as_str(), msg)
+  };
+  let _ = writeln!(logger.file.lock().unwrap(), "{}", line);
+}
+
+pub(crate) fn update_logging_settings(app: &tauri::AppHandle, f: impl FnOnce(&mut LoggingSettings)) -> Result<(), String> {
+  let config_root = app_config_root(app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let mut settings = try_read_settings(&config_root)?;
+  f(settings.logging.get_or_insert_with(Default::default));
+  write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+// Takes effect on next launch.
+#[tauri::command]
+pub(crate) fn set_log_level(app: tauri::AppHandle, level: String) -> Result<(), String> {
+  let level = LogLevel::parse(&level)
+    .ok_or_else(|| format!("Unknown log level: {} (expected error, warn, info or debug)", level))?;
+  update_logging_settings(&app, |l| l.level = Some(level.as_str().to_string()))
+}
+
+// Takes effect on next launch.
+#[tauri::command]
+pub(crate) fn set_log_format(app: tauri::AppHandle, format: String) -> Result<(), String> {
+  let format = format.trim().to_lowercase();
+  if format != "text" && format != "json" {
+    return Err(format!("Unknown log format: {} (expected text or json)", format));
+  }
+  update_logging_settings(&app, |l| l.format = Some(format))
+}
+
+// Logs are append-heavy; in a synced config root they'd churn sync constantly, so keep them local.
+pub(crate) fn logs_dir(config_root: &PathBuf) -> PathBuf {
+  if is_synced_path(config_root) {
+    local_cache_dir().join("logs")
+  } else {
+    config_root.join("logs")
+  }
+}
+
+// A log past this size is rotated when its process (re)starts; LOG_GENERATIONS old ones are kept.
+pub(crate) const LOG_ROTATE_BYTES: u64 = 10 * 1024 * 1024;
+
+pub(crate) const LOG_GENERATIONS: u32 = 5;
+
+pub(crate) fn compress_rotated_logs(settings: &AppSettings) -> bool {
+  settings.logging.as_ref().and_then(|l| l.compress_rotated).unwrap_or(true)
+}
+
+pub(crate) fn log_generation_path(log: &PathBuf, generation: u32, gz: bool) -> PathBuf {
+  let mut name = log.file_name().unwrap_or_default().to_os_string();
+  name.push(format!(".{}{}", generation, if gz { ".gz" } else { "" }));
+  log.with_file_name(name)
+}
+
+// Replaces `src` with `src.gz`.
+pub(crate) fn gzip_file(src: &PathBuf) -> io::Result<()> {
+  let status = if cfg!(windows) {
+    quiet_status(
+      Command::new("powershell")
+        .args([
+          "-NoProfile",
+          "-NonInteractive",
+          "-Command",
+          "$in = [IO.File]::OpenRead($env:MOONDREAM_GZ_SRC); $out = [IO.File]::Create($env:MOONDREAM_GZ_SRC + '.gz');          $gz = New-Object IO.Compression.GZipStream($out, [IO.Compression.CompressionMode]::Compress);          $in.CopyTo($gz); $gz.Close(); $in.Close(); Remove-Item -LiteralPath $env:MOONDREAM_GZ_SRC",
+        ])
+        .env("MOONDREAM_GZ_SRC", src),
+    )?
+  } else {
+    quiet_status(Command::new("gzip").arg("-f").arg(src))?
+  };
+  if status.success() {
+    Ok(())
+  } else {
+    Err(io::Error::other(format!("gzip exited with {}", status)))
+  }
+}
+
+pub(crate) fn gunzip_file(src: &PathBuf) -> io::Result<Vec<u8>> {
+  if cfg!(windows) {
+    let tmp = std::env::temp_dir().join(format!("moondream-log-{}-{}", std::process::id(), unix_now_secs()));
+    let status = quiet_status(
+      Command::new("powershell")
+        .args([
+          "-NoProfile",
+          "-NonInteractive",
+          "-Command",
+          "$in = [IO.File]::OpenRead($env:MOONDREAM_GZ_SRC); $out = [IO.File]::Create($env:MOONDREAM_GZ_DST);          $gz = New-Object IO.Compression.GZipStream($in, [IO.Compression.CompressionMode]::Decompress);          $gz.CopyTo($out); $out.Close(); $gz.Close()",
+        ])
+        .env("MOONDREAM_GZ_SRC", src)
+        .env("MOONDREAM_GZ_DST", &tmp),
+    )?;
+    let data = if status.success() { std::fs::read(&tmp) } else { Err(io::Error::other("decompression failed")) };
+    let _ = std::fs::remove_file(&tmp);
+    data
+  } else {
+    let out = Command::new("gzip").arg("-dc").arg(src).stdin(Stdio::null()).stderr(Stdio::null()).output()?;
+    if out.status.success() {
+      Ok(out.stdout)
+    } else {
+      Err(io::Error::other(format!("gzip -dc exited with {}", out.status)))
+    }
+  }
+}
+
+// Call before opening `log` for append. Shifts `<name>.N[.gz]` up one (dropping the oldest), moves
+// the live log to `<name>.1` and gzips that in the background so startup doesn't wait on it.
+pub(crate) fn rotate_log(log: &PathBuf, compress: bool) {
+  let too_big = std::fs::metadata(log).map(|m| m.len() >= LOG_ROTATE_BYTES).unwrap_or(false);
+  if !too_big {
+    return;
+  }
+  for gz in [false, true] {
+    let _ = std::fs::remove_file(log_generation_path(log, LOG_GENERATIONS, gz));
+    for n in (1..LOG_GENERATIONS).rev() {
+      let from = log_generation_path(log, n, gz);
+      if from.exists() {
+        let _ = std::fs::rename(&from, log_generation_path(log, n + 1, gz));
+      }
+    }
+  }
+  let first = log_generation_path(log, 1, false);
+  if let Err(e) = std::fs::rename(log, &first) {
+    host_log(LogLevel::Warn, &format!("Couldn't rotate {}: {}", log.display(), e));
+    return;
+  }
+  if compress {
+    std::thread::spawn(move || {
+      if let Err(e) = gzip_file(&first) {
+        // The plain `.1` stays and is still readable by `read_log`.
+        host_log(LogLevel::Warn, &format!("Couldn't compress {}: {}", first.display(), e));
+      }
+    });
+  }
+}
+
+pub(crate) const LOG_CAP_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Clone, Serialize)]
+pub(crate) struct LogFileUsage {
+  pub(crate) name: String,
+  pub(crate) bytes: u64,
+  // None for a live log.
+  pub(crate) generation: Option<u32>,
+  pub(crate) compressed: bool,
+  pub(crate) modified: Option<u64>,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct LogUsage {
+  pub(crate) dir: String,
+  pub(crate) total_bytes: u64,
+  pub(crate) cap_bytes: Option<u64>,
+  pub(crate) files: Vec<LogFileUsage>,
+}
+
+pub(crate) fn log_cap_bytes(settings: &AppSettings) -> Option<u64> {
+  settings
+    .logging
+    .as_ref()
+    .and_then(|l| l.max_total_mb)
+    .filter(|mb| *mb > 0)
+    .map(|mb| mb.saturating_mul(1024 * 1024))
+}
+
+// `<name>.N` / `<name>.N.gz` (see `log_generation_path`) -> (N, gz).
+pub(crate) fn log_generation_of(name: &str) -> Option<(u32, bool)> {
+  let (rest, gz) = match name.strip_suffix(".gz") {
+    Some(rest) => (rest, true),
+    None => (name, false),
+  };
+  let (_, n) = rest.rsplit_once('.')?;
+  n.parse::<u32>().ok().map(|n| (n, gz))
+}
+
+pub(crate) fn log_files(dir: &PathBuf) -> Vec<LogFileUsage> {
+  let Ok(entries) = std::fs::read_dir(dir) else {
+    return Vec::new();
+  };
+  let mut files: Vec<LogFileUsage> = entries
+    .flatten()
+    .filter_map(|e| {
+      let meta = e.metadata().ok().filter(|m| m.is_file())?;
+      let name = e.file_name().to_string_lossy().to_string();
+      let generation = log_generation_of(&name);
+      Some(LogFileUsage {
+        bytes: meta.len(),
+        generation: generation.map(|(n, _)| n),
+        compressed: generation.map(|(_, gz)| gz).unwrap_or(false),
+        modified: mtime_secs(&meta),
+        name,
+      })
+    })
+    .collect();
+  files.sort_by(|a, b| a.name.cmp(&b.name));
+  files
+}
+
+#[tauri::command]
+pub(crate) fn log_usage(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<LogUsage, String> {
+  let config_root = managed_config_root(&app, &state)?;
+  let dir = logs_dir(&config_root);
+  let files = log_files(&dir);
+  Ok(LogUsage {
+    dir: dir.to_string_lossy().to_string(),
+    total_bytes: files.iter().map(|f| f.bytes).sum(),
+    cap_bytes: log_cap_bytes(&read_settings(&config_root)),
+    files,
+  })
+}
+
+// Deletes rotated generations until the dir fits `cap`: compressed ones first, then plain ones,
+// each oldest first. Live logs are never touched, so the total can stay above a tiny cap.
+pub(crate) fn prune_logs(dir: &PathBuf, cap: u64) -> (Vec<String>, u64) {
+  let files = log_files(dir);
+  let mut total: u64 = files.iter().map(|f| f.bytes).sum();
+  let mut rotated: Vec<&LogFileUsage> = files.iter().filter(|f| f.generation.is_some()).collect();
+  rotated.sort_by_key(|f| (!f.compressed, std::cmp::Reverse(f.generation), f.modified));
+  let (mut removed, mut freed) = (Vec::new(), 0);
+  for f in rotated {
+    if total <= cap {
+      break;
+    }
+    if std::fs::remove_file(dir.join(&f.name)).is_ok() {
+      total -= f.bytes;
+      freed += f.bytes;
+      removed.push(f.name.clone());
+    }
+  }
+  (removed, freed)
+}
+
+pub(crate) fn enforce_log_cap(app: &tauri::AppHandle, config_root: &PathBuf) {
+  let Some(cap) = log_cap_bytes(&read_settings(config_root)) else {
+    return;
+  };
+  let dir = logs_dir(config_root);
+  let (removed, freed) = prune_logs(&dir, cap);
+  if removed.is_empty() {
+    return;
+  }
+  host_log(LogLevel::Info, &format!("Pruned {} old log file(s) ({} bytes) to stay under logging.max_total_mb", removed.len(), freed));
+  if let Some(window) = app.get_window("main") {
+    dispatch_web_event(
+      &window,
+      "moondream:logs:pruned",
+      Some(serde_json::json!({ "removed": removed, "freed_bytes": freed })),
+    );
+  }
+}
+
+// At startup, then every LOG_CAP_INTERVAL (settings re-read each time).
+pub(crate) fn schedule_log_cap(app: tauri::AppHandle, config_root: PathBuf) {
+  std::thread::spawn(move || loop {
+    enforce_log_cap(&app, &config_root);
+    std::thread::sleep(LOG_CAP_INTERVAL);
+  });
+}
+
+#[tauri::command]
+pub(crate) fn reveal_logs(app: tauri::AppHandle) -> Result<(), String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let dir = logs_dir(&config_root);
+  std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+  open_in_file_manager(&dir)
+}
+
+// Upper bound per `read_log` call so a huge request can't pull a whole log into memory.
+pub(crate) const LOG_CHUNK_MAX_BYTES: u64 = 1024 * 1024;
+
+#[derive(Clone, Serialize)]
+pub(crate) struct LogChunk {
+  pub(crate) which: String,
+  // 0 = the live log, N = `<name>.N[.gz]` (offsets are into the decompressed text).
+  pub(crate) generation: u32,
+  pub(crate) path: String,
+  pub(crate) offset: u64,
+  // Offset to pass for the following chunk (offset + bytes read).
+  pub(crate) next_offset: u64,
+  pub(crate) data: String,
+  pub(crate) total_size: u64,
+  pub(crate) has_more: bool,
+}
+
+pub(crate) fn log_file_name(which: &str) -> Option<&'static str> {
+  match which {
+    "server" => Some("next-server.log"),
+    "worker" => Some("moondream-worker.log"),
+    "station" => Some("moondream-station.log"),
+    "host" => Some("host.log"),
+    _ => None,
+  }
+}
+
+// Rotated generations are small enough (LOG_ROTATE_BYTES) to decompress whole per call.
+pub(crate) fn read_rotated_log(which: String, generation: u32, live: &PathBuf, offset: u64, max_bytes: u64) -> Result<LogChunk, String> {
+  let plain = log_generation_path(live, generation, false);
+  let gz = log_generation_path(live, generation, true);
+  // A plain `.N` is one whose compression failed (or was turned off).
+  let (path, data) = if plain.exists() {
+    (plain.clone(), std::fs::read(&plain))
+  } else if gz.exists() {
+    (gz.clone(), gunzip_file(&gz))
+  } else {
+    return Err(format!("No generation {} of the {} log", generation, which));
+  };
+  let data = data.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+  let total_size = data.len() as u64;
+  let offset = offset.min(total_size);
+  let next_offset = offset + max_bytes.min(LOG_CHUNK_MAX_BYTES).min(total_size - offset);
+  Ok(LogChunk {
+    which,
+    generation,
+    path: path.to_string_lossy().to_string(),
+    offset,
+    next_offset,
+    data: String::from_utf8_lossy(&data[offset as usize..next_offset as usize]).to_string(),
+    total_size,
+    has_more: next_offset < total_size,
+  })
+}
+
+// `generation` None/0 reads the live log; 1..=LOG_GENERATIONS reads a rotated one.
+#[tauri::command]
+pub(crate) fn read_log(
+  app: tauri::AppHandle,
+  which: String,
+  offset: u64,
+  max_bytes: u64,
+  generation: Option<u32>,
+) -> Result<LogChunk, String> {
+  use std::io::{Seek, SeekFrom};
+
+  let name = log_file_name(&which).ok_or_else(|| format!("Unknown log: {} (expected server, worker, station or host)", which))?;
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let path = logs_dir(&config_root).join(name);
+  match generation.unwrap_or(0) {
+    0 => {}
+    n if n > LOG_GENERATIONS => return Err(format!("Only {} rotated generations are kept", LOG_GENERATIONS)),
+    n => return read_rotated_log(which, n, &path, offset, max_bytes),
+  }
+  let mut file = match std::fs::File::open(&path) {
+    Ok(f) => f,
+    // Nothing logged yet: an empty log, not an error.
+    Err(e) if e.kind() == ErrorKind::NotFound => {
+      return Ok(LogChunk {
+        which,
+        generation: 0,
+        path: path.to_string_lossy().to_string(),
+        offset: 0,
+        next_offset: 0,
+        data: String::new(),
+        total_size: 0,
+        has_more: false,
+      });
+    }
+    Err(e) => return Err(e.to_string()),
+  };
+  let total_size = file.metadata().map_err(|e| e.to_string())?.len();
+  let offset = offset.min(total_size);
+  let len = max_bytes.min(LOG_CHUNK_MAX_BYTES).min(total_size - offset);
+
+  let mut buf = vec![0u8; len as usize];
+  file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+  file.read_exact(&mut buf).map_err(|e| e.to_string())?;
+
+  let next_offset = offset + len;
+  Ok(LogChunk {
+    which,
+    generation: 0,
+    path: path.to_string_lossy().to_string(),
+    offset,
+    next_offset,
+    // Chunk edges can split a multi-byte char; those bytes show up as U+FFFD.
+    data: String::from_utf8_lossy(&buf).to_string(),
+    total_size,
+    has_more: next_offset < total_size,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn log_generations() {
+    assert_eq!(log_generation_of("host.log.1"), Some((1, false)));
+    assert_eq!(log_generation_of("next-server.log.3.gz"), Some((3, true)));
+    assert_eq!(log_generation_of("host.log"), None);
+    assert_eq!(log_generation_of("worker.log.gz"), None);
+  }
+
+  #[test]
+  fn pruning_drops_compressed_then_oldest_generations() {
+    let dir = scratch_dir("prune-logs");
+    for (name, len) in [("host.log", 100), ("host.log.1", 100), ("host.log.2", 100), ("host.log.3.gz", 50), ("host.log.4.gz", 50)] {
+      std::fs::write(dir.join(name), vec![b'x'; len]).unwrap();
+    }
+    let (removed, freed) = prune_logs(&dir, 250);
+    assert_eq!(removed, vec!["host.log.4.gz", "host.log.3.gz", "host.log.2"]);
+    assert_eq!(freed, 200);
+    // The live log is never pruned, even when it alone is over the cap.
+    let (removed, _) = prune_logs(&dir, 0);
+    assert_eq!(removed, vec!["host.log.1"]);
+    assert!(dir.join("host.log").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+}
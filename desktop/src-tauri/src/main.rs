@@ -13,606 +13,335 @@ use std::time::{Duration, Instant};
 use std::io::{Read, Write};
 
 use serde::{Deserialize, Serialize};
-use tauri::Manager;
+use tauri::{ClipboardManager, Manager};
 use tauri::{AboutMetadata, CustomMenuItem, Menu, MenuItem, Submenu};
 
+mod ai;
+mod database;
+mod diagnostics;
+mod logs;
+mod menu;
+mod server;
+mod settings;
+mod storage;
+mod window;
+mod worker;
+
+use ai::*;
+use database::*;
+use diagnostics::*;
+use logs::*;
+use menu::*;
+use server::*;
+use settings::*;
+use storage::*;
+use window::*;
+use worker::*;
+
 struct ServerState {
   port: Mutex<Option<u16>>,
+  // Set instead of `port` when the server listens on a Unix socket (`server.transport = "unix"`).
+  socket: Mutex<Option<PathBuf>>,
   child: Mutex<Option<Child>>,
   worker: Mutex<Option<Child>>,
   station: Mutex<Option<Child>>,
+  // Resolved at startup (release builds only); used to respawn the server at runtime.
+  config_root: Mutex<Option<PathBuf>>,
+  data_dir: Mutex<Option<PathBuf>>,
+  node: Mutex<Option<NodeInfo>>,
+  // Last result of the storage watchdog (see `watch_storage`); true for a read-only library too.
+  storage_offline: Mutex<bool>,
+  // The library is there but refuses writes (e.g. the volume flipped to read-only).
+  storage_read_only: Mutex<bool>,
+  // User-requested pause (`pause_worker`); the storage watchdog can pause independently.
+  worker_paused: Mutex<bool>,
+  // A VACUUM or file-copy backup is running (see `with_worker_held`); the worker is held off meanwhile.
+  db_compacting: Mutex<bool>,
+  // `MOONDREAM_CONCURRENCY` the running worker was started with.
+  worker_concurrency: Mutex<Option<u8>>,
+  // `MOONDREAM_PROCESS_SINCE` the running worker was started with (None = all assets).
+  worker_process_since: Mutex<Option<String>>,
+  // Startup skipped `spawn_worker` because we were on battery (`worker.defer_on_battery`).
+  worker_deferred: Mutex<bool>,
+  // `MOONDREAM_DB_PATH` the running worker was started with (fallback for `worker_db_mismatch`).
+  worker_db_path: Mutex<Option<PathBuf>>,
+  // Unix seconds the running worker was spawned (see `worker_supports_control`).
+  worker_started_at: Mutex<Option<u64>>,
+  // See `record_route_changes`.
+  route_history: Mutex<std::collections::VecDeque<RouteEntry>>,
+  // The maintenance window the services are stopped (or held back) for.
+  maintenance_pause: Mutex<Option<String>>,
+  // `override_maintenance`: ignore maintenance windows for the rest of this session.
+  maintenance_override: Mutex<bool>,
+  // Set when the configured data dir overlapped the config root and the default was used instead.
+  storage_layout_error: Mutex<Option<String>>,
+  session_token: Mutex<Option<String>>,
+  // See `safe_mode_requested`; settings.json is ignored for the whole session.
+  safe_mode: Mutex<bool>,
+  // DB file the running server was given; the worker must be spawned with the same one.
+  db_path: Mutex<Option<PathBuf>>,
+  // Canvas focus mode as last reported by the UI (`report_focus_mode`); drives the menu checkmark.
+  focus_mode: Mutex<bool>,
+  // Canvas zoom (1.0 = 100%) as last reported by the UI (`report_zoom`), or restored from runtime.json.
+  canvas_zoom: Mutex<Option<f64>>,
+  // Settings each part was started with; compared against settings.json by `restart_required`.
+  launch_settings: Mutex<Option<AppSettings>>,
+  server_settings: Mutex<Option<AppSettings>>,
+  worker_settings: Mutex<Option<AppSettings>>,
+  // First `worker_runtime_info` probe of bin/moondream-worker this session.
+  worker_runtime: Mutex<Option<WorkerRuntimeInfo>>,
+  // Serializes `restart_next_server`; see there.
+  restart_lock: Mutex<()>,
+  restart_count: Mutex<u64>,
+  last_restart: Mutex<Option<(Instant, RestartResult)>>,
+  // What `apply_hardware_acceleration` did before the webview existed; fixed for the session.
+  hardware_acceleration: String,
+  migration_stream: Mutex<MigrationStream>,
+  // Recent server output while a debug panel follows it (see `start_server_log_stream`).
+  server_log: Mutex<ServerLogStream>,
+  // Tauri 1 can't read this back from the window, so `apply_always_on_top` records it.
+  always_on_top: Mutex<bool>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, Default)]
-struct AppSettings {
-  storage: Option<StorageSettings>,
-  ai: Option<AiSettings>,
-}
-
-#[derive(Clone, Debug, Deserialize, Serialize)]
-struct StorageSettings {
-  mode: Option<String>, // "local" | "icloud"
-  #[serde(alias = "icloudPath")]
-  icloud_path: Option<String>,
-  migration: Option<MigrationSettings>,
-}
-
-#[derive(Clone, Debug, Deserialize, Serialize)]
-struct MigrationSettings {
-  from: String,
-  to: String,
-  #[serde(alias = "requestedAt")]
-  requested_at: Option<String>,
-}
-
-#[derive(Clone, Debug, Deserialize, Serialize)]
-struct AiSettings {
-  provider: Option<String>, // "local_station" | "huggingface"
-  endpoint: Option<String>,
-  #[serde(alias = "hfToken")]
-  hf_token: Option<String>,
-}
-
-#[derive(Clone, Serialize)]
-struct ServerInfo {
-  port: u16,
-}
-
-#[tauri::command]
-fn server_port(state: tauri::State<ServerState>) -> Option<u16> {
-  *state.port.lock().unwrap()
+// Runs `cmd` with no stdin and its output discarded; for tools where only the exit status matters.
+fn quiet_status(cmd: &mut Command) -> io::Result<std::process::ExitStatus> {
+  cmd.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null()).status()
 }
 
-#[derive(Clone, Serialize)]
-struct StationStatus {
-  endpoint: String,
-  host: String,
-  port: u16,
-  reachable: bool,
-  installed: bool,
-  started_by_app: bool,
-  log_path: String,
-}
-
-fn parse_host_port(endpoint: &str) -> Option<(String, u16)> {
-  let mut s = endpoint.trim().to_string();
-  if s.is_empty() {
-    return None;
-  }
-  if let Some(idx) = s.find("://") {
-    s = s[(idx + 3)..].to_string();
-  }
-  // Strip credentials if present.
-  if let Some(at) = s.find('@') {
-    s = s[(at + 1)..].to_string();
-  }
-  // Cut path/query/fragment.
-  for sep in ['/', '?', '#'] {
-    if let Some(i) = s.find(sep) {
-      s = s[..i].to_string();
-      break;
-    }
-  }
-  if s.is_empty() {
-    return None;
-  }
-  // host[:port]
-  if let Some(colon) = s.rfind(':') {
-    let host = s[..colon].to_string();
-    let port_s = s[(colon + 1)..].to_string();
-    if let Ok(p) = port_s.parse::<u16>() {
-      return Some((host, p));
-    }
-  }
-  // Default (Station default varies by install; we standardize our local default).
-  Some((s, 2023))
-}
-
-fn tcp_reachable(host: &str, port: u16) -> bool {
-  TcpStream::connect(format!("{}:{}", host, port)).is_ok()
-}
-
-fn station_bin() -> String {
-  let env = std::env::var("MOONDREAM_STATION_BIN").unwrap_or_else(|_| "".to_string());
-  let env = env.trim().to_string();
-  if !env.is_empty() {
-    return env;
-  }
-  "moondream-station".to_string()
+fn command_stdout(program: &str, args: &[&str]) -> Option<String> {
+  let out = Command::new(program)
+    .args(args)
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .ok()?;
+  Some(String::from_utf8_lossy(&out.stdout).to_string())
 }
 
-#[tauri::command]
-fn station_status(
-  app: tauri::AppHandle,
-  state: tauri::State<ServerState>,
-  endpoint: Option<String>,
-) -> StationStatus {
-  let endpoint = endpoint
-    .unwrap_or_else(|| "http://localhost:2023/v1".to_string())
-    .trim()
-    .to_string();
-  let (host, port) = parse_host_port(&endpoint).unwrap_or_else(|| ("localhost".to_string(), 2023));
-  let reachable = tcp_reachable(&host, port);
-  let started_by_app = state.station.lock().unwrap().is_some();
-  let installed = Command::new(station_bin())
-    .arg("--help")
+fn open_in_file_manager(path: &PathBuf) -> Result<(), String> {
+  let opener = if cfg!(target_os = "macos") {
+    "open"
+  } else if cfg!(target_os = "windows") {
+    "explorer"
+  } else {
+    "xdg-open"
+  };
+  Command::new(opener)
+    .arg(path)
     .stdin(Stdio::null())
     .stdout(Stdio::null())
     .stderr(Stdio::null())
     .spawn()
-    .is_ok();
-
-  let config_root = app
-    .path_resolver()
-    .app_data_dir()
-    .map(|p| p.to_string_lossy().to_string())
-    .unwrap_or_else(|| "".to_string());
-  let log_path = if config_root.is_empty() {
-    "".to_string()
-  } else {
-    format!("{}/logs/moondream-station.log", config_root)
-  };
-
-  StationStatus {
-    endpoint,
-    host,
-    port,
-    reachable,
-    installed,
-    started_by_app,
-    log_path,
-  }
+    .map(|_| ())
+    .map_err(|e| e.to_string())
 }
 
-#[tauri::command]
-fn station_start(
-  app: tauri::AppHandle,
-  state: tauri::State<ServerState>,
-  endpoint: Option<String>,
-) -> Result<StationStatus, String> {
-  let endpoint = endpoint
-    .unwrap_or_else(|| "http://localhost:2023/v1".to_string())
-    .trim()
-    .to_string();
-  let (host, port) = parse_host_port(&endpoint).unwrap_or_else(|| ("localhost".to_string(), 2023));
-
-  // Only support local station auto-start.
-  let host_lc = host.to_lowercase();
-  if host_lc != "localhost" && host_lc != "127.0.0.1" && host_lc != "::1" {
-    return Err("Auto-start only supports localhost endpoints. Please start Station manually.".to_string());
-  }
-
-  if tcp_reachable(&host, port) {
-    return Ok(station_status(app, state, Some(endpoint)));
-  }
-
-  // Ensure log dir exists
-  let config_root = app
-    .path_resolver()
-    .app_data_dir()
-    .ok_or_else(|| "Missing app_data_dir".to_string())?;
-  std::fs::create_dir_all(&config_root).map_err(|e| e.to_string())?;
-  let log_dir = config_root.join("logs");
-  std::fs::create_dir_all(&log_dir).map_err(|e| e.to_string())?;
-  let log_path = log_dir.join("moondream-station.log");
-  let out = OpenOptions::new()
-    .create(true)
-    .append(true)
-    .open(&log_path)
-    .map_err(|e| e.to_string())?;
-  let err = out.try_clone().map_err(|e| e.to_string())?;
-
-  // Kill previous station child if we started one.
-  if let Some(mut prev) = state.station.lock().unwrap().take() {
-    let _ = prev.kill();
-  }
+const NOT_MANAGED: &str = "The library is not managed by the app in this build.";
 
-  let bin = station_bin();
-  let mut cmd = Command::new(bin);
-  cmd
-    .arg("start")
-    .arg(port.to_string())
-    .stdin(Stdio::null())
-    .stdout(Stdio::from(out))
-    .stderr(Stdio::from(err));
-
-  let child = cmd.spawn().map_err(|e| {
-    format!(
-      "Failed to start Moondream Station. Is it installed? Try: python3 -m pip install --user moondream-station. ({})",
-      e
-    )
-  })?;
-  *state.station.lock().unwrap() = Some(child);
-
-  // Wait briefly for port to open.
-  let ok = http_get_200(&host, port, "/", Duration::from_secs(6)) || tcp_reachable(&host, port);
-  if !ok {
-    // Keep it running (it might still be starting), but let the UI show current status.
-  }
+// Only set when this build runs the server itself (not in dev, where Tauri points at `next dev`).
+fn require_config_root(state: &ServerState) -> Result<PathBuf, String> {
+  state.config_root.lock().unwrap().clone().ok_or_else(|| NOT_MANAGED.to_string())
+}
 
-  Ok(station_status(app, state, Some(endpoint)))
+fn require_data_dir(state: &ServerState) -> Result<PathBuf, String> {
+  state.data_dir.lock().unwrap().clone().ok_or_else(|| NOT_MANAGED.to_string())
 }
 
-#[tauri::command]
-fn station_stop(app: tauri::AppHandle, state: tauri::State<ServerState>) -> StationStatus {
-  if let Some(mut c) = state.station.lock().unwrap().take() {
-    let _ = c.kill();
+fn managed_config_root(app: &tauri::AppHandle, state: &ServerState) -> Result<PathBuf, String> {
+  match state.config_root.lock().unwrap().clone() {
+    Some(p) => Ok(p),
+    None => app_config_root(app)
+      .ok_or_else(|| "Missing app_data_dir".to_string()),
   }
-  station_status(app, state, None)
 }
 
-fn pick_free_port() -> u16 {
-  // Bind to port 0 to let the OS pick an available port, then release it.
-  TcpListener::bind("127.0.0.1:0")
-    .ok()
-    .and_then(|l| l.local_addr().ok().map(|a| a.port()))
-    .unwrap_or(3210)
+fn unix_now_secs() -> u64 {
+  std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .unwrap_or_else(|_| Duration::from_secs(0))
+    .as_secs()
 }
 
-fn http_get_200(host: &str, port: u16, path: &str, timeout: Duration) -> bool {
-  let addr = format!("{}:{}", host, port);
-  let start = Instant::now();
-  while start.elapsed() < timeout {
-    if let Ok(mut stream) = TcpStream::connect(addr.as_str()) {
-      let _ = stream.set_read_timeout(Some(Duration::from_millis(250)));
-      let _ = stream.set_write_timeout(Some(Duration::from_millis(250)));
-      let req = format!(
-        "GET {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\n\r\n",
-        path, host, port
-      );
-      if stream.write_all(req.as_bytes()).is_ok() {
-        let mut buf = [0u8; 512];
-        if let Ok(n) = stream.read(&mut buf) {
-          let s = String::from_utf8_lossy(&buf[..n]);
-          if s.contains(" 200 ") {
-            return true;
-          }
+// Holds `start_services` back while the session is locked (`security.defer_until_unlock`) or a
+// maintenance window is open.
+fn defer_services(app: tauri::AppHandle, config_root: PathBuf, data_dir: PathBuf, settings: AppSettings, token: String) {
+  std::thread::spawn(move || {
+    let mut shown: Option<String> = None;
+    while let Some(hold) = startup_hold(&app, &read_settings(&config_root)) {
+      let (reason, title, message, window_range) = match hold {
+        StartupHold::Unlock => (
+          "unlock",
+          "Waiting for unlock",
+          "Reference starts its local server once you unlock your session.".to_string(),
+          None,
+        ),
+        StartupHold::Maintenance(w) => (
+          "maintenance",
+          "Paused for maintenance",
+          format!("The local server is paused for maintenance ({}). It starts when the window ends.", w),
+          Some(w),
+        ),
+      };
+      *app.state::<ServerState>().maintenance_pause.lock().unwrap() = window_range;
+      if shown.as_deref() != Some(message.as_str()) {
+        host_log(LogLevel::Info, &format!("Deferring the server and worker: {}", message));
+        if let Some(window) = app.get_window("main") {
+          show_hold_overlay(&window, reason, title, &message);
         }
+        shown = Some(message);
       }
+      std::thread::sleep(DEFER_POLL_INTERVAL);
     }
-    std::thread::sleep(Duration::from_millis(150));
-  }
-  false
-}
-
-fn resource_path(app: &tauri::AppHandle, rel: &str) -> Option<PathBuf> {
-  app
-    .path_resolver()
-    .resource_dir()
-    // We bundle assets under `Contents/Resources/resources/...` (mirrors `src-tauri/resources/...`).
-    .map(|d| d.join("resources").join(rel))
+    *app.state::<ServerState>().maintenance_pause.lock().unwrap() = None;
+    host_log(LogLevel::Info, "Starting deferred services");
+    if let Some(window) = app.get_window("main") {
+      let _ = window.eval(CLEAR_HOLD_JS);
+    }
+    if let Err(e) = start_services(&app, config_root.clone(), data_dir, settings, false, token) {
+      let msg = format!("Failed to start the local server: {}", e);
+      host_log(LogLevel::Error, &msg);
+      if let Some(window) = app.get_window("main") {
+        show_startup_failure(&window, &msg, &logs_dir(&config_root).join("next-server.log"));
+      }
+    }
+  });
 }
 
-fn default_icloud_dir() -> Option<PathBuf> {
-  let home = std::env::var("HOME").ok()?;
-  let root = PathBuf::from(home)
-    .join("Library")
-    .join("Mobile Documents")
-    .join("com~apple~CloudDocs");
-
-  // Branding change: default to "Reference", but keep compatibility with existing installs
-  // that may already have data under the previous folder name.
-  let new = root.join("Reference");
-  let old = root.join("Moondream");
-  if old.exists() && !new.exists() {
-    return Some(old);
-  }
-  Some(new)
-}
+// Everything that opens a port or touches the library: the server, the worker, the watchers and
+// the optional status endpoint. Runs from `setup`, or later from `defer_services`.
+fn start_services(
+  app: &tauri::AppHandle,
+  config_root: PathBuf,
+  data_dir: PathBuf,
+  settings: AppSettings,
+  safe_mode: bool,
+  token: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let addr = match server_transport(&settings)?.as_str() {
+    "unix" => ServerAddr::Unix(server_socket_path(&config_root)),
+    _ => ServerAddr::Tcp(pick_server_port(&settings)),
+  };
+  set_server_addr(&app.state::<ServerState>(), &addr);
 
-fn read_settings(config_root: &PathBuf) -> AppSettings {
-  let p = config_root.join("settings.json");
-  let data = std::fs::read_to_string(p);
-  if let Ok(s) = data {
-    serde_json::from_str::<AppSettings>(&s).unwrap_or_default()
-  } else {
-    AppSettings::default()
+  // Computed once from the final data_dir (after any migration override) for both processes.
+  if !safe_mode {
+    settle_db_location(&config_root, &data_dir, &settings);
   }
-}
-
-fn write_settings(config_root: &PathBuf, settings: &AppSettings) {
-  let p = config_root.join("settings.json");
-  if let Ok(s) = serde_json::to_string_pretty(settings) {
-    let _ = std::fs::write(p, s);
+  let db_path = resolve_db_path(&config_root, &data_dir, &settings);
+  host_log(LogLevel::Info, &format!("DB path: {}", db_path.display()));
+  let child = spawn_next_server(app, &addr, &config_root, &data_dir, &db_path, &settings)?;
+  {
+    let state = app.state::<ServerState>();
+    *state.child.lock().unwrap() = Some(child);
   }
-}
 
-fn is_dir_empty(p: &PathBuf) -> bool {
-  match std::fs::read_dir(p) {
-    Ok(mut it) => it.next().is_none(),
-    Err(_) => true,
+  // Wait for readiness before starting the worker (the ready path must only pass once the DB
+  // schema exists, so the worker won't crash on a fresh DB).
+  let ready_path = server_ready_path(&settings);
+  if ready_path != DEFAULT_READY_PATH {
+    host_log(LogLevel::Info, &format!("Using server.ready_path {} for readiness probes", ready_path));
   }
-}
-
-fn copy_dir_all(from: &PathBuf, to: &PathBuf) -> io::Result<()> {
-  std::fs::create_dir_all(to)?;
-  for entry in std::fs::read_dir(from)? {
-    let entry = entry?;
-    let ft = entry.file_type()?;
-    let src = entry.path();
-    let dst = to.join(entry.file_name());
-    if ft.is_dir() {
-      copy_dir_all(&src, &dst)?;
-    } else if ft.is_file() {
-      std::fs::create_dir_all(dst.parent().unwrap_or(to))?;
-      std::fs::copy(&src, &dst)?;
+  if server_get_200(&addr, &ready_path, Duration::from_secs(8)) {
+    if let ServerAddr::Tcp(port) = addr {
+      record_healthy_port(&config_root, port);
     }
+  } else {
+    // Keep waiting in the background; if it never comes up, replace the spinner with an error page.
+    await_startup_readiness(app.clone(), addr.clone(), ready_path.clone(), config_root.clone());
   }
-  Ok(())
-}
 
-fn move_dir(from: &PathBuf, to: &PathBuf) -> io::Result<()> {
-  // Fast path: same volume rename.
-  if std::fs::rename(from, to).is_ok() {
-    return Ok(());
+  // Start the bundled worker automatically (best-effort). It will talk to the local AI station.
+  // If the station isn't running, the worker will log errors and keep retrying.
+  if !safe_mode {
+    start_worker_if_allowed(app, &db_path, &config_root, &settings);
   }
-  // Fallback: recursive copy + delete.
-  copy_dir_all(from, to)?;
-  std::fs::remove_dir_all(from)?;
-  Ok(())
-}
 
-fn apply_pending_migration(config_root: &PathBuf, settings: &mut AppSettings) -> Option<PathBuf> {
-  let mig = settings.storage.as_ref().and_then(|s| s.migration.as_ref())?;
-  let from = PathBuf::from(mig.from.clone());
-  let to = PathBuf::from(mig.to.clone());
-  if from == to {
-    // Nothing to do.
-    if let Some(st) = settings.storage.as_mut() {
-      st.migration = None;
-    }
-    write_settings(config_root, settings);
-    return None;
+  watch_storage(app.clone(), pause_worker_when_offline(&settings));
+  watch_settings_file(app.clone(), config_root.clone());
+  schedule_log_cap(app.clone(), config_root.clone());
+  record_route_changes(app);
+  if !safe_mode {
+    schedule_retention(app.clone(), config_root.clone());
+    schedule_auto_compact(app.clone(), config_root.clone());
+    watch_maintenance(app.clone(), config_root.clone());
   }
-
-  // If source doesn't exist, clear and continue.
-  if !from.exists() {
-    if let Some(st) = settings.storage.as_mut() {
-      st.migration = None;
-    }
-    write_settings(config_root, settings);
-    return None;
+  if let Some(port) = settings.server.as_ref().and_then(|s| s.status_port) {
+    serve_status(app.clone(), port);
   }
 
-  // If destination exists and is not empty, back it up before moving in.
-  if to.exists() && !is_dir_empty(&to) {
-    let ts = std::time::SystemTime::now()
-      .duration_since(std::time::UNIX_EPOCH)
-      .unwrap_or_else(|_| Duration::from_secs(0))
-      .as_secs();
-    let name = to
-      .file_name()
-      .and_then(|s| s.to_str())
-      .unwrap_or("data")
-      .to_string();
-    let backup = to
-      .parent()
-      .unwrap_or(config_root)
-      .join(format!("{}-backup-{}", name, ts));
-    let _ = std::fs::rename(&to, &backup);
-  }
+  // Nudge the internal loading page so it can redirect as soon as health is ready.
+  if let Some(window) = app.get_window("main") {
+    // Helpful for debugging if the local server never becomes ready.
+    let _ = window.eval(
+      &format!(
+        "window.__MOONDREAM_LOG_HINT__ = \"~/Library/Application Support/{}/logs/next-server.log\";",
+        "com.moondream.desktop"
+      )
+    );
+    inject_session_token(&window, &token, false);
+    inject_theme(&window, &settings);
+    if safe_mode {
+      // Pages that mount later should check the `safe_mode` command.
+      let _ = window.eval("window.__MOONDREAM_SAFE_MODE__ = true;");
+      dispatch_web_event(&window, "moondream:safe-mode", None);
+    }
 
-  if let Some(parent) = to.parent() {
-    let _ = std::fs::create_dir_all(parent);
-  }
+    match addr {
+      ServerAddr::Tcp(port) => {
+        // The initial `ui/index.html` is plain HTML and does not import @tauri-apps/api.
+        // Provide the chosen port via a global so the page can poll `/api/health` and redirect
+        // without relying on `window.__TAURI__.invoke(...)` being present.
+        let _ = window.eval(&format!("window.__MOONDREAM_PORT__ = {};", port));
 
-  match move_dir(&from, &to) {
-    Ok(()) => {
-      if let Some(st) = settings.storage.as_mut() {
-        st.migration = None;
+        // Keep emitting too (useful if we later switch to a JS listener).
+        let _ = window.emit("moondream://server-ready", ServerInfo { port });
+      }
+      ServerAddr::Unix(_) => {
+        // There's no port for the loading page to poll; we already waited for readiness above.
+        let url = serde_json::to_string(&format!("{}/", addr.base_url())).unwrap_or_default();
+        let _ = window.eval(&format!("window.location.replace({});", url));
       }
-      write_settings(config_root, settings);
-      None
-    }
-    Err(_) => {
-      // Migration failed; keep using the old location for this run so the library isn't "missing".
-      Some(from)
     }
   }
-}
 
-fn resolve_data_dir(config_root: &PathBuf, settings: &AppSettings) -> PathBuf {
-  let mode = settings
-    .storage
-    .as_ref()
-    .and_then(|s| s.mode.as_deref())
-    .unwrap_or("local")
-    .to_lowercase();
-
-  if mode == "icloud" {
-    let p = settings
-      .storage
-      .as_ref()
-      .and_then(|s| s.icloud_path.as_ref())
-      .map(PathBuf::from)
-      .or_else(default_icloud_dir);
-    if let Some(p) = p {
-      return p;
-    }
-  }
-  config_root.join("data")
+  Ok(())
 }
 
-fn spawn_next_server(
-  app: &tauri::AppHandle,
-  port: u16,
-  config_root: &PathBuf,
-  data_dir: &PathBuf,
-  settings: &AppSettings,
-) -> io::Result<Child> {
-  let next_dir = resource_path(app, "next")
-    .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Missing resource_dir"))?;
-  let server_js = next_dir.join("server.js");
-  if !server_js.exists() {
-    return Err(io::Error::new(
-      ErrorKind::NotFound,
-      format!("Missing Next server bundle at {}", server_js.display()),
-    ));
-  }
-
-  // Require bundled Node so the desktop app is truly standalone.
-  let node = resource_path(app, "bin/node").ok_or_else(|| {
-    io::Error::new(ErrorKind::NotFound, "Missing resource_dir (bin/node)")
-  })?;
-  if !node.exists() {
-    return Err(io::Error::new(
-      ErrorKind::NotFound,
-      format!("Missing bundled Node at {}", node.display()),
-    ));
+fn main() {
+  // Webview switches must be in place before tauri.conf.json's window is created, which is
+  // before `setup` runs; so read settings.json here, ahead of the app.
+  let mut context = tauri::generate_context!();
+  let early_settings = if safe_mode_requested() {
+    AppSettings::default()
+  } else {
+    tauri::api::path::app_data_dir(context.config())
+      .map(config_root_from_default)
+      .map(|root| read_settings(&root))
+      .unwrap_or_default()
+  };
+  let hardware_acceleration = apply_hardware_acceleration(&early_settings);
+  let locale = menu_locale(&early_settings);
+  if theme_preset(&early_settings).is_some() {
+    for w in context.config_mut().tauri.windows.iter_mut() {
+      w.theme = native_theme(&early_settings);
+    }
   }
-
-  std::fs::create_dir_all(&data_dir)?;
-
-  // Log server output so "server not ready" errors are debuggable in standalone builds.
-  let log_dir = config_root.join("logs");
-  std::fs::create_dir_all(&log_dir)?;
-  let server_log_path = log_dir.join("next-server.log");
-  let log_file = OpenOptions::new()
-    .create(true)
-    .append(true)
-    .open(&server_log_path)?;
-  let log_file_err = log_file.try_clone()?;
-
-  let mut cmd = Command::new(node);
-  cmd
-    .current_dir(&next_dir)
-    .arg("server.js")
-    .env("HOSTNAME", "127.0.0.1")
-    .env("PORT", port.to_string())
-    .env("NODE_ENV", "production")
-    .env("NEXT_TELEMETRY_DISABLED", "1")
-    .env("MOONDREAM_DATA_DIR", &data_dir)
-    .env("MOONDREAM_APP_CONFIG_DIR", config_root)
-    .env("MOONDREAM_SETTINGS_PATH", config_root.join("settings.json"))
-    // Pass AI config through so the UI (and server routes, if needed) can read it.
-    .env(
-      "MOONDREAM_PROVIDER",
-      settings
-        .ai
-        .as_ref()
-        .and_then(|a| a.provider.as_ref())
-        .map(|s| s.as_str())
-        .unwrap_or("local_station"),
-    )
-    .env(
-      "MOONDREAM_ENDPOINT",
-      settings
-        .ai
-        .as_ref()
-        .and_then(|a| a.endpoint.as_ref())
-        .map(|s| s.as_str())
-        .unwrap_or("http://localhost:2023/v1"),
-    )
-    // Optional: HF endpoint/token (used by the bundled worker; safe to expose to local server too).
-    .env(
-      "HF_ENDPOINT_URL",
-      settings
-        .ai
-        .as_ref()
-        .and_then(|a| a.endpoint.as_ref())
-        .map(|s| s.as_str())
-        .unwrap_or(""),
-    )
-    .env(
-      "HF_TOKEN",
-      settings
-        .ai
-        .as_ref()
-        .and_then(|a| a.hf_token.as_ref())
-        .map(|s| s.as_str())
-        .unwrap_or(""),
-    )
-    // Ensure the Node server and the Python worker (if used) can share the same DB file.
-    .env("MOONDREAM_DB_PATH", data_dir.join("moondream.sqlite3"))
-    .stdin(Stdio::null())
-    .stdout(Stdio::from(log_file))
-    .stderr(Stdio::from(log_file_err));
-
-  cmd.spawn()
-}
-
-fn spawn_worker(
-  app: &tauri::AppHandle,
-  db_path: &PathBuf,
-  config_root: &PathBuf,
-  settings: &AppSettings,
-) -> io::Result<Child> {
-  let worker = resource_path(app, "bin/moondream-worker")
-    .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Missing resource_dir (bin/moondream-worker)"))?;
-  if !worker.exists() {
-    return Err(io::Error::new(
-      ErrorKind::NotFound,
-      format!("Missing bundled worker at {}", worker.display()),
-    ));
+  if early_settings.ui.as_ref().and_then(|u| u.native_file_drop).unwrap_or(false) {
+    for w in context.config_mut().tauri.windows.iter_mut() {
+      w.file_drop_enabled = true;
+    }
   }
 
-  // Log worker output
-  let log_dir = config_root.join("logs");
-  std::fs::create_dir_all(&log_dir)?;
-  let log_path = log_dir.join("moondream-worker.log");
-  let out = OpenOptions::new().create(true).append(true).open(&log_path)?;
-  let err = out.try_clone()?;
-
-  let endpoint = settings
-    .ai
-    .as_ref()
-    .and_then(|a| a.endpoint.as_ref())
-    .cloned()
-    .unwrap_or_else(|| "http://localhost:2023/v1".to_string());
-  let provider = settings
-    .ai
-    .as_ref()
-    .and_then(|a| a.provider.as_ref())
-    .cloned()
-    .unwrap_or_else(|| "local_station".to_string());
-  let hf_token = settings
-    .ai
-    .as_ref()
-    .and_then(|a| a.hf_token.as_ref())
-    .cloned()
-    .unwrap_or_else(|| "".to_string());
-
-  let mut cmd = Command::new(worker);
-  cmd
-    .env("PYTHONUNBUFFERED", "1")
-    .env("MOONDREAM_DB_PATH", db_path)
-    .env("MOONDREAM_PROVIDER", provider)
-    .env("MOONDREAM_ENDPOINT", endpoint)
-    // HF provider expects these env vars (safe to set even when provider != huggingface).
-    .env("HF_ENDPOINT_URL", settings.ai.as_ref().and_then(|a| a.endpoint.as_ref()).cloned().unwrap_or_default())
-    .env("HF_TOKEN", hf_token)
-    .env("MOONDREAM_POLL_SECONDS", std::env::var("MOONDREAM_POLL_SECONDS").unwrap_or_else(|_| "1.0".to_string()))
-    // Default: do NOT auto-retry "failed" forever (prevents tight loops if a file is missing on disk).
-    // Transient Station/network errors are already re-queued to "pending" by the worker.
-    .env("MOONDREAM_RETRY_FAILED", std::env::var("MOONDREAM_RETRY_FAILED").unwrap_or_else(|_| "0".to_string()))
-    .env("MOONDREAM_APP_CONFIG_DIR", config_root)
-    .stdin(Stdio::null())
-    .stdout(Stdio::from(out))
-    .stderr(Stdio::from(err));
-
-  cmd.spawn()
-}
-
-fn dispatch_web_event(window: &tauri::Window, event_name: &str) {
-  // Fire a CustomEvent in the webview so the Next.js UI can react.
-  // Note: this runs after the webview has navigated to http://127.0.0.1:<port>/.
-  let js = format!(
-    "window.dispatchEvent(new CustomEvent({:?}));",
-    event_name
-  );
-  let _ = window.eval(&js);
-}
-
-fn main() {
-  let settings = CustomMenuItem::new("settings".to_string(), "Settings").accelerator("CmdOrCtrl+,");
+  let settings = CustomMenuItem::new("settings".to_string(), tr(locale, "settings")).accelerator("CmdOrCtrl+,");
   let command_palette =
-    CustomMenuItem::new("command_palette".to_string(), "Command Palette").accelerator("CmdOrCtrl+K");
+    CustomMenuItem::new("command_palette".to_string(), tr(locale, "command_palette")).accelerator("CmdOrCtrl+K");
   // Mirrors the in-app shortcut (Cmd/Ctrl+F) used to open the command palette search.
   let find_assets =
-    CustomMenuItem::new("find_assets".to_string(), "Find…").accelerator("CmdOrCtrl+F");
+    CustomMenuItem::new("find_assets".to_string(), tr(locale, "find")).accelerator("CmdOrCtrl+F");
   // Project-context Settings shortcut used in the UI (Cmd/Ctrl+.).
   let project_settings =
-    CustomMenuItem::new("project_settings".to_string(), "Project Settings…").accelerator("CmdOrCtrl+.");
+    CustomMenuItem::new("project_settings".to_string(), tr(locale, "project_settings")).accelerator("CmdOrCtrl+.");
+  let copy_project_link_item = CustomMenuItem::new("copy_project_link".to_string(), tr(locale, "copy_project_link"));
   // On macOS, users expect ⌘⌫ ("Command+Delete") as the "delete selection" shortcut.
   // Avoid CmdOrCtrl+Backspace because Ctrl+Backspace is a common text-editing shortcut on Windows/Linux.
   let delete_accel = if cfg!(target_os = "macos") {
@@ -621,9 +350,22 @@ fn main() {
     "Backspace"
   };
   let delete_selection =
-    CustomMenuItem::new("delete_selection".to_string(), "Delete Selection").accelerator(delete_accel);
-  let reset_zoom = CustomMenuItem::new("reset_zoom".to_string(), "Reset Zoom (10%)").accelerator("CmdOrCtrl+0");
-  let focus_toggle = CustomMenuItem::new("focus_toggle".to_string(), "Focus Toggle").accelerator("Space");
+    CustomMenuItem::new("delete_selection".to_string(), tr(locale, "delete_selection")).accelerator(delete_accel);
+  let reset_zoom = CustomMenuItem::new("reset_zoom".to_string(), tr(locale, "reset_zoom")).accelerator("CmdOrCtrl+0");
+  let zoom_in = CustomMenuItem::new("zoom_in".to_string(), tr(locale, "zoom_in")).accelerator("CmdOrCtrl+=");
+  let zoom_out = CustomMenuItem::new("zoom_out".to_string(), tr(locale, "zoom_out")).accelerator("CmdOrCtrl+-");
+  let reset_view_item = CustomMenuItem::new("reset_view".to_string(), tr(locale, "reset_view")).accelerator("CmdOrCtrl+Shift+0");
+  let zoom_preset_items: Vec<CustomMenuItem> = ZOOM_PRESETS
+    .iter()
+    .map(|p| {
+      let id = format!("zoom_preset_{}", p);
+      let label = tr(locale, &id);
+      CustomMenuItem::new(id, label)
+    })
+    .collect();
+  let focus_toggle = CustomMenuItem::new("focus_toggle".to_string(), tr(locale, "focus_toggle")).accelerator("Space");
+  // Checkmark follows `ui.always_on_top` (see `apply_always_on_top`).
+  let keep_on_top = CustomMenuItem::new("keep_on_top".to_string(), tr(locale, "keep_on_top"));
 
   // ---------------------------------------------------------------------------
   // Shortcut reference menu
@@ -636,58 +378,61 @@ fn main() {
   // ---------------------------------------------------------------------------
 
   // Global / app-wide
-  let sc_command_palette = CustomMenuItem::new("sc_command_palette".to_string(), "Command Palette")
+  let sc_command_palette = CustomMenuItem::new("sc_command_palette".to_string(), tr(locale, "command_palette"))
     .accelerator("CmdOrCtrl+K")
     .disabled();
-  let sc_find_assets_mod = CustomMenuItem::new("sc_find_assets_mod".to_string(), "Find Assets / Search")
+  let sc_find_assets_mod = CustomMenuItem::new("sc_find_assets_mod".to_string(), tr(locale, "find_assets"))
     .accelerator("CmdOrCtrl+F")
     .disabled();
-  let sc_find_assets_plain = CustomMenuItem::new("sc_find_assets_plain".to_string(), "Find Assets / Search")
+  let sc_find_assets_plain = CustomMenuItem::new("sc_find_assets_plain".to_string(), tr(locale, "find_assets"))
     // Plain "F" is handled by the web UI when not typing.
     .accelerator("F")
     .disabled();
-  let sc_close_escape = CustomMenuItem::new("sc_close_escape".to_string(), "Close / Cancel / Dismiss")
+  let sc_close_escape = CustomMenuItem::new("sc_close_escape".to_string(), tr(locale, "close_escape"))
     .accelerator("Esc")
     .disabled();
 
   // Project / navigation
-  let sc_project_settings_mod = CustomMenuItem::new("sc_project_settings_mod".to_string(), "Project Settings…")
+  let sc_project_settings_mod = CustomMenuItem::new("sc_project_settings_mod".to_string(), tr(locale, "project_settings"))
     .accelerator("CmdOrCtrl+.")
     .disabled();
   // "." alone is handled by the web UI (project context); we keep it as label text to avoid
   // any platform-specific accelerator parsing quirks for punctuation.
   let sc_project_settings_plain =
-    CustomMenuItem::new("sc_project_settings_plain".to_string(), "Project Settings… (.)").disabled();
-  let sc_settings_mod = CustomMenuItem::new("sc_settings_mod".to_string(), "Settings")
+    CustomMenuItem::new("sc_project_settings_plain".to_string(), format!("{} (.)", tr(locale, "project_settings"))).disabled();
+  let sc_settings_mod = CustomMenuItem::new("sc_settings_mod".to_string(), tr(locale, "settings"))
     .accelerator("CmdOrCtrl+,")
     .disabled();
 
   // Canvas
-  let sc_canvas_minimap = CustomMenuItem::new("sc_canvas_minimap".to_string(), "Toggle Minimap")
+  let sc_canvas_minimap = CustomMenuItem::new("sc_canvas_minimap".to_string(), tr(locale, "toggle_minimap"))
     .accelerator("M")
     .disabled();
   let sc_canvas_reset_zoom_plain =
-    CustomMenuItem::new("sc_canvas_reset_zoom_plain".to_string(), "Reset Zoom (10%)").accelerator("0").disabled();
-  let sc_canvas_reset_zoom_mod = CustomMenuItem::new("sc_canvas_reset_zoom_mod".to_string(), "Reset Zoom (10%)")
+    CustomMenuItem::new("sc_canvas_reset_zoom_plain".to_string(), tr(locale, "reset_zoom")).accelerator("0").disabled();
+  let sc_canvas_reset_zoom_mod = CustomMenuItem::new("sc_canvas_reset_zoom_mod".to_string(), tr(locale, "reset_zoom"))
     .accelerator("CmdOrCtrl+0")
     .disabled();
-  let sc_canvas_focus = CustomMenuItem::new("sc_canvas_focus".to_string(), "Focus Toggle")
+  let sc_canvas_focus = CustomMenuItem::new("sc_canvas_focus".to_string(), tr(locale, "focus_toggle"))
     .accelerator("Space")
     .disabled();
-  let sc_canvas_undo_delete = CustomMenuItem::new("sc_canvas_undo_delete".to_string(), "Undo Delete Selection")
+  let sc_canvas_undo_delete = CustomMenuItem::new("sc_canvas_undo_delete".to_string(), tr(locale, "undo_delete_selection"))
     .accelerator("CmdOrCtrl+Z")
     .disabled();
-  let sc_canvas_delete = CustomMenuItem::new("sc_canvas_delete".to_string(), "Delete Selection (Backspace/Delete)")
+  let sc_canvas_delete = CustomMenuItem::new("sc_canvas_delete".to_string(), format!("{} (Backspace/Delete)", tr(locale, "delete_selection")))
     .disabled();
   // Arrow-key navigation is handled by the web UI; display as text so we don't bind arrow keys globally.
   let sc_canvas_arrows =
-    CustomMenuItem::new("sc_canvas_arrows".to_string(), "Navigate Selection (← ↑ → ↓)").disabled();
+    CustomMenuItem::new("sc_canvas_arrows".to_string(), format!("{} (← ↑ → ↓)", tr(locale, "navigate_selection"))).disabled();
   // Dev/testing shortcut (kept visible because it exists in the app, but clearly labeled).
   let sc_canvas_ripple_test =
     CustomMenuItem::new("sc_canvas_ripple_test".to_string(), "Ripple Test (dev) (R)").disabled();
 
+  // Not the native Quit item: that terminates without a CloseRequested, skipping `request_quit`.
+  let quit = CustomMenuItem::new("quit".to_string(), tr(locale, "quit")).accelerator("CmdOrCtrl+Q");
+
   let app_menu = Menu::new()
-    .add_native_item(MenuItem::About("Reference".to_string(), AboutMetadata::default()))
+    .add_native_item(MenuItem::About("Reference".to_string(), AboutMetadata::new().comments(tr(locale, "about_comments"))))
     .add_native_item(MenuItem::Separator)
     .add_item(settings.clone())
     .add_native_item(MenuItem::Separator)
@@ -695,10 +440,11 @@ fn main() {
     .add_native_item(MenuItem::HideOthers)
     .add_native_item(MenuItem::ShowAll)
     .add_native_item(MenuItem::Separator)
-    .add_native_item(MenuItem::Quit);
+    .add_item(quit);
 
   let file_menu = Menu::new()
     .add_item(project_settings.clone())
+    .add_item(copy_project_link_item.clone())
     .add_item(settings.clone())
     .add_native_item(MenuItem::Separator)
     .add_native_item(MenuItem::CloseWindow);
@@ -714,9 +460,16 @@ fn main() {
     .add_native_item(MenuItem::Separator)
     .add_item(find_assets.clone());
 
-  let view_menu = Menu::new()
-    .add_item(command_palette.clone())
-    .add_item(reset_zoom.clone())
+  let view_menu = zoom_preset_items.into_iter().fold(
+    Menu::new()
+      .add_item(command_palette.clone())
+      .add_item(zoom_in.clone())
+      .add_item(zoom_out.clone())
+      .add_item(reset_zoom.clone())
+      .add_item(reset_view_item),
+    |menu, item| menu.add_item(item),
+  );
+  let view_menu = view_menu
     .add_item(focus_toggle.clone())
     .add_item(delete_selection.clone())
     .add_native_item(MenuItem::Separator)
@@ -724,7 +477,9 @@ fn main() {
 
   let window_menu = Menu::new()
     .add_native_item(MenuItem::Minimize)
-    .add_native_item(MenuItem::Zoom);
+    .add_native_item(MenuItem::Zoom)
+    .add_native_item(MenuItem::Separator)
+    .add_item(keep_on_top);
 
   let shortcuts_global_menu = Menu::new()
     .add_item(sc_command_palette.clone())
@@ -752,161 +507,430 @@ fn main() {
     .add_item(sc_canvas_ripple_test.clone());
 
   let shortcuts_menu = Menu::new()
-    .add_submenu(Submenu::new("Global", shortcuts_global_menu))
-    .add_submenu(Submenu::new("Project", shortcuts_project_menu))
-    .add_submenu(Submenu::new("Canvas", shortcuts_canvas_menu));
+    .add_submenu(Submenu::new(tr(locale, "menu_global"), shortcuts_global_menu))
+    .add_submenu(Submenu::new(tr(locale, "menu_project"), shortcuts_project_menu))
+    .add_submenu(Submenu::new(tr(locale, "menu_canvas"), shortcuts_canvas_menu));
 
   // macOS requires submenus for top-level items.
   let menu = Menu::new()
     .add_submenu(Submenu::new("Reference", app_menu))
-    .add_submenu(Submenu::new("File", file_menu))
-    .add_submenu(Submenu::new("Edit", edit_menu))
-    .add_submenu(Submenu::new("View", view_menu))
-    .add_submenu(Submenu::new("Shortcuts", shortcuts_menu))
-    .add_submenu(Submenu::new("Window", window_menu));
+    .add_submenu(Submenu::new(tr(locale, "menu_file"), file_menu))
+    .add_submenu(Submenu::new(tr(locale, "menu_edit"), edit_menu))
+    .add_submenu(Submenu::new(tr(locale, "menu_view"), view_menu))
+    .add_submenu(Submenu::new(tr(locale, "menu_shortcuts"), shortcuts_menu))
+    .add_submenu(Submenu::new(tr(locale, "menu_window"), window_menu));
 
-  tauri::Builder::default()
+  let builder = tauri::Builder::default()
     .manage(ServerState {
       port: Mutex::new(None),
+      socket: Mutex::new(None),
       child: Mutex::new(None),
       worker: Mutex::new(None),
       station: Mutex::new(None),
+      config_root: Mutex::new(None),
+      data_dir: Mutex::new(None),
+      node: Mutex::new(None),
+      storage_offline: Mutex::new(false),
+      storage_read_only: Mutex::new(false),
+      worker_paused: Mutex::new(false),
+      db_compacting: Mutex::new(false),
+      worker_concurrency: Mutex::new(None),
+      worker_process_since: Mutex::new(None),
+      worker_deferred: Mutex::new(false),
+      worker_db_path: Mutex::new(None),
+      worker_started_at: Mutex::new(None),
+      route_history: Mutex::new(std::collections::VecDeque::new()),
+      maintenance_pause: Mutex::new(None),
+      maintenance_override: Mutex::new(false),
+      storage_layout_error: Mutex::new(None),
+      session_token: Mutex::new(None),
+      safe_mode: Mutex::new(false),
+      db_path: Mutex::new(None),
+      focus_mode: Mutex::new(false),
+      canvas_zoom: Mutex::new(None),
+      launch_settings: Mutex::new(None),
+      server_settings: Mutex::new(None),
+      worker_settings: Mutex::new(None),
+      worker_runtime: Mutex::new(None),
+      restart_lock: Mutex::new(()),
+      restart_count: Mutex::new(0),
+      last_restart: Mutex::new(None),
+      hardware_acceleration: hardware_acceleration.to_string(),
+      migration_stream: Mutex::new(MigrationStream::default()),
+      server_log: Mutex::new(ServerLogStream::default()),
+      always_on_top: Mutex::new(false),
     })
+    .manage(MenuBridge::default())
     .menu(menu)
+    .register_uri_scheme_protocol(UNIX_PROXY_SCHEME, |app, request| {
+      let sock = app.state::<ServerState>().socket.lock().unwrap().clone();
+      match sock {
+        #[cfg(unix)]
+        Some(sock) => unix_proxy(&sock, request),
+        _ => Err("The local server is not listening on a Unix socket".into()),
+      }
+    })
     .on_menu_event(|event| {
-      let id = event.menu_item_id();
-      match id {
-        "settings" => {
-          // Navigate within the Next.js app.
-          // If we're currently on a project route, preserve projectId so Settings can enable project-scoped actions
-          // like "Retry failed AI".
-          let _ = event.window().eval(
-            "(function(){const m=window.location.pathname.match(/^\\/projects\\/([^\\/]+)/);const pid=m&&m[1];window.location.href=pid?('/settings?projectId='+encodeURIComponent(pid)):'/settings';})()"
-          );
-        }
-        "project_settings" => {
-          // Navigate with a fade, and include projectId when we're currently in /projects/:id.
-          //
-          // Mirrors the in-app shortcut logic:
-          // - "." opens Settings (project context)
-          // - Cmd+. / Ctrl+. also opens Settings (project context)
-          //
-          // Note: we do this here (rather than relying only on a web listener) so it still works
-          // even if the project page hasn't mounted its listeners yet.
-          let js = r#"
-            (function () {
-              try { window.dispatchEvent(new Event("moondream:route-fade:start")); } catch (_) {}
-              var m = (window.location && window.location.pathname || "").match(/^\/projects\/([^\/?#]+)/);
-              var pid = m && m[1] ? decodeURIComponent(m[1]) : null;
-              var url = pid ? ("/settings?projectId=" + encodeURIComponent(pid)) : "/settings";
-              window.setTimeout(function () { window.location.href = url; }, 220);
-            })();
-          "#;
-          let _ = event.window().eval(js);
-        }
-        "command_palette" => {
-          dispatch_web_event(event.window(), "moondream:command-palette:toggle");
-        }
-        "find_assets" => {
-          dispatch_web_event(event.window(), "moondream:command-palette:open");
-        }
-        "delete_selection" => {
-          dispatch_web_event(event.window(), "moondream:canvas:delete-selection");
-        }
-        "reset_zoom" => {
-          dispatch_web_event(event.window(), "moondream:canvas:reset-zoom");
-        }
-        "focus_toggle" => {
-          dispatch_web_event(event.window(), "moondream:canvas:focus-toggle");
-        }
-        _ => {}
+      // Quit isn't offered to the web UI; it must always work.
+      if event.menu_item_id() == "quit" {
+        request_quit(event.window());
+        return;
       }
+      // Window state is the host's; nothing for the web UI to decide.
+      if event.menu_item_id() == "keep_on_top" {
+        let window = event.window();
+        let enabled = !*window.state::<ServerState>().always_on_top.lock().unwrap();
+        let _ = set_always_on_top(window.app_handle(), window.clone(), enabled);
+        return;
+      }
+      bridge_menu_event(event.window().clone(), event.menu_item_id().to_string());
     })
-    .invoke_handler(tauri::generate_handler![server_port, station_status, station_start, station_stop])
+    .invoke_handler(tauri::generate_handler![
+      server::server_port,
+      ai::station_status,
+      ai::station_start,
+      ai::station_stop,
+      server::restart_server,
+      server::check_node,
+      server::set_node_source,
+      diagnostics::about_info,
+      window::copy_project_link,
+      storage::validate_data_dir,
+      worker::trigger_worker_sweep,
+      server::check_resources,
+      settings::set_autostart,
+      settings::get_autostart,
+      storage::storage_status,
+      storage::rename_library,
+      diagnostics::full_status,
+      database::apply_retention,
+      database::set_retention_policy,
+      menu::menu_ack,
+      worker::worker_status,
+      worker::pause_worker,
+      worker::resume_worker,
+      server::session_token,
+      server::rotate_session_token,
+      logs::read_log,
+      settings::safe_mode,
+      diagnostics::diagnostics_text,
+      storage::evict_icloud_library,
+      worker::test_ai_pipeline,
+      logs::set_log_level,
+      logs::set_log_format,
+      window::save_layout,
+      window::apply_layout,
+      window::list_layouts,
+      settings::edit_settings_file,
+      worker::worker_queue,
+      logs::reveal_logs,
+      storage::hot_migrate,
+      storage::plan_migration,
+      storage::find_library_candidates,
+      storage::merge_library,
+      storage::export_assets,
+      storage::db_sync_risk,
+      server::stop_services,
+      storage::benchmark_dir,
+      worker::retry_failed_jobs,
+      server::diagnose_port,
+      menu::focus_mode,
+      menu::report_focus_mode,
+      storage::paths,
+      settings::restart_required,
+      settings::relaunch_app,
+      diagnostics::capture_screenshot,
+      diagnostics::export_diagnostics,
+      settings::set_config_root,
+      storage::find_icloud_conflicts,
+      storage::resolve_icloud_conflict,
+      server::list_moondream_processes,
+      server::kill_process,
+      worker::worker_runtime_info,
+      storage::thumbnail,
+      window::set_hardware_acceleration,
+      storage::start_migration_stream,
+      storage::stop_migration_stream,
+      storage::filesystem_info,
+      menu::set_always_on_top,
+      settings::export_settings,
+      settings::import_settings,
+      ai::check_ai_endpoint,
+      database::checkpoint_wal,
+      database::export_database,
+      storage::list_icloud_roots,
+      storage::select_icloud_root,
+      diagnostics::self_test,
+      worker::restart_worker,
+      server::get_server_log_level,
+      server::set_server_log_level,
+      worker::repair_worker,
+      server::await_server_port,
+      window::route_history,
+      worker::warm_ai,
+      storage::fix_permissions,
+      menu::set_locale,
+      server::maintenance_status,
+      server::override_maintenance,
+      storage::estimate_migration,
+      storage::recheck_storage,
+      settings::save_profile,
+      settings::load_profile,
+      settings::list_profiles,
+      settings::delete_profile,
+      database::schema_compat,
+      diagnostics::contact_support,
+      menu::zoom_presets,
+      menu::set_zoom,
+      menu::canvas_zoom,
+      menu::report_zoom,
+      ai::network_status,
+      ai::probe_endpoint,
+      menu::reset_view,
+      window::monitors,
+      database::report_deletes,
+      database::compact_database,
+      window::cycle_window,
+      window::list_themes,
+      window::apply_theme,
+      logs::log_usage,
+      storage::import_dropped_files,
+      worker::arch_info,
+      server::start_server_log_stream,
+      server::stop_server_log_stream
+    ])
     .setup(|app| {
       // In dev, Tauri points at the running Next dev server (http://localhost:3000).
       if cfg!(debug_assertions) {
         return Ok(());
       }
 
-      let port = pick_free_port();
-      {
-        let state = app.state::<ServerState>();
-        *state.port.lock().unwrap() = Some(port);
-      }
-
       let handle = app.handle();
-      let config_root = app
-        .path_resolver()
-        .app_data_dir()
+      let config_root = app_config_root(&handle)
         .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Missing app_data_dir"))?;
       std::fs::create_dir_all(&config_root)?;
-
-      let settings = read_settings(&config_root);
-      let mut settings = settings;
-      let override_data_dir = apply_pending_migration(&config_root, &mut settings);
-      let data_dir = override_data_dir.unwrap_or_else(|| resolve_data_dir(&config_root, &settings));
+      migrate_legacy_config_root(&config_root);
+      // Logging settings are honored even in safe mode (they can't keep the app from starting).
+      let _ = init_host_logger(&config_root, &read_settings(&config_root));
+      finish_config_root_move(&config_root);
+
+      let safe_mode = safe_mode_requested();
+      let (settings, data_dir) = if safe_mode {
+        // Leave settings.json (including any pending migration) untouched for after the fix.
+        (AppSettings::default(), config_root.join("data"))
+      } else {
+        upgrade_legacy_settings(&config_root);
+        let settings = read_settings(&config_root);
+        let mut settings = settings;
+        let override_data_dir =
+          apply_pending_migration(&config_root, &mut settings, &mut |ev| emit_migration_event(&handle, ev));
+        let data_dir = override_data_dir.unwrap_or_else(|| resolve_data_dir(&config_root, &settings));
+        (settings, data_dir)
+      };
+      let data_dir = match data_dir_layout_error(&config_root, &data_dir) {
+        Some(err) => {
+          let fallback = config_root.join("data");
+          host_log(LogLevel::Error, &format!("{}; using {} instead", err, fallback.display()));
+          *app.state::<ServerState>().storage_layout_error.lock().unwrap() = Some(err.clone());
+          if let Some(window) = app.get_window("main") {
+            dispatch_web_event(
+              &window,
+              "moondream:storage:invalid-layout",
+              Some(serde_json::json!({ "error": err, "path": data_dir, "fallback": fallback })),
+            );
+          }
+          fallback
+        }
+        None => data_dir,
+      };
       std::fs::create_dir_all(&data_dir)?;
-
-      let child = spawn_next_server(&handle, port, &config_root, &data_dir, &settings)?;
+      if !dir_writable(&data_dir) {
+        // Sync clients sometimes reset permissions; try the quick repair before giving up.
+        let report = repair_permissions(&data_dir, &resolve_db_path(&config_root, &data_dir, &settings), false);
+        host_log(
+          LogLevel::Warn,
+          &format!(
+            "{} wasn't writable; fixed {} of {} permission issue(s)",
+            data_dir.display(),
+            report.issues.iter().filter(|i| i.fixed).count(),
+            report.issues_found
+          ),
+        );
+        if !report.writable {
+          let details: Vec<String> = report
+            .issues
+            .iter()
+            .filter(|i| !i.fixed)
+            .take(5)
+            .map(|i| format!("• {}: {}", i.path, i.problem))
+            .collect();
+          let msg = format!(
+            "Reference can't write to its library at {}.\n\n{}",
+            data_dir.display(),
+            if details.is_empty() { "Check that the folder is writable by your user.".to_string() } else { details.join("\n") }
+          );
+          host_log(LogLevel::Error, &msg);
+          let exit_handle = handle.clone();
+          let mut dialog = tauri::api::dialog::MessageDialogBuilder::new("Reference can't start", msg)
+            .kind(tauri::api::dialog::MessageDialogKind::Error);
+          if let Some(window) = app.get_window("main") {
+            dialog = dialog.parent(&window);
+          }
+          dialog.show(move |_| exit_handle.exit(1));
+          return Ok(());
+        }
+      }
+      host_log(
+        LogLevel::Info,
+        &format!(
+          "Starting {} {} (data dir: {}{})",
+          app.package_info().name,
+          app.package_info().version,
+          data_dir.display(),
+          if safe_mode { ", safe mode" } else { "" }
+        ),
+      );
       {
         let state = app.state::<ServerState>();
-        *state.child.lock().unwrap() = Some(child);
+        *state.config_root.lock().unwrap() = Some(config_root.clone());
+        *state.data_dir.lock().unwrap() = Some(data_dir.clone());
+        *state.safe_mode.lock().unwrap() = safe_mode;
+        *state.launch_settings.lock().unwrap() = Some(settings.clone());
+      }
+      if !safe_mode {
+        // The server would fail (or worse, half-migrate) against a DB from a newer version.
+        let compat = check_schema_compat(&handle, &resolve_db_path(&config_root, &data_dir, &settings));
+        match compat.action.as_str() {
+          "update_app" => {
+            let msg = format!(
+              "The library at {} was last opened by a newer version of {} (database schema {}; this version supports up to {}).\n\nUpdate the app to open this library.",
+              data_dir.display(),
+              app.package_info().name,
+              compat.db_version.unwrap_or_default(),
+              compat.bundle_version.unwrap_or_default()
+            );
+            host_log(LogLevel::Error, &msg);
+            let exit_handle = handle.clone();
+            let mut dialog = tauri::api::dialog::MessageDialogBuilder::new("This library needs a newer version", msg)
+              .kind(tauri::api::dialog::MessageDialogKind::Error);
+            if let Some(window) = app.get_window("main") {
+              dialog = dialog.parent(&window);
+            }
+            dialog.show(move |_| exit_handle.exit(1));
+            return Ok(());
+          }
+          "migrate" => {
+            host_log(
+              LogLevel::Info,
+              &format!(
+                "Library schema {} will be migrated to {}",
+                compat.db_version.unwrap_or_default(),
+                compat.bundle_version.unwrap_or_default()
+              ),
+            );
+            if let Some(window) = app.get_window("main") {
+              dispatch_web_event(&window, "moondream:schema:migrating", serde_json::to_value(&compat).ok());
+            }
+          }
+          _ => {}
+        }
+      }
+      let mut rt = read_runtime(&config_root);
+      *app.state::<ServerState>().canvas_zoom.lock().unwrap() = rt.last_zoom;
+      if rt.first_launch_at.is_none() {
+        rt.first_launch_at = Some(unix_now_secs());
+        write_runtime(&config_root, &rt);
       }
 
-      // Ensure the DB schema exists before starting the worker (so it won't crash on a fresh DB).
-      // Hitting /api/projects forces `getDb()` + migrations.
-      let _ = http_get_200("127.0.0.1", port, "/api/projects", Duration::from_secs(8));
+      // Fresh token per launch; the server reads it from MOONDREAM_SESSION_TOKEN_FILE.
+      let token = generate_token();
+      write_session_token(&config_root, &token)?;
+      *app.state::<ServerState>().session_token.lock().unwrap() = Some(token.clone());
 
-      // Start the bundled worker automatically (best-effort). It will talk to the local AI station.
-      // If the station isn't running, the worker will log errors and keep retrying.
-      let db_path = data_dir.join("moondream.sqlite3");
-      if let Ok(w) = spawn_worker(&handle, &db_path, &config_root, &settings) {
-        let state = app.state::<ServerState>();
-        *state.worker.lock().unwrap() = Some(w);
+      if !safe_mode && launched_at_login() && settings.behavior.as_ref().and_then(|b| b.start_hidden).unwrap_or(false) {
+        // Minimize rather than hide: Tauri has no dock "reopen" hook, so a hidden window
+        // would be unreachable without a tray icon.
+        if let Some(window) = app.get_window("main") {
+          let _ = window.minimize();
+        }
+      }
+      if !safe_mode && settings.ui.as_ref().and_then(|u| u.always_on_top).unwrap_or(false) {
+        if let Some(window) = app.get_window("main") {
+          let _ = apply_always_on_top(&window, true);
+        }
       }
 
-      // Nudge the internal loading page so it can redirect as soon as health is ready.
-      if let Some(window) = app.get_window("main") {
-        // The initial `ui/index.html` is plain HTML and does not import @tauri-apps/api.
-        // Provide the chosen port via a global so the page can poll `/api/health` and redirect
-        // without relying on `window.__TAURI__.invoke(...)` being present.
-        let _ = window.eval(&format!("window.__MOONDREAM_PORT__ = {};", port));
-        // Helpful for debugging if the local server never becomes ready.
-        let _ = window.eval(
-          &format!(
-            "window.__MOONDREAM_LOG_HINT__ = \"~/Library/Application Support/{}/logs/next-server.log\";",
-            "com.moondream.desktop"
-          )
+      // Report every missing piece at once instead of failing on the first spawn error.
+      let report = resource_report(&handle, &settings);
+      if !report.ok {
+        let lines: Vec<String> = report
+          .items
+          .iter()
+          .filter(|i| !i.ok)
+          .map(|i| {
+            format!(
+              "• {}{}: {}",
+              i.name,
+              if i.required { "" } else { " (optional)" },
+              i.detail.clone().unwrap_or_default()
+            )
+          })
+          .collect();
+        let msg = format!(
+          "Your Reference install is incomplete. Please reinstall the app.\n\n{}",
+          lines.join("\n")
         );
+        let exit_handle = handle.clone();
+        let mut dialog = tauri::api::dialog::MessageDialogBuilder::new("Reference can't start", msg)
+          .kind(tauri::api::dialog::MessageDialogKind::Error);
+        if let Some(window) = app.get_window("main") {
+          dialog = dialog.parent(&window);
+        }
+        dialog.show(move |_| exit_handle.exit(1));
+        return Ok(());
+      }
 
-        // Keep emitting too (useful if we later switch to a JS listener).
-        let _ = window.emit("moondream://server-ready", ServerInfo { port });
+      if !safe_mode && startup_hold(&handle, &settings).is_some() {
+        defer_services(handle.clone(), config_root, data_dir, settings, token);
+        return Ok(());
       }
+      start_services(&handle, config_root, data_dir, settings, safe_mode, token)?;
 
       Ok(())
     })
-    .on_window_event(|event| {
-      if let tauri::WindowEvent::CloseRequested { api, .. } = event.event() {
+    .on_window_event(|event| match event.event() {
+      tauri::WindowEvent::CloseRequested { api, .. } => {
         api.prevent_close();
 
-        // Best-effort: stop the local server on app close.
-        let state = event.window().state::<ServerState>();
-        if let Some(mut child) = state.child.lock().unwrap().take() {
-          let _ = child.kill();
-        }
-        if let Some(mut worker) = state.worker.lock().unwrap().take() {
-          let _ = worker.kill();
-        }
-        if let Some(mut station) = state.station.lock().unwrap().take() {
-          let _ = station.kill();
-        }
-
-        let _ = event.window().close();
+        // Stop the local server and worker on app close, giving them time to exit cleanly.
+        request_quit(event.window());
       }
-    })
-    .run(tauri::generate_context!())
-    .expect("error while running tauri application");
+      // Only delivered with `ui.native_file_drop`; results arrive as `moondream:import:progress`.
+      tauri::WindowEvent::FileDrop(tauri::FileDropEvent::Dropped(paths)) if !paths.is_empty() => {
+        let app = event.window().app_handle();
+        let paths: Vec<String> = paths.iter().map(|p| p.to_string_lossy().to_string()).collect();
+        std::thread::spawn(move || {
+          if let Err(e) = import_dropped_files(app, paths) {
+            host_log(LogLevel::Warn, &format!("Dropped files weren't imported: {}", e));
+          }
+        });
+      }
+      _ => {}
+    });
+
+  // A missing/broken system webview surfaces here (as an error, or a panic from the windowing layer).
+  match std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || builder.run(context))) {
+    Ok(Ok(())) => {}
+    Ok(Err(e)) => report_webview_failure(&e.to_string()),
+    Err(panic) => report_webview_failure(&panic_message(panic.as_ref())),
+  }
 }
 
-
+// Fresh per-test temp dir, shared by the module tests.
+#[cfg(test)]
+fn scratch_dir(name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("moondream-test-{}-{}", std::process::id(), name));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(&dir).unwrap();
+  dir
+}
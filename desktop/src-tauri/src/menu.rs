@@ -0,0 +1,457 @@
+use crate::*;
+
+// Canvas zoom the host asks for on "Reset Zoom" (matches the menu label).
+pub(crate) const RESET_ZOOM: f64 = 0.1;
+
+// Multiplicative zoom step for Zoom In / Zoom Out (zoom-in multiplies, zoom-out divides).
+pub(crate) const ZOOM_STEP: f64 = 1.25;
+
+// View > Zoom to N% (menu ids `zoom_preset_<N>`).
+pub(crate) const ZOOM_PRESETS: [f32; 3] = [25.0, 50.0, 100.0];
+
+pub(crate) const ZOOM_MIN_PERCENT: f32 = 1.0;
+
+pub(crate) const ZOOM_MAX_PERCENT: f32 = 800.0;
+
+// How long a menu click waits for the webview's answer before the built-in behavior runs. The
+// answer normally arrives within milliseconds; this only elapses when the page can't invoke.
+pub(crate) const MENU_ACK_TIMEOUT: Duration = Duration::from_millis(300);
+
+#[derive(Default)]
+pub(crate) struct MenuBridge {
+  pub(crate) next_token: Mutex<u64>,
+  pub(crate) pending: Mutex<std::collections::HashMap<u64, std::sync::mpsc::Sender<bool>>>,
+  // The last click got no answer (no IPC on this page); later clicks skip the wait until one arrives.
+  pub(crate) ack_missing: Mutex<bool>,
+}
+
+// Every menu click first fires a cancelable `moondream:menu:<id>` CustomEvent (detail: { id, token }).
+// A listener that calls `preventDefault()` during dispatch takes over the action; listeners must
+// decide synchronously, since the page answers `menu_ack` right after dispatching. Otherwise the
+// hardcoded behavior in `run_menu_action` runs, at once, or after MENU_ACK_TIMEOUT if the page
+// couldn't answer.
+pub(crate) fn bridge_menu_event(window: tauri::Window, id: String) {
+  let bridge = window.state::<MenuBridge>();
+  let token = {
+    let mut next = bridge.next_token.lock().unwrap();
+    *next += 1;
+    *next
+  };
+  let (tx, rx) = std::sync::mpsc::channel();
+  bridge.pending.lock().unwrap().insert(token, tx);
+
+  let event_name = format!("moondream:menu:{}", id);
+  let js = format!(
+    r#"
+      (function () {{
+        var handled = false;
+        try {{
+          handled = !window.dispatchEvent(new CustomEvent({name}, {{ cancelable: true, detail: {{ id: {id}, token: {token} }} }}));
+        }} catch (_) {{}}
+        var t = window.__TAURI__;
+        var invoke = window.__TAURI_INVOKE__ || (t && (t.invoke || (t.tauri && t.tauri.invoke)));
+        if (typeof invoke === "function") {{
+          Promise.resolve(invoke("menu_ack", {{ token: {token}, handled: handled }})).catch(function () {{}});
+        }}
+      }})();
+    "#,
+    name = serde_json::to_string(&event_name).unwrap_or_default(),
+    id = serde_json::to_string(&id).unwrap_or_default(),
+    token = token
+  );
+  let _ = window.eval(&js);
+
+  if *bridge.ack_missing.lock().unwrap() {
+    // The last click went unanswered (no IPC on this page), so don't make every click wait for
+    // nothing. An answer to this one still clears the flag for the next.
+    bridge.pending.lock().unwrap().remove(&token);
+    std::thread::spawn(move || run_menu_action(&window, &id));
+    return;
+  }
+
+  // Wait off the main thread so a hung webview can't stall the menu.
+  std::thread::spawn(move || {
+    let answer = rx.recv_timeout(MENU_ACK_TIMEOUT).ok();
+    let bridge = window.state::<MenuBridge>();
+    bridge.pending.lock().unwrap().remove(&token);
+    *bridge.ack_missing.lock().unwrap() = answer.is_none();
+    if !answer.unwrap_or(false) {
+      run_menu_action(&window, &id);
+    }
+  });
+}
+
+#[tauri::command]
+pub(crate) fn focus_mode(state: tauri::State<ServerState>) -> bool {
+  *state.focus_mode.lock().unwrap()
+}
+
+// sessionStorage key the root page checks on load, so the reset survives the navigation (and pages
+// that haven't registered listeners yet). Holds the reset-zoom detail; the page removes it once applied.
+pub(crate) const PENDING_RESET_VIEW_KEY: &str = "moondream:pending-reset-view";
+
+// "Go home": root route, default zoom, canvas centered.
+pub(crate) fn apply_reset_view(window: &tauri::Window) {
+  let detail = serde_json::to_string(&serde_json::json!({ "zoom": RESET_ZOOM })).unwrap_or_default();
+  let js = format!(
+    r#"
+      (function () {{
+        try {{ window.sessionStorage.setItem({key:?}, {detail:?}); }} catch (_) {{}}
+        if (window.location.pathname !== "/") {{
+          window.location.href = "/";
+          return;
+        }}
+        window.dispatchEvent(new CustomEvent("moondream:canvas:reset-zoom", {{ detail: {detail} }}));
+        window.dispatchEvent(new CustomEvent("moondream:canvas:center"));
+      }})();
+    "#,
+    key = PENDING_RESET_VIEW_KEY,
+    detail = detail
+  );
+  let _ = window.eval(&js);
+}
+
+#[tauri::command]
+pub(crate) fn reset_view(window: tauri::Window) {
+  apply_reset_view(&window);
+}
+
+#[tauri::command]
+pub(crate) fn zoom_presets() -> Vec<f32> {
+  ZOOM_PRESETS.to_vec()
+}
+
+// The UI applies it from `moondream:canvas:set-zoom` (detail: { zoom, percent }, zoom = percent / 100)
+// and confirms via `report_zoom`.
+pub(crate) fn apply_zoom(window: &tauri::Window, percent: f32) -> Result<(), String> {
+  if !percent.is_finite() || !(ZOOM_MIN_PERCENT..=ZOOM_MAX_PERCENT).contains(&percent) {
+    return Err(format!("Zoom must be between {}% and {}%", ZOOM_MIN_PERCENT, ZOOM_MAX_PERCENT));
+  }
+  dispatch_web_event(
+    window,
+    "moondream:canvas:set-zoom",
+    Some(serde_json::json!({ "zoom": percent as f64 / 100.0, "percent": percent })),
+  );
+  Ok(())
+}
+
+#[tauri::command]
+pub(crate) fn set_zoom(window: tauri::Window, percent: f32) -> Result<(), String> {
+  apply_zoom(&window, percent)
+}
+
+// Current canvas zoom; before the UI has reported one, the zoom it had at the end of the last session.
+#[tauri::command]
+pub(crate) fn canvas_zoom(state: tauri::State<ServerState>) -> Option<f64> {
+  *state.canvas_zoom.lock().unwrap()
+}
+
+// Called by the UI once a zoom change settles (debounced: each call rewrites runtime.json).
+#[tauri::command]
+pub(crate) fn report_zoom(state: tauri::State<ServerState>, zoom: f64) {
+  if !zoom.is_finite() || zoom <= 0.0 {
+    return;
+  }
+  let previous = state.canvas_zoom.lock().unwrap().replace(zoom);
+  if previous == Some(zoom) {
+    return;
+  }
+  if let Some(config_root) = state.config_root.lock().unwrap().clone() {
+    let mut rt = read_runtime(&config_root);
+    rt.last_zoom = Some(zoom);
+    write_runtime(&config_root, &rt);
+  }
+}
+
+// Called by the UI whenever focus mode changes (from the menu, the Space shortcut, or on page load).
+#[tauri::command]
+pub(crate) fn report_focus_mode(window: tauri::Window, state: tauri::State<ServerState>, enabled: bool) {
+  *state.focus_mode.lock().unwrap() = enabled;
+  let _ = window.menu_handle().get_item("focus_toggle").set_selected(enabled);
+}
+
+pub(crate) fn apply_always_on_top(window: &tauri::Window, enabled: bool) -> Result<(), String> {
+  window.set_always_on_top(enabled).map_err(|e| e.to_string())?;
+  *window.state::<ServerState>().always_on_top.lock().unwrap() = enabled;
+  let _ = window.menu_handle().get_item("keep_on_top").set_selected(enabled);
+  Ok(())
+}
+
+#[tauri::command]
+pub(crate) fn set_always_on_top(app: tauri::AppHandle, window: tauri::Window, enabled: bool) -> Result<(), String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let mut settings = try_read_settings(&config_root)?;
+  apply_always_on_top(&window, enabled)?;
+  settings.ui.get_or_insert_with(Default::default).always_on_top = Some(enabled);
+  write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub(crate) fn menu_ack(bridge: tauri::State<MenuBridge>, token: u64, handled: bool) {
+  *bridge.ack_missing.lock().unwrap() = false;
+  if let Some(tx) = bridge.pending.lock().unwrap().remove(&token) {
+    let _ = tx.send(handled);
+  }
+}
+
+// Hardcoded menu behavior; runs only when the webview didn't claim the click (see `bridge_menu_event`).
+pub(crate) fn run_menu_action(window: &tauri::Window, id: &str) {
+  match id {
+    "settings" => {
+      // Navigate within the Next.js app.
+      // If we're currently on a project route, preserve projectId so Settings can enable project-scoped actions
+      // like "Retry failed AI".
+      let _ = window.eval(
+        "(function(){const m=window.location.pathname.match(/^\\/projects\\/([^\\/]+)/);const pid=m&&m[1];window.location.href=pid?('/settings?projectId='+encodeURIComponent(pid)):'/settings';})()"
+      );
+    }
+    "project_settings" => {
+      // Navigate with a fade, and include projectId when we're currently in /projects/:id.
+      //
+      // Mirrors the in-app shortcut logic:
+      // - "." opens Settings (project context)
+      // - Cmd+. / Ctrl+. also opens Settings (project context)
+      //
+      // Note: we do this here (rather than relying only on a web listener) so it still works
+      // even if the project page hasn't mounted its listeners yet.
+      let js = r#"
+        (function () {
+          try { window.dispatchEvent(new Event("moondream:route-fade:start")); } catch (_) {}
+          var m = (window.location && window.location.pathname || "").match(/^\/projects\/([^\/?#]+)/);
+          var pid = m && m[1] ? decodeURIComponent(m[1]) : null;
+          var url = pid ? ("/settings?projectId=" + encodeURIComponent(pid)) : "/settings";
+          window.setTimeout(function () { window.location.href = url; }, 220);
+        })();
+      "#;
+      let _ = window.eval(js);
+    }
+    "copy_project_link" => {
+      let _ = copy_project_link(window.clone());
+    }
+    "command_palette" => {
+      dispatch_web_event(window, "moondream:command-palette:toggle", None);
+    }
+    "find_assets" => {
+      dispatch_web_event(window, "moondream:command-palette:open", None);
+    }
+    "delete_selection" => {
+      dispatch_web_event(window, "moondream:canvas:delete-selection", None);
+    }
+    "reset_zoom" => {
+      dispatch_web_event(window, "moondream:canvas:reset-zoom", Some(serde_json::json!({ "zoom": RESET_ZOOM })));
+    }
+    "zoom_in" => {
+      dispatch_web_event(window, "moondream:canvas:zoom-in", Some(serde_json::json!({ "step": ZOOM_STEP })));
+    }
+    "zoom_out" => {
+      dispatch_web_event(window, "moondream:canvas:zoom-out", Some(serde_json::json!({ "step": ZOOM_STEP })));
+    }
+    "reset_view" => apply_reset_view(window),
+    id if id.starts_with("zoom_preset_") => {
+      if let Ok(percent) = id["zoom_preset_".len()..].parse::<f32>() {
+        let _ = apply_zoom(window, percent);
+      }
+    }
+    "focus_toggle" => {
+      // The UI applies `enabled` and confirms via `report_focus_mode`; state only changes then.
+      let enabled = !*window.state::<ServerState>().focus_mode.lock().unwrap();
+      dispatch_web_event(window, "moondream:canvas:focus-toggle", Some(serde_json::json!({ "enabled": enabled })));
+    }
+    _ => {}
+  }
+}
+
+// Native menu labels and About text; the webview localizes itself. Missing keys fall back to English.
+pub(crate) const MENU_TRANSLATIONS: &[(&str, &[(&str, &str)])] = &[
+  (
+    "en",
+    &[
+      ("settings", "Settings"),
+      ("command_palette", "Command Palette"),
+      ("find", "Find…"),
+      ("find_assets", "Find Assets / Search"),
+      ("project_settings", "Project Settings…"),
+      ("copy_project_link", "Copy Project Link"),
+      ("delete_selection", "Delete Selection"),
+      ("undo_delete_selection", "Undo Delete Selection"),
+      ("navigate_selection", "Navigate Selection"),
+      ("reset_zoom", "Reset Zoom (10%)"),
+      ("zoom_in", "Zoom In"),
+      ("zoom_out", "Zoom Out"),
+      ("reset_view", "Reset View"),
+      ("zoom_preset_25", "Zoom to 25%"),
+      ("zoom_preset_50", "Zoom to 50%"),
+      ("zoom_preset_100", "Zoom to 100%"),
+      ("focus_toggle", "Focus Toggle"),
+      ("toggle_minimap", "Toggle Minimap"),
+      ("close_escape", "Close / Cancel / Dismiss"),
+      ("keep_on_top", "Keep on Top"),
+      ("quit", "Quit Reference"),
+      ("about_comments", "A local-first canvas for arranging images."),
+      ("menu_file", "File"),
+      ("menu_edit", "Edit"),
+      ("menu_view", "View"),
+      ("menu_shortcuts", "Shortcuts"),
+      ("menu_window", "Window"),
+      ("menu_global", "Global"),
+      ("menu_project", "Project"),
+      ("menu_canvas", "Canvas"),
+    ],
+  ),
+  (
+    "nl",
+    &[
+      ("settings", "Instellingen"),
+      ("command_palette", "Opdrachtenpalet"),
+      ("find", "Zoek…"),
+      ("find_assets", "Zoek bestanden"),
+      ("project_settings", "Projectinstellingen…"),
+      ("copy_project_link", "Kopieer projectlink"),
+      ("delete_selection", "Verwijder selectie"),
+      ("undo_delete_selection", "Verwijderen ongedaan maken"),
+      ("navigate_selection", "Door selectie navigeren"),
+      ("reset_zoom", "Zoom herstellen (10%)"),
+      ("zoom_in", "Zoom in"),
+      ("zoom_out", "Zoom uit"),
+      ("reset_view", "Weergave herstellen"),
+      ("zoom_preset_25", "Zoom naar 25%"),
+      ("zoom_preset_50", "Zoom naar 50%"),
+      ("zoom_preset_100", "Zoom naar 100%"),
+      ("focus_toggle", "Focus aan/uit"),
+      ("toggle_minimap", "Minikaart aan/uit"),
+      ("close_escape", "Sluiten / Annuleren"),
+      ("keep_on_top", "Altijd bovenaan"),
+      ("quit", "Stop Reference"),
+      ("about_comments", "Een lokaal canvas om afbeeldingen te ordenen."),
+      ("menu_file", "Archief"),
+      ("menu_edit", "Wijzig"),
+      ("menu_view", "Weergave"),
+      ("menu_shortcuts", "Sneltoetsen"),
+      ("menu_window", "Venster"),
+      ("menu_global", "Algemeen"),
+      ("menu_project", "Project"),
+      ("menu_canvas", "Canvas"),
+    ],
+  ),
+  (
+    "de",
+    &[
+      ("settings", "Einstellungen"),
+      ("command_palette", "Befehlspalette"),
+      ("find", "Suchen…"),
+      ("find_assets", "Dateien suchen"),
+      ("project_settings", "Projekteinstellungen…"),
+      ("copy_project_link", "Projektlink kopieren"),
+      ("delete_selection", "Auswahl löschen"),
+      ("undo_delete_selection", "Löschen widerrufen"),
+      ("navigate_selection", "Auswahl bewegen"),
+      ("reset_zoom", "Zoom zurücksetzen (10%)"),
+      ("zoom_in", "Vergrößern"),
+      ("zoom_out", "Verkleinern"),
+      ("reset_view", "Ansicht zurücksetzen"),
+      ("zoom_preset_25", "Zoom auf 25 %"),
+      ("zoom_preset_50", "Zoom auf 50 %"),
+      ("zoom_preset_100", "Zoom auf 100 %"),
+      ("focus_toggle", "Fokus umschalten"),
+      ("toggle_minimap", "Minikarte umschalten"),
+      ("close_escape", "Schließen / Abbrechen"),
+      ("keep_on_top", "Immer im Vordergrund"),
+      ("quit", "Reference beenden"),
+      ("about_comments", "Eine lokale Leinwand zum Anordnen von Bildern."),
+      ("menu_file", "Ablage"),
+      ("menu_edit", "Bearbeiten"),
+      ("menu_view", "Darstellung"),
+      ("menu_shortcuts", "Tastenkürzel"),
+      ("menu_window", "Fenster"),
+      ("menu_global", "Allgemein"),
+      ("menu_project", "Projekt"),
+      ("menu_canvas", "Leinwand"),
+    ],
+  ),
+  (
+    "fr",
+    &[
+      ("settings", "Réglages"),
+      ("command_palette", "Palette de commandes"),
+      ("find", "Rechercher…"),
+      ("find_assets", "Rechercher des fichiers"),
+      ("project_settings", "Réglages du projet…"),
+      ("copy_project_link", "Copier le lien du projet"),
+      ("delete_selection", "Supprimer la sélection"),
+      ("undo_delete_selection", "Annuler la suppression"),
+      ("navigate_selection", "Parcourir la sélection"),
+      ("reset_zoom", "Réinitialiser le zoom (10%)"),
+      ("zoom_in", "Zoom avant"),
+      ("zoom_out", "Zoom arrière"),
+      ("reset_view", "Réinitialiser la vue"),
+      ("zoom_preset_25", "Zoom à 25 %"),
+      ("zoom_preset_50", "Zoom à 50 %"),
+      ("zoom_preset_100", "Zoom à 100 %"),
+      ("focus_toggle", "Mode focus"),
+      ("toggle_minimap", "Afficher la mini-carte"),
+      ("close_escape", "Fermer / Annuler"),
+      ("keep_on_top", "Toujours au premier plan"),
+      ("quit", "Quitter Reference"),
+      ("about_comments", "Un canevas local pour organiser des images."),
+      ("menu_file", "Fichier"),
+      ("menu_edit", "Édition"),
+      ("menu_view", "Présentation"),
+      ("menu_shortcuts", "Raccourcis"),
+      ("menu_window", "Fenêtre"),
+      ("menu_global", "Général"),
+      ("menu_project", "Projet"),
+      ("menu_canvas", "Canevas"),
+    ],
+  ),
+];
+
+// "de-CH" / "de_CH.UTF-8" match "de". Unknown locales resolve to "en".
+pub(crate) fn supported_locale(requested: &str) -> Option<&'static str> {
+  let lang = requested.trim().split(['-', '_', '.']).next().unwrap_or("").to_lowercase();
+  MENU_TRANSLATIONS.iter().map(|(l, _)| *l).find(|l| *l == lang)
+}
+
+pub(crate) fn menu_locale(settings: &AppSettings) -> &'static str {
+  settings
+    .ui
+    .as_ref()
+    .and_then(|u| u.locale.as_deref())
+    .and_then(supported_locale)
+    .unwrap_or("en")
+}
+
+pub(crate) fn tr(locale: &str, key: &str) -> &'static str {
+  let lookup = |l: &str| {
+    MENU_TRANSLATIONS
+      .iter()
+      .find(|(name, _)| *name == l)
+      .and_then(|(_, strings)| strings.iter().find(|(k, _)| *k == key))
+      .map(|(_, v)| *v)
+  };
+  lookup(locale).or_else(|| lookup("en")).unwrap_or("")
+}
+
+// `locale` = None goes back to English. The menu is built once at launch, so this returns whether a
+// relaunch is needed for it to show.
+#[tauri::command]
+pub(crate) fn set_locale(app: tauri::AppHandle, state: tauri::State<ServerState>, locale: Option<String>) -> Result<bool, String> {
+  let locale = match locale {
+    Some(l) => Some(
+      supported_locale(&l)
+        .ok_or_else(|| {
+          let known: Vec<&str> = MENU_TRANSLATIONS.iter().map(|(l, _)| *l).collect();
+          format!("Unsupported locale {:?} (available: {})", l, known.join(", "))
+        })?
+        .to_string(),
+    ),
+    None => None,
+  };
+  let config_root = managed_config_root(&app, &state)?;
+  let mut settings = try_read_settings(&config_root)?;
+  settings.ui.get_or_insert_with(Default::default).locale = locale;
+  write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+  let launched = state.launch_settings.lock().unwrap().clone().unwrap_or_default();
+  Ok(menu_locale(&launched) != menu_locale(&settings))
+}
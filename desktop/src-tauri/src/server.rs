@@ -0,0 +1,2006 @@
+use crate::*;
+
+#[derive(Clone, Serialize)]
+pub(crate) struct ServerInfo {
+  pub(crate) port: u16,
+}
+
+#[tauri::command]
+pub(crate) fn server_port(state: tauri::State<ServerState>) -> Option<u16> {
+  *state.port.lock().unwrap()
+}
+
+pub(crate) const AWAIT_PORT_MAX_TIMEOUT: Duration = Duration::from_secs(60);
+
+// For UI that loads before `moondream://server-ready`: one call instead of polling `server_port`.
+// Runs off the main thread; returns None on timeout (or when the server uses a unix socket).
+#[tauri::command(async)]
+pub(crate) fn await_server_port(state: tauri::State<ServerState>, timeout_ms: u64) -> Option<u16> {
+  let deadline = Instant::now() + Duration::from_millis(timeout_ms).min(AWAIT_PORT_MAX_TIMEOUT);
+  loop {
+    if let Some(port) = *state.port.lock().unwrap() {
+      return Some(port);
+    }
+    if Instant::now() >= deadline {
+      return None;
+    }
+    std::thread::sleep(Duration::from_millis(50));
+  }
+}
+
+// The new port (None on a Unix socket) or why the restart failed.
+pub(crate) type RestartResult = Result<Option<u16>, String>;
+
+// Restarts never start closer together than this, whatever triggered them.
+pub(crate) const MIN_RESTART_INTERVAL: Duration = Duration::from_secs(5);
+
+// Every server restart goes through here. One runs at a time; a caller that had to wait for
+// another restart gets that restart's result instead of restarting again, so colliding
+// triggers collapse into one. `trigger` ("manual", "node_source", "migration", ...) is
+// reported with `moondream:server:restarting`.
+pub(crate) fn restart_next_server(app: &tauri::AppHandle, trigger: &str) -> RestartResult {
+  let state = app.state::<ServerState>();
+  // `watch_maintenance` clears the pause before its own restart; anyone else waits for that.
+  if let Some(w) = state.maintenance_pause.lock().unwrap().clone() {
+    if !*state.maintenance_override.lock().unwrap() {
+      host_log(LogLevel::Info, &format!("restart ({}) refused: maintenance window {}", trigger, w));
+      return Err(format!("The local server is paused for maintenance ({})", w));
+    }
+  }
+  let seen = *state.restart_count.lock().unwrap();
+  let _guard = loop {
+    let guard = state.restart_lock.lock().unwrap();
+    let last = state.last_restart.lock().unwrap().clone();
+    if *state.restart_count.lock().unwrap() != seen {
+      if let Some((_, result)) = last {
+        host_log(LogLevel::Info, &format!("restart ({}) coalesced with the one that just ran", trigger));
+        return result;
+      }
+    }
+    let wait = last.map(|(at, _)| MIN_RESTART_INTERVAL.saturating_sub(at.elapsed())).unwrap_or_default();
+    if wait.is_zero() {
+      break guard;
+    }
+    // Wait out the rate limit without the lock; whoever gets it first restarts, the rest coalesce.
+    drop(guard);
+    host_log(LogLevel::Info, &format!("restart ({}) rate-limited; waiting {:?}", trigger, wait));
+    std::thread::sleep(wait);
+  };
+  host_log(LogLevel::Info, &format!("restarting server ({})", trigger));
+  if let Some(window) = app.get_window("main") {
+    dispatch_web_event(&window, "moondream:server:restarting", Some(serde_json::json!({ "trigger": trigger })));
+  }
+  let started = Instant::now();
+  let result = restart_next_server_now(app);
+  *state.last_restart.lock().unwrap() = Some((started, result.clone()));
+  *state.restart_count.lock().unwrap() += 1;
+  result
+}
+
+pub(crate) fn restart_next_server_now(app: &tauri::AppHandle) -> RestartResult {
+  let state = app.state::<ServerState>();
+  let config_root = require_config_root(&state)?;
+  let data_dir = require_data_dir(&state)?;
+
+  // Remember where the user was so the restarted server can put them back there.
+  if let Some(route) = app.get_window("main").and_then(|w| current_route(&w)) {
+    let mut rt = read_runtime(&config_root);
+    rt.last_route = Some(route);
+    write_runtime(&config_root, &rt);
+  }
+
+  if let Some(mut prev) = state.child.lock().unwrap().take() {
+    let _ = prev.kill();
+    let _ = prev.wait();
+  }
+
+  let settings = effective_settings(&state, &config_root);
+  if !worker_running(&state) {
+    settle_db_location(&config_root, &data_dir, &settings);
+  }
+  let prev_addr = current_server_addr(&state);
+  let addr = match server_transport(&settings).map_err(|e| e.to_string())?.as_str() {
+    "unix" => ServerAddr::Unix(server_socket_path(&config_root)),
+    _ => ServerAddr::Tcp(pick_restart_port(&config_root, &settings)),
+  };
+  let db_path = resolve_db_path(&config_root, &data_dir, &settings);
+  let child = spawn_next_server(app, &addr, &config_root, &data_dir, &db_path, &settings)
+    .map_err(|e| e.to_string())?;
+  *state.child.lock().unwrap() = Some(child);
+  set_server_addr(&state, &addr);
+
+  if !server_get_200(&addr, &server_ready_path(&settings), Duration::from_secs(15)) {
+    let mut msg = format!("Server did not become ready at {}.", addr.base_url());
+    if let ServerAddr::Tcp(port) = addr {
+      if let Some(note) = port_conflict_note(&state, port) {
+        msg = format!("{} {}", msg, note);
+      }
+    }
+    return Err(format!(
+      "{} See {}",
+      msg,
+      logs_dir(&config_root).join("next-server.log").display()
+    ));
+  }
+  if let ServerAddr::Tcp(port) = addr {
+    record_healthy_port(&config_root, port);
+  }
+
+  // Only restore routes the new server still serves (e.g. a deleted project 404s).
+  let route = read_runtime(&config_root)
+    .last_route
+    .filter(|r| server_status(&addr, r, Duration::from_secs(3)) == Some(200));
+
+  if let Some(window) = app.get_window("main") {
+    if prev_addr.as_ref() == Some(&addr) && route.is_some() {
+      // Same origin and route: a plain reload keeps the URL (and browser caches) intact.
+      let _ = window.eval("window.location.reload();");
+    } else {
+      // The old origin (or route) is gone; point the webview at the new server.
+      let mut js = String::new();
+      if let ServerAddr::Tcp(port) = addr {
+        js.push_str(&format!("window.__MOONDREAM_PORT__ = {}; ", port));
+      }
+      let url = format!("{}{}", addr.base_url(), route.as_deref().unwrap_or("/"));
+      js.push_str(&format!(
+        "window.location.replace({});",
+        serde_json::to_string(&url).unwrap_or_default()
+      ));
+      let _ = window.eval(&js);
+    }
+    if let ServerAddr::Tcp(port) = addr {
+      let _ = window.emit("moondream://server-ready", ServerInfo { port });
+    }
+  }
+  Ok(match addr {
+    ServerAddr::Tcp(port) => Some(port),
+    ServerAddr::Unix(_) => None,
+  })
+}
+
+#[tauri::command(async)]
+pub(crate) fn restart_server(app: tauri::AppHandle) -> Result<Option<u16>, String> {
+  let port = restart_next_server(&app, "manual")?;
+  // Bring the worker back too if it's down (e.g. after stop_services).
+  let state = app.state::<ServerState>();
+  if let (Some(config_root), Some(db_path)) =
+    (state.config_root.lock().unwrap().clone(), state.db_path.lock().unwrap().clone())
+  {
+    let settings = effective_settings(&state, &config_root);
+    start_worker_if_allowed(&app, &db_path, &config_root, &settings);
+  }
+  Ok(port)
+}
+
+#[tauri::command]
+pub(crate) fn check_node(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<NodeInfo, String> {
+  // Prefer the Node the running server was actually started with.
+  let active = state.node.lock().unwrap().clone();
+  let mut info = match active {
+    Some(info) => info,
+    None => {
+      let config_root = app_config_root(&app)
+        .ok_or_else(|| "Missing app_data_dir".to_string())?;
+      resolve_node(&app, &read_settings(&config_root)).map_err(|e| e.to_string())?
+    }
+  };
+  info.version = node_version(&PathBuf::from(&info.path));
+  Ok(info)
+}
+
+#[tauri::command(async)]
+pub(crate) fn set_node_source(
+  app: tauri::AppHandle,
+  state: tauri::State<ServerState>,
+  source: String,
+) -> Result<NodeInfo, String> {
+  let source = source.trim().to_lowercase();
+  if !["bundled", "system", "auto"].contains(&source.as_str()) {
+    return Err(format!("Unknown node source \"{}\" (expected bundled, system, or auto)", source));
+  }
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let mut settings = try_read_settings(&config_root)?;
+
+  // Fail before persisting if the requested Node can't be found.
+  let mut candidate = settings.clone();
+  candidate.server.get_or_insert(ServerSettings::default()).node_source = Some(source.clone());
+  resolve_node(&app, &candidate).map_err(|e| e.to_string())?;
+
+  settings.server.get_or_insert(ServerSettings::default()).node_source = Some(source);
+  write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+
+  if state.child.lock().unwrap().is_some() {
+    restart_next_server(&app, "node_source")?;
+  }
+  check_node(app, state)
+}
+
+pub(crate) const PORT_PICK_ATTEMPTS: usize = 5;
+
+pub(crate) fn pick_free_port() -> u16 {
+  // Bind to port 0 to let the OS pick an available port, then release it.
+  // Re-check each pick: some endpoint agents grab loopback ports as soon as they're released.
+  let mut last = None;
+  for _ in 0..PORT_PICK_ATTEMPTS {
+    let picked = TcpListener::bind("127.0.0.1:0")
+      .ok()
+      .and_then(|l| l.local_addr().ok().map(|a| a.port()));
+    if let Some(p) = picked {
+      if port_available(p) {
+        return p;
+      }
+      last = Some(p);
+    }
+  }
+  host_log(
+    LogLevel::Warn,
+    &format!("No port stayed free after {} picks; trying anyway", PORT_PICK_ATTEMPTS),
+  );
+  last.unwrap_or(3210)
+}
+
+pub(crate) fn pick_free_port_in_range(start: u16, end: u16) -> Option<u16> {
+  (start..=end).find(|p| port_available(*p))
+}
+
+pub(crate) fn configured_port_range(settings: &AppSettings) -> Option<(u16, u16)> {
+  match settings.server.as_ref().and_then(|s| s.port_range) {
+    Some((start, end)) if start > 0 && start <= end => Some((start, end)),
+    Some((start, end)) => {
+      host_log(
+        LogLevel::Warn,
+        &format!("Ignoring invalid server.port_range [{}, {}]", start, end),
+      );
+      None
+    }
+    None => None,
+  }
+}
+
+pub(crate) fn pick_server_port(settings: &AppSettings) -> u16 {
+  if let Some((start, end)) = configured_port_range(settings) {
+    match pick_free_port_in_range(start, end) {
+      Some(p) => {
+        host_log(LogLevel::Info, &format!("Scanned ports {}-{}; using {}", start, end, p));
+        return p;
+      }
+      None => host_log(
+        LogLevel::Warn,
+        &format!(
+          "No free port in {}-{}; falling back to a random port. {}",
+          start,
+          end,
+          port_diagnosis(start).describe()
+        ),
+      ),
+    }
+  }
+  let p = pick_free_port();
+  host_log(LogLevel::Info, &format!("Using port {}", p));
+  p
+}
+
+pub(crate) fn port_available(port: u16) -> bool {
+  TcpListener::bind(("127.0.0.1", port)).is_ok()
+}
+
+#[derive(Clone, Serialize, Default)]
+pub(crate) struct PortDiagnosis {
+  pub(crate) port: u16,
+  pub(crate) in_use: bool,
+  // Unknown when the platform tool is missing or the holder belongs to another user.
+  pub(crate) holder_pid: Option<u32>,
+  pub(crate) holder_name: Option<String>,
+}
+
+impl PortDiagnosis {
+  pub(crate) fn describe(&self) -> String {
+    match (self.holder_name.as_deref(), self.holder_pid) {
+      (Some(name), Some(pid)) => format!("Port {} is in use by {} (pid {}).", self.port, name, pid),
+      (None, Some(pid)) => format!("Port {} is in use by pid {}.", self.port, pid),
+      _ if self.in_use => format!("Port {} is in use by another process.", self.port),
+      _ => format!("Port {} is free.", self.port),
+    }
+  }
+}
+
+// `lsof -F pc` prints one field per line: `p<pid>` then `c<command>`.
+pub(crate) fn port_holder_lsof(port: u16) -> Option<(u32, Option<String>)> {
+  let out = command_stdout("lsof", &["-nP", &format!("-iTCP:{}", port), "-sTCP:LISTEN", "-Fpc"])?;
+  let pid = out.lines().find_map(|l| l.strip_prefix('p')).and_then(|p| p.trim().parse().ok())?;
+  let name = out.lines().find_map(|l| l.strip_prefix('c')).map(|c| c.trim().to_string());
+  Some((pid, name))
+}
+
+// `ss -ltnpH` lines end in `users:(("node",pid=123,fd=20))` (Linux without lsof).
+pub(crate) fn port_holder_ss(port: u16) -> Option<(u32, Option<String>)> {
+  let out = command_stdout("ss", &["-ltnpH", &format!("sport = :{}", port)])?;
+  let users = out.lines().find_map(|l| l.split("users:((").nth(1))?;
+  let name = users.split('"').nth(1).map(|n| n.to_string());
+  let pid = users.split("pid=").nth(1)?.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()?;
+  Some((pid, name))
+}
+
+// `netstat -ano` rows: `TCP  127.0.0.1:3210  0.0.0.0:0  LISTENING  1234`; the name comes from tasklist CSV.
+pub(crate) fn port_holder_netstat(port: u16) -> Option<(u32, Option<String>)> {
+  let out = command_stdout("netstat", &["-ano", "-p", "tcp"])?;
+  let suffix = format!(":{}", port);
+  let pid: u32 = out.lines().find_map(|l| {
+    let cols: Vec<&str> = l.split_whitespace().collect();
+    match cols.as_slice() {
+      [_, local, _, state, pid] if local.ends_with(&suffix) && state.eq_ignore_ascii_case("LISTENING") => pid.parse().ok(),
+      _ => None,
+    }
+  })?;
+  let name = command_stdout("tasklist", &["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+    .and_then(|t| t.lines().next().and_then(|l| l.split('"').nth(1)).map(|n| n.to_string()));
+  Some((pid, name))
+}
+
+pub(crate) fn port_diagnosis(port: u16) -> PortDiagnosis {
+  let mut d = PortDiagnosis {
+    port,
+    in_use: !port_available(port),
+    ..Default::default()
+  };
+  if !d.in_use {
+    return d;
+  }
+  let holder = if cfg!(target_os = "windows") {
+    port_holder_netstat(port)
+  } else {
+    port_holder_lsof(port).or_else(|| port_holder_ss(port))
+  };
+  if let Some((pid, name)) = holder {
+    d.holder_pid = Some(pid);
+    d.holder_name = name;
+  }
+  d
+}
+
+#[tauri::command(async)]
+pub(crate) fn diagnose_port(port: u16) -> PortDiagnosis {
+  port_diagnosis(port)
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct ProcInfo {
+  pub(crate) pid: u32,
+  pub(crate) kind: String, // "server" | "worker"
+  pub(crate) command: String,
+}
+
+// (pid, full command line) for every process we can see.
+pub(crate) fn list_processes() -> Vec<(u32, String)> {
+  let out = if cfg!(target_os = "windows") {
+    command_stdout(
+      "powershell",
+      &[
+        "-NoProfile",
+        "-Command",
+        "Get-CimInstance Win32_Process | ForEach-Object { \"$($_.ProcessId)`t$($_.CommandLine)\" }",
+      ],
+    )
+  } else {
+    command_stdout("ps", &["-axww", "-o", "pid=,command="])
+  };
+  out
+    .unwrap_or_default()
+    .lines()
+    .filter_map(|line| {
+      let line = line.trim();
+      let (pid, command) = line.split_once(|c: char| c.is_whitespace())?;
+      Some((pid.parse().ok()?, command.trim().to_string()))
+    })
+    .collect()
+}
+
+pub(crate) fn process_cwd(pid: u32) -> Option<PathBuf> {
+  if cfg!(target_os = "linux") {
+    return std::fs::read_link(format!("/proc/{}/cwd", pid)).ok();
+  }
+  if cfg!(target_os = "macos") {
+    let out = command_stdout("lsof", &["-a", "-p", &pid.to_string(), "-d", "cwd", "-Fn"])?;
+    return out.lines().find_map(|l| l.strip_prefix('n')).map(PathBuf::from);
+  }
+  None
+}
+
+// Only our bundle layout counts: a bare `node server.js` elsewhere could be anyone's.
+pub(crate) fn moondream_process_kind(pid: u32, command: &str) -> Option<&'static str> {
+  let command = command.replace('\\', "/");
+  if command.contains("resources/bin/moondream-worker") {
+    return Some("worker");
+  }
+  let runs_server_js = command.split_whitespace().any(|arg| arg == "server.js" || arg.ends_with("/server.js"));
+  if !runs_server_js {
+    return None;
+  }
+  // The server is started as `node server.js` from `resources/next`; with a system node only the cwd says so.
+  if command.contains("resources/bin/node")
+    || process_cwd(pid).map(|d| d.ends_with("resources/next")).unwrap_or(false)
+  {
+    return Some("server");
+  }
+  None
+}
+
+pub(crate) fn tracked_pids(state: &ServerState) -> Vec<u32> {
+  [&state.child, &state.worker, &state.station]
+    .iter()
+    .filter_map(|c| c.lock().unwrap().as_ref().map(|c| c.id()))
+    .chain(std::iter::once(std::process::id()))
+    .collect()
+}
+
+// Leftover server/worker processes from earlier (crashed) instances; this instance's own are excluded.
+#[tauri::command(async)]
+pub(crate) fn list_moondream_processes(state: tauri::State<ServerState>) -> Vec<ProcInfo> {
+  let ours = tracked_pids(&state);
+  list_processes()
+    .into_iter()
+    .filter(|(pid, _)| !ours.contains(pid))
+    .filter_map(|(pid, command)| {
+      let kind = moondream_process_kind(pid, &command)?;
+      Some(ProcInfo { pid, kind: kind.to_string(), command })
+    })
+    .collect()
+}
+
+#[tauri::command(async)]
+pub(crate) fn kill_process(window: tauri::Window, state: tauri::State<ServerState>, pid: u32) -> Result<bool, String> {
+  if tracked_pids(&state).contains(&pid) {
+    return Err("That process belongs to this instance; use Restart/Stop instead.".to_string());
+  }
+  // Re-check right before killing: the pid may have exited and been reused since it was listed.
+  let Some(proc_info) = list_processes()
+    .into_iter()
+    .find(|(p, _)| *p == pid)
+    .and_then(|(p, command)| moondream_process_kind(p, &command).map(|kind| (kind, command)))
+  else {
+    return Err(format!("Process {} is not a Reference server or worker (or already exited).", pid));
+  };
+  let confirmed = tauri::api::dialog::blocking::confirm(
+    Some(&window),
+    "End process?",
+    format!("End the stray Reference {} (pid {})?\n\n{}", proc_info.0, pid, proc_info.1),
+  );
+  if !confirmed {
+    return Ok(false);
+  }
+  host_log(LogLevel::Warn, &format!("killing stray {} process {}", proc_info.0, pid));
+  let run = |program: &str, args: &[&str]| {
+    quiet_status(Command::new(program).args(args))
+  };
+  let pid_arg = pid.to_string();
+  let status = if cfg!(target_os = "windows") {
+    run("taskkill", &["/PID", &pid_arg, "/F"])
+  } else {
+    // Give it the same chance to close the DB cleanly as our own children get on quit.
+    let _ = run("kill", &["-TERM", &pid_arg]);
+    let start = Instant::now();
+    while start.elapsed() < DEFAULT_SHUTDOWN_GRACE {
+      if !run("kill", &["-0", &pid_arg]).map(|s| s.success()).unwrap_or(false) {
+        return Ok(true);
+      }
+      std::thread::sleep(Duration::from_millis(100));
+    }
+    run("kill", &["-KILL", &pid_arg])
+  };
+  match status {
+    Ok(s) if s.success() => Ok(true),
+    Ok(s) => Err(format!("Couldn't end process {} ({})", pid, s)),
+    Err(e) => Err(e.to_string()),
+  }
+}
+
+// Reuse the last port the server was healthy on so the webview origin stays stable across restarts.
+pub(crate) fn pick_restart_port(config_root: &PathBuf, settings: &AppSettings) -> u16 {
+  let in_range = |p: u16| {
+    configured_port_range(settings)
+      .map(|(start, end)| (start..=end).contains(&p))
+      .unwrap_or(true)
+  };
+  match read_runtime(config_root).last_port {
+    Some(p) if in_range(p) && port_available(p) => p,
+    _ => pick_server_port(settings),
+  }
+}
+
+pub(crate) fn record_healthy_port(config_root: &PathBuf, port: u16) {
+  let mut rt = read_runtime(config_root);
+  if rt.last_port != Some(port) {
+    rt.last_port = Some(port);
+    write_runtime(config_root, &rt);
+  }
+}
+
+pub(crate) fn read_http_status<S: Read + Write>(stream: &mut S, host_header: &str, path: &str) -> Option<u16> {
+  let req = format!(
+    "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+    path, host_header
+  );
+  stream.write_all(req.as_bytes()).ok()?;
+  let mut buf = [0u8; 512];
+  let n = stream.read(&mut buf).ok()?;
+  let head = String::from_utf8_lossy(&buf[..n]);
+  // "HTTP/1.1 200 OK"
+  head.split_whitespace().nth(1)?.parse::<u16>().ok()
+}
+
+// Single request; returns the HTTP status code if the server answered.
+pub(crate) fn http_status(host: &str, port: u16, path: &str, timeout: Duration) -> Option<u16> {
+  let addr = format!("{}:{}", host, port);
+  let sock = std::net::ToSocketAddrs::to_socket_addrs(addr.as_str()).ok()?.next()?;
+  let mut stream = TcpStream::connect_timeout(&sock, timeout).ok()?;
+  let _ = stream.set_read_timeout(Some(timeout));
+  let _ = stream.set_write_timeout(Some(timeout));
+  read_http_status(&mut stream, &addr, path)
+}
+
+pub(crate) fn http_get_200(host: &str, port: u16, path: &str, timeout: Duration) -> bool {
+  let addr = format!("{}:{}", host, port);
+  let start = Instant::now();
+  while start.elapsed() < timeout {
+    if let Ok(mut stream) = TcpStream::connect(addr.as_str()) {
+      let _ = stream.set_read_timeout(Some(Duration::from_millis(250)));
+      let _ = stream.set_write_timeout(Some(Duration::from_millis(250)));
+      let req = format!(
+        "GET {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\n\r\n",
+        path, host, port
+      );
+      if stream.write_all(req.as_bytes()).is_ok() {
+        let mut buf = [0u8; 512];
+        if let Ok(n) = stream.read(&mut buf) {
+          let s = String::from_utf8_lossy(&buf[..n]);
+          if s.contains(" 200 ") {
+            return true;
+          }
+        }
+      }
+    }
+    std::thread::sleep(Duration::from_millis(150));
+  }
+  false
+}
+
+// Where the local Next server listens (`server.transport`).
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ServerAddr {
+  Tcp(u16),
+  Unix(PathBuf),
+}
+
+// Webview origin in unix mode; requests are proxied onto the socket by `unix_proxy`.
+pub(crate) const UNIX_PROXY_SCHEME: &str = "moondream-app";
+
+impl ServerAddr {
+  pub(crate) fn base_url(&self) -> String {
+    match self {
+      ServerAddr::Tcp(port) => format!("http://127.0.0.1:{}", port),
+      ServerAddr::Unix(_) => format!("{}://localhost", UNIX_PROXY_SCHEME),
+    }
+  }
+}
+
+pub(crate) fn server_transport(settings: &AppSettings) -> io::Result<String> {
+  let transport = settings
+    .server
+    .as_ref()
+    .and_then(|s| s.transport.as_deref())
+    .unwrap_or("tcp")
+    .trim()
+    .to_lowercase();
+  match transport.as_str() {
+    "tcp" => Ok(transport),
+    "unix" if cfg!(unix) => Ok(transport),
+    "unix" => Err(io::Error::new(
+      ErrorKind::Unsupported,
+      "server.transport = \"unix\" is not supported on this platform; use \"tcp\"",
+    )),
+    other => Err(io::Error::new(
+      ErrorKind::InvalidInput,
+      format!("Unknown server.transport \"{}\" (expected tcp or unix)", other),
+    )),
+  }
+}
+
+pub(crate) fn server_socket_path(config_root: &PathBuf) -> PathBuf {
+  // Keep this short: macOS caps Unix socket paths at 104 bytes.
+  config_root.join("next.sock")
+}
+
+pub(crate) fn current_server_addr(state: &ServerState) -> Option<ServerAddr> {
+  if let Some(sock) = state.socket.lock().unwrap().clone() {
+    return Some(ServerAddr::Unix(sock));
+  }
+  state.port.lock().unwrap().map(ServerAddr::Tcp)
+}
+
+pub(crate) fn set_server_addr(state: &ServerState, addr: &ServerAddr) {
+  match addr {
+    ServerAddr::Tcp(port) => {
+      *state.port.lock().unwrap() = Some(*port);
+      *state.socket.lock().unwrap() = None;
+    }
+    ServerAddr::Unix(sock) => {
+      *state.port.lock().unwrap() = None;
+      *state.socket.lock().unwrap() = Some(sock.clone());
+    }
+  }
+}
+
+#[cfg(unix)]
+pub(crate) fn unix_http_status(sock: &PathBuf, path: &str, timeout: Duration) -> Option<u16> {
+  let mut stream = std::os::unix::net::UnixStream::connect(sock).ok()?;
+  let _ = stream.set_read_timeout(Some(timeout));
+  let _ = stream.set_write_timeout(Some(timeout));
+  read_http_status(&mut stream, "localhost", path)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn unix_http_status(_sock: &PathBuf, _path: &str, _timeout: Duration) -> Option<u16> {
+  None
+}
+
+pub(crate) fn server_status(addr: &ServerAddr, path: &str, timeout: Duration) -> Option<u16> {
+  match addr {
+    ServerAddr::Tcp(port) => http_status("127.0.0.1", *port, path, timeout),
+    ServerAddr::Unix(sock) => unix_http_status(sock, path, timeout),
+  }
+}
+
+// Measured from the moment the request starts going out.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ExchangeTiming {
+  pub(crate) first_byte: Option<Duration>,
+  pub(crate) total: Option<Duration>,
+}
+
+// HTTP/1.0 so the response is never chunked; returns (status, body).
+pub(crate) fn http_exchange<S: Read + Write>(
+  stream: &mut S,
+  host_header: &str,
+  method: &str,
+  path: &str,
+  body: Option<&str>,
+  token: Option<&str>,
+) -> Option<(u16, String)> {
+  http_exchange_timed(stream, host_header, method, path, body, token, &mut ExchangeTiming::default())
+}
+
+pub(crate) fn http_exchange_timed<S: Read + Write>(
+  stream: &mut S,
+  host_header: &str,
+  method: &str,
+  path: &str,
+  body: Option<&str>,
+  token: Option<&str>,
+  timing: &mut ExchangeTiming,
+) -> Option<(u16, String)> {
+  let start = Instant::now();
+  let mut req = format!("{} {} HTTP/1.0\r\nHost: {}\r\n", method, path, host_header);
+  if let Some(token) = token {
+    req.push_str(&format!("{}: {}\r\n", SESSION_TOKEN_HEADER, token));
+  }
+  if let Some(body) = body {
+    req.push_str(&format!(
+      "Content-Type: application/json\r\nContent-Length: {}\r\n",
+      body.len()
+    ));
+  }
+  req.push_str("\r\n");
+  req.push_str(body.unwrap_or(""));
+  stream.write_all(req.as_bytes()).ok()?;
+  let mut first = [0u8; 1];
+  let n = stream.read(&mut first).ok()?;
+  timing.first_byte = Some(start.elapsed());
+  let mut raw = first[..n].to_vec();
+  stream.read_to_end(&mut raw).ok()?;
+  timing.total = Some(start.elapsed());
+  let text = String::from_utf8_lossy(&raw);
+  let (head, body) = text.split_once("\r\n\r\n")?;
+  let status = head.split_whitespace().nth(1)?.parse::<u16>().ok()?;
+  Some((status, body.to_string()))
+}
+
+pub(crate) fn server_request(
+  addr: &ServerAddr,
+  method: &str,
+  path: &str,
+  body: Option<&str>,
+  token: Option<&str>,
+  timeout: Duration,
+) -> Option<(u16, String)> {
+  match addr {
+    ServerAddr::Tcp(port) => {
+      let sock = std::net::SocketAddr::from(([127, 0, 0, 1], *port));
+      let mut stream = TcpStream::connect_timeout(&sock, timeout).ok()?;
+      let _ = stream.set_read_timeout(Some(timeout));
+      let _ = stream.set_write_timeout(Some(timeout));
+      http_exchange(&mut stream, &format!("127.0.0.1:{}", port), method, path, body, token)
+    }
+    #[cfg(unix)]
+    ServerAddr::Unix(sock) => {
+      let mut stream = std::os::unix::net::UnixStream::connect(sock).ok()?;
+      let _ = stream.set_read_timeout(Some(timeout));
+      let _ = stream.set_write_timeout(Some(timeout));
+      http_exchange(&mut stream, "localhost", method, path, body, token)
+    }
+    #[cfg(not(unix))]
+    ServerAddr::Unix(_) => None,
+  }
+}
+
+// A server build without the route: Next answers 404 (405 for a method the route lacks) with an
+// HTML page, while the routes themselves always answer JSON.
+pub(crate) fn route_missing(status: u16, body: &str) -> bool {
+  matches!(status, 404 | 405) && !body.trim_start().starts_with('{')
+}
+
+pub(crate) const DEFAULT_READY_PATH: &str = "/api/health";
+
+pub(crate) fn server_ready_path(settings: &AppSettings) -> String {
+  match settings
+    .server
+    .as_ref()
+    .and_then(|s| s.ready_path.as_deref())
+    .map(|p| p.trim())
+  {
+    None | Some("") => DEFAULT_READY_PATH.to_string(),
+    Some(p) if p.starts_with('/') => p.to_string(),
+    Some(p) => {
+      host_log(
+        LogLevel::Warn,
+        &format!("server.ready_path {:?} must start with '/'; using {}", p, DEFAULT_READY_PATH),
+      );
+      DEFAULT_READY_PATH.to_string()
+    }
+  }
+}
+
+pub(crate) fn server_get_200(addr: &ServerAddr, path: &str, timeout: Duration) -> bool {
+  match addr {
+    ServerAddr::Tcp(port) => http_get_200("127.0.0.1", *port, path, timeout),
+    ServerAddr::Unix(_) => {
+      let start = Instant::now();
+      while start.elapsed() < timeout {
+        if server_status(addr, path, Duration::from_millis(250)) == Some(200) {
+          return true;
+        }
+        std::thread::sleep(Duration::from_millis(150));
+      }
+      false
+    }
+  }
+}
+
+// Forwards a webview request to the Next server's Unix socket. HTTP/1.0 + `Connection: close`
+// keeps Node from chunking the response, so the body is simply everything after the headers.
+#[cfg(unix)]
+// Per socket read/write, not the whole exchange: a large asset may take longer than this overall.
+pub(crate) const UNIX_PROXY_IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Responses are buffered whole before they're handed to the webview.
+pub(crate) const UNIX_PROXY_MAX_RESPONSE: u64 = 1024 * 1024 * 1024;
+
+pub(crate) fn unix_proxy(
+  sock: &PathBuf,
+  request: &tauri::http::Request,
+) -> Result<tauri::http::Response, Box<dyn std::error::Error>> {
+  let uri = request.uri();
+  // "moondream-app://localhost/path?query" -> "/path?query"
+  let rest = uri.find("://").map(|i| &uri[(i + 3)..]).unwrap_or(uri);
+  let path = rest.find('/').map(|i| &rest[i..]).unwrap_or("/");
+
+  let mut head = format!(
+    "{} {} HTTP/1.0\r\nHost: localhost\r\nConnection: close\r\n",
+    request.method().as_str(),
+    path
+  );
+  for (name, value) in request.headers().iter() {
+    let name = name.as_str();
+    if ["host", "connection", "content-length"].iter().any(|h| name.eq_ignore_ascii_case(h)) {
+      continue;
+    }
+    if let Ok(v) = value.to_str() {
+      head.push_str(&format!("{}: {}\r\n", name, v));
+    }
+  }
+  let body = request.body();
+  if !body.is_empty() {
+    head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+  }
+  head.push_str("\r\n");
+
+  let mut stream = std::os::unix::net::UnixStream::connect(sock)?;
+  stream.set_read_timeout(Some(UNIX_PROXY_IO_TIMEOUT))?;
+  stream.set_write_timeout(Some(UNIX_PROXY_IO_TIMEOUT))?;
+  stream.write_all(head.as_bytes())?;
+  stream.write_all(body)?;
+  let mut raw = Vec::new();
+  (&mut stream).take(UNIX_PROXY_MAX_RESPONSE + 1).read_to_end(&mut raw)?;
+  if raw.len() as u64 > UNIX_PROXY_MAX_RESPONSE {
+    return Err(format!("Response from local server is over {} MB", UNIX_PROXY_MAX_RESPONSE / (1024 * 1024)).into());
+  }
+
+  let split = raw
+    .windows(4)
+    .position(|w| w == b"\r\n\r\n")
+    .ok_or("Malformed response from local server")?;
+  let resp_head = String::from_utf8_lossy(&raw[..split]).to_string();
+  let mut lines = resp_head.lines();
+  let status = lines
+    .next()
+    .and_then(|l| l.split_whitespace().nth(1))
+    .and_then(|c| c.parse::<u16>().ok())
+    .ok_or("Malformed status line from local server")?;
+
+  let mut builder = tauri::http::ResponseBuilder::new().status(status);
+  for line in lines {
+    if let Some((k, v)) = line.split_once(':') {
+      let k = k.trim();
+      if k.eq_ignore_ascii_case("transfer-encoding") || k.eq_ignore_ascii_case("connection") {
+        continue;
+      }
+      builder = builder.header(k, v.trim());
+    }
+  }
+  builder.body(raw[(split + 4)..].to_vec())
+}
+
+pub(crate) fn resource_path(app: &tauri::AppHandle, rel: &str) -> Option<PathBuf> {
+  app
+    .path_resolver()
+    .resource_dir()
+    // We bundle assets under `Contents/Resources/resources/...` (mirrors `src-tauri/resources/...`).
+    .map(|d| d.join("resources").join(rel))
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct NodeInfo {
+  pub(crate) source: String, // "bundled" | "system"
+  pub(crate) path: String,
+  pub(crate) version: Option<String>,
+}
+
+pub(crate) fn node_source(settings: &AppSettings) -> String {
+  settings
+    .server
+    .as_ref()
+    .and_then(|s| s.node_source.as_deref())
+    .unwrap_or("auto")
+    .trim()
+    .to_lowercase()
+}
+
+pub(crate) fn find_system_node() -> Option<PathBuf> {
+  let name = if cfg!(target_os = "windows") { "node.exe" } else { "node" };
+  let mut dirs: Vec<PathBuf> = std::env::var_os("PATH")
+    .map(|p| std::env::split_paths(&p).collect())
+    .unwrap_or_default();
+  // Apps launched from Finder don't inherit the shell PATH, so also check the usual install locations.
+  for d in ["/opt/homebrew/bin", "/usr/local/bin", "/usr/bin"] {
+    dirs.push(PathBuf::from(d));
+  }
+  dirs.into_iter().map(|d| d.join(name)).find(|p| p.is_file())
+}
+
+pub(crate) fn node_version(node: &PathBuf) -> Option<String> {
+  let out = Command::new(node)
+    .arg("--version")
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .ok()?;
+  let v = String::from_utf8_lossy(&out.stdout).trim().to_string();
+  if out.status.success() && !v.is_empty() {
+    Some(v)
+  } else {
+    None
+  }
+}
+
+pub(crate) fn resolve_node(app: &tauri::AppHandle, settings: &AppSettings) -> io::Result<NodeInfo> {
+  let bundled = resource_path(app, "bin/node")
+    .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Missing resource_dir (bin/node)"))?;
+  select_node(&node_source(settings), &bundled, find_system_node)
+}
+
+// `system_node` is only consulted when the bundled binary isn't used.
+pub(crate) fn select_node(
+  source: &str,
+  bundled: &PathBuf,
+  system_node: impl FnOnce() -> Option<PathBuf>,
+) -> io::Result<NodeInfo> {
+  let use_bundled = match source {
+    "bundled" => {
+      if !bundled.exists() {
+        return Err(io::Error::new(
+          ErrorKind::NotFound,
+          format!("Missing bundled Node at {}", bundled.display()),
+        ));
+      }
+      true
+    }
+    "system" => false,
+    // "auto": prefer bundled so the desktop app stays standalone, fall back to system Node.
+    _ => bundled.exists(),
+  };
+
+  if use_bundled {
+    return Ok(NodeInfo {
+      source: "bundled".to_string(),
+      path: bundled.to_string_lossy().to_string(),
+      version: None,
+    });
+  }
+
+  let system = system_node().ok_or_else(|| {
+    io::Error::new(
+      ErrorKind::NotFound,
+      format!(
+        "No Node found (node_source = {}). Bundled Node missing at {} and `node` is not on PATH.",
+        source,
+        bundled.display()
+      ),
+    )
+  })?;
+  Ok(NodeInfo {
+    source: "system".to_string(),
+    path: system.to_string_lossy().to_string(),
+    version: None,
+  })
+}
+
+pub(crate) fn is_executable(p: &PathBuf) -> bool {
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(p)
+      .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+      .unwrap_or(false)
+  }
+  #[cfg(not(unix))]
+  {
+    p.is_file()
+  }
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct ResourceCheck {
+  pub(crate) name: String,
+  pub(crate) path: String,
+  pub(crate) exists: bool,
+  pub(crate) executable: Option<bool>, // None when the item doesn't need to be executable
+  pub(crate) required: bool,
+  pub(crate) ok: bool,
+  pub(crate) detail: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct ResourceReport {
+  pub(crate) ok: bool, // all required items are usable
+  pub(crate) items: Vec<ResourceCheck>,
+}
+
+pub(crate) fn check_resource(name: &str, path: Option<PathBuf>, needs_exec: bool, required: bool) -> ResourceCheck {
+  let Some(path) = path else {
+    return ResourceCheck {
+      name: name.to_string(),
+      path: "".to_string(),
+      exists: false,
+      executable: None,
+      required,
+      ok: false,
+      detail: Some("Missing resource_dir".to_string()),
+    };
+  };
+  let exists = path.exists();
+  let executable = if needs_exec { Some(is_executable(&path)) } else { None };
+  let ok = exists && executable != Some(false);
+  let detail = if !exists {
+    Some(format!("Not found at {}", path.display()))
+  } else if executable == Some(false) {
+    Some(format!("Not executable (try: chmod +x \"{}\")", path.display()))
+  } else {
+    None
+  };
+  ResourceCheck {
+    name: name.to_string(),
+    path: path.to_string_lossy().to_string(),
+    exists,
+    executable,
+    required,
+    ok,
+    detail,
+  }
+}
+
+pub(crate) fn resource_report(app: &tauri::AppHandle, settings: &AppSettings) -> ResourceReport {
+  // Node follows `server.node_source`, so a system Node counts as a complete install.
+  let node = match resolve_node(app, settings) {
+    Ok(info) => check_resource("node", Some(PathBuf::from(info.path)), true, true),
+    Err(e) => ResourceCheck {
+      name: "node".to_string(),
+      path: resource_path(app, "bin/node")
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_default(),
+      exists: false,
+      executable: None,
+      required: true,
+      ok: false,
+      detail: Some(e.to_string()),
+    },
+  };
+  let items = vec![
+    node,
+    check_resource("next/server.js", resource_path(app, "next/server.js"), false, true),
+    // The worker is best-effort (AI enrichment only); report it but don't block startup.
+    check_resource("bin/moondream-worker", resource_path(app, "bin/moondream-worker"), true, false),
+  ];
+  ResourceReport {
+    ok: items.iter().all(|i| i.ok || !i.required),
+    items,
+  }
+}
+
+#[tauri::command]
+pub(crate) fn check_resources(app: tauri::AppHandle) -> Result<ResourceReport, String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  Ok(resource_report(&app, &read_settings(&config_root)))
+}
+
+// Session token contract: the server requires `X-Moondream-Token` on /api requests (except
+// /api/health) and reads the expected value from MOONDREAM_SESSION_TOKEN_FILE. It caches the
+// value and re-reads the file on POST /api/session/reload, authenticated with the token it
+// currently holds; once that returns 2xx, the previous token is rejected. Servers without the
+// route answer 404/405 and keep whatever token they started with.
+pub(crate) const SESSION_TOKEN_FILE: &str = "session-token";
+
+pub(crate) const SESSION_TOKEN_HEADER: &str = "X-Moondream-Token";
+
+pub(crate) fn generate_token() -> String {
+  let mut bytes = [0u8; 32];
+  let from_os = std::fs::File::open("/dev/urandom")
+    .and_then(|mut f| f.read_exact(&mut bytes))
+    .is_ok();
+  if !from_os {
+    // No /dev/urandom (Windows): RandomState is seeded from the OS RNG, so its hashes are unpredictable.
+    use std::hash::{BuildHasher, Hasher};
+    for chunk in bytes.chunks_mut(8) {
+      let mut h = std::collections::hash_map::RandomState::new().build_hasher();
+      h.write_u128(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos());
+      chunk.copy_from_slice(&h.finish().to_le_bytes());
+    }
+  }
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Written via a temp file + rename so the server never reads a half-written token.
+pub(crate) fn write_session_token(config_root: &PathBuf, token: &str) -> io::Result<()> {
+  let path = config_root.join(SESSION_TOKEN_FILE);
+  let tmp = config_root.join(format!("{}.tmp", SESSION_TOKEN_FILE));
+  std::fs::write(&tmp, token)?;
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600))?;
+  }
+  std::fs::rename(&tmp, &path)
+}
+
+pub(crate) fn inject_session_token(window: &tauri::Window, token: &str, rotated: bool) {
+  let _ = window.eval(&format!(
+    "window.__MOONDREAM_SESSION_TOKEN__ = {};",
+    serde_json::to_string(token).unwrap_or_default()
+  ));
+  if rotated {
+    dispatch_web_event(window, "moondream:session-token:rotated", Some(serde_json::json!({ "token": token })));
+  }
+}
+
+// Page globals the host sets at launch (see `start_services`); re-applied after a window cycle.
+pub(crate) fn reinject_globals(window: &tauri::Window, state: &ServerState) {
+  if let Some(token) = state.session_token.lock().unwrap().clone() {
+    inject_session_token(window, &token, false);
+  }
+  if let Some(ServerAddr::Tcp(port)) = current_server_addr(state) {
+    let _ = window.eval(&format!("window.__MOONDREAM_PORT__ = {};", port));
+  }
+  if *state.safe_mode.lock().unwrap() {
+    let _ = window.eval("window.__MOONDREAM_SAFE_MODE__ = true;");
+  }
+  let config_root = state.config_root.lock().unwrap().clone();
+  if let Some(config_root) = config_root {
+    inject_theme(window, &effective_settings(state, &config_root));
+  }
+}
+
+// Globals don't survive navigation; pages can always fetch the current token here.
+#[tauri::command]
+pub(crate) fn session_token(state: tauri::State<ServerState>) -> Option<String> {
+  state.session_token.lock().unwrap().clone()
+}
+
+#[tauri::command(async)]
+pub(crate) fn rotate_session_token(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<String, String> {
+  let config_root = require_config_root(&state)?;
+  let addr = current_server_addr(&state).ok_or_else(|| "The local server is not running".to_string())?;
+
+  // The server re-reads the token file on reload, so the new token goes to disk first; the reload
+  // itself is authenticated with the token the server still holds.
+  let previous = state.session_token.lock().unwrap().clone();
+  let token_path = config_root.join(SESSION_TOKEN_FILE);
+  let previous_file = std::fs::read(&token_path).ok();
+  let token = generate_token();
+  write_session_token(&config_root, &token).map_err(|e| e.to_string())?;
+
+  let reload = |auth: Option<&str>| server_request(&addr, "POST", "/api/session/reload", None, auth, Duration::from_secs(5));
+  let answer = reload(previous.as_deref());
+  let failure = match answer.as_ref() {
+    Some((status, _)) if (200..300).contains(status) => None,
+    Some((404 | 405, _)) => Some(
+      "This server build can't reload its session token (no /api/session/reload); the token was not rotated."
+        .to_string(),
+    ),
+    Some((status, body)) => Some(format!("Server rejected the token reload ({}): {}", status, body.trim())),
+    None => Some("The local server did not respond".to_string()),
+  };
+  if let Some(e) = failure {
+    // Keep host and server on the old token.
+    let restored = match previous_file {
+      Some(bytes) => std::fs::write(&token_path, bytes),
+      None => std::fs::remove_file(&token_path),
+    };
+    if let Err(re) = restored {
+      host_log(LogLevel::Error, &format!("Failed to restore the session token file: {}", re));
+    }
+    // A timed-out reload may still have switched the server; have it re-read the restored file.
+    if answer.is_none() {
+      let _ = reload(Some(&token));
+    }
+    return Err(e);
+  }
+  *state.session_token.lock().unwrap() = Some(token.clone());
+
+  if let Some(window) = app.get_window("main") {
+    inject_session_token(&window, &token, true);
+  }
+  Ok(token)
+}
+
+pub(crate) fn verify_binaries_enabled(settings: &AppSettings) -> bool {
+  settings
+    .security
+    .as_ref()
+    .and_then(|s| s.verify_binaries)
+    .unwrap_or(false)
+}
+
+// Ok(false) = no verifier on this platform (skipped); Err = present but not validly signed.
+pub(crate) fn check_signature(path: &PathBuf) -> Result<bool, String> {
+  let run = |cmd: &mut Command| {
+    cmd
+      .stdin(Stdio::null())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .output()
+      .map_err(|e| format!("couldn't run the verifier: {}", e))
+  };
+  if cfg!(target_os = "macos") {
+    let out = run(Command::new("codesign").args(["--verify", "--strict", "--deep"]).arg(path))?;
+    if out.status.success() {
+      return Ok(true);
+    }
+    return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+  }
+  if cfg!(target_os = "windows") {
+    let script = format!(
+      "(Get-AuthenticodeSignature -LiteralPath '{}').Status",
+      path.to_string_lossy().replace('\'', "''")
+    );
+    let out = run(Command::new("powershell").args(["-NoProfile", "-NonInteractive", "-Command", &script]))?;
+    let status = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    if status == "Valid" {
+      return Ok(true);
+    }
+    return Err(format!("Authenticode status: {}", if status.is_empty() { "unknown" } else { &status }));
+  }
+  Ok(false)
+}
+
+// Guarded by `security.verify_binaries`; refuses to run a bundled binary whose signature fails.
+pub(crate) fn verify_bundled_binary(path: &PathBuf, settings: &AppSettings) -> io::Result<()> {
+  if !verify_binaries_enabled(settings) {
+    return Ok(());
+  }
+  match check_signature(path) {
+    Ok(true) => {
+      host_log(LogLevel::Info, &format!("Signature OK: {}", path.display()));
+      Ok(())
+    }
+    Ok(false) => {
+      host_log(
+        LogLevel::Info,
+        &format!("Signature check not supported on this platform; skipped {}", path.display()),
+      );
+      Ok(())
+    }
+    Err(e) => {
+      let msg = format!("Refusing to run {}: code signature verification failed ({})", path.display(), e);
+      host_log(LogLevel::Error, &msg);
+      Err(io::Error::new(ErrorKind::PermissionDenied, msg))
+    }
+  }
+}
+
+pub(crate) fn spawn_next_server(
+  app: &tauri::AppHandle,
+  addr: &ServerAddr,
+  config_root: &PathBuf,
+  data_dir: &PathBuf,
+  db_path: &PathBuf,
+  settings: &AppSettings,
+) -> io::Result<Child> {
+  let (child, node_info) = spawn_next_server_process(app, addr, config_root, data_dir, db_path, settings)?;
+  let state = app.state::<ServerState>();
+  *state.node.lock().unwrap() = Some(node_info);
+  *state.db_path.lock().unwrap() = Some(db_path.clone());
+  *state.server_settings.lock().unwrap() = Some(settings.clone());
+  Ok(child)
+}
+
+// Starts a server without recording it as the app's own (see `self_test`).
+pub(crate) fn spawn_next_server_process(
+  app: &tauri::AppHandle,
+  addr: &ServerAddr,
+  config_root: &PathBuf,
+  data_dir: &PathBuf,
+  db_path: &PathBuf,
+  settings: &AppSettings,
+) -> io::Result<(Child, NodeInfo)> {
+  let next_dir = resource_path(app, "next")
+    .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Missing resource_dir"))?;
+  let server_js = next_dir.join("server.js");
+  if !server_js.exists() {
+    return Err(io::Error::new(
+      ErrorKind::NotFound,
+      format!("Missing Next server bundle at {}", server_js.display()),
+    ));
+  }
+
+  // Bundled Node by default so the desktop app is truly standalone (see `server.node_source`).
+  let node_info = resolve_node(app, settings)?;
+  let node = PathBuf::from(&node_info.path);
+  // A system Node is the user's own install (often unsigned, e.g. Homebrew); only ours is checked.
+  if node_info.source == "bundled" {
+    verify_bundled_binary(&node, settings)?;
+  }
+
+  std::fs::create_dir_all(data_dir)?;
+
+  // Log server output so "server not ready" errors are debuggable in standalone builds.
+  let log_dir = logs_dir(config_root);
+  std::fs::create_dir_all(&log_dir)?;
+  let server_log_path = log_dir.join("next-server.log");
+  rotate_log(&server_log_path, compress_rotated_logs(settings));
+  let log_file = OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(&server_log_path)?;
+  let log_file_err = log_file.try_clone()?;
+
+  let mut cmd = Command::new(node);
+  cmd
+    .current_dir(&next_dir)
+    .arg("server.js")
+    .env("HOSTNAME", "127.0.0.1")
+    .env("NODE_ENV", "production")
+    .env("NEXT_TELEMETRY_DISABLED", "1")
+    .env("MOONDREAM_DATA_DIR", data_dir)
+    .env("MOONDREAM_APP_CONFIG_DIR", config_root)
+    .env("MOONDREAM_SETTINGS_PATH", config_root.join("settings.json"))
+    // Pass AI config through so the UI (and server routes, if needed) can read it.
+    .env("MOONDREAM_PROVIDER", ai_config(settings).provider)
+    .env("MOONDREAM_ENDPOINT", ai_config(settings).endpoint)
+    // Optional: HF endpoint/token (used by the bundled worker; safe to expose to local server too).
+    .env(
+      "HF_ENDPOINT_URL",
+      settings
+        .ai
+        .as_ref()
+        .and_then(|a| a.endpoint.as_ref())
+        .map(|s| s.as_str())
+        .unwrap_or(""),
+    )
+    .env(
+      "HF_TOKEN",
+      settings
+        .ai
+        .as_ref()
+        .and_then(|a| a.hf_token.as_ref())
+        .map(|s| s.as_str())
+        .unwrap_or(""),
+    )
+    // Ensure the Node server and the Python worker (if used) can share the same DB file.
+    .env("MOONDREAM_DB_PATH", db_path)
+    .env("MOONDREAM_SESSION_TOKEN_FILE", config_root.join(SESSION_TOKEN_FILE))
+    // Contract: the server drops log lines below this level ("error" | "warn" | "info" | "debug").
+    .env("MOONDREAM_SERVER_LOG", server_log_level(settings).as_str())
+    .stdin(Stdio::null())
+    .stdout(Stdio::from(log_file))
+    .stderr(Stdio::from(log_file_err));
+  match addr {
+    ServerAddr::Tcp(port) => {
+      cmd.env("PORT", port.to_string());
+    }
+    ServerAddr::Unix(sock) => {
+      // server.js listens on MOONDREAM_SOCKET_PATH instead of HOSTNAME/PORT when it's set.
+      // A stale socket from a crashed run would make that listen() fail with EADDRINUSE.
+      let _ = std::fs::remove_file(sock);
+      cmd.env("MOONDREAM_SOCKET_PATH", sock);
+    }
+  }
+
+  let child = cmd.spawn()?;
+  Ok((child, node_info))
+}
+
+// One line of server output, as sent to `start_server_log_stream` subscribers.
+#[derive(Clone, Serialize)]
+pub(crate) struct ServerLogLine {
+  pub(crate) seq: u64,
+  pub(crate) line: String,
+}
+
+#[derive(Default)]
+pub(crate) struct ServerLogStream {
+  pub(crate) next_seq: u64,
+  // Newest last; capped by SERVER_LOG_BUFFER_LINES / SERVER_LOG_BUFFER_BYTES, oldest dropped first.
+  pub(crate) lines: std::collections::VecDeque<ServerLogLine>,
+  pub(crate) bytes: usize,
+  // Bumped by start/stop; a tail thread exits once it no longer matches.
+  pub(crate) generation: u64,
+}
+
+pub(crate) const SERVER_LOG_EVENT: &str = "moondream:server:log";
+
+pub(crate) const SERVER_LOG_BUFFER_LINES: usize = 5000;
+
+pub(crate) const SERVER_LOG_BUFFER_BYTES: usize = 2 * 1024 * 1024;
+
+// Longer lines (minified stack traces, JSON dumps) are cut in the buffer; the log file keeps them whole.
+pub(crate) const SERVER_LOG_LINE_MAX: usize = 8 * 1024;
+
+pub(crate) const SERVER_LOG_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub(crate) fn push_server_log_line(stream: &mut ServerLogStream, raw: &[u8]) -> ServerLogLine {
+  let mut line = String::from_utf8_lossy(raw).trim_end().to_string();
+  if line.len() > SERVER_LOG_LINE_MAX {
+    let mut cut = SERVER_LOG_LINE_MAX;
+    while !line.is_char_boundary(cut) {
+      cut -= 1;
+    }
+    line.truncate(cut);
+    line.push('…');
+  }
+  stream.next_seq += 1;
+  let entry = ServerLogLine { seq: stream.next_seq, line };
+  stream.bytes += entry.line.len();
+  stream.lines.push_back(entry.clone());
+  while stream.lines.len() > SERVER_LOG_BUFFER_LINES || stream.bytes > SERVER_LOG_BUFFER_BYTES {
+    match stream.lines.pop_front() {
+      Some(old) => stream.bytes -= old.line.len(),
+      None => break,
+    }
+  }
+  entry
+}
+
+// Rotation at a server restart replaces next-server.log with a new file.
+pub(crate) fn same_log_file(open: &std::fs::File, path: &PathBuf, offset: u64) -> bool {
+  let Ok(current) = std::fs::metadata(path) else {
+    return false;
+  };
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::MetadataExt;
+    match open.metadata() {
+      Ok(m) if (m.dev(), m.ino()) == (current.dev(), current.ino()) => {}
+      _ => return false,
+    }
+  }
+  // Elsewhere only a shrink gives the swap away.
+  #[cfg(not(unix))]
+  let _ = open;
+  current.len() >= offset
+}
+
+// Follows next-server.log while `generation` is current. The server keeps writing straight to
+// the file, so a slow (or stopped) reader here can never hold it up.
+pub(crate) fn tail_server_log(app: tauri::AppHandle, path: PathBuf, mut offset: u64, generation: u64) {
+  std::thread::spawn(move || {
+    use std::io::{Seek, SeekFrom};
+    let mut file: Option<std::fs::File> = None;
+    let mut partial: Vec<u8> = Vec::new();
+    loop {
+      std::thread::sleep(SERVER_LOG_POLL_INTERVAL);
+      if app.state::<ServerState>().server_log.lock().unwrap().generation != generation {
+        return;
+      }
+      if !file.as_ref().map(|f| same_log_file(f, &path, offset)).unwrap_or(false) {
+        if file.is_some() {
+          // A new file: read it from the start.
+          offset = 0;
+          partial.clear();
+        }
+        file = std::fs::File::open(&path).ok();
+      }
+      let Some(f) = file.as_mut() else {
+        continue;
+      };
+      let mut chunk = Vec::new();
+      if f.seek(SeekFrom::Start(offset)).is_err() || f.read_to_end(&mut chunk).is_err() || chunk.is_empty() {
+        continue;
+      }
+      offset += chunk.len() as u64;
+      partial.extend_from_slice(&chunk);
+      let Some(end) = partial.iter().rposition(|b| *b == b'\n') else {
+        continue;
+      };
+      let complete: Vec<u8> = partial.drain(..=end).collect();
+      let state = app.state::<ServerState>();
+      let lines: Vec<ServerLogLine> = {
+        let mut stream = state.server_log.lock().unwrap();
+        if stream.generation != generation {
+          return;
+        }
+        complete.split(|b| *b == b'\n').filter(|l| !l.is_empty()).map(|l| push_server_log_line(&mut stream, l)).collect()
+      };
+      for line in lines {
+        let _ = app.emit_all(SERVER_LOG_EVENT, line);
+      }
+    }
+  });
+}
+
+// Subscribes to `moondream:server:log` events; returns the recent lines (the end of
+// next-server.log, within the buffer caps) so the panel starts with history. Subscribing again
+// replaces the previous subscription.
+#[tauri::command(async)]
+pub(crate) fn start_server_log_stream(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<Vec<ServerLogLine>, String> {
+  let config_root = managed_config_root(&app, &state)?;
+  let path = logs_dir(&config_root).join("next-server.log");
+  let mut data = Vec::new();
+  let mut start = 0u64;
+  if let Ok(mut f) = std::fs::File::open(&path) {
+    use std::io::{Seek, SeekFrom};
+    let len = f.metadata().map(|m| m.len()).unwrap_or(0);
+    start = len.saturating_sub(SERVER_LOG_BUFFER_BYTES as u64);
+    if f.seek(SeekFrom::Start(start)).is_err() || f.read_to_end(&mut data).is_err() {
+      (start, data) = (0, Vec::new());
+    }
+  }
+  // Starting mid-file: skip the cut-off first line.
+  let skip = match (start > 0, data.iter().position(|b| *b == b'\n')) {
+    (true, Some(i)) => i + 1,
+    _ => 0,
+  };
+  let recent = &data[skip..];
+  // Only complete lines; the tail picks up the rest.
+  let complete = recent.iter().rposition(|b| *b == b'\n').map(|i| i + 1).unwrap_or(0);
+  let offset = start + (skip + complete) as u64;
+
+  let mut stream = state.server_log.lock().unwrap();
+  stream.generation += 1;
+  stream.lines.clear();
+  stream.bytes = 0;
+  for line in recent[..complete].split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+    push_server_log_line(&mut stream, line);
+  }
+  tail_server_log(app.clone(), path, offset, stream.generation);
+  Ok(stream.lines.iter().cloned().collect())
+}
+
+#[tauri::command]
+pub(crate) fn stop_server_log_stream(state: tauri::State<ServerState>) {
+  let mut stream = state.server_log.lock().unwrap();
+  stream.generation += 1;
+  stream.lines.clear();
+  stream.bytes = 0;
+}
+
+// Total time (including the initial probe in setup) before startup is declared failed.
+pub(crate) const STARTUP_READY_TIMEOUT: Duration = Duration::from_secs(45);
+
+// Injected over the loading page. Buttons call commands through the IPC bridge every page has.
+pub(crate) const STARTUP_FAILURE_JS: &str = r#"
+  (function () {
+    var error = __MOONDREAM_ERROR__;
+    var logPath = __MOONDREAM_LOG__;
+    document.title = "Reference";
+    document.body.innerHTML =
+      '<div style="position:fixed;inset:0;display:flex;align-items:center;justify-content:center;background:#111;color:#eee;font-family:-apple-system,BlinkMacSystemFont,system-ui,sans-serif">' +
+      '<div style="max-width:560px;padding:24px">' +
+      '<h2 style="margin:0 0 12px;font-weight:600">The local server didn&#39;t start</h2>' +
+      '<pre id="md-error" style="white-space:pre-wrap;background:#1c1c1c;padding:12px;border-radius:8px;font-size:12px"></pre>' +
+      '<p style="opacity:.7;font-size:12px">Log: <code id="md-log"></code></p>' +
+      '<div id="md-actions" style="display:flex;gap:8px"></div>' +
+      '<p id="md-status" style="opacity:.7;font-size:12px;min-height:1em"></p>' +
+      '</div></div>';
+    document.getElementById("md-error").textContent = error;
+    document.getElementById("md-log").textContent = logPath;
+    var status = document.getElementById("md-status");
+    var actions = document.getElementById("md-actions");
+    [
+      ["Restart Server", "restart_server", "Restarting…", "Restarted."],
+      ["Reveal Logs", "reveal_logs", "", ""],
+      ["Copy Diagnostics", "diagnostics_text", "", "Diagnostics copied to the clipboard."]
+    ].forEach(function (a) {
+      var b = document.createElement("button");
+      b.textContent = a[0];
+      b.onclick = function () {
+        status.textContent = a[2];
+        window.__TAURI_INVOKE__(a[1]).then(
+          function () { status.textContent = a[3]; },
+          function (e) { status.textContent = String(e); }
+        );
+      };
+      actions.appendChild(b);
+    });
+  })();
+"#;
+
+pub(crate) fn show_startup_failure(window: &tauri::Window, error: &str, log_path: &PathBuf) {
+  let js = STARTUP_FAILURE_JS
+    .replace("__MOONDREAM_ERROR__", &serde_json::to_string(error).unwrap_or_default())
+    .replace(
+      "__MOONDREAM_LOG__",
+      &serde_json::to_string(&log_path.to_string_lossy().to_string()).unwrap_or_default(),
+    );
+  let _ = window.eval(&js);
+}
+
+// Explains a failed server start when something other than our own server holds its port.
+pub(crate) fn port_conflict_note(state: &ServerState, port: u16) -> Option<String> {
+  let ours = state.child.lock().unwrap().as_ref().map(|c| c.id());
+  let d = port_diagnosis(port);
+  if !d.in_use || (d.holder_pid.is_some() && d.holder_pid == ours) {
+    return None;
+  }
+  Some(format!("{} Quit it or set server.port_range to avoid it.", d.describe()))
+}
+
+pub(crate) fn await_startup_readiness(app: tauri::AppHandle, addr: ServerAddr, ready_path: String, config_root: PathBuf) {
+  std::thread::spawn(move || {
+    let start = Instant::now();
+    let error = loop {
+      if server_status(&addr, &ready_path, Duration::from_secs(1)) == Some(200) {
+        if let ServerAddr::Tcp(port) = addr {
+          record_healthy_port(&config_root, port);
+        }
+        return;
+      }
+      // No point waiting out the timeout once the process is gone.
+      let exited = match app.state::<ServerState>().child.lock().unwrap().as_mut() {
+        Some(child) => child.try_wait().ok().flatten().map(|s| s.to_string()),
+        None => Some("not running".to_string()),
+      };
+      if let Some(status) = exited {
+        let note = match addr {
+          ServerAddr::Tcp(port) => port_conflict_note(&app.state::<ServerState>(), port),
+          ServerAddr::Unix(_) => None,
+        };
+        break match note {
+          Some(note) => format!("The local server exited ({}). {}", status, note),
+          None => format!("The local server exited ({}).", status),
+        };
+      }
+      if start.elapsed() >= STARTUP_READY_TIMEOUT {
+        break format!(
+          "The local server at {} did not answer {} within {}s.",
+          addr.base_url(),
+          ready_path,
+          STARTUP_READY_TIMEOUT.as_secs()
+        );
+      }
+      std::thread::sleep(Duration::from_millis(500));
+    };
+    host_log(LogLevel::Error, &error);
+    if let Some(window) = app.get_window("main") {
+      show_startup_failure(&window, &error, &logs_dir(&config_root).join("next-server.log"));
+    }
+  });
+}
+
+pub(crate) const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+// Quitting must never hang on a stuck child.
+pub(crate) const MAX_SHUTDOWN_GRACE_SECS: u64 = 30;
+
+pub(crate) fn shutdown_grace(settings: &AppSettings) -> Duration {
+  settings
+    .behavior
+    .as_ref()
+    .and_then(|b| b.shutdown_grace_seconds)
+    .map(|s| Duration::from_secs(s.min(MAX_SHUTDOWN_GRACE_SECS)))
+    .unwrap_or(DEFAULT_SHUTDOWN_GRACE)
+}
+
+// SIGTERM first so Node/Python can flush and close the DB; SIGKILL if it outlives `grace`.
+// Returns whether it exited on its own.
+pub(crate) fn stop_child_gracefully(name: &str, mut child: Child, grace: Duration) -> bool {
+  if cfg!(unix) {
+    let _ = quiet_status(Command::new("kill").arg("-TERM").arg(child.id().to_string()));
+    let start = Instant::now();
+    while start.elapsed() < grace {
+      if let Ok(Some(_)) = child.try_wait() {
+        host_log(LogLevel::Info, &format!("{} exited after {:?}", name, start.elapsed()));
+        return true;
+      }
+      std::thread::sleep(Duration::from_millis(100));
+    }
+    host_log(LogLevel::Warn, &format!("{} didn't exit within {:?}; killing it", name, grace));
+  }
+  let _ = child.kill();
+  let _ = child.wait();
+  false
+}
+
+// Worker first so nothing writes to the DB while the server shuts down.
+pub(crate) fn stop_services_gracefully(state: &ServerState, grace: Duration) {
+  let worker = state.worker.lock().unwrap().take();
+  let child = state.child.lock().unwrap().take();
+  if let Some(w) = worker {
+    stop_child_gracefully("worker", w, grace);
+  }
+  if let Some(c) = child {
+    stop_child_gracefully("server", c, grace);
+  }
+}
+
+pub(crate) fn close_after_shutdown(window: &tauri::Window) {
+  let state = window.state::<ServerState>();
+  let settings = state.config_root.lock().unwrap().clone().map(|c| read_settings(&c)).unwrap_or_default();
+  stop_services_gracefully(&state, shutdown_grace(&settings));
+  if let Some(mut station) = state.station.lock().unwrap().take() {
+    let _ = station.kill();
+  }
+  let _ = window.close();
+}
+
+// Shared by the window's close button and the Quit menu item; with `behavior.confirm_quit`
+// nothing is stopped unless the user confirms, so cancelling leaves the server/worker running.
+pub(crate) fn request_quit(window: &tauri::Window) {
+  let state = window.state::<ServerState>();
+  let settings = state.config_root.lock().unwrap().clone().map(|c| read_settings(&c)).unwrap_or_default();
+  let confirm = settings.behavior.as_ref().and_then(|b| b.confirm_quit).unwrap_or(false);
+  if !confirm {
+    close_after_shutdown(window);
+    return;
+  }
+  let target = window.clone();
+  tauri::api::dialog::MessageDialogBuilder::new("Quit Reference?", "The local server and the worker will be stopped.")
+    .kind(tauri::api::dialog::MessageDialogKind::Warning)
+    .buttons(tauri::api::dialog::MessageDialogButtons::OkCancelWithLabels(
+      "Quit".to_string(),
+      "Cancel".to_string(),
+    ))
+    .parent(window)
+    .show(move |quit| {
+      if quit {
+        close_after_shutdown(&target);
+      }
+    });
+}
+
+// Rendered in place (the server that served the current page is gone, so we can't navigate).
+pub(crate) const SERVICES_STOPPED_JS: &str = r#"
+  (function () {
+    document.title = "Reference";
+    document.body.innerHTML =
+      '<div style="position:fixed;inset:0;display:flex;align-items:center;justify-content:center;background:#111;color:#eee;font-family:-apple-system,BlinkMacSystemFont,system-ui,sans-serif">' +
+      '<div style="max-width:560px;padding:24px">' +
+      '<h2 style="margin:0 0 12px;font-weight:600">Services stopped</h2>' +
+      '<p style="opacity:.7;font-size:13px">The local server and the worker are not running.</p>' +
+      '<div id="md-actions" style="display:flex;gap:8px"></div>' +
+      '<p id="md-status" style="opacity:.7;font-size:12px;min-height:1em"></p>' +
+      '<pre id="md-output" style="white-space:pre-wrap;font-size:12px"></pre>' +
+      '</div></div>';
+    var status = document.getElementById("md-status");
+    var output = document.getElementById("md-output");
+    var actions = document.getElementById("md-actions");
+    [
+      ["Start Services", "restart_server", "Starting…", "Started."],
+      ["Check Resources", "check_resources", "", ""],
+      ["Reveal Logs", "reveal_logs", "", ""],
+      ["Copy Diagnostics", "diagnostics_text", "", "Diagnostics copied to the clipboard."]
+    ].forEach(function (a) {
+      var b = document.createElement("button");
+      b.textContent = a[0];
+      b.onclick = function () {
+        status.textContent = a[2];
+        output.textContent = "";
+        window.__TAURI_INVOKE__(a[1]).then(
+          function (r) {
+            status.textContent = a[3];
+            if (a[1] === "check_resources") output.textContent = JSON.stringify(r, null, 2);
+          },
+          function (e) { status.textContent = String(e); }
+        );
+      };
+      actions.appendChild(b);
+    });
+  })();
+"#;
+
+// Stops the server and worker but keeps the app (and window) up on a control page.
+#[tauri::command(async)]
+pub(crate) fn stop_services(app: tauri::AppHandle) -> Result<(), String> {
+  let state = app.state::<ServerState>();
+  let settings = state.config_root.lock().unwrap().clone().map(|c| read_settings(&c)).unwrap_or_default();
+  stop_services_gracefully(&state, shutdown_grace(&settings));
+  host_log(LogLevel::Info, "Services stopped from the app");
+  if let Some(window) = app.get_window("main") {
+    let _ = window.eval(SERVICES_STOPPED_JS);
+  }
+  Ok(())
+}
+
+pub(crate) fn server_log_level(settings: &AppSettings) -> LogLevel {
+  settings
+    .server
+    .as_ref()
+    .and_then(|s| s.log_level.as_deref())
+    .and_then(LogLevel::parse)
+    .unwrap_or(LogLevel::Info)
+}
+
+// The level the running server was started with, else what the next start will use.
+#[tauri::command]
+pub(crate) fn get_server_log_level(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<String, String> {
+  let settings = match state.server_settings.lock().unwrap().clone() {
+    Some(s) => s,
+    None => read_settings(&managed_config_root(&app, &state)?),
+  };
+  Ok(server_log_level(&settings).as_str().to_string())
+}
+
+// `level` = None goes back to "info". Restarts the server so it takes effect; returns the new port.
+#[tauri::command(async)]
+pub(crate) fn set_server_log_level(
+  app: tauri::AppHandle,
+  state: tauri::State<ServerState>,
+  level: Option<String>,
+) -> Result<Option<u16>, String> {
+  let level = match level {
+    Some(l) => Some(
+      LogLevel::parse(&l)
+        .ok_or_else(|| format!("Unknown log level {:?} (expected error, warn, info or debug)", l))?
+        .as_str()
+        .to_string(),
+    ),
+    None => None,
+  };
+  let config_root = managed_config_root(&app, &state)?;
+  let mut settings = try_read_settings(&config_root)?;
+  settings.server.get_or_insert_with(Default::default).log_level = level;
+  write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+  restart_next_server(&app, "log_level")
+}
+
+pub(crate) const MAINTENANCE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+// "HH:MM-HH:MM" -> (start, end) in minutes since midnight.
+pub(crate) fn parse_maintenance_window(s: &str) -> Option<(u32, u32)> {
+  let minutes = |t: &str| -> Option<u32> {
+    let (h, m) = t.trim().split_once(':')?;
+    let (h, m) = (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?);
+    (h < 24 && m < 60).then_some(h * 60 + m)
+  };
+  let (start, end) = s.split_once('-')?;
+  Some((minutes(start)?, minutes(end)?))
+}
+
+// Local wall-clock time from the OS (std has no time zone support).
+pub(crate) fn local_minute_of_day() -> Option<u32> {
+  let out = if cfg!(windows) {
+    Command::new("powershell")
+      .args(["-NoProfile", "-NonInteractive", "-Command", "Get-Date -Format HH:mm"])
+      .stderr(Stdio::null())
+      .output()
+      .ok()?
+  } else {
+    Command::new("date").arg("+%H:%M").stderr(Stdio::null()).output().ok()?
+  };
+  let text = String::from_utf8_lossy(&out.stdout).to_string();
+  let (h, m) = text.trim().split_once(':')?;
+  Some(h.parse::<u32>().ok()? * 60 + m.parse::<u32>().ok()?)
+}
+
+pub(crate) fn maintenance_windows(settings: &AppSettings) -> Vec<String> {
+  settings.behavior.as_ref().and_then(|b| b.maintenance_window.clone()).unwrap_or_default()
+}
+
+// The configured window we're in right now (None when overridden for this session).
+pub(crate) fn active_maintenance_window(state: &ServerState, settings: &AppSettings) -> Option<String> {
+  let windows = maintenance_windows(settings);
+  if windows.is_empty() || *state.maintenance_override.lock().unwrap() {
+    return None;
+  }
+  let now = local_minute_of_day()?;
+  windows.into_iter().find(|w| in_maintenance_window(w, now))
+}
+
+// `now` in minutes since midnight; a window whose end is before its start runs past midnight.
+pub(crate) fn in_maintenance_window(window: &str, now: u32) -> bool {
+  match parse_maintenance_window(window) {
+    Some((start, end)) if start <= end => (start..end).contains(&now),
+    Some((start, end)) => now >= start || now < end,
+    None => false,
+  }
+}
+
+// Stops the running server/worker when a window opens and brings them back when it closes.
+pub(crate) fn watch_maintenance(app: tauri::AppHandle, config_root: PathBuf) {
+  std::thread::spawn(move || loop {
+    let state = app.state::<ServerState>();
+    let settings = read_settings(&config_root);
+    let active = active_maintenance_window(&state, &settings);
+    let paused = state.maintenance_pause.lock().unwrap().clone();
+    match (active, paused) {
+      (Some(w), None) => {
+        host_log(LogLevel::Info, &format!("Maintenance window {}: stopping the server and worker", w));
+        *state.maintenance_pause.lock().unwrap() = Some(w.clone());
+        stop_services_gracefully(&state, shutdown_grace(&settings));
+        if let Some(window) = app.get_window("main") {
+          show_hold_overlay(
+            &window,
+            "maintenance",
+            "Paused for maintenance",
+            &format!("The local server is paused for maintenance ({}). It restarts when the window ends.", w),
+          );
+          dispatch_web_event(&window, "moondream:maintenance:paused", Some(serde_json::json!({ "window": w })));
+        }
+      }
+      (None, Some(w)) => {
+        host_log(LogLevel::Info, &format!("Maintenance window {} over: restarting services", w));
+        *state.maintenance_pause.lock().unwrap() = None;
+        if let Some(window) = app.get_window("main") {
+          let _ = window.eval(CLEAR_HOLD_JS);
+        }
+        if let Err(e) = restart_server(app.clone()) {
+          host_log(LogLevel::Error, &format!("Restart after maintenance failed: {}", e));
+        }
+      }
+      _ => {}
+    }
+    // Short ticks so `override_maintenance` takes effect right away.
+    let tick = Instant::now();
+    while tick.elapsed() < MAINTENANCE_CHECK_INTERVAL {
+      std::thread::sleep(Duration::from_secs(1));
+      if *state.maintenance_override.lock().unwrap() && state.maintenance_pause.lock().unwrap().is_some() {
+        break;
+      }
+    }
+  });
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct MaintenanceStatus {
+  pub(crate) windows: Vec<String>,
+  pub(crate) paused_for: Option<String>,
+  pub(crate) overridden: bool,
+}
+
+pub(crate) fn maintenance_status_for(state: &ServerState, settings: &AppSettings) -> MaintenanceStatus {
+  MaintenanceStatus {
+    windows: maintenance_windows(settings),
+    paused_for: state.maintenance_pause.lock().unwrap().clone(),
+    overridden: *state.maintenance_override.lock().unwrap(),
+  }
+}
+
+#[tauri::command]
+pub(crate) fn maintenance_status(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<MaintenanceStatus, String> {
+  let config_root = managed_config_root(&app, &state)?;
+  Ok(maintenance_status_for(&state, &read_settings(&config_root)))
+}
+
+// Ignores maintenance windows until the app quits; a paused server/worker resumes within a second.
+#[tauri::command]
+pub(crate) fn override_maintenance(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<MaintenanceStatus, String> {
+  let config_root = managed_config_root(&app, &state)?;
+  *state.maintenance_override.lock().unwrap() = true;
+  host_log(LogLevel::Info, "Maintenance windows overridden for this session");
+  Ok(maintenance_status_for(&state, &read_settings(&config_root)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn node_source_selection() {
+    let dir = scratch_dir("node-source");
+    let bundled = dir.join("node");
+    let system = || Some(PathBuf::from("/usr/local/bin/node"));
+    let pick = |source: &str, system: &dyn Fn() -> Option<PathBuf>| {
+      select_node(source, &bundled, system).map(|n| (n.source, n.path))
+    };
+    // Nothing bundled: "auto" falls back to system Node, "bundled" refuses to.
+    assert_eq!(pick("auto", &system).unwrap(), ("system".to_string(), "/usr/local/bin/node".to_string()));
+    assert_eq!(pick("bundled", &system).unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(pick("auto", &|| None).unwrap_err().kind(), ErrorKind::NotFound);
+    std::fs::write(&bundled, b"").unwrap();
+    let bundled_path = bundled.to_string_lossy().to_string();
+    assert_eq!(pick("auto", &system).unwrap(), ("bundled".to_string(), bundled_path.clone()));
+    assert_eq!(pick("bundled", &|| None).unwrap(), ("bundled".to_string(), bundled_path));
+    assert_eq!(pick("system", &system).unwrap().0, "system");
+    assert_eq!(pick("system", &|| None).unwrap_err().kind(), ErrorKind::NotFound);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn missing_routes_are_told_apart_from_handler_errors() {
+    assert!(route_missing(404, "<!DOCTYPE html><html><head>"));
+    assert!(route_missing(405, ""));
+    assert!(!route_missing(404, " {\"error\":\"Project not found\"}"));
+    assert!(!route_missing(500, "<html>"));
+  }
+
+  #[test]
+  fn maintenance_window_parsing() {
+    assert_eq!(parse_maintenance_window("02:00-04:30"), Some((120, 270)));
+    assert_eq!(parse_maintenance_window(" 23:30 - 01:15 "), Some((1410, 75)));
+    assert_eq!(parse_maintenance_window("24:00-01:00"), None);
+    assert_eq!(parse_maintenance_window("02:60-03:00"), None);
+    assert_eq!(parse_maintenance_window("2-4"), None);
+    assert_eq!(parse_maintenance_window("02:00"), None);
+    assert_eq!(parse_maintenance_window(""), None);
+  }
+
+  #[test]
+  fn maintenance_window_membership() {
+    assert!(in_maintenance_window("02:00-04:00", 120));
+    assert!(in_maintenance_window("02:00-04:00", 239));
+    assert!(!in_maintenance_window("02:00-04:00", 240));
+    assert!(!in_maintenance_window("02:00-04:00", 119));
+    // Past midnight.
+    assert!(in_maintenance_window("23:00-01:00", 23 * 60 + 30));
+    assert!(in_maintenance_window("23:00-01:00", 30));
+    assert!(!in_maintenance_window("23:00-01:00", 60));
+    assert!(!in_maintenance_window("23:00-01:00", 12 * 60));
+    assert!(!in_maintenance_window("nightly", 0));
+  }
+
+  #[test]
+  fn server_log_lines_are_trimmed_and_cut() {
+    let mut stream = ServerLogStream::default();
+    assert_eq!(push_server_log_line(&mut stream, b"ready on :3000\r\n").line, "ready on :3000");
+    let long = "é".repeat(SERVER_LOG_LINE_MAX);
+    let entry = push_server_log_line(&mut stream, long.as_bytes());
+    assert!(entry.line.ends_with('…'));
+    assert!(entry.line.len() <= SERVER_LOG_LINE_MAX + '…'.len_utf8());
+    assert_eq!(entry.seq, 2);
+  }
+
+  #[test]
+  fn server_log_buffer_is_capped() {
+    let mut stream = ServerLogStream::default();
+    for i in 0..SERVER_LOG_BUFFER_LINES + 10 {
+      push_server_log_line(&mut stream, format!("line {}\n", i).as_bytes());
+    }
+    assert_eq!(stream.lines.len(), SERVER_LOG_BUFFER_LINES);
+    assert_eq!(stream.lines.front().unwrap().line, "line 10");
+    assert_eq!(stream.bytes, stream.lines.iter().map(|l| l.line.len()).sum::<usize>());
+    let long = "x".repeat(SERVER_LOG_LINE_MAX);
+    for _ in 0..SERVER_LOG_BUFFER_BYTES / SERVER_LOG_LINE_MAX + 2 {
+      push_server_log_line(&mut stream, long.as_bytes());
+    }
+    assert!(stream.bytes <= SERVER_LOG_BUFFER_BYTES);
+  }
+}
@@ -0,0 +1,1332 @@
+use crate::*;
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub(crate) struct AppSettings {
+  pub(crate) storage: Option<StorageSettings>,
+  pub(crate) ai: Option<AiSettings>,
+  pub(crate) server: Option<ServerSettings>,
+  pub(crate) behavior: Option<BehaviorSettings>,
+  pub(crate) retention: Option<RetentionSettings>,
+  pub(crate) worker: Option<WorkerSettings>,
+  pub(crate) logging: Option<LoggingSettings>,
+  pub(crate) layouts: Option<std::collections::BTreeMap<String, LayoutPreset>>,
+  pub(crate) security: Option<SecuritySettings>,
+  pub(crate) ui: Option<UiSettings>,
+  pub(crate) maintenance: Option<MaintenanceSettings>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub(crate) struct MaintenanceSettings {
+  // Compact the DB once this many assets have been deleted since the last compaction (unset/0 = off).
+  #[serde(alias = "autoCompactAfterDeletes")]
+  pub(crate) auto_compact_after_deletes: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub(crate) struct UiSettings {
+  // Webview GPU compositing: unset = platform default. Applied at launch only.
+  #[serde(alias = "hardwareAcceleration")]
+  pub(crate) hardware_acceleration: Option<bool>,
+  // Keep the main window above other apps (Window > Keep on Top).
+  #[serde(alias = "alwaysOnTop")]
+  pub(crate) always_on_top: Option<bool>,
+  // Language for native menu labels and About ("en" default; see MENU_TRANSLATIONS). Applied at launch.
+  pub(crate) locale: Option<String>,
+  // THEME_PRESETS name. The accent applies live; the light/dark window theme at launch.
+  pub(crate) theme: Option<String>,
+  // Let the host take file drops from Finder/Explorer and import them (`import_dropped_files`).
+  // Off by default: native drops stop the page's own HTML5 drop handling. Applied at launch.
+  #[serde(alias = "nativeFileDrop")]
+  pub(crate) native_file_drop: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub(crate) struct SecuritySettings {
+  // Check the code signature of bundled bin/node and bin/moondream-worker before running them.
+  #[serde(alias = "verifyBinaries")]
+  pub(crate) verify_binaries: Option<bool>,
+  // Don't start the server (or open any port) while the session is locked, e.g. a login item
+  // running behind the lock screen; start once it's unlocked.
+  #[serde(alias = "deferUntilUnlock")]
+  pub(crate) defer_until_unlock: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct StorageSettings {
+  pub(crate) mode: Option<String>, // "local" | "icloud"
+  #[serde(alias = "icloudPath")]
+  pub(crate) icloud_path: Option<String>,
+  pub(crate) migration: Option<MigrationSettings>,
+  // Pause the worker while the data dir is unreachable (default: true).
+  #[serde(alias = "pauseWorkerWhenOffline")]
+  pub(crate) pause_worker_when_offline: Option<bool>,
+  // "with_data" (default) | "local": keep the DB in config_root while assets stay in the library
+  // (avoids iCloud syncing the open database).
+  #[serde(alias = "dbLocation")]
+  pub(crate) db_location: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct MigrationSettings {
+  pub(crate) from: String,
+  pub(crate) to: String,
+  #[serde(alias = "requestedAt")]
+  pub(crate) requested_at: Option<String>,
+  #[serde(alias = "inProgress")]
+  pub(crate) in_progress: Option<bool>,
+  // Rename a non-empty destination to `<name>-backup-<ts>` first (default: true). When false,
+  // a non-empty destination aborts the migration instead of mixing two libraries.
+  #[serde(alias = "backupExisting")]
+  pub(crate) backup_existing: Option<bool>,
+  // Why the last attempt was aborted; the migration stays pending until this is resolved.
+  pub(crate) error: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub(crate) struct AiSettings {
+  pub(crate) provider: Option<String>, // "local_station" | "huggingface"
+  pub(crate) endpoint: Option<String>,
+  #[serde(alias = "hfToken")]
+  pub(crate) hf_token: Option<String>,
+}
+
+pub(crate) const DEFAULT_AI_PROVIDER: &str = "local_station";
+
+pub(crate) const DEFAULT_AI_ENDPOINT: &str = "http://localhost:2023/v1";
+
+// `settings.ai` with the defaults the server and worker are started with.
+pub(crate) struct AiConfig {
+  pub(crate) provider: String,
+  pub(crate) endpoint: String,
+  pub(crate) hf_token: String,
+}
+
+impl AiConfig {
+  pub(crate) fn is_local(&self) -> bool {
+    self.provider == DEFAULT_AI_PROVIDER
+  }
+}
+
+pub(crate) fn ai_config(settings: &AppSettings) -> AiConfig {
+  let ai = settings.ai.clone().unwrap_or_default();
+  AiConfig {
+    provider: ai.provider.unwrap_or_else(|| DEFAULT_AI_PROVIDER.to_string()),
+    endpoint: ai.endpoint.unwrap_or_else(|| DEFAULT_AI_ENDPOINT.to_string()),
+    hf_token: ai.hf_token.unwrap_or_default(),
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub(crate) struct ServerSettings {
+  #[serde(alias = "nodeSource")]
+  pub(crate) node_source: Option<String>, // "bundled" | "system" | "auto"
+  pub(crate) transport: Option<String>, // "tcp" (default) | "unix"
+  // Probed until it returns 200 at startup and after restarts (default: DEFAULT_READY_PATH).
+  #[serde(alias = "readyPath")]
+  pub(crate) ready_path: Option<String>,
+  // Inclusive [start, end]; when set, the first bindable port in it is used instead of a random one.
+  #[serde(alias = "portRange")]
+  pub(crate) port_range: Option<(u16, u16)>,
+  // Fixed loopback port for the host's own `/status` endpoint (see serve_status); unset = off.
+  #[serde(alias = "statusPort")]
+  pub(crate) status_port: Option<u16>,
+  // Minimum level the Next server logs (see `server_log_level`); separate from `logging.level`.
+  #[serde(alias = "logLevel")]
+  pub(crate) log_level: Option<String>, // "error" | "warn" | "info" (default) | "debug"
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub(crate) struct BehaviorSettings {
+  pub(crate) autostart: Option<bool>,
+  // Only applies to launches at login (see AUTOSTART_ARG).
+  #[serde(alias = "startHidden")]
+  pub(crate) start_hidden: Option<bool>,
+  // How long the server/worker get to exit cleanly on quit before being killed
+  // (default DEFAULT_SHUTDOWN_GRACE, capped at MAX_SHUTDOWN_GRACE_SECS).
+  #[serde(alias = "shutdownGraceSeconds")]
+  pub(crate) shutdown_grace_seconds: Option<u64>,
+  // Ask before closing the window / quitting (default off).
+  #[serde(alias = "confirmQuit")]
+  pub(crate) confirm_quit: Option<bool>,
+  // Local-time ranges ("HH:MM-HH:MM", may wrap past midnight) when the server and worker must not
+  // run, e.g. during backups. Checked at launch and every MAINTENANCE_CHECK_INTERVAL.
+  #[serde(alias = "maintenanceWindow")]
+  pub(crate) maintenance_window: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub(crate) struct WorkerSettings {
+  pub(crate) concurrency: Option<u8>, // parallel jobs, clamped to WORKER_CONCURRENCY_RANGE (default 1)
+  // Only process assets added at/after this point: an ISO-8601 timestamp or "first_launch".
+  // Unset = everything (see `process_since_cutoff`).
+  #[serde(alias = "processSince")]
+  pub(crate) process_since: Option<String>,
+  // Don't start the worker at launch while on battery; it starts once on AC (or via `restart_worker`).
+  #[serde(alias = "deferOnBattery")]
+  pub(crate) defer_on_battery: Option<bool>,
+}
+
+// Outer window geometry in physical pixels, plus the canvas zoom the web UI reported at save time.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct LayoutPreset {
+  pub(crate) x: i32,
+  pub(crate) y: i32,
+  pub(crate) width: u32,
+  pub(crate) height: u32,
+  pub(crate) zoom: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub(crate) struct LoggingSettings {
+  pub(crate) format: Option<String>, // "text" (default) | "json"
+  pub(crate) level: Option<String>,  // "error" | "warn" | "info" (default) | "debug"
+  // Gzip rotated generations (`<name>.1.gz`, ...); the live log stays plaintext. Default on.
+  #[serde(alias = "compressRotated")]
+  pub(crate) compress_rotated: Option<bool>,
+  // Cap on everything in the logs dir; oldest rotated generations go first (unset/0 = no cap).
+  #[serde(alias = "maxTotalMb")]
+  pub(crate) max_total_mb: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub(crate) struct RetentionSettings {
+  pub(crate) days: Option<u32>, // unset/0 = keep everything
+  pub(crate) compact: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub(crate) struct RuntimeInfo {
+  #[serde(alias = "lastPort")]
+  pub(crate) last_port: Option<u16>,
+  #[serde(alias = "lastRoute")]
+  pub(crate) last_route: Option<String>,
+  // Unix seconds of the last scheduled retention purge.
+  #[serde(alias = "lastRetentionAt")]
+  pub(crate) last_retention_at: Option<u64>,
+  // Unix seconds of the first launch that recorded it (anchor for `worker.process_since = "first_launch"`).
+  #[serde(alias = "firstLaunchAt")]
+  pub(crate) first_launch_at: Option<u64>,
+  // Canvas zoom last reported by the UI, offered back via `canvas_zoom` on the next launch.
+  #[serde(alias = "lastZoom")]
+  pub(crate) last_zoom: Option<f64>,
+  // Assets deleted since the DB was last compacted (see `maintenance.auto_compact_after_deletes`).
+  #[serde(alias = "deletesSinceCompact")]
+  pub(crate) deletes_since_compact: Option<u64>,
+  // Unix seconds of the last delete counted above.
+  #[serde(alias = "lastDeleteAt")]
+  pub(crate) last_delete_at: Option<u64>,
+}
+
+// How long to wait for an iCloud placeholder settings.json to download before using defaults.
+// Only the first read waits; later reads (watchers, status) shouldn't stall on it again.
+pub(crate) const SETTINGS_MATERIALIZE_WAIT: Duration = Duration::from_secs(3);
+
+pub(crate) static SETTINGS_WAITED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+// A not-yet-downloaded iCloud file: either the legacy `.name.icloud` stub, or the real name with a
+// non-zero size that reads back empty/fails until the File Provider materializes it.
+pub(crate) fn looks_like_placeholder(p: &PathBuf, read: &io::Result<String>) -> bool {
+  let stub = p
+    .file_name()
+    .map(|n| p.with_file_name(format!(".{}.icloud", n.to_string_lossy())).exists())
+    .unwrap_or(false);
+  let size = std::fs::metadata(p).map(|m| m.len()).unwrap_or(0);
+  let unreadable = match read {
+    Ok(s) => s.trim().is_empty() && size > 0,
+    Err(e) => e.kind() != ErrorKind::NotFound,
+  };
+  stub || unreadable
+}
+
+pub(crate) fn read_settings_text(p: &PathBuf) -> io::Result<String> {
+  let mut data = std::fs::read_to_string(p);
+  if !looks_like_placeholder(p, &data) || SETTINGS_WAITED.swap(true, std::sync::atomic::Ordering::SeqCst) {
+    return data;
+  }
+  host_log(LogLevel::Warn, &format!("{} isn't downloaded yet; waiting for iCloud", p.display()));
+  if cfg!(target_os = "macos") {
+    // Ask for it explicitly rather than waiting on the File Provider's own schedule.
+    let _ = quiet_status(Command::new("brctl").arg("download").arg(p));
+  }
+  let start = Instant::now();
+  while start.elapsed() < SETTINGS_MATERIALIZE_WAIT {
+    std::thread::sleep(Duration::from_millis(250));
+    data = std::fs::read_to_string(p);
+    if !looks_like_placeholder(p, &data) {
+      host_log(LogLevel::Info, &format!("{} downloaded after {:?}", p.display(), start.elapsed()));
+      return data;
+    }
+  }
+  host_log(LogLevel::Warn, &format!("{} still not downloaded; using defaults", p.display()));
+  data
+}
+
+// Top-level keys from before settings were grouped into sections: (legacy key, section, field).
+pub(crate) const LEGACY_SETTINGS_KEYS: &[(&str, &str, &str)] = &[
+  ("storageMode", "storage", "mode"),
+  ("icloudPath", "storage", "icloud_path"),
+  ("aiProvider", "ai", "provider"),
+  ("aiEndpoint", "ai", "endpoint"),
+  ("hfToken", "ai", "hf_token"),
+  ("nodeSource", "server", "node_source"),
+  ("portRange", "server", "port_range"),
+  ("confirmQuit", "behavior", "confirm_quit"),
+  ("retentionDays", "retention", "days"),
+];
+
+pub(crate) fn snake_case(key: &str) -> String {
+  let mut out = String::with_capacity(key.len() + 4);
+  for c in key.chars() {
+    if c.is_ascii_uppercase() {
+      if !out.is_empty() {
+        out.push('_');
+      }
+      out.push(c.to_ascii_lowercase());
+    } else {
+      out.push(c);
+    }
+  }
+  out
+}
+
+// Renames camelCase keys (serde aliases read them, but they'd linger next to the snake_case ones).
+// `layouts` is keyed by user-chosen names, so its keys are left alone.
+pub(crate) fn snake_case_keys(value: &mut serde_json::Value, path: &str, notes: &mut Vec<String>) {
+  let Some(obj) = value.as_object_mut() else {
+    return;
+  };
+  let keys: Vec<String> = obj.keys().cloned().collect();
+  for key in keys {
+    let snake = snake_case(&key);
+    let child = if path.is_empty() { snake.clone() } else { format!("{}.{}", path, snake) };
+    if snake != key {
+      if let Some(v) = obj.remove(&key) {
+        if obj.contains_key(&snake) {
+          notes.push(format!("dropped {} (superseded by {})", key, child));
+        } else {
+          notes.push(format!("{} -> {}", key, child));
+          obj.insert(snake.clone(), v);
+        }
+      }
+    }
+    if child != "layouts" {
+      if let Some(v) = obj.get_mut(&snake) {
+        snake_case_keys(v, &child, notes);
+      }
+    }
+  }
+}
+
+// Moves legacy keys to where `AppSettings` expects them. Returns what changed; empty = nothing to do.
+pub(crate) fn migrate_legacy_settings(value: &mut serde_json::Value) -> Vec<String> {
+  let mut notes = Vec::new();
+  let Some(obj) = value.as_object_mut() else {
+    return notes;
+  };
+  for (legacy, section, field) in LEGACY_SETTINGS_KEYS {
+    let Some(v) = obj.remove(*legacy).or_else(|| obj.remove(&snake_case(legacy))) else {
+      continue;
+    };
+    let target = obj.entry(section.to_string()).or_insert(serde_json::Value::Null);
+    if target.is_null() {
+      *target = serde_json::Value::Object(Default::default());
+    }
+    match target.as_object_mut() {
+      Some(sec) if !sec.contains_key(*field) => {
+        sec.insert(field.to_string(), v);
+        notes.push(format!("{} -> {}.{}", legacy, section, field));
+      }
+      _ => notes.push(format!("dropped {} ({}.{} is already set)", legacy, section, field)),
+    }
+  }
+  snake_case_keys(value, "", &mut notes);
+  notes
+}
+
+// Defaults when settings.json is missing or broken; anything that writes settings back uses
+// `try_read_settings` instead so a typo in the file isn't replaced by defaults.
+pub(crate) fn read_settings(config_root: &PathBuf) -> AppSettings {
+  try_read_settings(config_root).unwrap_or_default()
+}
+
+pub(crate) fn try_read_settings(config_root: &PathBuf) -> Result<AppSettings, String> {
+  let p = config_root.join("settings.json");
+  let s = match read_settings_text(&p) {
+    Ok(s) => s,
+    Err(e) if e.kind() == ErrorKind::NotFound => return Ok(AppSettings::default()),
+    Err(e) => return Err(format!("Couldn't read {}: {}", p.display(), e)),
+  };
+  // Legacy keys are honored in memory; `upgrade_legacy_settings` rewrites the file once at launch.
+  if let Ok(mut value) = serde_json::from_str::<serde_json::Value>(&s) {
+    if !migrate_legacy_settings(&mut value).is_empty() {
+      if let Ok(settings) = serde_json::from_value::<AppSettings>(value) {
+        return Ok(settings);
+      }
+    }
+  }
+  serde_json::from_str::<AppSettings>(&s)
+    .map_err(|e| format!("{} isn't valid ({}); fix it before changing settings", p.display(), e))
+}
+
+// Rewrites settings.json without legacy keys. Called once from `setup`, never in safe mode.
+pub(crate) fn upgrade_legacy_settings(config_root: &PathBuf) {
+  let Ok(s) = read_settings_text(&config_root.join("settings.json")) else {
+    return;
+  };
+  let Ok(mut value) = serde_json::from_str::<serde_json::Value>(&s) else {
+    return;
+  };
+  let notes = migrate_legacy_settings(&mut value);
+  if notes.is_empty() {
+    return;
+  }
+  if let Ok(settings) = serde_json::from_value::<AppSettings>(value) {
+    match write_settings(config_root, &settings) {
+      Ok(()) => host_log(LogLevel::Info, &format!("Migrated legacy settings keys: {}", notes.join(", "))),
+      Err(e) => host_log(LogLevel::Warn, &format!("Couldn't migrate legacy settings keys: {}", e)),
+    }
+  }
+}
+
+pub(crate) fn write_settings(config_root: &PathBuf, settings: &AppSettings) -> io::Result<()> {
+  if let Ok(to) = std::fs::read_to_string(config_root.join(CONFIG_ROOT_MOVING_TO_FILE)) {
+    return Err(io::Error::other(format!(
+      "The config folder is moving to {}; relaunch the app before changing settings",
+      to.trim()
+    )));
+  }
+  let p = config_root.join("settings.json");
+  let s = serde_json::to_string_pretty(settings).map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+  std::fs::write(&p, s).map_err(|e| io::Error::new(e.kind(), format!("Failed to write {}: {}", p.display(), e)))
+}
+
+#[tauri::command]
+pub(crate) fn edit_settings_file(app: tauri::AppHandle) -> Result<String, String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let path = config_root.join("settings.json");
+  if !path.exists() {
+    std::fs::create_dir_all(&config_root).map_err(|e| e.to_string())?;
+    write_settings(&config_root, &AppSettings::default()).map_err(|e| e.to_string())?;
+  }
+  let mut cmd = if cfg!(target_os = "macos") {
+    // -t: default *text* editor (a .json association may point at a browser).
+    let mut c = Command::new("open");
+    c.arg("-t");
+    c
+  } else if cfg!(target_os = "windows") {
+    Command::new("notepad")
+  } else {
+    Command::new("xdg-open")
+  };
+  cmd
+    .arg(&path)
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .spawn()
+    .map_err(|e| e.to_string())?;
+  Ok(path.to_string_lossy().to_string())
+}
+
+// Shareable settings file; `settings` holds AppSettings minus machine-specific fields.
+pub(crate) const SETTINGS_EXPORT_FORMAT: &str = "reference-settings";
+
+pub(crate) const SETTINGS_EXPORT_VERSION: u32 = 1;
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct SettingsExport {
+  pub(crate) format: String,
+  pub(crate) version: u32,
+  pub(crate) settings: AppSettings,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct SettingsTransfer {
+  pub(crate) path: String,
+  // Fields that are tied to one machine (absolute paths, window geometry, secrets). On export
+  // they're left out; on import they keep this machine's value, or the default if it has none.
+  pub(crate) machine_specific: Vec<String>,
+}
+
+// Moves machine-specific fields out of `settings`, returning the names of those that were set.
+pub(crate) fn strip_machine_specific(settings: &mut AppSettings) -> Vec<String> {
+  let mut stripped = Vec::new();
+  if let Some(storage) = settings.storage.as_mut() {
+    if storage.icloud_path.take().is_some() {
+      stripped.push("storage.icloud_path".to_string());
+    }
+    if storage.migration.take().is_some() {
+      stripped.push("storage.migration".to_string());
+    }
+  }
+  if let Some(ai) = settings.ai.as_mut() {
+    if ai.hf_token.take().is_some() {
+      stripped.push("ai.hf_token".to_string());
+    }
+  }
+  if settings.layouts.take().is_some() {
+    stripped.push("layouts".to_string());
+  }
+  stripped
+}
+
+#[tauri::command]
+pub(crate) fn export_settings(app: tauri::AppHandle, dest: String) -> Result<SettingsTransfer, String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let dest = PathBuf::from(dest.trim());
+  if !dest.is_absolute() {
+    return Err("Choose an absolute destination path.".to_string());
+  }
+  let mut settings = try_read_settings(&config_root)?;
+  let machine_specific = strip_machine_specific(&mut settings);
+  let export = SettingsExport {
+    format: SETTINGS_EXPORT_FORMAT.to_string(),
+    version: SETTINGS_EXPORT_VERSION,
+    settings,
+  };
+  let text = serde_json::to_string_pretty(&export).map_err(|e| e.to_string())?;
+  std::fs::write(&dest, text).map_err(|e| format!("Failed to write {}: {}", dest.display(), e))?;
+  Ok(SettingsTransfer {
+    path: dest.to_string_lossy().to_string(),
+    machine_specific,
+  })
+}
+
+// Accepts an `export_settings` file or a bare settings.json from another machine. Takes effect
+// like any settings.json edit (see `restart_required`).
+#[tauri::command]
+pub(crate) fn import_settings(app: tauri::AppHandle, source: String) -> Result<SettingsTransfer, String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let source = PathBuf::from(source.trim());
+  let text = std::fs::read_to_string(&source).map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+  let mut imported = match serde_json::from_str::<SettingsExport>(&text) {
+    Ok(export) if export.format == SETTINGS_EXPORT_FORMAT => {
+      if export.version > SETTINGS_EXPORT_VERSION {
+        return Err("This settings file was made by a newer version of Reference.".to_string());
+      }
+      export.settings
+    }
+    _ => serde_json::from_str::<AppSettings>(&text).map_err(|e| format!("Not a settings file: {}", e))?,
+  };
+  server_transport(&imported).map_err(|e| e.to_string())?;
+  if let Some((start, end)) = imported.server.as_ref().and_then(|s| s.port_range) {
+    if start > end || start == 0 {
+      return Err(format!("Invalid server.port_range [{}, {}]", start, end));
+    }
+  }
+
+  let mut machine_specific = strip_machine_specific(&mut imported);
+  let current = try_read_settings(&config_root)?;
+  let local_storage = current.storage.as_ref();
+  if let Some(storage) = imported.storage.as_mut() {
+    storage.icloud_path = local_storage.and_then(|s| s.icloud_path.clone());
+    storage.migration = local_storage.and_then(|s| s.migration.clone());
+  } else if let Some(local) = local_storage.filter(|s| s.icloud_path.is_some() || s.migration.is_some()) {
+    imported.storage = Some(StorageSettings {
+      mode: None,
+      icloud_path: local.icloud_path.clone(),
+      migration: local.migration.clone(),
+      pause_worker_when_offline: None,
+      db_location: None,
+    });
+  }
+  let local_token = current.ai.as_ref().and_then(|a| a.hf_token.clone());
+  if local_token.is_some() {
+    imported.ai.get_or_insert_with(Default::default).hf_token = local_token;
+  }
+  imported.layouts = current.layouts.clone();
+  machine_specific.sort();
+  machine_specific.dedup();
+
+  write_settings(&config_root, &imported).map_err(|e| e.to_string())?;
+  Ok(SettingsTransfer {
+    path: config_root.join("settings.json").to_string_lossy().to_string(),
+    machine_specific,
+  })
+}
+
+// Named full snapshots of settings.json (including machine-specific fields; they never leave this machine).
+pub(crate) fn profiles_dir(config_root: &PathBuf) -> PathBuf {
+  config_root.join("profiles")
+}
+
+pub(crate) fn profile_path(config_root: &PathBuf, name: &str) -> Result<PathBuf, String> {
+  let name = name.trim();
+  if name.is_empty() || name.len() > 64 {
+    return Err("Profile names must be 1–64 characters.".to_string());
+  }
+  if name.starts_with('.') || !name.chars().all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.')) {
+    return Err("Profile names may only use letters, digits, spaces, '-', '_' and '.'.".to_string());
+  }
+  // Would read as the live settings file (or its backup) when browsing the config dir.
+  let lower = name.to_lowercase();
+  if lower == "settings" || lower.starts_with("settings.") {
+    return Err(format!("\"{}\" is reserved for the active settings file.", name));
+  }
+  Ok(profiles_dir(config_root).join(format!("{}.json", name)))
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct SettingsProfile {
+  pub(crate) name: String,
+  pub(crate) path: String,
+  pub(crate) modified: Option<u64>,
+}
+
+pub(crate) fn settings_profile(path: &PathBuf) -> Option<SettingsProfile> {
+  if path.extension().and_then(|e| e.to_str()) != Some("json") {
+    return None;
+  }
+  let meta = std::fs::metadata(path).ok().filter(|m| m.is_file())?;
+  Some(SettingsProfile {
+    name: path.file_stem()?.to_string_lossy().to_string(),
+    path: path.to_string_lossy().to_string(),
+    modified: mtime_secs(&meta),
+  })
+}
+
+// Overwrites an existing profile of the same name.
+#[tauri::command]
+pub(crate) fn save_profile(app: tauri::AppHandle, name: String) -> Result<SettingsProfile, String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let path = profile_path(&config_root, &name)?;
+  std::fs::create_dir_all(profiles_dir(&config_root)).map_err(|e| e.to_string())?;
+  let text = serde_json::to_string_pretty(&try_read_settings(&config_root)?).map_err(|e| e.to_string())?;
+  std::fs::write(&path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+  settings_profile(&path).ok_or_else(|| format!("Failed to write {}", path.display()))
+}
+
+#[tauri::command]
+pub(crate) fn list_profiles(app: tauri::AppHandle) -> Result<Vec<SettingsProfile>, String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let mut profiles: Vec<SettingsProfile> = match std::fs::read_dir(profiles_dir(&config_root)) {
+    Ok(entries) => entries.flatten().filter_map(|e| settings_profile(&e.path())).collect(),
+    Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+    Err(e) => return Err(e.to_string()),
+  };
+  profiles.sort_by_key(|p| p.name.to_lowercase());
+  Ok(profiles)
+}
+
+// Replaces settings.json with the profile; the result lists what needs a server restart or relaunch.
+#[tauri::command]
+pub(crate) fn load_profile(app: tauri::AppHandle, state: tauri::State<ServerState>, name: String) -> Result<RestartStatus, String> {
+  let config_root = managed_config_root(&app, &state)?;
+  let path = profile_path(&config_root, &name)?;
+  let text = std::fs::read_to_string(&path).map_err(|e| match e.kind() {
+    ErrorKind::NotFound => format!("No profile named \"{}\"", name.trim()),
+    _ => format!("Failed to read {}: {}", path.display(), e),
+  })?;
+  let settings = serde_json::from_str::<AppSettings>(&text).map_err(|e| format!("Not a settings file: {}", e))?;
+  server_transport(&settings).map_err(|e| e.to_string())?;
+  write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+  restart_required(app, state)
+}
+
+#[tauri::command]
+pub(crate) fn delete_profile(app: tauri::AppHandle, name: String) -> Result<(), String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let path = profile_path(&config_root, &name)?;
+  match std::fs::remove_file(&path) {
+    Ok(()) => Ok(()),
+    Err(e) if e.kind() == ErrorKind::NotFound => Err(format!("No profile named \"{}\"", name.trim())),
+    Err(e) => Err(e.to_string()),
+  }
+}
+
+pub(crate) const SETTINGS_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+// An edit must sit unchanged this long before it's read (editors often write in several steps).
+pub(crate) const SETTINGS_DEBOUNCE: Duration = Duration::from_millis(750);
+
+pub(crate) fn file_stamp(p: &PathBuf) -> Option<(std::time::SystemTime, u64)> {
+  let meta = std::fs::metadata(p).ok()?;
+  Some((meta.modified().ok()?, meta.len()))
+}
+
+// Problems that would break startup; reported with the reload event instead of surfacing later.
+pub(crate) fn settings_warnings(settings: &AppSettings) -> Vec<String> {
+  let mut out = Vec::new();
+  if let Err(e) = server_transport(settings) {
+    out.push(e.to_string());
+  }
+  if let Some(p) = settings.server.as_ref().and_then(|s| s.ready_path.as_deref()) {
+    if !p.trim().is_empty() && !p.trim().starts_with('/') {
+      out.push(format!("server.ready_path must start with '/': {}", p));
+    }
+  }
+  if let Some(source) = settings.server.as_ref().and_then(|s| s.node_source.as_deref()) {
+    if !matches!(source, "bundled" | "system" | "auto") {
+      out.push(format!("Unknown server.node_source: {}", source));
+    }
+  }
+  out
+}
+
+// Notices external edits to settings.json: `moondream:settings:reloaded` (detail: { warnings })
+// when it parses, `moondream:settings:invalid` (detail: { error }) when it doesn't.
+// Our own `write_settings` calls trigger a reload too, which is harmless.
+pub(crate) fn watch_settings_file(app: tauri::AppHandle, config_root: PathBuf) {
+  std::thread::spawn(move || {
+    let path = config_root.join("settings.json");
+    let mut seen = file_stamp(&path);
+    loop {
+      std::thread::sleep(SETTINGS_WATCH_INTERVAL);
+      let mut stamp = file_stamp(&path);
+      if stamp == seen || stamp.is_none() {
+        continue;
+      }
+      loop {
+        std::thread::sleep(SETTINGS_DEBOUNCE);
+        let next = file_stamp(&path);
+        if next == stamp {
+          break;
+        }
+        stamp = next;
+      }
+      seen = stamp;
+
+      let raw = std::fs::read_to_string(&path).unwrap_or_default();
+      let (event, detail) = match serde_json::from_str::<AppSettings>(&raw) {
+        Ok(_) => {
+          let settings = read_settings(&config_root);
+          let warnings = settings_warnings(&settings);
+          host_log(LogLevel::Info, "settings.json changed; reloaded");
+          ("moondream:settings:reloaded", serde_json::json!({ "warnings": warnings }))
+        }
+        Err(e) => {
+          host_log(LogLevel::Warn, &format!("settings.json is invalid: {}", e));
+          ("moondream:settings:invalid", serde_json::json!({ "error": e.to_string() }))
+        }
+      };
+      if let Some(window) = app.get_window("main") {
+        dispatch_web_event(&window, event, Some(detail));
+      }
+    }
+  });
+}
+
+// Machine-local runtime hints (not user settings), kept next to settings.json.
+pub(crate) fn read_runtime(config_root: &PathBuf) -> RuntimeInfo {
+  std::fs::read_to_string(config_root.join("runtime.json"))
+    .ok()
+    .and_then(|s| serde_json::from_str::<RuntimeInfo>(&s).ok())
+    .unwrap_or_default()
+}
+
+pub(crate) fn write_runtime(config_root: &PathBuf, rt: &RuntimeInfo) {
+  if let Ok(s) = serde_json::to_string_pretty(rt) {
+    let _ = std::fs::write(config_root.join("runtime.json"), s);
+  }
+}
+
+// Tauri keys `app_data_dir` by bundle identifier, so an id change leaves the old config root behind.
+// Identifiers the desktop app has shipped under before `com.moondream.desktop`.
+pub(crate) const LEGACY_BUNDLE_IDS: &[&str] = &["com.moondream.app"];
+
+pub(crate) const CONFIG_ROOT_MIGRATED_MARKER: &str = ".config-root-migrated";
+
+// A custom config root (`set_config_root`) is recorded as an absolute path in this file inside
+// the OS default root, which is where every launch starts looking.
+pub(crate) const CONFIG_ROOT_REDIRECT_FILE: &str = "config-root-redirect";
+
+// Written into the new root; the next launch finishes the move from the root named inside.
+pub(crate) const CONFIG_ROOT_MOVED_FROM_FILE: &str = "config-root-moved-from";
+
+// Written into the old root: settings.json there is no longer read, so writes are refused
+// (see `write_settings`) until the relaunch.
+pub(crate) const CONFIG_ROOT_MOVING_TO_FILE: &str = "config-root-moving-to";
+
+// Everything `finish_config_root_move` brings over besides settings.json (already written by
+// `set_config_root`). `next.sock` and the redirect file stay where they are.
+pub(crate) const CONFIG_ROOT_DIRS: &[&str] = &["data", "backups", "profiles", "logs", "worker"];
+
+pub(crate) const CONFIG_ROOT_FILES: &[&str] = &["runtime.json", SESSION_TOKEN_FILE];
+
+pub(crate) fn app_config_root(app: &tauri::AppHandle) -> Option<PathBuf> {
+  app.path_resolver().app_data_dir().map(config_root_from_default)
+}
+
+// Follows CONFIG_ROOT_REDIRECT_FILE; usable before the app exists (see `main`).
+pub(crate) fn config_root_from_default(default_root: PathBuf) -> PathBuf {
+  let redirect = std::fs::read_to_string(default_root.join(CONFIG_ROOT_REDIRECT_FILE))
+    .ok()
+    .map(|s| PathBuf::from(s.trim()))
+    .filter(|p| p.is_absolute());
+  match redirect {
+    Some(p) if p.is_dir() => p,
+    Some(p) => {
+      // e.g. an unmounted drive: starting fresh there would hide the user's settings.
+      host_log(
+        LogLevel::Warn,
+        &format!("Config root redirect {} is unavailable; using {}", p.display(), default_root.display()),
+      );
+      default_root
+    }
+    None => default_root,
+  }
+}
+
+// Moves `from` to `to`, merging into an existing directory. Files already at the destination
+// (e.g. this launch's log) win; their old copies are left behind.
+pub(crate) fn merge_into(from: &PathBuf, to: &PathBuf) -> io::Result<()> {
+  if !to.exists() {
+    if from.is_dir() {
+      return move_dir(from, to);
+    }
+    return std::fs::rename(from, to).or_else(|_| {
+      std::fs::copy(from, to)?;
+      std::fs::remove_file(from)
+    });
+  }
+  if from.is_dir() && to.is_dir() {
+    for entry in std::fs::read_dir(from)? {
+      let name = entry?.file_name();
+      merge_into(&from.join(&name), &to.join(&name))?;
+    }
+    // Not empty if something was left behind.
+    let _ = std::fs::remove_dir(from);
+  }
+  Ok(())
+}
+
+// Runs at launch, before anything opens the library: brings over what the previous session
+// kept using after `set_config_root` (library, DB, backups, profiles, logs, ...).
+pub(crate) fn finish_config_root_move(config_root: &PathBuf) {
+  // Whatever the previous session was moving away from, it isn't this root any more.
+  let _ = std::fs::remove_file(config_root.join(CONFIG_ROOT_MOVING_TO_FILE));
+  let marker = config_root.join(CONFIG_ROOT_MOVED_FROM_FILE);
+  let Some(old) = std::fs::read_to_string(&marker).ok().map(|s| PathBuf::from(s.trim())) else {
+    return;
+  };
+  let db_files = ["", "-wal", "-shm"].map(|suffix| format!("{}{}", DB_FILE_NAME, suffix));
+  let names = CONFIG_ROOT_DIRS
+    .iter()
+    .chain(CONFIG_ROOT_FILES)
+    .map(|n| n.to_string())
+    .chain(db_files);
+  let mut failed = false;
+  for name in names {
+    let from = old.join(&name);
+    if !from.exists() {
+      continue;
+    }
+    if let Err(e) = merge_into(&from, &config_root.join(&name)) {
+      host_log(LogLevel::Error, &format!("Failed to move {}: {}", from.display(), e));
+      failed = true;
+    }
+  }
+  // Retried next launch if anything didn't make it.
+  if !failed {
+    let _ = std::fs::rename(old.join("settings.json"), old.join("settings.json.moved"));
+    let _ = std::fs::remove_file(old.join(CONFIG_ROOT_MOVING_TO_FILE));
+    let _ = std::fs::remove_file(&marker);
+    host_log(LogLevel::Info, &format!("Finished moving the config root from {}", old.display()));
+  }
+}
+
+// Takes full effect after a relaunch (`relaunch_app`); this session keeps using the old root.
+#[tauri::command]
+pub(crate) fn set_config_root(app: tauri::AppHandle, new_root: String) -> Result<(), String> {
+  let state = app.state::<ServerState>();
+  let old = managed_config_root(&app, &state)?;
+  let default_root = app.path_resolver().app_data_dir().ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let new_root = PathBuf::from(new_root.trim());
+  if !new_root.is_absolute() {
+    return Err(format!("Expected an absolute path, got {}", new_root.display()));
+  }
+  if new_root == old {
+    return Ok(());
+  }
+  if let Ok(to) = std::fs::read_to_string(old.join(CONFIG_ROOT_MOVING_TO_FILE)) {
+    return Err(format!("The config folder is already moving to {}; relaunch the app first", to.trim()));
+  }
+  if new_root.starts_with(&old) || old.starts_with(&new_root) {
+    return Err("The new config folder can't be inside the current one (or contain it).".to_string());
+  }
+  std::fs::create_dir_all(&new_root).map_err(|e| format!("Failed to create {}: {}", new_root.display(), e))?;
+  if !dir_writable(&new_root) {
+    return Err(format!("{} is not writable", new_root.display()));
+  }
+  if new_root.join("settings.json").exists() {
+    return Err(format!("{} already has a settings.json", new_root.display()));
+  }
+
+  // Paths that pointed inside the old root (pending migration, a library kept there) move with it.
+  let mut settings = try_read_settings(&old)?;
+  if let Some(st) = settings.storage.as_mut() {
+    st.icloud_path = st.icloud_path.as_deref().map(|p| rebase_path(p, &old, &new_root));
+    if let Some(mig) = st.migration.as_mut() {
+      mig.from = rebase_path(&mig.from, &old, &new_root);
+      mig.to = rebase_path(&mig.to, &old, &new_root);
+    }
+  }
+  write_settings(&new_root, &settings).map_err(|e| e.to_string())?;
+  // Read by `migrate_legacy_config_root` before the move finishes.
+  if old.join(CONFIG_ROOT_MIGRATED_MARKER).is_file() {
+    let _ = std::fs::copy(old.join(CONFIG_ROOT_MIGRATED_MARKER), new_root.join(CONFIG_ROOT_MIGRATED_MARKER));
+  }
+  std::fs::write(new_root.join(CONFIG_ROOT_MOVED_FROM_FILE), old.to_string_lossy().as_bytes())
+    .map_err(|e| e.to_string())?;
+
+  let redirect = default_root.join(CONFIG_ROOT_REDIRECT_FILE);
+  let written = if new_root == default_root {
+    std::fs::remove_file(&redirect).or_else(|e| if e.kind() == ErrorKind::NotFound { Ok(()) } else { Err(e) })
+  } else {
+    std::fs::create_dir_all(&default_root).and_then(|_| std::fs::write(&redirect, new_root.to_string_lossy().as_bytes()))
+  };
+  written.map_err(|e| format!("Failed to update {}: {}", redirect.display(), e))?;
+  std::fs::write(old.join(CONFIG_ROOT_MOVING_TO_FILE), new_root.to_string_lossy().as_bytes())
+    .map_err(|e| e.to_string())?;
+  host_log(
+    LogLevel::Info,
+    &format!("Config root set to {} (from {}); relaunch to apply", new_root.display(), old.display()),
+  );
+  Ok(())
+}
+
+pub(crate) fn legacy_config_roots(config_root: &PathBuf) -> Vec<PathBuf> {
+  let mut out = Vec::new();
+  // Escape hatch for ids we don't know about (e.g. custom builds).
+  if let Ok(p) = std::env::var("MOONDREAM_LEGACY_CONFIG_DIR") {
+    if !p.trim().is_empty() {
+      out.push(PathBuf::from(p.trim()));
+    }
+  }
+  if let Some(parent) = config_root.parent() {
+    for id in LEGACY_BUNDLE_IDS {
+      out.push(parent.join(id));
+    }
+  }
+  out.retain(|p| p != config_root);
+  out
+}
+
+pub(crate) fn rebase_path(p: &str, from: &PathBuf, to: &PathBuf) -> String {
+  match PathBuf::from(p).strip_prefix(from) {
+    Ok(rest) => to.join(rest).to_string_lossy().to_string(),
+    Err(_) => p.to_string(),
+  }
+}
+
+pub(crate) fn migrate_legacy_config_root(config_root: &PathBuf) {
+  let marker = config_root.join(CONFIG_ROOT_MIGRATED_MARKER);
+  if marker.exists() {
+    return;
+  }
+
+  let mut note = "no legacy config root found".to_string();
+  // Only adopt an old root when the current one has no settings of its own.
+  if !config_root.join("settings.json").exists() {
+    // A root whose settings.json doesn't parse is skipped rather than adopted as defaults.
+    let old = legacy_config_roots(config_root).into_iter().find_map(|p| {
+      if !p.join("settings.json").is_file() {
+        return None;
+      }
+      match try_read_settings(&p) {
+        Ok(settings) => Some((p, settings)),
+        Err(e) => {
+          host_log(LogLevel::Warn, &format!("Not adopting legacy config root: {}", e));
+          None
+        }
+      }
+    });
+    if let Some((old, mut settings)) = old {
+      // Paths inside the old root (local library, pending migrations) move with it.
+      if let Some(st) = settings.storage.as_mut() {
+        st.icloud_path = st.icloud_path.as_deref().map(|p| rebase_path(p, &old, config_root));
+        if let Some(mig) = st.migration.as_mut() {
+          mig.from = rebase_path(&mig.from, &old, config_root);
+          mig.to = rebase_path(&mig.to, &old, config_root);
+        }
+      }
+
+      // Data first: settings.json and the marker are what make the old root "adopted", so a
+      // failed move leaves neither behind and the next launch tries again.
+      let old_data = old.join("data");
+      let new_data = config_root.join("data");
+      note = if !old_data.is_dir() {
+        format!("migrated settings from {}", old.display())
+      } else if is_dir_empty(&new_data) {
+        let _ = std::fs::remove_dir(&new_data);
+        if let Err(e) = move_dir(&old_data, &new_data) {
+          host_log(LogLevel::Error, &format!("Couldn't move {} to {}: {}", old_data.display(), new_data.display(), e));
+          return;
+        }
+        format!("migrated settings and data from {}", old.display())
+      } else {
+        format!(
+          "migrated settings from {}; {} left in place ({} isn't empty)",
+          old.display(),
+          old_data.display(),
+          new_data.display()
+        )
+      };
+      if let Err(e) = write_settings(config_root, &settings) {
+        host_log(LogLevel::Error, &format!("Couldn't adopt legacy config root {}: {}", old.display(), e));
+        return;
+      }
+    }
+  }
+  let _ = std::fs::write(marker, note);
+}
+
+// Safe mode: `MOONDREAM_SAFE_MODE=1` or `--safe-mode`. Brings up only the server, with default
+// settings and the library at `config_root/data`, so bad settings can be fixed from the UI.
+// (Holding a modifier key at launch would need native event APIs Tauri 1 doesn't expose.)
+pub(crate) const SAFE_MODE_ARG: &str = "--safe-mode";
+
+pub(crate) fn safe_mode_requested() -> bool {
+  let env = std::env::var("MOONDREAM_SAFE_MODE").unwrap_or_default();
+  matches!(env.trim(), "1" | "true" | "yes") || std::env::args().any(|a| a == SAFE_MODE_ARG)
+}
+
+// settings.json as the running session sees it (defaults in safe mode).
+pub(crate) fn effective_settings(state: &ServerState, config_root: &PathBuf) -> AppSettings {
+  if *state.safe_mode.lock().unwrap() {
+    AppSettings::default()
+  } else {
+    read_settings(config_root)
+  }
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct PendingChange {
+  pub(crate) key: String,
+  pub(crate) applies_on: String, // "restart_server" | "restart_worker" | "relaunch"
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct RestartStatus {
+  pub(crate) required: bool,
+  pub(crate) relaunch_required: bool,
+  pub(crate) changes: Vec<PendingChange>,
+}
+
+// Which process picks each setting up, and when (anything not listed applies live or per call).
+pub(crate) enum AppliedBy {
+  Server,
+  Worker,
+  Launch,
+}
+
+// (settings key, what applies it, the setting's current value rendered for comparison)
+pub(crate) type RestartKey = (&'static str, AppliedBy, fn(&AppSettings) -> String);
+
+pub(crate) fn restart_keys() -> Vec<RestartKey> {
+  fn json<T: Serialize>(v: &T) -> String {
+    serde_json::to_string(v).unwrap_or_default()
+  }
+  fn server(s: &AppSettings) -> ServerSettings {
+    s.server.clone().unwrap_or_default()
+  }
+  fn storage(s: &AppSettings) -> Option<StorageSettings> {
+    s.storage.clone()
+  }
+  fn logging(s: &AppSettings) -> LoggingSettings {
+    s.logging.clone().unwrap_or_default()
+  }
+  fn ai(s: &AppSettings) -> Option<AiSettings> {
+    s.ai.clone()
+  }
+  vec![
+    ("server.transport", AppliedBy::Server, |s| json(&server(s).transport)),
+    ("server.port_range", AppliedBy::Server, |s| json(&server(s).port_range)),
+    ("server.node_source", AppliedBy::Server, |s| json(&server(s).node_source)),
+    ("server.ready_path", AppliedBy::Server, |s| json(&server(s).ready_path)),
+    ("server.log_level", AppliedBy::Server, |s| json(&server_log_level(s).as_str())),
+    ("ai.provider", AppliedBy::Worker, |s| json(&ai(s).and_then(|a| a.provider))),
+    ("ai.endpoint", AppliedBy::Worker, |s| json(&ai(s).and_then(|a| a.endpoint))),
+    ("ai.hf_token", AppliedBy::Worker, |s| json(&ai(s).and_then(|a| a.hf_token))),
+    ("worker.concurrency", AppliedBy::Worker, |s| json(&worker_concurrency(s))),
+    ("worker.process_since", AppliedBy::Worker, |s| json(&s.worker.as_ref().and_then(|w| w.process_since.clone()))),
+    ("storage.mode", AppliedBy::Launch, storage_mode),
+    ("storage.icloud_path", AppliedBy::Launch, |s| json(&storage(s).and_then(|st| st.icloud_path))),
+    ("storage.db_location", AppliedBy::Launch, |s| json(&db_location_local(s))),
+    ("server.status_port", AppliedBy::Launch, |s| json(&server(s).status_port)),
+    ("logging.level", AppliedBy::Launch, |s| json(&logging(s).level)),
+    ("logging.format", AppliedBy::Launch, |s| json(&logging(s).format)),
+    ("ui.locale", AppliedBy::Launch, |s| json(&menu_locale(s))),
+    ("ui.native_file_drop", AppliedBy::Launch, |s| json(&s.ui.as_ref().and_then(|u| u.native_file_drop))),
+    ("ui.theme", AppliedBy::Launch, |s| json(&native_theme(s).map(|t| format!("{:?}", t)))),
+    ("ui.hardware_acceleration", AppliedBy::Launch, |s| json(&s.ui.as_ref().and_then(|u| u.hardware_acceleration))),
+  ]
+}
+
+#[tauri::command]
+pub(crate) fn restart_required(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<RestartStatus, String> {
+  let config_root = managed_config_root(&app, &state)?;
+  let current = effective_settings(&state, &config_root);
+  let launch = state.launch_settings.lock().unwrap().clone();
+  let server = state.server_settings.lock().unwrap().clone();
+  let worker = state.worker_settings.lock().unwrap().clone();
+
+  let mut changes = Vec::new();
+  for (key, by, value) in restart_keys() {
+    // A part that isn't running has nothing stale to restart.
+    let (applied, applies_on) = match by {
+      AppliedBy::Server => (server.as_ref(), "restart_server"),
+      // restart_server only respawns a worker that's down; restart_worker replaces a running one.
+      AppliedBy::Worker => (worker.as_ref(), "restart_worker"),
+      AppliedBy::Launch => (launch.as_ref(), "relaunch"),
+    };
+    if let Some(applied) = applied {
+      if value(applied) != value(&current) {
+        changes.push(PendingChange {
+          key: key.to_string(),
+          applies_on: applies_on.to_string(),
+        });
+      }
+    }
+  }
+  Ok(RestartStatus {
+    required: !changes.is_empty(),
+    relaunch_required: changes.iter().any(|c| c.applies_on == "relaunch"),
+    changes,
+  })
+}
+
+#[tauri::command]
+pub(crate) fn relaunch_app(app: tauri::AppHandle) {
+  // restart() exits without going through CloseRequested, so stop the children here first.
+  // The new process gets the same arguments (e.g. --safe-mode).
+  let state = app.state::<ServerState>();
+  let settings = state.config_root.lock().unwrap().clone().map(|c| read_settings(&c)).unwrap_or_default();
+  stop_services_gracefully(&state, shutdown_grace(&settings));
+  if let Some(mut station) = state.station.lock().unwrap().take() {
+    let _ = station.kill();
+  }
+  app.restart();
+}
+
+#[tauri::command]
+pub(crate) fn safe_mode(state: tauri::State<ServerState>) -> bool {
+  *state.safe_mode.lock().unwrap()
+}
+
+// Passed by the login item so an autostart launch can be told apart from a normal one.
+pub(crate) const AUTOSTART_ARG: &str = "--autostart";
+
+pub(crate) fn launched_at_login() -> bool {
+  std::env::args().any(|a| a == AUTOSTART_ARG)
+}
+
+pub(crate) fn autostart_exe() -> Result<String, String> {
+  std::env::current_exe()
+    .map(|p| p.to_string_lossy().to_string())
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn login_item_path(identifier: &str) -> Option<PathBuf> {
+  let home = std::env::var("HOME").ok()?;
+  Some(
+    PathBuf::from(home)
+      .join("Library")
+      .join("LaunchAgents")
+      .join(format!("{}.plist", identifier)),
+  )
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn set_login_item(identifier: &str, enabled: bool) -> Result<(), String> {
+  let path = login_item_path(identifier).ok_or_else(|| "Missing HOME".to_string())?;
+  if !enabled {
+    if path.exists() {
+      std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+    }
+    return Ok(());
+  }
+  let exe = autostart_exe()?
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;");
+  let plist = format!(
+    r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{}</string>
+  <key>ProgramArguments</key>
+  <array>
+    <string>{}</string>
+    <string>{}</string>
+  </array>
+  <key>RunAtLoad</key>
+  <true/>
+</dict>
+</plist>
+"#,
+    identifier, exe, AUTOSTART_ARG
+  );
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+  }
+  std::fs::write(&path, plist).map_err(|e| e.to_string())
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn login_item_enabled(identifier: &str) -> bool {
+  login_item_path(identifier).map(|p| p.exists()).unwrap_or(false)
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) const WINDOWS_RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+
+#[cfg(target_os = "windows")]
+pub(crate) fn set_login_item(identifier: &str, enabled: bool) -> Result<(), String> {
+  let mut cmd = Command::new("reg");
+  if enabled {
+    let value = format!("\"{}\" {}", autostart_exe()?, AUTOSTART_ARG);
+    cmd.args(["add", WINDOWS_RUN_KEY, "/v", identifier, "/t", "REG_SZ", "/d", &value, "/f"]);
+  } else {
+    cmd.args(["delete", WINDOWS_RUN_KEY, "/v", identifier, "/f"]);
+  }
+  let status = quiet_status(&mut cmd).map_err(|e| e.to_string())?;
+  // `reg delete` fails when the value is already gone, which is fine.
+  if status.success() || login_item_enabled(identifier) == enabled {
+    Ok(())
+  } else {
+    Err(format!("reg.exe exited with {}", status))
+  }
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) fn login_item_enabled(identifier: &str) -> bool {
+  quiet_status(Command::new("reg").args(["query", WINDOWS_RUN_KEY, "/v", identifier]))
+    .map(|s| s.success())
+    .unwrap_or(false)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+pub(crate) fn login_item_path(identifier: &str) -> Option<PathBuf> {
+  // XDG autostart spec: $XDG_CONFIG_HOME/autostart (default ~/.config/autostart).
+  let config = std::env::var("XDG_CONFIG_HOME")
+    .ok()
+    .filter(|s| !s.trim().is_empty())
+    .map(PathBuf::from)
+    .or_else(|| std::env::var("HOME").ok().map(|h| PathBuf::from(h).join(".config")))?;
+  Some(config.join("autostart").join(format!("{}.desktop", identifier)))
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+pub(crate) fn set_login_item(identifier: &str, enabled: bool) -> Result<(), String> {
+  let path = login_item_path(identifier).ok_or_else(|| "Missing HOME".to_string())?;
+  if !enabled {
+    if path.exists() {
+      std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+    }
+    return Ok(());
+  }
+  let entry = format!(
+    "[Desktop Entry]\nType=Application\nName=Reference\nExec=\"{}\" {}\nX-GNOME-Autostart-enabled=true\n",
+    autostart_exe()?,
+    AUTOSTART_ARG
+  );
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+  }
+  std::fs::write(&path, entry).map_err(|e| e.to_string())
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+pub(crate) fn login_item_enabled(identifier: &str) -> bool {
+  login_item_path(identifier).map(|p| p.exists()).unwrap_or(false)
+}
+
+#[tauri::command]
+pub(crate) fn set_autostart(app: tauri::AppHandle, enabled: bool) -> Result<(), String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let mut settings = try_read_settings(&config_root)?;
+  let identifier = app.config().tauri.bundle.identifier.clone();
+  set_login_item(&identifier, enabled)?;
+
+  settings.behavior.get_or_insert_with(Default::default).autostart = Some(enabled);
+  write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+#[tauri::command]
+pub(crate) fn get_autostart(app: tauri::AppHandle) -> bool {
+  // The OS login item is the source of truth (users can remove it outside the app).
+  login_item_enabled(&app.config().tauri.bundle.identifier)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn config_root_move_merges_and_settings_wait_for_relaunch() {
+    let dir = scratch_dir("config-root-move");
+    let (old, new) = (dir.join("old"), dir.join("new"));
+    std::fs::create_dir_all(old.join("logs")).unwrap();
+    std::fs::create_dir_all(old.join("profiles")).unwrap();
+    std::fs::create_dir_all(new.join("logs")).unwrap();
+    std::fs::write(old.join("logs/host.log"), b"old").unwrap();
+    std::fs::write(old.join("logs/host.log.1"), b"older").unwrap();
+    std::fs::write(new.join("logs/host.log"), b"new").unwrap();
+    std::fs::write(old.join("profiles/work.json"), b"{}").unwrap();
+    std::fs::write(old.join("runtime.json"), b"{}").unwrap();
+    std::fs::write(old.join("settings.json"), b"{}").unwrap();
+    std::fs::write(old.join(CONFIG_ROOT_MOVING_TO_FILE), new.to_string_lossy().as_bytes()).unwrap();
+    assert!(write_settings(&old, &AppSettings::default()).is_err());
+    std::fs::write(new.join(CONFIG_ROOT_MOVED_FROM_FILE), old.to_string_lossy().as_bytes()).unwrap();
+
+    finish_config_root_move(&new);
+    assert_eq!(std::fs::read(new.join("logs/host.log")).unwrap(), b"new");
+    assert!(new.join("logs/host.log.1").is_file() && new.join("profiles/work.json").is_file());
+    assert!(new.join("runtime.json").is_file() && !old.join("runtime.json").exists());
+    assert!(!new.join(CONFIG_ROOT_MOVED_FROM_FILE).exists() && !old.join(CONFIG_ROOT_MOVING_TO_FILE).exists());
+    assert!(old.join("settings.json.moved").is_file());
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn legacy_settings_move_into_sections() {
+    let mut value = serde_json::json!({
+      "storageMode": "icloud",
+      "aiEndpoint": "http://127.0.0.1:2020/v1",
+      "ai": { "provider": "local_station" },
+      "server": { "nodeSource": "system" }
+    });
+    let notes = migrate_legacy_settings(&mut value);
+    assert_eq!(notes.len(), 3, "{:?}", notes);
+    assert!(value.get("storageMode").is_none());
+    assert!(value.get("aiEndpoint").is_none());
+    let settings: AppSettings = serde_json::from_value(value).unwrap();
+    assert_eq!(settings.storage.and_then(|s| s.mode).as_deref(), Some("icloud"));
+    let ai = settings.ai.unwrap();
+    assert_eq!(ai.endpoint.as_deref(), Some("http://127.0.0.1:2020/v1"));
+    assert_eq!(ai.provider.as_deref(), Some("local_station"));
+    assert_eq!(settings.server.and_then(|s| s.node_source).as_deref(), Some("system"));
+  }
+
+  #[test]
+  fn legacy_settings_never_override_current_keys() {
+    let mut value = serde_json::json!({ "aiProvider": "huggingface", "ai": { "provider": "local_station" } });
+    let notes = migrate_legacy_settings(&mut value);
+    assert_eq!(notes, vec!["dropped aiProvider (ai.provider is already set)".to_string()]);
+    assert_eq!(value["ai"]["provider"], serde_json::json!("local_station"));
+    assert!(value.get("aiProvider").is_none());
+  }
+
+  #[test]
+  fn current_settings_need_no_migration() {
+    let mut value = serde_json::json!({
+      "ai": { "provider": "local_station" },
+      "layouts": { "myLayout": { "x": 0, "y": 0, "width": 800, "height": 600 } }
+    });
+    let before = value.clone();
+    assert!(migrate_legacy_settings(&mut value).is_empty());
+    assert_eq!(value, before);
+  }
+}
@@ -0,0 +1,2500 @@
+use crate::*;
+
+pub(crate) fn default_icloud_dir() -> Option<PathBuf> {
+  let home = std::env::var("HOME").ok()?;
+  let root = PathBuf::from(home)
+    .join("Library")
+    .join("Mobile Documents")
+    .join("com~apple~CloudDocs");
+  Some(icloud_library_in(&root))
+}
+
+// The library folder inside an iCloud Drive root.
+pub(crate) fn icloud_library_in(root: &PathBuf) -> PathBuf {
+  // Branding change: default to "Reference", but keep compatibility with existing installs
+  // that may already have data under the previous folder name.
+  let new = root.join("Reference");
+  let old = root.join("Moondream");
+  if old.exists() && !new.exists() {
+    return old;
+  }
+  new
+}
+
+// iCloud Drive roots this user can write to: `*~CloudDocs` containers under Mobile Documents
+// (normally just com~apple~CloudDocs) and File Provider iCloud mounts under CloudStorage.
+pub(crate) fn icloud_roots() -> Vec<PathBuf> {
+  let Some(home) = std::env::var_os("HOME").map(PathBuf::from) else {
+    return Vec::new();
+  };
+  let library = home.join("Library");
+  let mut roots = Vec::new();
+  for (dir, matches) in [
+    (library.join("Mobile Documents"), (|n: &str| n.ends_with("~CloudDocs")) as fn(&str) -> bool),
+    (library.join("CloudStorage"), |n: &str| n.starts_with("iCloud")),
+  ] {
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+      continue;
+    };
+    for entry in entries.flatten() {
+      let path = entry.path();
+      if matches(&entry.file_name().to_string_lossy()) && path.is_dir() && dir_writable(&path) {
+        roots.push(path);
+      }
+    }
+  }
+  roots.sort();
+  roots
+}
+
+#[tauri::command]
+pub(crate) fn list_icloud_roots() -> Vec<String> {
+  icloud_roots().iter().map(|r| r.to_string_lossy().to_string()).collect()
+}
+
+// Points `storage.icloud_path` at the library folder inside `root` (one of `list_icloud_roots`).
+// If the library is in iCloud now, a `storage.migration` moves it there on the next launch.
+#[tauri::command]
+pub(crate) fn select_icloud_root(app: tauri::AppHandle, state: tauri::State<ServerState>, root: String) -> Result<String, String> {
+  let root = PathBuf::from(root.trim());
+  if !icloud_roots().contains(&root) {
+    return Err(format!("{} isn't an available, writable iCloud Drive folder.", root.display()));
+  }
+  let config_root = managed_config_root(&app, &state)?;
+  let library = icloud_library_in(&root);
+  let mut settings = try_read_settings(&config_root)?;
+  let in_icloud = storage_mode(&settings) == "icloud";
+  let current = state.data_dir.lock().unwrap().clone();
+  let storage = settings.storage.get_or_insert(StorageSettings {
+    mode: None,
+    icloud_path: None,
+    migration: None,
+    pause_worker_when_offline: None,
+    db_location: None,
+  });
+  storage.icloud_path = Some(library.to_string_lossy().to_string());
+  if let Some(from) = current.filter(|c| in_icloud && c != &library && c.exists()) {
+    storage.migration = Some(MigrationSettings {
+      from: from.to_string_lossy().to_string(),
+      to: library.to_string_lossy().to_string(),
+      requested_at: Some(sqlite_datetime(unix_now_secs() as i64)),
+      in_progress: None,
+      backup_existing: None,
+      error: None,
+    });
+  }
+  write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+  Ok(library.to_string_lossy().to_string())
+}
+
+pub(crate) fn is_dir_empty(p: &PathBuf) -> bool {
+  match std::fs::read_dir(p) {
+    Ok(mut it) => it.next().is_none(),
+    Err(_) => true,
+  }
+}
+
+pub(crate) fn copy_dir_all(from: &PathBuf, to: &PathBuf) -> io::Result<()> {
+  copy_dir_progress(from, to, &mut |_, _| {})
+}
+
+// `on_file` gets each source file and its size once it's in place (copied or already there).
+pub(crate) fn copy_dir_progress(from: &PathBuf, to: &PathBuf, on_file: &mut dyn FnMut(&PathBuf, u64)) -> io::Result<()> {
+  std::fs::create_dir_all(to)?;
+  for entry in std::fs::read_dir(from)? {
+    let entry = entry?;
+    let ft = entry.file_type()?;
+    let src = entry.path();
+    let dst = to.join(entry.file_name());
+    if ft.is_dir() {
+      copy_dir_progress(&src, &dst, on_file)?;
+    } else if ft.is_file() {
+      let meta = entry.metadata()?;
+      // Resumable: files finished by an earlier (interrupted) run already match size + mtime.
+      if already_copied(&meta, &dst) {
+        on_file(&src, meta.len());
+        continue;
+      }
+      std::fs::create_dir_all(dst.parent().unwrap_or(to))?;
+      copy_file_preserving_mtime(&src, &dst, &meta)?;
+      on_file(&src, meta.len());
+    }
+  }
+  Ok(())
+}
+
+pub(crate) fn copy_file_preserving_mtime(src: &PathBuf, dst: &PathBuf, meta: &std::fs::Metadata) -> io::Result<()> {
+  std::fs::copy(src, dst)?;
+  // Preserve mtime so `already_copied` recognizes this file next time.
+  if let Ok(mtime) = meta.modified() {
+    if let Ok(f) = OpenOptions::new().write(true).open(dst) {
+      let _ = f.set_modified(mtime);
+    }
+  }
+  Ok(())
+}
+
+pub(crate) fn already_copied(src_meta: &std::fs::Metadata, dst: &PathBuf) -> bool {
+  let Ok(dst_meta) = std::fs::metadata(dst) else {
+    return false;
+  };
+  dst_meta.is_file()
+    && dst_meta.len() == src_meta.len()
+    && matches!((src_meta.modified(), dst_meta.modified()), (Ok(a), Ok(b)) if a == b)
+}
+
+pub(crate) fn move_dir(from: &PathBuf, to: &PathBuf) -> io::Result<()> {
+  // Fast path: same volume rename.
+  if std::fs::rename(from, to).is_ok() {
+    return Ok(());
+  }
+  // Fallback: recursive copy + delete.
+  copy_dir_all(from, to)?;
+  std::fs::remove_dir_all(from)?;
+  Ok(())
+}
+
+// One step of a library move, as sent to `start_migration_stream` subscribers.
+#[derive(Clone, Serialize, Default)]
+pub(crate) struct MigrationEvent {
+  pub(crate) seq: u64,
+  pub(crate) phase: String, // "scanning" | "copying" | "verifying" | "done" | "error"
+  pub(crate) files_done: u64,
+  pub(crate) files_total: u64,
+  pub(crate) bytes_done: u64,
+  pub(crate) bytes_total: u64,
+  pub(crate) file: Option<String>, // relative to the library root
+  pub(crate) error: Option<String>,
+}
+
+impl MigrationEvent {
+  pub(crate) fn phase(phase: &str) -> Self {
+    MigrationEvent {
+      phase: phase.to_string(),
+      ..Default::default()
+    }
+  }
+}
+
+#[derive(Default)]
+pub(crate) struct MigrationStream {
+  pub(crate) subscribed: bool,
+  pub(crate) next_seq: u64,
+  // The current (or last) migration, latest event per phase; replayed to late subscribers.
+  pub(crate) events: Vec<MigrationEvent>,
+}
+
+pub(crate) const MIGRATION_EVENT: &str = "moondream://migration";
+
+pub(crate) const MIGRATION_EVENT_INTERVAL: Duration = Duration::from_millis(250);
+
+pub(crate) fn emit_migration_event(app: &tauri::AppHandle, mut ev: MigrationEvent) {
+  let state = app.state::<ServerState>();
+  let subscribed = {
+    let mut stream = state.migration_stream.lock().unwrap();
+    let finished = |e: &MigrationEvent| e.phase == "done" || e.phase == "error";
+    if stream.events.last().map(finished).unwrap_or(false) {
+      // A new migration: the previous one's events are history.
+      stream.events.clear();
+    }
+    stream.next_seq += 1;
+    ev.seq = stream.next_seq;
+    if stream.events.last().map(|e| e.phase == ev.phase).unwrap_or(false) {
+      stream.events.pop();
+    }
+    stream.events.push(ev.clone());
+    stream.subscribed
+  };
+  if subscribed {
+    let _ = app.emit_all(MIGRATION_EVENT, ev);
+  }
+}
+
+// Subscribes to `moondream://migration` events; returns what already happened, so a screen that
+// opens mid-migration (or after it ended) starts from the right phase.
+#[tauri::command]
+pub(crate) fn start_migration_stream(state: tauri::State<ServerState>) -> Vec<MigrationEvent> {
+  let mut stream = state.migration_stream.lock().unwrap();
+  stream.subscribed = true;
+  stream.events.clone()
+}
+
+#[tauri::command]
+pub(crate) fn stop_migration_stream(state: tauri::State<ServerState>) {
+  state.migration_stream.lock().unwrap().subscribed = false;
+}
+
+// Like `move_dir`, reporting scanning/copying/verifying as it goes (throttled to
+// MIGRATION_EVENT_INTERVAL). Ending the stream with done/error is up to the caller.
+pub(crate) fn move_dir_streamed(from: &PathBuf, to: &PathBuf, emit: &mut dyn FnMut(MigrationEvent)) -> io::Result<()> {
+  emit(MigrationEvent::phase("scanning"));
+  if std::fs::rename(from, to).is_ok() {
+    return Ok(());
+  }
+  let mut files = Vec::new();
+  collect_files(from, &mut files)?;
+  let sizes: Vec<u64> = files
+    .iter()
+    .map(|f| std::fs::metadata(f).map(|m| m.len()).unwrap_or(0))
+    .collect();
+  let files_total = files.len() as u64;
+  let bytes_total: u64 = sizes.iter().sum();
+  emit(MigrationEvent {
+    files_total,
+    bytes_total,
+    ..MigrationEvent::phase("scanning")
+  });
+
+  let relative = |f: &PathBuf| f.strip_prefix(from).unwrap_or(f).to_string_lossy().to_string();
+  let (mut files_done, mut bytes_done) = (0u64, 0u64);
+  let mut last_emit: Option<Instant> = None;
+  copy_dir_progress(from, to, &mut |file, bytes| {
+    files_done += 1;
+    bytes_done += bytes;
+    if last_emit.map(|t| t.elapsed() >= MIGRATION_EVENT_INTERVAL).unwrap_or(true) || files_done == files_total {
+      emit(MigrationEvent {
+        files_done,
+        files_total,
+        bytes_done,
+        bytes_total,
+        file: Some(relative(file)),
+        ..MigrationEvent::phase("copying")
+      });
+      last_emit = Some(Instant::now());
+    }
+  })?;
+
+  // Nothing is deleted until every file is confirmed at the destination with the same size.
+  last_emit = None;
+  for (i, (file, size)) in files.iter().zip(&sizes).enumerate() {
+    let rel = relative(file);
+    let copied = std::fs::metadata(to.join(&rel)).map(|m| m.len()).ok();
+    if copied != Some(*size) {
+      return Err(io::Error::other(format!("{} didn't copy intact", rel)));
+    }
+    let checked = i as u64 + 1;
+    if last_emit.map(|t| t.elapsed() >= MIGRATION_EVENT_INTERVAL).unwrap_or(true) || checked == files_total {
+      emit(MigrationEvent {
+        files_done: checked,
+        files_total,
+        bytes_done: bytes_total,
+        bytes_total,
+        file: Some(rel),
+        ..MigrationEvent::phase("verifying")
+      });
+      last_emit = Some(Instant::now());
+    }
+  }
+  std::fs::remove_dir_all(from)?;
+  Ok(())
+}
+
+// Closes a migration stream started by `move_dir_streamed`, whatever happened.
+pub(crate) fn finish_migration_stream(emit: &mut dyn FnMut(MigrationEvent), result: &io::Result<()>) {
+  match result {
+    Ok(()) => emit(MigrationEvent::phase("done")),
+    Err(e) => emit(MigrationEvent {
+      error: Some(e.to_string()),
+      ..MigrationEvent::phase("error")
+    }),
+  }
+}
+
+// `<name>-backup-<unix secs>`, with `-2`, `-3`, … appended until the name is free, so two
+// migrations in the same second (or a clock stuck before 1970, which reads as 0) never collide.
+pub(crate) fn migration_backup_path(config_root: &PathBuf, to: &PathBuf) -> PathBuf {
+  let ts = match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+    Ok(d) => d.as_secs(),
+    Err(e) => {
+      host_log(LogLevel::Warn, &format!("System clock is before 1970 ({}); backup names use 0", e));
+      0
+    }
+  };
+  let name = to
+    .file_name()
+    .and_then(|s| s.to_str())
+    .unwrap_or("data")
+    .to_string();
+  let parent = to.parent().unwrap_or(config_root);
+  let base = format!("{}-backup-{}", name, ts);
+  let mut candidate = parent.join(&base);
+  let mut n = 2u32;
+  while candidate.exists() {
+    candidate = parent.join(format!("{}-{}", base, n));
+    n += 1;
+  }
+  candidate
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct MigrationPlan {
+  pub(crate) from: String,
+  pub(crate) to: String,
+  // The destination already has files in it.
+  pub(crate) destination_non_empty: bool,
+  pub(crate) backup_existing: bool,
+  // Where the existing destination would be moved (when it's non-empty and backups are on).
+  pub(crate) backup_path: Option<String>,
+  // Set when the migration will not run as configured.
+  pub(crate) blocked_reason: Option<String>,
+  // The destination differs in case sensitivity (see `case_change_warning`).
+  pub(crate) case_warning: Option<String>,
+  pub(crate) last_error: Option<String>,
+}
+
+// What `apply_pending_migration` will do on the next launch, without doing it.
+#[tauri::command]
+pub(crate) fn plan_migration(app: tauri::AppHandle) -> Result<Option<MigrationPlan>, String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let settings = read_settings(&config_root);
+  let Some(mig) = settings.storage.as_ref().and_then(|s| s.migration.as_ref()) else {
+    return Ok(None);
+  };
+  let to = PathBuf::from(&mig.to);
+  let resuming = mig.in_progress.unwrap_or(false);
+  let backup_existing = mig.backup_existing.unwrap_or(true);
+  let destination_non_empty = to.exists() && !is_dir_empty(&to);
+  let needs_backup = !resuming && destination_non_empty;
+  let case_warning = case_change_warning(&PathBuf::from(&mig.from), &to);
+  Ok(Some(MigrationPlan {
+    from: mig.from.clone(),
+    to: mig.to.clone(),
+    destination_non_empty,
+    backup_existing,
+    backup_path: if needs_backup && backup_existing {
+      Some(migration_backup_path(&config_root, &to).to_string_lossy().to_string())
+    } else {
+      None
+    },
+    blocked_reason: if needs_backup && !backup_existing {
+      Some(format!("{} is not empty and backups are off", to.display()))
+    } else if !PathBuf::from(&mig.from).exists() {
+      Some(format!("{} no longer exists", mig.from))
+    } else if let Some((msg, true)) = case_warning.as_ref() {
+      Some(msg.clone())
+    } else {
+      None
+    },
+    case_warning: case_warning.map(|(msg, _)| msg),
+    last_error: mig.error.clone(),
+  }))
+}
+
+pub(crate) fn apply_pending_migration(
+  config_root: &PathBuf,
+  settings: &mut AppSettings,
+  emit: &mut dyn FnMut(MigrationEvent),
+) -> Option<PathBuf> {
+  let save = |settings: &AppSettings| {
+    if let Err(e) = write_settings(config_root, settings) {
+      host_log(LogLevel::Error, &format!("Migration: {}", e));
+    }
+  };
+  let mig = settings.storage.as_ref().and_then(|s| s.migration.as_ref())?;
+  let from = PathBuf::from(mig.from.clone());
+  let to = PathBuf::from(mig.to.clone());
+  // Set once the move has started; a partial destination after a crash is ours, not user data.
+  let resuming = mig.in_progress.unwrap_or(false);
+  if from == to {
+    // Nothing to do.
+    if let Some(st) = settings.storage.as_mut() {
+      st.migration = None;
+    }
+    save(settings);
+    return None;
+  }
+
+  // If source doesn't exist, clear and continue.
+  if !from.exists() {
+    if let Some(st) = settings.storage.as_mut() {
+      st.migration = None;
+    }
+    save(settings);
+    return None;
+  }
+
+  if !resuming {
+    if let Some((msg, true)) = case_change_warning(&from, &to) {
+      host_log(LogLevel::Error, &format!("Migration aborted: {}", msg));
+      if let Some(mig) = settings.storage.as_mut().and_then(|s| s.migration.as_mut()) {
+        mig.error = Some(msg);
+      }
+      save(settings);
+      return Some(from);
+    }
+  }
+
+  // If destination exists and is not empty, back it up before moving in (or refuse, if opted out).
+  if !resuming && to.exists() && !is_dir_empty(&to) {
+    if !mig.backup_existing.unwrap_or(true) {
+      let msg = format!(
+        "{} is not empty and storage.migration.backup_existing is off; empty it or allow the backup.",
+        to.display()
+      );
+      host_log(LogLevel::Error, &format!("Migration aborted: {}", msg));
+      if let Some(mig) = settings.storage.as_mut().and_then(|s| s.migration.as_mut()) {
+        mig.error = Some(msg);
+      }
+      save(settings);
+      return Some(from);
+    }
+    let backup = migration_backup_path(config_root, &to);
+    // Moving into a destination we failed to clear would mix two libraries.
+    if let Err(e) = std::fs::rename(&to, &backup) {
+      let msg = format!("Couldn't back up {} to {}: {}", to.display(), backup.display(), e);
+      host_log(LogLevel::Error, &format!("Migration aborted: {}", msg));
+      if let Some(mig) = settings.storage.as_mut().and_then(|s| s.migration.as_mut()) {
+        mig.error = Some(msg);
+      }
+      save(settings);
+      return Some(from);
+    }
+  }
+
+  if let Some(parent) = to.parent() {
+    let _ = std::fs::create_dir_all(parent);
+  }
+
+  if !resuming {
+    if let Some(mig) = settings.storage.as_mut().and_then(|s| s.migration.as_mut()) {
+      mig.in_progress = Some(true);
+    }
+    save(settings);
+  }
+
+  let moved = move_dir_streamed(&from, &to, emit);
+  finish_migration_stream(emit, &moved);
+  match moved {
+    Ok(()) => {
+      if let Some(st) = settings.storage.as_mut() {
+        st.migration = None;
+      }
+      save(settings);
+      None
+    }
+    Err(_) => {
+      // Migration failed; keep using the old location for this run so the library isn't "missing".
+      Some(from)
+    }
+  }
+}
+
+pub(crate) fn storage_mode(settings: &AppSettings) -> String {
+  settings
+    .storage
+    .as_ref()
+    .and_then(|s| s.mode.as_deref())
+    .unwrap_or("local")
+    .to_lowercase()
+}
+
+pub(crate) fn resolve_data_dir(config_root: &PathBuf, settings: &AppSettings) -> PathBuf {
+  let mode = settings
+    .storage
+    .as_ref()
+    .and_then(|s| s.mode.as_deref())
+    .unwrap_or("local")
+    .to_lowercase();
+
+  if mode == "icloud" {
+    let p = settings
+      .storage
+      .as_ref()
+      .and_then(|s| s.icloud_path.as_ref())
+      .map(PathBuf::from)
+      .or_else(default_icloud_dir);
+    if let Some(p) = p {
+      return p;
+    }
+  }
+  config_root.join("data")
+}
+
+// Closest existing ancestor (a not-yet-created library dir is judged by where it would be created).
+pub(crate) fn existing_ancestor(p: &PathBuf) -> Option<PathBuf> {
+  p.ancestors().find(|a| a.exists()).map(|a| a.to_path_buf())
+}
+
+pub(crate) fn dir_writable(p: &PathBuf) -> bool {
+  let probe = p.join(format!(".moondream-write-test-{}", std::process::id()));
+  match std::fs::write(&probe, b"ok") {
+    Ok(()) => {
+      let _ = std::fs::remove_file(&probe);
+      true
+    }
+    Err(_) => false,
+  }
+}
+
+// Cache dir name for things that must stay on this machine (see `logs_dir`).
+pub(crate) const LOCAL_CACHE_NAME: &str = "com.moondream.desktop";
+
+// iCloud Drive, or a macOS File Provider tree (Dropbox/OneDrive/Google Drive live under ~/Library/CloudStorage).
+pub(crate) fn is_synced_path(p: &PathBuf) -> bool {
+  is_icloud_path(p) || p.components().any(|c| c.as_os_str() == "CloudStorage")
+}
+
+pub(crate) fn local_cache_dir() -> PathBuf {
+  let env_dir = |k: &str| std::env::var(k).ok().filter(|v| !v.is_empty()).map(PathBuf::from);
+  let base = if cfg!(target_os = "macos") {
+    env_dir("HOME").map(|h| h.join("Library").join("Caches"))
+  } else if cfg!(target_os = "windows") {
+    env_dir("LOCALAPPDATA")
+  } else {
+    env_dir("XDG_CACHE_HOME").or_else(|| env_dir("HOME").map(|h| h.join(".cache")))
+  };
+  base.unwrap_or_else(std::env::temp_dir).join(LOCAL_CACHE_NAME)
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct AppPaths {
+  pub(crate) config_root: Option<String>,
+  pub(crate) data_dir: Option<String>,
+  pub(crate) db_path: Option<String>,
+  pub(crate) logs_dir: Option<String>,
+  // True when logs were moved out of a synced config root.
+  pub(crate) logs_local: bool,
+}
+
+#[tauri::command]
+pub(crate) fn paths(app: tauri::AppHandle, state: tauri::State<ServerState>) -> AppPaths {
+  let show = |p: &PathBuf| p.to_string_lossy().to_string();
+  let config_root = state
+    .config_root
+    .lock()
+    .unwrap()
+    .clone()
+    .or_else(|| app_config_root(&app));
+  AppPaths {
+    config_root: config_root.as_ref().map(show),
+    data_dir: state.data_dir.lock().unwrap().as_ref().map(show),
+    db_path: state.db_path.lock().unwrap().as_ref().map(show),
+    logs_dir: config_root.as_ref().map(|c| show(&logs_dir(c))),
+    logs_local: config_root.as_ref().map(is_synced_path).unwrap_or(false),
+  }
+}
+
+pub(crate) fn is_icloud_path(p: &PathBuf) -> bool {
+  p.components().any(|c| c.as_os_str() == "Mobile Documents")
+    || default_icloud_dir()
+      .and_then(|d| d.parent().map(|r| r.to_path_buf()))
+      .map(|root| p.starts_with(root))
+      .unwrap_or(false)
+}
+
+pub(crate) fn free_bytes(p: &PathBuf) -> Option<u64> {
+  if cfg!(target_os = "windows") {
+    return None;
+  }
+  // POSIX output: header line, then "<fs> <blocks> <used> <avail> <capacity> <mount>" in 1K blocks.
+  let out = Command::new("df")
+    .arg("-Pk")
+    .arg(p)
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .ok()?;
+  let text = String::from_utf8_lossy(&out.stdout);
+  let line = text.lines().nth(1)?;
+  let avail_kb = line.split_whitespace().nth(3)?.parse::<u64>().ok()?;
+  Some(avail_kb * 1024)
+}
+
+pub(crate) fn is_moondream_library(p: &PathBuf) -> bool {
+  p.join("moondream.sqlite3").is_file() || p.join("projects").is_dir()
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct LibraryCandidate {
+  pub(crate) path: String,
+  pub(crate) has_db: bool,
+  pub(crate) file_count: u64,
+  pub(crate) size_bytes: u64,
+  // Unix seconds of the most recently modified file (the likely "current" copy is the newest).
+  pub(crate) newest_mtime: Option<u64>,
+  pub(crate) is_current: bool,
+}
+
+// Dirs in `root` whose name starts with one of `prefixes` (catches "X 2", "X-backup-<ts>", …).
+pub(crate) fn sibling_dirs(root: &PathBuf, prefixes: &[String]) -> Vec<PathBuf> {
+  let Ok(entries) = std::fs::read_dir(root) else {
+    return Vec::new();
+  };
+  entries
+    .flatten()
+    .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+    .filter(|e| {
+      let name = e.file_name().to_string_lossy().to_lowercase();
+      prefixes.iter().any(|p| name.starts_with(&p.to_lowercase()))
+    })
+    .map(|e| e.path())
+    .collect()
+}
+
+pub(crate) fn library_candidate(p: &PathBuf, current: Option<&PathBuf>) -> LibraryCandidate {
+  let mut files = Vec::new();
+  let _ = collect_files(p, &mut files);
+  let metas: Vec<std::fs::Metadata> = files.iter().filter_map(|f| std::fs::metadata(f).ok()).collect();
+  LibraryCandidate {
+    path: p.to_string_lossy().to_string(),
+    has_db: db_path_for(p).is_file(),
+    file_count: metas.len() as u64,
+    size_bytes: metas.iter().map(|m| m.len()).sum(),
+    newest_mtime: metas
+      .iter()
+      .filter_map(|m| m.modified().ok())
+      .max()
+      .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+      .map(|d| d.as_secs()),
+    is_current: current == Some(p),
+  }
+}
+
+// Scans where libraries end up (app data, iCloud Drive, next to the configured folder),
+// including sync-conflict and backup copies.
+#[tauri::command]
+pub(crate) fn find_library_candidates(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<Vec<LibraryCandidate>, String> {
+  let config_root = managed_config_root(&app, &state)?;
+  let settings = read_settings(&config_root);
+  let current = state.data_dir.lock().unwrap().clone();
+
+  let mut dirs = sibling_dirs(&config_root, &["data".to_string()]);
+  let mut icloud_places: Vec<PathBuf> = Vec::new();
+  if let Some(p) = settings.storage.as_ref().and_then(|s| s.icloud_path.as_ref()) {
+    icloud_places.push(PathBuf::from(p));
+  }
+  if let Some(d) = default_icloud_dir() {
+    icloud_places.push(d.clone());
+    if let Some(root) = d.parent() {
+      icloud_places.push(root.join("Reference"));
+      icloud_places.push(root.join("Moondream"));
+    }
+  }
+  for place in icloud_places {
+    if let (Some(parent), Some(name)) = (place.parent(), place.file_name()) {
+      dirs.extend(sibling_dirs(&parent.to_path_buf(), &[name.to_string_lossy().to_string()]));
+    }
+  }
+  if let Some(c) = current.as_ref() {
+    dirs.push(c.clone());
+  }
+
+  dirs.sort();
+  dirs.dedup();
+  Ok(
+    dirs
+      .iter()
+      .filter(|d| is_moondream_library(d))
+      .map(|d| library_candidate(d, current.as_ref()))
+      .collect(),
+  )
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct ImportRequest {
+  pub(crate) path: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct ImportResult {
+  pub(crate) imported: u64,
+  pub(crate) skipped: u64,
+}
+
+// Merge = import another library into the current one; the source folder is left untouched.
+// Server contract: POST /api/library/import {"path": "<library dir>"} copies projects/assets that
+// aren't already present (matched by id) and returns {"imported": n, "skipped": n}.
+#[tauri::command(async)]
+pub(crate) fn merge_library(app: tauri::AppHandle, path: String) -> Result<ImportResult, String> {
+  let state = app.state::<ServerState>();
+  let src = PathBuf::from(path.trim());
+  if !src.is_absolute() || !is_moondream_library(&src) {
+    return Err(format!("{} is not a library", src.display()));
+  }
+  if state.data_dir.lock().unwrap().as_ref() == Some(&src) {
+    return Err("That's the current library.".to_string());
+  }
+  let addr = current_server_addr(&state).ok_or_else(|| "The local server is not running".to_string())?;
+  let token = state.session_token.lock().unwrap().clone();
+  let body = serde_json::to_string(&ImportRequest {
+    path: src.to_string_lossy().to_string(),
+  })
+  .map_err(|e| e.to_string())?;
+  match server_request(&addr, "POST", "/api/library/import", Some(&body), token.as_deref(), Duration::from_secs(600)) {
+    Some((200, body)) => serde_json::from_str::<ImportResult>(&body).map_err(|e| format!("Unexpected import response: {}", e)),
+    Some((status, body)) if route_missing(status, &body) => {
+      Err("This server build can't merge libraries (no /api/library/import); nothing was imported.".to_string())
+    }
+    Some((status, body)) => Err(format!("Import failed ({}): {}", status, body.trim())),
+    None => Err("The local server did not respond".to_string()),
+  }
+}
+
+pub(crate) const DROP_IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "heic", "heif", "avif", "tif", "tiff", "bmp"];
+
+pub(crate) const DROP_MAX_FILE_BYTES: u64 = 200 * 1024 * 1024;
+
+pub(crate) const DROP_INGEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Serialize)]
+pub(crate) struct DroppedFile {
+  pub(crate) path: String,
+  pub(crate) ok: bool,
+  // Where it was copied in the library (`inbox/`), if it got that far.
+  pub(crate) stored_path: Option<String>,
+  pub(crate) asset_id: Option<String>,
+  pub(crate) error: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct DropImportResult {
+  pub(crate) imported: u32,
+  pub(crate) failed: u32,
+  pub(crate) files: Vec<DroppedFile>,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct IngestRequest {
+  pub(crate) path: String,
+  #[serde(rename = "projectId")]
+  pub(crate) project_id: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+pub(crate) struct IngestResponse {
+  pub(crate) id: Option<String>,
+}
+
+// Regular image files only, outside the library; returns the canonical path.
+pub(crate) fn validate_dropped_file(path: &str, data_dir: &PathBuf) -> Result<PathBuf, String> {
+  let p = PathBuf::from(path.trim());
+  if !p.is_absolute() {
+    return Err("Not an absolute path".to_string());
+  }
+  let real = p.canonicalize().map_err(|e| e.to_string())?;
+  let meta = std::fs::metadata(&real).map_err(|e| e.to_string())?;
+  if !meta.is_file() {
+    return Err("Not a file".to_string());
+  }
+  let ext = real.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+  if !DROP_IMAGE_EXTENSIONS.contains(&ext.as_str()) {
+    return Err(format!("Unsupported file type \"{}\"", ext));
+  }
+  if meta.len() == 0 || meta.len() > DROP_MAX_FILE_BYTES {
+    return Err(format!("File size must be between 1 byte and {} MB", DROP_MAX_FILE_BYTES / (1024 * 1024)));
+  }
+  if data_dir.canonicalize().map(|d| real.starts_with(d)).unwrap_or(false) {
+    return Err("Already in the library".to_string());
+  }
+  Ok(real)
+}
+
+// `inbox/<ts>-<name>` with the name reduced to safe characters (never a path component).
+pub(crate) fn inbox_destination(data_dir: &PathBuf, src: &PathBuf) -> PathBuf {
+  let name: String = src
+    .file_name()
+    .map(|n| n.to_string_lossy().to_string())
+    .unwrap_or_default()
+    .chars()
+    .map(|c| if c.is_alphanumeric() || matches!(c, '.' | '-' | '_' | ' ') { c } else { '_' })
+    .collect();
+  let name = name.trim_start_matches('.').to_string();
+  let dir = data_dir.join("inbox");
+  let stamp = unix_now_secs();
+  let mut dest = dir.join(format!("{}-{}", stamp, name));
+  let mut n = 1;
+  while dest.exists() {
+    dest = dir.join(format!("{}-{}-{}", stamp, n, name));
+    n += 1;
+  }
+  dest
+}
+
+// Copies dropped images into the library's `inbox/` and asks the server to index each one.
+// Server contract: POST /api/library/ingest {"path": "<file in the library>", "projectId": id|null}
+// creates the asset (in the given project, or the default one), queues it for AI and returns
+// {"id": "<asset id>"}; it may move the file out of inbox/. Emits `moondream:import:progress`
+// ({ done, total, path, ok }) after each file. A server without the route fails the whole call.
+#[tauri::command(async)]
+pub(crate) fn import_dropped_files(app: tauri::AppHandle, paths: Vec<String>) -> Result<DropImportResult, String> {
+  let state = app.state::<ServerState>();
+  let data_dir = state.data_dir.lock().unwrap().clone().ok_or_else(|| "No library is open".to_string())?;
+  let addr = current_server_addr(&state).ok_or_else(|| "The local server is not running".to_string())?;
+  let token = state.session_token.lock().unwrap().clone();
+  let window = app.get_window("main");
+  let project_id = window.as_ref().and_then(|w| project_id_from_path(w.url().path()));
+  std::fs::create_dir_all(data_dir.join("inbox")).map_err(|e| e.to_string())?;
+
+  let total = paths.len();
+  let mut files = Vec::new();
+  let mut unsupported = false;
+  for (i, path) in paths.into_iter().enumerate() {
+    let mut file = DroppedFile {
+      path: path.clone(),
+      ok: false,
+      stored_path: None,
+      asset_id: None,
+      error: None,
+    };
+    let outcome = validate_dropped_file(&path, &data_dir).and_then(|src| {
+      let dest = inbox_destination(&data_dir, &src);
+      std::fs::copy(&src, &dest).map_err(|e| format!("Copy failed: {}", e))?;
+      file.stored_path = Some(dest.to_string_lossy().to_string());
+      let body = serde_json::to_string(&IngestRequest {
+        path: dest.to_string_lossy().to_string(),
+        project_id: project_id.clone(),
+      })
+      .map_err(|e| e.to_string())?;
+      match server_request(&addr, "POST", "/api/library/ingest", Some(&body), token.as_deref(), DROP_INGEST_TIMEOUT) {
+        Some((status, body)) if (200..300).contains(&status) => {
+          Ok(serde_json::from_str::<IngestResponse>(&body).ok().and_then(|r| r.id))
+        }
+        // Nothing would ever index the copy.
+        Some((status, body)) if route_missing(status, &body) => {
+          let _ = std::fs::remove_file(&dest);
+          unsupported = true;
+          Err(String::new())
+        }
+        // The copy stays in inbox/ so nothing the user dropped is lost.
+        Some((status, body)) => Err(format!("Indexing failed ({}): {}", status, body.trim())),
+        None => Err("The local server did not respond".to_string()),
+      }
+    });
+    if unsupported {
+      return Err("This version of the local server can't import dropped files".to_string());
+    }
+    match outcome {
+      Ok(id) => {
+        file.ok = true;
+        file.asset_id = id;
+      }
+      Err(e) => file.error = Some(e),
+    }
+    if let Some(window) = window.as_ref() {
+      dispatch_web_event(
+        window,
+        "moondream:import:progress",
+        Some(serde_json::json!({ "done": i + 1, "total": total, "path": file.path, "ok": file.ok })),
+      );
+    }
+    files.push(file);
+  }
+  let imported = files.iter().filter(|f| f.ok).count() as u32;
+  Ok(DropImportResult {
+    imported,
+    failed: files.len() as u32 - imported,
+    files,
+  })
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct ExportIssue {
+  pub(crate) path: String,
+  pub(crate) reason: String,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct ExportReport {
+  pub(crate) copied: Vec<String>,
+  pub(crate) skipped: Vec<ExportIssue>,
+  pub(crate) failed: Vec<ExportIssue>,
+}
+
+// Resolves a library-relative path, rejecting anything that escapes the data dir.
+pub(crate) fn resolve_in_data_dir(data_dir: &PathBuf, relative: &str) -> Result<PathBuf, String> {
+  let rel = std::path::Path::new(relative.trim());
+  if rel.as_os_str().is_empty()
+    || !rel.components().all(|c| matches!(c, std::path::Component::Normal(_)))
+  {
+    return Err("Not a path inside the library".to_string());
+  }
+  let root = data_dir.canonicalize().map_err(|e| e.to_string())?;
+  let full = root.join(rel).canonicalize().map_err(|e| e.to_string())?;
+  // Symlinks could still point outside; check the resolved path too.
+  if !full.starts_with(&root) {
+    return Err("Not a path inside the library".to_string());
+  }
+  if !full.is_file() {
+    return Err("Not a file".to_string());
+  }
+  Ok(full)
+}
+
+// Copies library files (paths relative to the data dir) into `dest`, flat, keeping file names.
+// Existing files in `dest` are never overwritten. Progress: `moondream:export:progress` {done, total}.
+#[tauri::command(async)]
+pub(crate) fn export_assets(app: tauri::AppHandle, relative_paths: Vec<String>, dest: String) -> Result<ExportReport, String> {
+  let state = app.state::<ServerState>();
+  let data_dir = state
+    .data_dir
+    .lock()
+    .unwrap()
+    .clone()
+    .ok_or_else(|| "No library is open".to_string())?;
+  let dest = PathBuf::from(dest.trim());
+  if !dest.is_absolute() {
+    return Err("Choose an absolute destination folder.".to_string());
+  }
+  if dest.starts_with(&data_dir) {
+    return Err("Choose a destination outside the library.".to_string());
+  }
+  std::fs::create_dir_all(&dest).map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+
+  let window = app.get_window("main");
+  let total = relative_paths.len() as u64;
+  let mut report = ExportReport {
+    copied: Vec::new(),
+    skipped: Vec::new(),
+    failed: Vec::new(),
+  };
+  for (i, rel) in relative_paths.iter().enumerate() {
+    let issue = |reason: String| ExportIssue {
+      path: rel.clone(),
+      reason,
+    };
+    match resolve_in_data_dir(&data_dir, rel) {
+      Err(e) => report.failed.push(issue(e)),
+      Ok(src) => {
+        let dst = dest.join(src.file_name().unwrap_or_default());
+        match std::fs::metadata(&src) {
+          Err(e) => report.failed.push(issue(e.to_string())),
+          Ok(meta) if already_copied(&meta, &dst) => report.skipped.push(issue("Already exported".to_string())),
+          Ok(_) if dst.exists() => report.skipped.push(issue(format!("{} already exists", dst.display()))),
+          Ok(meta) => match copy_file_preserving_mtime(&src, &dst, &meta) {
+            Ok(()) => report.copied.push(rel.clone()),
+            Err(e) => report.failed.push(issue(e.to_string())),
+          },
+        }
+      }
+    }
+    if let Some(w) = window.as_ref() {
+      dispatch_web_event(
+        w,
+        "moondream:export:progress",
+        Some(serde_json::json!({ "done": i as u64 + 1, "total": total })),
+      );
+    }
+  }
+  Ok(report)
+}
+
+pub(crate) const THUMBNAIL_PX_RANGE: (u32, u32) = (16, 4096);
+
+pub(crate) const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mov", "m4v", "webm", "mkv", "avi"];
+
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+  const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+  let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+  for chunk in bytes.chunks(3) {
+    let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+    for i in 0..4 {
+      if i <= chunk.len() {
+        out.push(TABLE[(n >> (18 - 6 * i) & 63) as usize] as char);
+      } else {
+        out.push('=');
+      }
+    }
+  }
+  out
+}
+
+// Scales `src` (first frame for GIFs/videos) to fit `max_px` and writes a PNG to `dst`,
+// using whatever the platform ships; videos need ffmpeg.
+pub(crate) fn render_thumbnail(src: &PathBuf, dst: &PathBuf, max_px: u32) -> Result<(), String> {
+  let is_video = src
+    .extension()
+    .and_then(|e| e.to_str())
+    .map(|e| VIDEO_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+    .unwrap_or(false);
+  let run = |program: &str, args: &[&std::ffi::OsStr]| -> Result<(), String> {
+    let status = quiet_status(Command::new(program).args(args))
+      .map_err(|e| format!("{} isn't available: {}", program, e))?;
+    if status.success() && dst.exists() {
+      Ok(())
+    } else {
+      Err(format!("{} couldn't make a thumbnail ({})", program, status))
+    }
+  };
+  let size = max_px.to_string();
+  if is_video {
+    let scale = format!("scale='min({0},iw)':'min({0},ih)':force_original_aspect_ratio=decrease", max_px);
+    return run(
+      "ffmpeg",
+      &["-y".as_ref(), "-loglevel".as_ref(), "error".as_ref(), "-i".as_ref(), src.as_os_str(),
+        "-frames:v".as_ref(), "1".as_ref(), "-vf".as_ref(), scale.as_ref(), dst.as_os_str()],
+    );
+  }
+  if cfg!(target_os = "macos") {
+    return run(
+      "sips",
+      &["-s".as_ref(), "format".as_ref(), "png".as_ref(), "-Z".as_ref(), size.as_ref(), src.as_os_str(),
+        "--out".as_ref(), dst.as_os_str()],
+    );
+  }
+  if cfg!(target_os = "windows") {
+    let script = format!(
+      "Add-Type -AssemblyName System.Drawing; $i=[System.Drawing.Image]::FromFile('{src}'); \
+       $s=[Math]::Min(1.0, {max}/[Math]::Max($i.Width,$i.Height)); \
+       $b=New-Object System.Drawing.Bitmap([int][Math]::Max(1,$i.Width*$s)),([int][Math]::Max(1,$i.Height*$s)); \
+       $g=[System.Drawing.Graphics]::FromImage($b); \
+       $g.InterpolationMode=[System.Drawing.Drawing2D.InterpolationMode]::HighQualityBicubic; \
+       $g.DrawImage($i,0,0,$b.Width,$b.Height); $b.Save('{dst}',[System.Drawing.Imaging.ImageFormat]::Png); \
+       $g.Dispose(); $b.Dispose(); $i.Dispose()",
+      src = src.to_string_lossy().replace('\'', "''"),
+      dst = dst.to_string_lossy().replace('\'', "''"),
+      max = max_px
+    );
+    return run("powershell", &["-NoProfile".as_ref(), "-Command".as_ref(), script.as_ref()]);
+  }
+  // ImageMagick 7 is `magick`, 6 is `convert`; `[0]` = first frame, `>` = only ever shrink.
+  let mut first_frame = src.as_os_str().to_os_string();
+  first_frame.push("[0]");
+  let geometry = format!("{0}x{0}>", max_px);
+  let args: [&std::ffi::OsStr; 4] = [&first_frame, "-thumbnail".as_ref(), geometry.as_ref(), dst.as_os_str()];
+  run("magick", &args).or_else(|_| run("convert", &args))
+}
+
+// 64-bit FNV-1a. Cache file names must stay the same across builds, which `DefaultHasher`
+// doesn't promise.
+pub(crate) fn fnv1a64(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| (h ^ *b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+// Makes temp file names unique between concurrent requests in this process.
+pub(crate) static THUMBNAIL_TMP_SEQ: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+// Thumbnails live in the local cache (never the synced library), keyed by source path, size,
+// mtime and `max_px`, so an edited source gets a new thumbnail and stale ones are just never hit.
+#[tauri::command(async)]
+pub(crate) fn thumbnail(app: tauri::AppHandle, relative_path: String, max_px: u32) -> Result<String, String> {
+  let state = app.state::<ServerState>();
+  let data_dir = state
+    .data_dir
+    .lock()
+    .unwrap()
+    .clone()
+    .ok_or_else(|| "No library is open".to_string())?;
+  let src = resolve_in_data_dir(&data_dir, &relative_path)?;
+  let meta = std::fs::metadata(&src).map_err(|e| e.to_string())?;
+  let max_px = max_px.clamp(THUMBNAIL_PX_RANGE.0, THUMBNAIL_PX_RANGE.1);
+
+  let mtime = meta
+    .modified()
+    .ok()
+    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+    .map(|d| d.as_nanos())
+    .unwrap_or(0);
+  let key = format!("{}\0{}\0{}\0{}", src.to_string_lossy(), meta.len(), mtime, max_px);
+  let hash = fnv1a64(key.as_bytes());
+  let cache_dir = local_cache_dir().join("thumbnails");
+  let cached = cache_dir.join(format!("{:016x}.png", hash));
+  if !cached.exists() {
+    std::fs::create_dir_all(&cache_dir).map_err(|e| e.to_string())?;
+    // Render beside the final name and rename, so a concurrent request never reads half a file.
+    let seq = THUMBNAIL_TMP_SEQ.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let tmp = cache_dir.join(format!("{:016x}.{}-{}.tmp.png", hash, std::process::id(), seq));
+    let rendered = render_thumbnail(&src, &tmp, max_px);
+    if let Err(e) = rendered {
+      let _ = std::fs::remove_file(&tmp);
+      return Err(e);
+    }
+    std::fs::rename(&tmp, &cached).map_err(|e| e.to_string())?;
+  }
+  let bytes = std::fs::read(&cached).map_err(|e| e.to_string())?;
+  Ok(format!("data:image/png;base64,{}", base64_encode(&bytes)))
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct DirValidation {
+  pub(crate) path: String,
+  pub(crate) exists: bool,
+  pub(crate) writable: bool,
+  pub(crate) is_moondream_library: bool,
+  pub(crate) free_bytes: Option<u64>,
+  pub(crate) on_icloud: bool,
+  pub(crate) filesystem: FsInfo,
+}
+
+pub(crate) fn validate_dir(path: &PathBuf) -> DirValidation {
+  let exists = path.is_dir();
+  let probe_dir = existing_ancestor(path);
+  DirValidation {
+    path: path.to_string_lossy().to_string(),
+    exists,
+    writable: probe_dir.as_ref().map(dir_writable).unwrap_or(false),
+    is_moondream_library: exists && is_moondream_library(path),
+    free_bytes: probe_dir.as_ref().and_then(free_bytes),
+    on_icloud: is_icloud_path(path),
+    filesystem: fs_info(path),
+  }
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct FsInfo {
+  pub(crate) path: String,
+  // None = couldn't probe (e.g. the volume is read-only).
+  pub(crate) case_sensitive: Option<bool>,
+  pub(crate) supports_symlinks: Option<bool>,
+  pub(crate) is_network: bool,
+  pub(crate) free_bytes: Option<u64>,
+}
+
+// Probes the volume `path` is (or would be created) on with real files in a scratch dir.
+pub(crate) fn fs_info(path: &PathBuf) -> FsInfo {
+  let probe_root = existing_ancestor(path);
+  let scratch = probe_root
+    .as_ref()
+    .map(|d| d.join(format!(".moondream-fs-probe-{}", std::process::id())));
+  let mut case_sensitive = None;
+  let mut supports_symlinks = None;
+  if let Some(dir) = scratch.as_ref().filter(|d| std::fs::create_dir(d).is_ok()) {
+    let upper = dir.join("A");
+    if std::fs::write(&upper, b"").is_ok() {
+      case_sensitive = Some(!dir.join("a").exists());
+      let link = dir.join("link");
+      #[cfg(unix)]
+      let linked = std::os::unix::fs::symlink(&upper, &link);
+      #[cfg(windows)]
+      let linked = std::os::windows::fs::symlink_file(&upper, &link);
+      supports_symlinks = Some(linked.is_ok());
+    }
+    let _ = std::fs::remove_dir_all(dir);
+  }
+  FsInfo {
+    path: path.to_string_lossy().to_string(),
+    case_sensitive,
+    supports_symlinks,
+    is_network: probe_root.as_ref().map(is_network_path).unwrap_or(false),
+    free_bytes: probe_root.as_ref().and_then(free_bytes),
+  }
+}
+
+// UNC paths on Windows; elsewhere df's source column: `host:/export` (NFS, sshfs) or
+// `//user@host/share` (SMB, AFP). Mapped Windows drive letters aren't detected.
+pub(crate) fn is_network_path(p: &PathBuf) -> bool {
+  if cfg!(target_os = "windows") {
+    return p.to_string_lossy().starts_with("\\\\");
+  }
+  let Some(out) = command_stdout("df", &["-P", &p.to_string_lossy()]) else {
+    return false;
+  };
+  let Some(source) = out.lines().nth(1).and_then(|l| l.split_whitespace().next()) else {
+    return false;
+  };
+  source.starts_with("//") || (source.contains(":/") && !source.starts_with('/'))
+}
+
+#[tauri::command(async)]
+pub(crate) fn filesystem_info(path: String) -> Result<FsInfo, String> {
+  let p = PathBuf::from(path.trim());
+  if !p.is_absolute() {
+    return Err(format!("Expected an absolute path, got {}", path));
+  }
+  Ok(fs_info(&p))
+}
+
+// Names in the same folder that differ only by case; they'd overwrite each other on a
+// case-insensitive volume.
+pub(crate) fn case_collisions(dir: &PathBuf) -> Vec<String> {
+  let mut found = Vec::new();
+  let Ok(entries) = std::fs::read_dir(dir) else {
+    return found;
+  };
+  let mut seen = std::collections::HashSet::new();
+  for entry in entries.flatten() {
+    let name = entry.file_name().to_string_lossy().to_string();
+    if !seen.insert(name.to_lowercase()) {
+      found.push(entry.path().to_string_lossy().to_string());
+    }
+    if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+      found.extend(case_collisions(&entry.path()));
+    }
+  }
+  found
+}
+
+// Some(message) when moving `from` -> `to` changes case sensitivity; `blocking` when files
+// in `from` would collide on the destination.
+pub(crate) fn case_change_warning(from: &PathBuf, to: &PathBuf) -> Option<(String, bool)> {
+  let (Some(src), Some(dst)) = (fs_info(from).case_sensitive, fs_info(to).case_sensitive) else {
+    return None;
+  };
+  if src == dst {
+    return None;
+  }
+  if dst {
+    return Some((
+      "The destination is case-sensitive and the library isn't; references that differ from a file's name only by case will stop resolving.".to_string(),
+      false,
+    ));
+  }
+  let collisions = case_collisions(from);
+  if collisions.is_empty() {
+    return Some(("The destination is case-insensitive; the library is case-sensitive.".to_string(), false));
+  }
+  Some((
+    format!(
+      "The destination is case-insensitive and {} file(s) in the library differ only by case from another (e.g. {}); they would overwrite each other.",
+      collisions.len(),
+      collisions[0]
+    ),
+    true,
+  ))
+}
+
+// Resolves symlinks through the closest existing ancestor, so a not-yet-created dir compares too.
+pub(crate) fn normalize_path(p: &PathBuf) -> PathBuf {
+  let Some(base) = existing_ancestor(p) else {
+    return p.clone();
+  };
+  let rest = p.strip_prefix(&base).map(|r| r.to_path_buf()).unwrap_or_default();
+  base.canonicalize().map(|b| b.join(rest)).unwrap_or_else(|_| p.clone())
+}
+
+// The server serves everything under the data dir, so settings and logs must not be in it; and
+// inside the config root only the default `data` folder is the library's.
+pub(crate) fn data_dir_layout_error(config_root: &PathBuf, data_dir: &PathBuf) -> Option<String> {
+  let (root, data) = (normalize_path(config_root), normalize_path(data_dir));
+  if data == root {
+    Some(format!("The library can't be the app's config folder ({})", root.display()))
+  } else if root.starts_with(&data) {
+    Some(format!("The library at {} contains the app's config folder", data.display()))
+  } else if data.starts_with(&root) && !data.starts_with(root.join("data")) {
+    Some(format!("The library at {} is inside the app's config folder", data.display()))
+  } else {
+    None
+  }
+}
+
+#[tauri::command]
+pub(crate) fn validate_data_dir(app: tauri::AppHandle, path: String) -> Result<DirValidation, String> {
+  let path = path.trim();
+  if path.is_empty() {
+    return Err("Path is empty".to_string());
+  }
+  let p = PathBuf::from(path);
+  if !p.is_absolute() {
+    return Err(format!("Expected an absolute path, got {}", path));
+  }
+  if let Some(err) = app_config_root(&app).and_then(|root| data_dir_layout_error(&root, &p)) {
+    return Err(err);
+  }
+  Ok(validate_dir(&p))
+}
+
+pub(crate) const BENCH_FILE_BYTES: usize = 32 * 1024 * 1024;
+
+pub(crate) const BENCH_BLOCK_BYTES: usize = 1024 * 1024;
+
+pub(crate) const BENCH_RANDOM_BLOCK_BYTES: usize = 4096;
+
+pub(crate) const BENCH_RANDOM_OPS: usize = 2048;
+
+// Below this sequential write speed a library feels sluggish (imports, thumbnails, DB writes).
+pub(crate) const SLOW_DIR_MBPS: f64 = 20.0;
+
+#[derive(Clone, Serialize, Default)]
+pub(crate) struct DiskBenchmark {
+  pub(crate) path: String,
+  pub(crate) ok: bool,
+  pub(crate) error: Option<String>,
+  pub(crate) seq_write_mbps: f64,
+  // Reads follow the writes, so the OS cache can inflate these on fast local disks.
+  pub(crate) seq_read_mbps: f64,
+  pub(crate) random_write_mbps: f64,
+  pub(crate) random_read_mbps: f64,
+  pub(crate) slow: bool,
+}
+
+pub(crate) fn mbps(bytes: usize, elapsed: Duration) -> f64 {
+  let secs = elapsed.as_secs_f64().max(1e-6);
+  (bytes as f64 / (1024.0 * 1024.0) / secs * 10.0).round() / 10.0
+}
+
+pub(crate) fn run_benchmark(file: &PathBuf, report: &mut DiskBenchmark) -> io::Result<()> {
+  use std::io::{Seek, SeekFrom};
+  let block = vec![0x5au8; BENCH_BLOCK_BYTES];
+  let mut f = OpenOptions::new().create(true).read(true).write(true).truncate(true).open(file)?;
+
+  let start = Instant::now();
+  for _ in 0..BENCH_FILE_BYTES / BENCH_BLOCK_BYTES {
+    f.write_all(&block)?;
+  }
+  f.sync_all()?;
+  report.seq_write_mbps = mbps(BENCH_FILE_BYTES, start.elapsed());
+
+  let mut buf = vec![0u8; BENCH_BLOCK_BYTES];
+  f.seek(SeekFrom::Start(0))?;
+  let start = Instant::now();
+  for _ in 0..BENCH_FILE_BYTES / BENCH_BLOCK_BYTES {
+    f.read_exact(&mut buf)?;
+  }
+  report.seq_read_mbps = mbps(BENCH_FILE_BYTES, start.elapsed());
+
+  // Small IO at scattered offsets (simple LCG; reproducible, no RNG dependency).
+  let blocks = (BENCH_FILE_BYTES / BENCH_RANDOM_BLOCK_BYTES) as u64;
+  let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+  let mut offsets = Vec::with_capacity(BENCH_RANDOM_OPS);
+  for _ in 0..BENCH_RANDOM_OPS {
+    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+    offsets.push((seed >> 33) % blocks * BENCH_RANDOM_BLOCK_BYTES as u64);
+  }
+  let small = &block[..BENCH_RANDOM_BLOCK_BYTES];
+  let start = Instant::now();
+  for off in &offsets {
+    f.seek(SeekFrom::Start(*off))?;
+    f.write_all(small)?;
+  }
+  f.sync_all()?;
+  report.random_write_mbps = mbps(BENCH_RANDOM_OPS * BENCH_RANDOM_BLOCK_BYTES, start.elapsed());
+
+  let mut small_buf = vec![0u8; BENCH_RANDOM_BLOCK_BYTES];
+  let start = Instant::now();
+  for off in offsets.iter().rev() {
+    f.seek(SeekFrom::Start(*off))?;
+    f.read_exact(&mut small_buf)?;
+  }
+  report.random_read_mbps = mbps(BENCH_RANDOM_OPS * BENCH_RANDOM_BLOCK_BYTES, start.elapsed());
+  Ok(())
+}
+
+// Measures a candidate library location before a migration commits to it. A dir that doesn't
+// exist yet is measured where it would be created.
+#[tauri::command(async)]
+pub(crate) fn benchmark_dir(path: String) -> DiskBenchmark {
+  let p = PathBuf::from(path.trim());
+  let mut report = DiskBenchmark {
+    path: p.to_string_lossy().to_string(),
+    ..Default::default()
+  };
+  let target = match existing_ancestor(&p) {
+    Some(t) if p.is_absolute() => t,
+    _ => {
+      report.error = Some("Expected an absolute path".to_string());
+      return report;
+    }
+  };
+  let file = target.join(format!(".moondream-bench-{}", std::process::id()));
+  let result = run_benchmark(&file, &mut report);
+  let _ = std::fs::remove_file(&file);
+  match result {
+    Ok(()) => {
+      report.ok = true;
+      report.slow = report.seq_write_mbps < SLOW_DIR_MBPS;
+    }
+    Err(e) => report.error = Some(e.to_string()),
+  }
+  report
+}
+
+// Fixed cost per copied file (create, copy metadata, verify) on top of raw throughput.
+pub(crate) const MIGRATION_PER_FILE_SECS: f64 = 0.002;
+
+#[derive(Clone, Serialize)]
+pub(crate) struct MigrationEstimate {
+  pub(crate) from: String,
+  pub(crate) to: String,
+  pub(crate) files: u64,
+  pub(crate) bytes: u64,
+  // Same volume: the move is a rename and finishes almost instantly.
+  pub(crate) same_volume: bool,
+  pub(crate) source_read_mbps: Option<f64>,
+  pub(crate) dest_write_mbps: Option<f64>,
+  pub(crate) estimated_secs: u64,
+}
+
+#[cfg(unix)]
+pub(crate) fn same_volume(a: &PathBuf, b: &PathBuf) -> bool {
+  use std::os::unix::fs::MetadataExt;
+  match (std::fs::metadata(a), existing_ancestor(b).and_then(|b| std::fs::metadata(b).ok())) {
+    (Ok(a), Some(b)) => a.dev() == b.dev(),
+    _ => false,
+  }
+}
+
+// Drive letter / UNC share.
+#[cfg(not(unix))]
+pub(crate) fn same_volume(a: &PathBuf, b: &PathBuf) -> bool {
+  let prefix = |p: &PathBuf| p.components().next().map(|c| c.as_os_str().to_ascii_lowercase());
+  prefix(a).is_some() && prefix(a) == prefix(b)
+}
+
+// Every file in the library with its size.
+pub(crate) fn library_files(dir: &PathBuf) -> Vec<(PathBuf, u64)> {
+  let mut files = Vec::new();
+  let _ = collect_files(dir, &mut files);
+  files
+    .into_iter()
+    .map(|f| {
+      let len = std::fs::metadata(&f).map(|m| m.len()).unwrap_or(0);
+      (f, len)
+    })
+    .collect()
+}
+
+// Below this much data a read speed is mostly open/close overhead.
+pub(crate) const SOURCE_SAMPLE_MIN_BYTES: usize = 1024 * 1024;
+
+// Read speed of the source measured on its own largest files (up to BENCH_FILE_BYTES), so
+// estimating never writes into the library. Files read recently may come from the OS cache.
+pub(crate) fn sample_read_mbps(files: &[(PathBuf, u64)]) -> Option<f64> {
+  let mut largest: Vec<&(PathBuf, u64)> = files.iter().collect();
+  largest.sort_by_key(|(_, len)| std::cmp::Reverse(*len));
+  let mut buf = vec![0u8; BENCH_BLOCK_BYTES];
+  let mut read = 0;
+  let start = Instant::now();
+  for (file, _) in largest {
+    let Ok(mut f) = std::fs::File::open(file) else {
+      continue;
+    };
+    while read < BENCH_FILE_BYTES {
+      match f.read(&mut buf) {
+        Ok(0) | Err(_) => break,
+        Ok(n) => read += n,
+      }
+    }
+    if read >= BENCH_FILE_BYTES {
+      break;
+    }
+  }
+  (read >= SOURCE_SAMPLE_MIN_BYTES).then(|| mbps(read, start.elapsed()))
+}
+
+// Cross-volume moves are copied at the slower of source reads and destination writes. Only the
+// destination gets a benchmark file; the source is measured by reading what's already there.
+pub(crate) fn estimate_move(from: &PathBuf, to: &PathBuf) -> MigrationEstimate {
+  let library = library_files(from);
+  let (files, bytes) = (library.len() as u64, library.iter().map(|(_, len)| len).sum::<u64>());
+  let mut estimate = MigrationEstimate {
+    from: from.to_string_lossy().to_string(),
+    to: to.to_string_lossy().to_string(),
+    files,
+    bytes,
+    same_volume: same_volume(from, to),
+    source_read_mbps: None,
+    dest_write_mbps: None,
+    estimated_secs: 0,
+  };
+  if estimate.same_volume {
+    return estimate;
+  }
+  let dest = benchmark_dir(to.to_string_lossy().to_string());
+  estimate.source_read_mbps = sample_read_mbps(&library);
+  estimate.dest_write_mbps = dest.ok.then_some(dest.seq_write_mbps);
+  let mbps = match (estimate.source_read_mbps, estimate.dest_write_mbps) {
+    (Some(r), Some(w)) => r.min(w),
+    (Some(x), None) | (None, Some(x)) => x,
+    (None, None) => SLOW_DIR_MBPS,
+  }
+  .max(0.1);
+  let secs = bytes as f64 / (1024.0 * 1024.0) / mbps + files as f64 * MIGRATION_PER_FILE_SECS;
+  estimate.estimated_secs = secs.ceil() as u64;
+  estimate
+}
+
+// `to` is a storage mode ("local" | "icloud", as for `hot_migrate`) or an absolute path.
+#[tauri::command(async)]
+pub(crate) fn estimate_migration(app: tauri::AppHandle, to: String) -> Result<MigrationEstimate, String> {
+  let state = app.state::<ServerState>();
+  let config_root = managed_config_root(&app, &state)?;
+  let settings = read_settings(&config_root);
+  let from = state
+    .data_dir
+    .lock()
+    .unwrap()
+    .clone()
+    .unwrap_or_else(|| resolve_data_dir(&config_root, &settings));
+  let dest = match to.trim().to_lowercase().as_str() {
+    mode @ ("local" | "icloud") => storage_mode_dir(&config_root, &settings, mode)
+      .ok_or_else(|| "iCloud Drive isn't available on this Mac.".to_string())?,
+    _ => {
+      let p = PathBuf::from(to.trim());
+      if !p.is_absolute() {
+        return Err(format!("Expected local, icloud or an absolute path, got {}", to));
+      }
+      p
+    }
+  };
+  Ok(estimate_move(&from, &dest))
+}
+
+pub(crate) fn format_duration_estimate(secs: u64) -> String {
+  match secs {
+    0..=59 => "under a minute".to_string(),
+    60..=3599 => format!("about {} min", secs.div_ceil(60)),
+    _ => format!("about {:.1} h", secs as f64 / 3600.0),
+  }
+}
+
+// The server keeps these open; evicting them would turn every query into an iCloud download.
+pub(crate) fn is_live_db_file(p: &PathBuf) -> bool {
+  p.file_name()
+    .and_then(|n| n.to_str())
+    .map(|n| n.starts_with("moondream.sqlite3"))
+    .unwrap_or(false)
+}
+
+pub(crate) fn collect_files(dir: &PathBuf, out: &mut Vec<PathBuf>) -> io::Result<()> {
+  for entry in std::fs::read_dir(dir)? {
+    let entry = entry?;
+    let ty = entry.file_type()?;
+    let path = entry.path();
+    if ty.is_dir() {
+      collect_files(&path, out)?;
+    } else if ty.is_file() {
+      out.push(path);
+    }
+  }
+  Ok(())
+}
+
+// Past this, only the count keeps growing (a fully reset library would list every file).
+pub(crate) const PERM_ISSUES_MAX: usize = 500;
+
+#[derive(Clone, Serialize)]
+pub(crate) struct PermIssue {
+  pub(crate) path: String,
+  pub(crate) problem: String,
+  pub(crate) action: String, // "chown" | "chmod" | "clear_readonly"
+  pub(crate) fixed: bool,
+  pub(crate) error: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct PermReport {
+  pub(crate) data_dir: String,
+  pub(crate) db_path: String,
+  pub(crate) checked: u64,
+  pub(crate) issues_found: u64,
+  pub(crate) issues: Vec<PermIssue>, // at most PERM_ISSUES_MAX
+  // Both the data dir and the DB accept writes after the fixes.
+  pub(crate) writable: bool,
+}
+
+#[cfg(unix)]
+pub(crate) fn current_uid() -> Option<u32> {
+  let out = Command::new("id").arg("-u").stderr(Stdio::null()).output().ok()?;
+  String::from_utf8_lossy(&out.stdout).trim().parse().ok()
+}
+
+// Owner must be us with u+rw (u+rwx for dirs). chown only succeeds for root, but is still tried
+// and reported so the user knows what to fix by hand.
+#[cfg(unix)]
+pub(crate) fn fix_path_permissions(path: &PathBuf, uid: Option<u32>) -> Vec<PermIssue> {
+  use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+  let mut issues = Vec::new();
+  let Ok(meta) = std::fs::symlink_metadata(path) else {
+    return issues;
+  };
+  if meta.file_type().is_symlink() {
+    return issues;
+  }
+  if let Some(uid) = uid.filter(|u| *u != meta.uid()) {
+    let res = Command::new("chown").arg(uid.to_string()).arg(path).stdin(Stdio::null()).output();
+    let error = match res {
+      Ok(out) if out.status.success() => None,
+      Ok(out) => Some(String::from_utf8_lossy(&out.stderr).trim().to_string()),
+      Err(e) => Some(e.to_string()),
+    };
+    issues.push(PermIssue {
+      path: path.to_string_lossy().to_string(),
+      problem: format!("Owned by uid {} instead of {}", meta.uid(), uid),
+      action: "chown".to_string(),
+      fixed: error.is_none(),
+      error,
+    });
+  }
+  let need = if meta.is_dir() { 0o700 } else { 0o600 };
+  let mode = meta.permissions().mode();
+  if mode & need != need {
+    let mut perms = meta.permissions();
+    perms.set_mode(mode | need);
+    let error = std::fs::set_permissions(path, perms).err().map(|e| e.to_string());
+    issues.push(PermIssue {
+      path: path.to_string_lossy().to_string(),
+      problem: format!("Mode {:o} isn't owner-writable", mode & 0o777),
+      action: "chmod".to_string(),
+      fixed: error.is_none(),
+      error,
+    });
+  }
+  issues
+}
+
+#[cfg(not(unix))]
+pub(crate) fn current_uid() -> Option<u32> {
+  None
+}
+
+// Windows has no owner bits to repair from here; a sync client can only leave the read-only attribute.
+#[cfg(not(unix))]
+#[allow(clippy::permissions_set_readonly_false)]
+pub(crate) fn fix_path_permissions(path: &PathBuf, _uid: Option<u32>) -> Vec<PermIssue> {
+  let mut issues = Vec::new();
+  let Ok(meta) = std::fs::metadata(path) else {
+    return issues;
+  };
+  if meta.permissions().readonly() {
+    let mut perms = meta.permissions();
+    perms.set_readonly(false);
+    let error = std::fs::set_permissions(path, perms).err().map(|e| e.to_string());
+    issues.push(PermIssue {
+      path: path.to_string_lossy().to_string(),
+      problem: "Read-only".to_string(),
+      action: "clear_readonly".to_string(),
+      fixed: error.is_none(),
+      error,
+    });
+  }
+  issues
+}
+
+// `recursive` = false is the quick pass `setup` runs: the data dir, the DB (with -wal/-shm) and its folder.
+pub(crate) fn repair_permissions(data_dir: &PathBuf, db_path: &PathBuf, recursive: bool) -> PermReport {
+  let uid = current_uid();
+  let mut targets = vec![data_dir.clone()];
+  if let Some(parent) = db_path.parent() {
+    targets.push(parent.to_path_buf());
+  }
+  for suffix in ["", "-wal", "-shm"] {
+    let mut name = db_path.as_os_str().to_os_string();
+    name.push(suffix);
+    targets.push(PathBuf::from(name));
+  }
+  if recursive {
+    let mut pending = vec![data_dir.clone()];
+    while let Some(dir) = pending.pop() {
+      let Ok(entries) = std::fs::read_dir(&dir) else {
+        continue;
+      };
+      for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+          pending.push(path.clone());
+        }
+        targets.push(path);
+      }
+    }
+  }
+  targets.sort();
+  targets.dedup();
+
+  let mut report = PermReport {
+    data_dir: data_dir.to_string_lossy().to_string(),
+    db_path: db_path.to_string_lossy().to_string(),
+    checked: 0,
+    issues_found: 0,
+    issues: Vec::new(),
+    writable: false,
+  };
+  for target in targets.iter().filter(|t| t.exists()) {
+    report.checked += 1;
+    for issue in fix_path_permissions(target, uid) {
+      report.issues_found += 1;
+      if report.issues.len() < PERM_ISSUES_MAX {
+        report.issues.push(issue);
+      }
+    }
+  }
+  let db_writable = !db_path.exists() || OpenOptions::new().append(true).open(db_path).is_ok();
+  report.writable = dir_writable(data_dir) && db_writable;
+  report
+}
+
+// Full pass over the library; for when sync reset permissions and uploads or jobs fail to write.
+#[tauri::command(async)]
+pub(crate) fn fix_permissions(app: tauri::AppHandle) -> Result<PermReport, String> {
+  let state = app.state::<ServerState>();
+  let data_dir = state
+    .data_dir
+    .lock()
+    .unwrap()
+    .clone()
+    .ok_or_else(|| "The library location isn't known yet".to_string())?;
+  let db_path = match state.db_path.lock().unwrap().clone() {
+    Some(p) => p,
+    None => {
+      let config_root = managed_config_root(&app, &state)?;
+      resolve_db_path(&config_root, &data_dir, &effective_settings(&state, &config_root))
+    }
+  };
+  let report = repair_permissions(&data_dir, &db_path, true);
+  host_log(
+    if report.writable { LogLevel::Info } else { LogLevel::Warn },
+    &format!(
+      "fix_permissions: {} checked, {} issue(s), writable={}",
+      report.checked, report.issues_found, report.writable
+    ),
+  );
+  Ok(report)
+}
+
+#[tauri::command]
+pub(crate) fn evict_icloud_library(app: tauri::AppHandle) -> Result<(), String> {
+  let state = app.state::<ServerState>();
+  let config_root = managed_config_root(&app, &state)?;
+  if storage_mode(&effective_settings(&state, &config_root)) != "icloud" {
+    return Ok(());
+  }
+  if !cfg!(target_os = "macos") {
+    return Err("iCloud eviction is only available on macOS.".to_string());
+  }
+  let data_dir = require_data_dir(&state)?;
+
+  let mut files = Vec::new();
+  collect_files(&data_dir, &mut files).map_err(|e| e.to_string())?;
+  let mut failed = 0usize;
+  for file in files.iter().filter(|f| !is_live_db_file(f)) {
+    // `brctl evict` drops the local copy but keeps the file in iCloud (re-downloaded on access).
+    let ok = quiet_status(Command::new("brctl").arg("evict").arg(file))
+      .map(|s| s.success())
+      .unwrap_or(false);
+    if !ok {
+      failed += 1;
+    }
+  }
+  if failed > 0 {
+    return Err(format!("{} file(s) could not be evicted (not yet uploaded or in use).", failed));
+  }
+  Ok(())
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct SyncRisk {
+  pub(crate) level: String, // "none" | "elevated" | "high"
+  pub(crate) db_path: Option<String>,
+  pub(crate) in_synced_tree: bool,
+  // None when the File Provider couldn't be queried (not macOS, or tool unavailable).
+  pub(crate) uploading: Option<bool>,
+  // "moondream 2.sqlite3"-style copies iCloud leaves behind after a conflicting sync.
+  pub(crate) conflict_copies: Vec<String>,
+  pub(crate) recommendation: Option<String>,
+}
+
+// Best-effort per-item sync state from `fileproviderctl evaluate` (prints the item's attributes
+// as `key = value` lines). Returns (uploading, has_unresolved_conflicts).
+pub(crate) fn fileprovider_state(p: &PathBuf) -> Option<(bool, bool)> {
+  if !cfg!(target_os = "macos") {
+    return None;
+  }
+  let out = Command::new("fileproviderctl")
+    .arg("evaluate")
+    .arg(p)
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .ok()
+    .filter(|o| o.status.success())?;
+  let text = String::from_utf8_lossy(&out.stdout).to_lowercase();
+  let flag = |key: &str| {
+    text.lines().any(|l| {
+      let l = l.trim();
+      l.starts_with(key) && (l.ends_with("= 1") || l.ends_with("= yes") || l.ends_with("= true"))
+    })
+  };
+  Some((flag("isuploading"), flag("hasunresolvedconflicts")))
+}
+
+pub(crate) fn db_conflict_copies(db: &PathBuf) -> Vec<String> {
+  let (Some(dir), Some(stem)) = (db.parent(), db.file_stem()) else {
+    return Vec::new();
+  };
+  let stem = stem.to_string_lossy().to_string();
+  let Ok(entries) = std::fs::read_dir(dir) else {
+    return Vec::new();
+  };
+  entries
+    .flatten()
+    .map(|e| e.file_name().to_string_lossy().to_string())
+    .filter(|n| n.starts_with(&format!("{} ", stem)) && n.contains(".sqlite3"))
+    .collect()
+}
+
+#[tauri::command]
+pub(crate) fn db_sync_risk(app: tauri::AppHandle) -> Result<SyncRisk, String> {
+  let state = app.state::<ServerState>();
+  let config_root = managed_config_root(&app, &state)?;
+  let db = state.db_path.lock().unwrap().clone();
+  let mut risk = SyncRisk {
+    level: "none".to_string(),
+    db_path: db.as_ref().map(|p| p.to_string_lossy().to_string()),
+    in_synced_tree: false,
+    uploading: None,
+    conflict_copies: Vec::new(),
+    recommendation: None,
+  };
+  let Some(db) = db else {
+    return Ok(risk);
+  };
+  if storage_mode(&effective_settings(&state, &config_root)) != "icloud" || !is_icloud_path(&db) {
+    return Ok(risk);
+  }
+
+  risk.in_synced_tree = true;
+  risk.conflict_copies = db_conflict_copies(&db);
+  let fp = fileprovider_state(&db);
+  risk.uploading = fp.map(|(uploading, _)| uploading);
+  let conflicted = fp.map(|(_, c)| c).unwrap_or(false) || !risk.conflict_copies.is_empty();
+  risk.level = if conflicted || risk.uploading == Some(true) { "high" } else { "elevated" }.to_string();
+  risk.recommendation = Some(if conflicted {
+    "iCloud has conflicting copies of the database. Quit Reference on your other Macs, then keep the database local (storage.db_location = \"local\") and sync only assets.".to_string()
+  } else {
+    "The live database is synced by iCloud, which can corrupt it if two Macs write at once. Keep the database local (storage.db_location = \"local\") and sync only assets.".to_string()
+  });
+  Ok(risk)
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct ConflictFile {
+  pub(crate) kind: String, // "conflict" (`name 2.ext` next to `name.ext`) | "placeholder" (`.name.ext.icloud`)
+  pub(crate) path: String,
+  // The file it duplicates / stands in for.
+  pub(crate) original_path: String,
+  pub(crate) original_exists: bool,
+  pub(crate) size: u64,
+  pub(crate) mtime: Option<u64>,
+  pub(crate) original_size: Option<u64>,
+  pub(crate) original_mtime: Option<u64>,
+}
+
+pub(crate) fn mtime_secs(meta: &std::fs::Metadata) -> Option<u64> {
+  meta
+    .modified()
+    .ok()
+    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+    .map(|d| d.as_secs())
+}
+
+// `photo 2.jpg` -> `photo.jpg` (iCloud numbers conflicting copies from 2 up).
+pub(crate) fn conflict_original_name(name: &str) -> Option<String> {
+  let (stem, ext) = match name.rsplit_once('.') {
+    Some((s, e)) if !s.is_empty() => (s, Some(e)),
+    _ => (name, None),
+  };
+  let (base, n) = stem.rsplit_once(' ')?;
+  if base.is_empty() || n.is_empty() || !n.bytes().all(|b| b.is_ascii_digit()) || n.parse::<u32>().ok()? < 2 {
+    return None;
+  }
+  Some(match ext {
+    Some(e) => format!("{}.{}", base, e),
+    None => base.to_string(),
+  })
+}
+
+pub(crate) fn conflict_file(p: &PathBuf) -> Option<ConflictFile> {
+  let name = p.file_name()?.to_string_lossy().to_string();
+  let (kind, original) = if let Some(inner) = name.strip_prefix('.').and_then(|n| n.strip_suffix(".icloud")) {
+    ("placeholder", p.with_file_name(inner))
+  } else {
+    let original = p.with_file_name(conflict_original_name(&name)?);
+    // Only a numbered name next to its original is a conflict; `Scan 2.jpg` alone is just a name.
+    if !original.is_file() {
+      return None;
+    }
+    ("conflict", original)
+  };
+  let meta = std::fs::metadata(p).ok()?;
+  let original_meta = std::fs::metadata(&original).ok();
+  Some(ConflictFile {
+    kind: kind.to_string(),
+    path: p.to_string_lossy().to_string(),
+    original_path: original.to_string_lossy().to_string(),
+    original_exists: original_meta.is_some(),
+    size: meta.len(),
+    mtime: mtime_secs(&meta),
+    original_size: original_meta.as_ref().map(|m| m.len()),
+    original_mtime: original_meta.as_ref().and_then(mtime_secs),
+  })
+}
+
+pub(crate) fn icloud_library_dir(app: &tauri::AppHandle, state: &ServerState) -> Result<Option<PathBuf>, String> {
+  let config_root = managed_config_root(app, state)?;
+  if storage_mode(&effective_settings(state, &config_root)) != "icloud" {
+    return Ok(None);
+  }
+  Ok(state.data_dir.lock().unwrap().clone())
+}
+
+#[tauri::command(async)]
+pub(crate) fn find_icloud_conflicts(app: tauri::AppHandle) -> Result<Vec<ConflictFile>, String> {
+  let state = app.state::<ServerState>();
+  let Some(data_dir) = icloud_library_dir(&app, &state)? else {
+    return Ok(Vec::new());
+  };
+  let mut files = Vec::new();
+  collect_files(&data_dir, &mut files).map_err(|e| e.to_string())?;
+  Ok(files.iter().filter_map(conflict_file).collect())
+}
+
+// action: "delete" removes a conflict copy; "keep" makes the copy the real file (replacing the
+// original); "download" asks iCloud to materialize a placeholder.
+#[tauri::command(async)]
+pub(crate) fn resolve_icloud_conflict(app: tauri::AppHandle, path: String, action: String) -> Result<(), String> {
+  let state = app.state::<ServerState>();
+  let data_dir = icloud_library_dir(&app, &state)?.ok_or_else(|| "The library is not in iCloud.".to_string())?;
+  let p = PathBuf::from(path.trim());
+  // Only files we'd have reported, and only inside the library.
+  let root = data_dir.canonicalize().map_err(|e| e.to_string())?;
+  let inside = p.parent().and_then(|d| d.canonicalize().ok()).map(|d| d.starts_with(&root)).unwrap_or(false);
+  let conflict = conflict_file(&p).filter(|_| inside).ok_or_else(|| format!("{} is not an iCloud conflict file", p.display()))?;
+  let original = PathBuf::from(&conflict.original_path);
+
+  match (conflict.kind.as_str(), action.trim()) {
+    ("conflict", "delete") => std::fs::remove_file(&p).map_err(|e| e.to_string()),
+    ("conflict", "keep") => {
+      if is_live_db_file(&original) {
+        return Err("The database can't be replaced while the app is running.".to_string());
+      }
+      std::fs::rename(&p, &original).map_err(|e| e.to_string())
+    }
+    ("placeholder", "download") => {
+      let ok = quiet_status(Command::new("brctl").arg("download").arg(&original))
+        .map(|s| s.success())
+        .unwrap_or(false);
+      if ok {
+        Ok(())
+      } else {
+        Err(format!("iCloud couldn't download {}", original.display()))
+      }
+    }
+    (kind, other) => Err(format!("Can't {} a {} file", other, kind)),
+  }
+}
+
+pub(crate) fn validate_library_name(name: &str) -> Result<String, String> {
+  let name = name.trim();
+  if name.is_empty() {
+    return Err("Library name is empty".to_string());
+  }
+  if name.starts_with('.') {
+    return Err("Library name can't start with a dot".to_string());
+  }
+  if name.len() > 255 {
+    return Err("Library name is too long".to_string());
+  }
+  if let Some(c) = name
+    .chars()
+    .find(|c| matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control())
+  {
+    return Err(format!("Library name can't contain {:?}", c));
+  }
+  Ok(name.to_string())
+}
+
+#[tauri::command(async)]
+pub(crate) fn rename_library(app: tauri::AppHandle, new_name: String) -> Result<String, String> {
+  let name = validate_library_name(&new_name)?;
+  let state = app.state::<ServerState>();
+  if *state.safe_mode.lock().unwrap() {
+    return Err("The library can't be renamed in safe mode.".to_string());
+  }
+  let config_root = require_config_root(&state)?;
+  let data_dir = require_data_dir(&state)?;
+
+  let mut settings = try_read_settings(&config_root)?;
+  let mode = storage_mode(&settings);
+  // The local library is the app's own `data` dir; only a user-visible (iCloud) folder has a name to change.
+  if mode != "icloud" {
+    return Err("Only iCloud libraries can be renamed.".to_string());
+  }
+
+  let parent = data_dir
+    .parent()
+    .ok_or_else(|| format!("Library has no parent folder: {}", data_dir.display()))?;
+  let dest = parent.join(&name);
+  if dest == data_dir {
+    return Ok(dest.to_string_lossy().to_string());
+  }
+  // A case-only rename "exists" on case-insensitive volumes but is still a plain rename.
+  let case_only = dest.file_name().map(|n| n.to_string_lossy().to_lowercase())
+    == data_dir.file_name().map(|n| n.to_string_lossy().to_lowercase());
+  if dest.exists() && !case_only {
+    return Err(format!("A folder named \"{}\" already exists in {}", name, parent.display()));
+  }
+
+  // Nothing may hold the SQLite file open while the folder moves.
+  if let Some(mut child) = state.child.lock().unwrap().take() {
+    let _ = child.kill();
+    let _ = child.wait();
+  }
+  if let Some(mut worker) = state.worker.lock().unwrap().take() {
+    let _ = worker.kill();
+    let _ = worker.wait();
+  }
+
+  let moved = move_dir(&data_dir, &dest);
+  let mut saved = Ok(());
+  if moved.is_ok() {
+    let storage = settings.storage.get_or_insert_with(|| StorageSettings {
+      mode: Some("icloud".to_string()),
+      icloud_path: None,
+      migration: None,
+      pause_worker_when_offline: None,
+      db_location: None,
+    });
+    storage.icloud_path = Some(dest.to_string_lossy().to_string());
+    saved = write_settings(&config_root, &settings);
+    *state.data_dir.lock().unwrap() = Some(dest.clone());
+  }
+
+  // Bring everything back up either way (at the old location if the move failed).
+  let library = state.data_dir.lock().unwrap().clone().unwrap_or(data_dir);
+  let restarted = restart_next_server(&app, "migration");
+  let db_path = resolve_db_path(&config_root, &library, &settings);
+  start_worker_if_allowed(&app, &db_path, &config_root, &settings);
+
+  moved.map_err(|e| format!("Failed to rename library: {}", e))?;
+  saved.map_err(|e| format!("Renamed the library, but settings.json still points at the old name: {}", e))?;
+  restarted?;
+  Ok(dest.to_string_lossy().to_string())
+}
+
+pub(crate) const DB_FILE_NAME: &str = "moondream.sqlite3";
+
+pub(crate) fn db_path_for(data_dir: &PathBuf) -> PathBuf {
+  data_dir.join(DB_FILE_NAME)
+}
+
+pub(crate) fn db_location_local(settings: &AppSettings) -> bool {
+  settings
+    .storage
+    .as_ref()
+    .and_then(|s| s.db_location.as_deref())
+    .map(|l| l.eq_ignore_ascii_case("local"))
+    .unwrap_or(false)
+}
+
+// The one place the live DB path is derived; server and worker both get this value.
+// Never picks a missing file while the other location still holds the DB (see settle_db_location).
+pub(crate) fn resolve_db_path(config_root: &PathBuf, data_dir: &PathBuf, settings: &AppSettings) -> PathBuf {
+  let (local, with_data) = (config_root.join(DB_FILE_NAME), db_path_for(data_dir));
+  let (want, other) = if db_location_local(settings) {
+    (local, with_data)
+  } else {
+    (with_data, local)
+  };
+  if !want.exists() && other.exists() {
+    other
+  } else {
+    want
+  }
+}
+
+// Moves the DB (with its -wal/-shm) to the location `storage.db_location` asks for. Only call
+// while neither the server nor the worker has it open. An existing DB at the target wins.
+pub(crate) fn settle_db_location(config_root: &PathBuf, data_dir: &PathBuf, settings: &AppSettings) {
+  let (from, to) = if db_location_local(settings) {
+    (db_path_for(data_dir), config_root.join(DB_FILE_NAME))
+  } else {
+    (config_root.join(DB_FILE_NAME), db_path_for(data_dir))
+  };
+  if !from.exists() {
+    return;
+  }
+  if to.exists() {
+    host_log(
+      LogLevel::Warn,
+      &format!("Not moving {}: a database already exists at {}", from.display(), to.display()),
+    );
+    return;
+  }
+  let _ = std::fs::create_dir_all(to.parent().unwrap_or(config_root));
+  for suffix in ["", "-wal", "-shm"] {
+    let src = PathBuf::from(format!("{}{}", from.display(), suffix));
+    let dst = PathBuf::from(format!("{}{}", to.display(), suffix));
+    if !src.exists() {
+      continue;
+    }
+    // rename fails across volumes (iCloud -> local is usually the same disk, but not always).
+    let moved = std::fs::rename(&src, &dst).or_else(|_| {
+      let meta = std::fs::metadata(&src)?;
+      copy_file_preserving_mtime(&src, &dst, &meta)?;
+      std::fs::remove_file(&src)
+    });
+    match moved {
+      Ok(()) => host_log(LogLevel::Info, &format!("Moved {} -> {}", src.display(), dst.display())),
+      Err(e) => {
+        host_log(LogLevel::Error, &format!("Failed to move {}: {}", src.display(), e));
+        return;
+      }
+    }
+  }
+}
+
+// Shown over the current page while `hot_migrate` runs; the restart that follows navigates it away.
+pub(crate) const MIGRATION_OVERLAY_JS: &str = r#"
+  (function () {
+    if (document.getElementById("md-migrating")) return;
+    var d = document.createElement("div");
+    d.id = "md-migrating";
+    d.style.cssText = "position:fixed;inset:0;z-index:2147483647;display:flex;flex-direction:column;align-items:center;justify-content:center;background:rgba(17,17,17,.96);color:#eee;font-family:-apple-system,BlinkMacSystemFont,system-ui,sans-serif";
+    d.innerHTML = '<h2 style="margin:0 0 8px;font-weight:600">Moving your library…</h2><p id="md-migrating-progress" style="opacity:.7;font-size:13px;margin:0"></p>';
+    document.body.appendChild(d);
+    window.dispatchEvent(new CustomEvent("moondream:migration:start"));
+  })();
+"#;
+
+pub(crate) fn report_migration_progress(window: &tauri::Window, done: u64, total: u64) {
+  let pct = (done * 100).checked_div(total).unwrap_or(100);
+  let _ = window.eval(&format!(
+    "(function(){{var el=document.getElementById(\"md-migrating-progress\");if(el)el.textContent=\"{}%\";\
+     window.dispatchEvent(new CustomEvent(\"moondream:migration:progress\",{{detail:{{done:{},total:{}}}}}));}})();",
+    pct, done, total
+  ));
+}
+
+pub(crate) fn storage_mode_dir(config_root: &PathBuf, settings: &AppSettings, mode: &str) -> Option<PathBuf> {
+  match mode {
+    "local" => Some(config_root.join("data")),
+    "icloud" => settings
+      .storage
+      .as_ref()
+      .and_then(|s| s.icloud_path.as_ref())
+      .map(PathBuf::from)
+      .or_else(default_icloud_dir),
+    _ => None,
+  }
+}
+
+// Moves the library to another storage mode without relaunching (the restart-time path is
+// `storage.migration`). The server is asked to close its DB first:
+// POST /api/maintenance/close-db checkpoints the WAL and closes the handle (2xx when done).
+// Servers without the route are simply stopped after the host's own checkpoint.
+#[tauri::command(async)]
+pub(crate) fn hot_migrate(app: tauri::AppHandle, to: String) -> Result<String, String> {
+  let mode = to.trim().to_lowercase();
+  if mode != "local" && mode != "icloud" {
+    return Err(format!("Unknown storage mode: {} (expected local or icloud)", to));
+  }
+  let state = app.state::<ServerState>();
+  if *state.safe_mode.lock().unwrap() {
+    return Err("Storage can't be changed in safe mode.".to_string());
+  }
+  let config_root = require_config_root(&state)?;
+  let data_dir = require_data_dir(&state)?;
+
+  let mut settings = try_read_settings(&config_root)?;
+  let dest = storage_mode_dir(&config_root, &settings, &mode)
+    .ok_or_else(|| "iCloud Drive isn't available on this Mac.".to_string())?;
+  let set_mode = |settings: &mut AppSettings| {
+    let storage = settings.storage.get_or_insert(StorageSettings {
+      mode: None,
+      icloud_path: None,
+      migration: None,
+      pause_worker_when_offline: None,
+      db_location: None,
+    });
+    storage.mode = Some(mode.clone());
+    if mode == "icloud" {
+      storage.icloud_path = Some(dest.to_string_lossy().to_string());
+    }
+    storage.migration = None;
+  };
+  if dest == data_dir {
+    set_mode(&mut settings);
+    write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+    return Ok(dest.to_string_lossy().to_string());
+  }
+  if dest.exists() && !is_dir_empty(&dest) {
+    return Err(format!("{} already contains files; move or empty it first.", dest.display()));
+  }
+  match case_change_warning(&data_dir, &dest) {
+    Some((msg, true)) => return Err(msg),
+    Some((msg, false)) => host_log(LogLevel::Warn, &format!("hot_migrate: {}", msg)),
+    None => {}
+  }
+
+  let window = app.get_window("main");
+  let estimate = estimate_move(&data_dir, &dest);
+  if !estimate.same_volume {
+    let confirmed = tauri::api::dialog::blocking::confirm(
+      window.as_ref(),
+      "Move library?",
+      format!(
+        "{} files ({:.1} GB) will be copied to {}. This will take {}, and the app is unavailable meanwhile.",
+        estimate.files,
+        estimate.bytes as f64 / 1e9,
+        dest.display(),
+        format_duration_estimate(estimate.estimated_secs)
+      ),
+    );
+    if !confirmed {
+      return Err("Cancelled".to_string());
+    }
+  }
+  if let Some(w) = window.as_ref() {
+    let _ = w.eval(MIGRATION_OVERLAY_JS);
+  }
+
+  if let Some(mut worker) = state.worker.lock().unwrap().take() {
+    let _ = worker.kill();
+    let _ = worker.wait();
+  }
+  if let Some(db_path) = current_db_path(&state) {
+    // The worker is gone, so nothing should hold the checkpoint off; the move then copies a minimal DB.
+    if let Err(e) = checkpoint_wal_now(&state, &db_path) {
+      host_log(LogLevel::Warn, &format!("hot_migrate: WAL checkpoint failed: {}", e));
+    }
+  }
+  if let Some(addr) = current_server_addr(&state) {
+    let token = state.session_token.lock().unwrap().clone();
+    // Best-effort: the kill below closes the DB either way, this just avoids leaving a hot WAL.
+    match server_request(&addr, "POST", "/api/maintenance/close-db", None, token.as_deref(), Duration::from_secs(10)) {
+      Some((status, body)) if route_missing(status, &body) => {
+        host_log(LogLevel::Info, "hot_migrate: server has no close-db route; relying on the checkpoint and stop")
+      }
+      Some((status, _)) if !(200..300).contains(&status) => {
+        host_log(LogLevel::Warn, &format!("hot_migrate: close-db answered {}", status))
+      }
+      _ => {}
+    }
+  }
+  if let Some(mut child) = state.child.lock().unwrap().take() {
+    let _ = child.kill();
+    let _ = child.wait();
+  }
+
+  if let Some(parent) = dest.parent() {
+    let _ = std::fs::create_dir_all(parent);
+  }
+  let mut emit = |ev: MigrationEvent| {
+    if ev.phase == "copying" {
+      if let Some(w) = window.as_ref() {
+        report_migration_progress(w, ev.bytes_done, ev.bytes_total);
+      }
+    }
+    emit_migration_event(&app, ev);
+  };
+  let moved = move_dir_streamed(&data_dir, &dest, &mut emit);
+  finish_migration_stream(&mut emit, &moved);
+  let mut saved = Ok(());
+  if moved.is_ok() {
+    set_mode(&mut settings);
+    saved = write_settings(&config_root, &settings);
+    *state.data_dir.lock().unwrap() = Some(dest.clone());
+  }
+
+  // Bring everything back up either way (at the old location if the move failed).
+  let library = state.data_dir.lock().unwrap().clone().unwrap_or(data_dir);
+  let restarted = restart_next_server(&app, "migration");
+  let db_path = resolve_db_path(&config_root, &library, &settings);
+  start_worker_if_allowed(&app, &db_path, &config_root, &settings);
+  if let Some(w) = window.as_ref() {
+    dispatch_web_event(
+      w,
+      if moved.is_ok() { "moondream:migration:done" } else { "moondream:migration:failed" },
+      None,
+    );
+  }
+
+  moved.map_err(|e| format!("Failed to move the library: {}", e))?;
+  saved.map_err(|e| format!("Moved the library, but settings.json still points at the old location: {}", e))?;
+  restarted?;
+  Ok(dest.to_string_lossy().to_string())
+}
+
+pub(crate) const STORAGE_WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+pub(crate) fn storage_online(data_dir: &PathBuf) -> bool {
+  data_dir.is_dir() && dir_writable(data_dir)
+}
+
+pub(crate) const READ_ONLY_BANNER_JS: &str = r#"
+  (function () {
+    if (document.getElementById("md-read-only")) return;
+    var el = document.createElement("div");
+    el.id = "md-read-only";
+    el.style.cssText = "position:fixed;top:0;left:0;right:0;z-index:2147483646;display:flex;gap:12px;align-items:center;justify-content:center;padding:8px 12px;background:#7a5b00;color:#fff;font:13px -apple-system,BlinkMacSystemFont,system-ui,sans-serif";
+    var text = document.createElement("span");
+    text.textContent = "The library is read-only, so changes can't be saved and AI processing is paused.";
+    var b = document.createElement("button");
+    b.textContent = "Check Again";
+    b.onclick = function () {
+      b.disabled = true;
+      window.__TAURI_INVOKE__("recheck_storage").then(
+        function () { b.disabled = false; },
+        function () { b.disabled = false; }
+      );
+    };
+    el.appendChild(text);
+    el.appendChild(b);
+    (document.body || document.documentElement).appendChild(el);
+  })();
+"#;
+
+pub(crate) const CLEAR_READ_ONLY_BANNER_JS: &str = r#"
+  (function () {
+    var el = document.getElementById("md-read-only");
+    if (el) el.remove();
+  })();
+"#;
+
+// Probes the data dir and applies any change: state, worker pause, banner and one of
+// `moondream:storage:online` / `:offline` / `:read-only`. `first` re-syncs the pause flag even
+// without a change (clears one left behind by a crash).
+pub(crate) fn probe_storage(app: &tauri::AppHandle, config_root: &PathBuf, data_dir: &PathBuf, pause_worker: bool, first: bool) {
+  let state = app.state::<ServerState>();
+  let online = storage_online(data_dir);
+  // A write test failing on a dir that's still there.
+  let read_only = !online && data_dir.is_dir();
+  let was = (!*state.storage_offline.lock().unwrap(), *state.storage_read_only.lock().unwrap());
+  let changed = was != (online, read_only);
+  if !changed && !first {
+    return;
+  }
+  *state.storage_offline.lock().unwrap() = !online;
+  *state.storage_read_only.lock().unwrap() = read_only;
+  sync_worker_pause(&state, config_root, pause_worker);
+  if !changed {
+    return;
+  }
+  if read_only {
+    host_log(LogLevel::Warn, &format!("{} is read-only; pausing the worker", data_dir.display()));
+  }
+  if let Some(window) = app.get_window("main") {
+    let _ = window.eval(if read_only { READ_ONLY_BANNER_JS } else { CLEAR_READ_ONLY_BANNER_JS });
+    let event = if online {
+      "moondream:storage:online"
+    } else if read_only {
+      "moondream:storage:read-only"
+    } else {
+      "moondream:storage:offline"
+    };
+    dispatch_web_event(&window, event, None);
+  }
+}
+
+// Polls the data dir so an unmounted drive, iCloud sign-out or a volume turning read-only surfaces
+// as an event instead of silent IO errors.
+pub(crate) fn watch_storage(app: tauri::AppHandle, pause_worker: bool) {
+  std::thread::spawn(move || {
+    let mut first = true;
+    loop {
+      let state = app.state::<ServerState>();
+      let config_root = state.config_root.lock().unwrap().clone();
+      let data_dir = state.data_dir.lock().unwrap().clone();
+      if let (Some(config_root), Some(data_dir)) = (config_root, data_dir) {
+        probe_storage(&app, &config_root, &data_dir, pause_worker, first);
+        first = false;
+      }
+      std::thread::sleep(STORAGE_WATCH_INTERVAL);
+    }
+  });
+}
+
+// Re-probes right away (e.g. after the user fixed permissions); a writable library resumes the worker.
+#[tauri::command]
+pub(crate) fn recheck_storage(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<StorageStatus, String> {
+  let config_root = managed_config_root(&app, &state)?;
+  let data_dir = state
+    .data_dir
+    .lock()
+    .unwrap()
+    .clone()
+    .ok_or_else(|| "The library location isn't known yet".to_string())?;
+  probe_storage(&app, &config_root, &data_dir, pause_worker_when_offline(&read_settings(&config_root)), false);
+  Ok(storage_status(state))
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct StorageStatus {
+  pub(crate) path: Option<String>,
+  pub(crate) online: bool,
+  pub(crate) read_only: bool,
+  // Why the configured library was ignored at launch (see `data_dir_layout_error`).
+  pub(crate) layout_error: Option<String>,
+}
+
+// For pages that mount after the last watchdog event fired.
+#[tauri::command]
+pub(crate) fn storage_status(state: tauri::State<ServerState>) -> StorageStatus {
+  StorageStatus {
+    path: state
+      .data_dir
+      .lock()
+      .unwrap()
+      .as_ref()
+      .map(|p| p.to_string_lossy().to_string()),
+    online: !*state.storage_offline.lock().unwrap(),
+    read_only: *state.storage_read_only.lock().unwrap(),
+    layout_error: state.storage_layout_error.lock().unwrap().clone(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn library_names() {
+    assert_eq!(validate_library_name("  Moodboards "), Ok("Moodboards".to_string()));
+    assert_eq!(validate_library_name("Références 2024"), Ok("Références 2024".to_string()));
+    assert!(validate_library_name("").is_err());
+    assert!(validate_library_name("   ").is_err());
+    assert!(validate_library_name(".hidden").is_err());
+    assert!(validate_library_name("a/b").is_err());
+    assert!(validate_library_name("a:b").is_err());
+    assert!(validate_library_name("tab\there").is_err());
+    assert!(validate_library_name(&"x".repeat(256)).is_err());
+  }
+
+  #[test]
+  fn db_path_follows_db_location() {
+    let root = scratch_dir("db-path");
+    let (config_root, data_dir) = (root.join("config"), root.join("library"));
+    let with_data = AppSettings::default();
+    let local = AppSettings {
+      storage: Some(StorageSettings {
+        mode: None,
+        icloud_path: None,
+        migration: None,
+        pause_worker_when_offline: None,
+        db_location: Some("local".to_string()),
+      }),
+      ..Default::default()
+    };
+    assert_eq!(resolve_db_path(&config_root, &data_dir, &with_data), data_dir.join("moondream.sqlite3"));
+    assert_eq!(resolve_db_path(&config_root, &data_dir, &local), config_root.join("moondream.sqlite3"));
+    // Not moved yet: keep using the existing DB instead of starting an empty one.
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::write(data_dir.join("moondream.sqlite3"), b"").unwrap();
+    assert_eq!(resolve_db_path(&config_root, &data_dir, &local), data_dir.join("moondream.sqlite3"));
+    std::fs::create_dir_all(&config_root).unwrap();
+    std::fs::write(config_root.join("moondream.sqlite3"), b"").unwrap();
+    assert_eq!(resolve_db_path(&config_root, &data_dir, &local), config_root.join("moondream.sqlite3"));
+    let _ = std::fs::remove_dir_all(&root);
+  }
+
+  #[test]
+  fn thumbnail_keys_are_stable() {
+    // Reference values for 64-bit FNV-1a.
+    assert_eq!(fnv1a64(b""), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(fnv1a64(b"a"), 0xaf63_dc4c_8601_ec8c);
+    assert_eq!(fnv1a64(b"foobar"), 0x8594_4171_f739_67e8);
+  }
+
+  #[test]
+  fn source_speed_is_read_from_existing_files() {
+    let dir = scratch_dir("source-speed");
+    std::fs::write(dir.join("small.jpg"), vec![0u8; 1024]).unwrap();
+    assert_eq!(sample_read_mbps(&library_files(&dir)), None);
+    std::fs::write(dir.join("large.mov"), vec![0u8; 2 * SOURCE_SAMPLE_MIN_BYTES]).unwrap();
+    assert!(sample_read_mbps(&library_files(&dir)).is_some_and(|m| m > 0.0));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn icloud_conflict_names() {
+    assert_eq!(conflict_original_name("photo 2.jpg").as_deref(), Some("photo.jpg"));
+    assert_eq!(conflict_original_name("my photo 12.jpeg").as_deref(), Some("my photo.jpeg"));
+    assert_eq!(conflict_original_name("moondream 2.sqlite3").as_deref(), Some("moondream.sqlite3"));
+    assert_eq!(conflict_original_name("settings 3").as_deref(), Some("settings"));
+    assert_eq!(conflict_original_name("photo.jpg"), None);
+    assert_eq!(conflict_original_name("photo 1.jpg"), None);
+    assert_eq!(conflict_original_name("photo 2a.jpg"), None);
+    assert_eq!(conflict_original_name(" 2.jpg"), None);
+  }
+}
@@ -0,0 +1,686 @@
+use crate::*;
+
+// Path + query + fragment of the page the webview is showing, if it's served by our local server.
+pub(crate) fn current_route(window: &tauri::Window) -> Option<String> {
+  let url = window.url();
+  if url.host_str() != Some("127.0.0.1") && url.scheme() != UNIX_PROXY_SCHEME {
+    return None;
+  }
+  let mut route = url.path().to_string();
+  if let Some(q) = url.query() {
+    route.push('?');
+    route.push_str(q);
+  }
+  if let Some(f) = url.fragment() {
+    route.push('#');
+    route.push_str(f);
+  }
+  Some(route)
+}
+
+pub(crate) const ROUTE_HISTORY_MAX: usize = 100;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct RouteEntry {
+  pub(crate) route: String,
+  pub(crate) title: Option<String>,
+  #[serde(default)]
+  pub(crate) at: u64, // unix seconds, set by the host
+}
+
+// The UI emits `moondream:route-changed` with `{"route": "/projects/..", "title": ".."}` on every
+// navigation. Kept in ServerState, so it outlives reloads and server restarts (not app restarts).
+pub(crate) fn record_route_changes(app: &tauri::AppHandle) {
+  let handle = app.clone();
+  app.listen_global("moondream:route-changed", move |event| {
+    let Some(mut entry) = event.payload().and_then(|p| serde_json::from_str::<RouteEntry>(p).ok()) else {
+      return;
+    };
+    entry.at = unix_now_secs();
+    let state = handle.state::<ServerState>();
+    let mut history = state.route_history.lock().unwrap();
+    // Reloads and in-page re-renders re-announce the same route; just refresh it.
+    if history.back().map(|last| last.route == entry.route).unwrap_or(false) {
+      history.pop_back();
+    }
+    history.push_back(entry);
+    while history.len() > ROUTE_HISTORY_MAX {
+      history.pop_front();
+    }
+  });
+}
+
+// Newest first.
+#[tauri::command]
+pub(crate) fn route_history(state: tauri::State<ServerState>, limit: usize) -> Vec<RouteEntry> {
+  state.route_history.lock().unwrap().iter().rev().take(limit).cloned().collect()
+}
+
+// How long `cycle_window` keeps the window hidden, and how often it checks the server meanwhile.
+pub(crate) const CYCLE_HIDDEN: Duration = Duration::from_millis(1500);
+
+pub(crate) const CYCLE_PROBE_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Serialize)]
+pub(crate) struct CycleReport {
+  pub(crate) ok: bool,
+  // Every health probe while hidden (and right after showing) returned 200.
+  pub(crate) server_healthy: bool,
+  pub(crate) health_probes: u32,
+  pub(crate) worker_stayed_up: bool,
+  pub(crate) route_before: Option<String>,
+  pub(crate) route_after: Option<String>,
+  pub(crate) route_restored: bool,
+  pub(crate) zoom_before: Option<f64>,
+  pub(crate) zoom_after: Option<f64>,
+  pub(crate) zoom_restored: bool,
+  pub(crate) hidden_ms: u64,
+}
+
+// Regression check for hide/show: hides the main window, watches the server, shows it again and
+// re-applies globals, then compares route and zoom with what they were before.
+#[tauri::command(async)]
+pub(crate) fn cycle_window(app: tauri::AppHandle) -> Result<CycleReport, String> {
+  let state = app.state::<ServerState>();
+  let window = app.get_window("main").ok_or_else(|| "No main window".to_string())?;
+  let addr = current_server_addr(&state).ok_or_else(|| "The local server is not running".to_string())?;
+  let route_before = current_route(&window);
+  let zoom_before = *state.canvas_zoom.lock().unwrap();
+  let worker_before = worker_running(&state);
+
+  window.hide().map_err(|e| e.to_string())?;
+  let start = Instant::now();
+  let (mut probes, mut healthy) = (0, true);
+  while start.elapsed() < CYCLE_HIDDEN {
+    probes += 1;
+    healthy &= server_status(&addr, "/api/health", STATUS_PROBE_TIMEOUT) == Some(200);
+    std::thread::sleep(CYCLE_PROBE_INTERVAL);
+  }
+  let hidden_ms = start.elapsed().as_millis() as u64;
+  window.show().map_err(|e| e.to_string())?;
+  let _ = window.set_focus();
+  reinject_globals(&window, &state);
+  if let Some(zoom) = zoom_before {
+    dispatch_web_event(&window, "moondream:canvas:set-zoom", Some(serde_json::json!({ "zoom": zoom, "percent": zoom * 100.0 })));
+  }
+  std::thread::sleep(CYCLE_PROBE_INTERVAL);
+  probes += 1;
+  healthy &= server_status(&addr, "/api/health", STATUS_PROBE_TIMEOUT) == Some(200);
+
+  let route_after = current_route(&window);
+  let zoom_after = *state.canvas_zoom.lock().unwrap();
+  let worker_stayed_up = !worker_before || worker_running(&state);
+  let route_restored = route_after == route_before;
+  let zoom_restored = zoom_after == zoom_before;
+  Ok(CycleReport {
+    ok: healthy && worker_stayed_up && route_restored && zoom_restored,
+    server_healthy: healthy,
+    health_probes: probes,
+    worker_stayed_up,
+    route_before,
+    route_after,
+    route_restored,
+    zoom_before,
+    zoom_after,
+    zoom_restored,
+    hidden_ms,
+  })
+}
+
+// Sets the webview engine's own switches; only has an effect before the webview is created.
+// Variables the user already set win. Returns the effective mode for `about_info`.
+pub(crate) fn apply_hardware_acceleration(settings: &AppSettings) -> &'static str {
+  let Some(enabled) = settings.ui.as_ref().and_then(|u| u.hardware_acceleration) else {
+    return "default";
+  };
+  let set_default = |key: &str, value: &str| {
+    if std::env::var_os(key).is_none() {
+      std::env::set_var(key, value);
+    }
+  };
+  if cfg!(target_os = "linux") {
+    if enabled {
+      set_default("WEBKIT_FORCE_COMPOSITING_MODE", "1");
+    } else {
+      set_default("WEBKIT_DISABLE_COMPOSITING_MODE", "1");
+      set_default("WEBKIT_DISABLE_DMABUF_RENDERER", "1");
+    }
+  } else if cfg!(target_os = "windows") {
+    // WebView2 reads extra Chromium switches from here; GPU is already on by default.
+    if !enabled {
+      let key = "WEBVIEW2_ADDITIONAL_BROWSER_ARGUMENTS";
+      let current = std::env::var(key).unwrap_or_default();
+      if !current.contains("--disable-gpu") {
+        std::env::set_var(key, format!("{} --disable-gpu", current).trim());
+      }
+    }
+  } else {
+    // WKWebView has no switch for this.
+    return "unsupported";
+  }
+  if enabled {
+    "on"
+  } else {
+    "off"
+  }
+}
+
+// `enabled` = None goes back to the platform default. Returns whether a relaunch is needed.
+#[tauri::command]
+pub(crate) fn set_hardware_acceleration(
+  app: tauri::AppHandle,
+  state: tauri::State<ServerState>,
+  enabled: Option<bool>,
+) -> Result<bool, String> {
+  let config_root = app_config_root(&app)
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let mut settings = try_read_settings(&config_root)?;
+  settings.ui.get_or_insert_with(Default::default).hardware_acceleration = enabled;
+  write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+  let launched = state
+    .launch_settings
+    .lock()
+    .unwrap()
+    .as_ref()
+    .and_then(|s| s.ui.as_ref())
+    .and_then(|u| u.hardware_acceleration);
+  Ok(launched != enabled)
+}
+
+// (name, window appearance, accent). "system" follows the OS; unset keeps tauri.conf.json's Dark.
+pub(crate) const THEME_PRESETS: &[(&str, &str, Option<&str>)] = &[
+  ("system", "system", None),
+  ("midnight", "dark", Some("#7c5cff")),
+  ("graphite", "dark", Some("#8e8e93")),
+  ("daylight", "light", Some("#0a84ff")),
+  ("sand", "light", Some("#c8894a")),
+];
+
+pub(crate) fn theme_preset(settings: &AppSettings) -> Option<&'static (&'static str, &'static str, Option<&'static str>)> {
+  let name = settings.ui.as_ref()?.theme.as_deref()?;
+  THEME_PRESETS.iter().find(|(n, _, _)| n.eq_ignore_ascii_case(name.trim()))
+}
+
+// Window theme for tauri.conf.json's main window: None = follow the OS.
+pub(crate) fn native_theme(settings: &AppSettings) -> Option<tauri::Theme> {
+  match theme_preset(settings).map(|(_, appearance, _)| *appearance) {
+    Some("light") => Some(tauri::Theme::Light),
+    Some("system") => None,
+    _ => Some(tauri::Theme::Dark),
+  }
+}
+
+// Neither Tauri 1 nor std can tint a title bar, so the accent only reaches the webview for now.
+pub(crate) fn native_accent_supported() -> bool {
+  false
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct ThemeInfo {
+  pub(crate) name: String,
+  pub(crate) appearance: String, // "system" | "dark" | "light"
+  pub(crate) accent: Option<String>,
+  pub(crate) active: bool,
+}
+
+pub(crate) fn theme_info(preset: &(&str, &str, Option<&str>), active: bool) -> ThemeInfo {
+  ThemeInfo {
+    name: preset.0.to_string(),
+    appearance: preset.1.to_string(),
+    accent: preset.2.map(|a| a.to_string()),
+    active,
+  }
+}
+
+// `window.__MOONDREAM_THEME__` ({ name, appearance, accent } or null) plus `moondream:theme:changed`.
+pub(crate) fn inject_theme(window: &tauri::Window, settings: &AppSettings) {
+  let theme = theme_preset(settings).map(|p| theme_info(p, true));
+  let json = serde_json::to_string(&theme).unwrap_or_else(|_| "null".to_string());
+  let _ = window.eval(&format!("window.__MOONDREAM_THEME__ = {};", json));
+  dispatch_web_event(window, "moondream:theme:changed", serde_json::to_value(&theme).ok());
+}
+
+#[tauri::command]
+pub(crate) fn list_themes(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<Vec<ThemeInfo>, String> {
+  let config_root = managed_config_root(&app, &state)?;
+  let active = theme_preset(&read_settings(&config_root)).map(|p| p.0);
+  Ok(THEME_PRESETS.iter().map(|p| theme_info(p, Some(p.0) == active)).collect())
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct ThemeApplied {
+  pub(crate) theme: ThemeInfo,
+  // The window's light/dark theme is fixed when it's created.
+  pub(crate) relaunch_required: bool,
+  pub(crate) native_accent: bool,
+}
+
+#[tauri::command]
+pub(crate) fn apply_theme(app: tauri::AppHandle, state: tauri::State<ServerState>, name: String) -> Result<ThemeApplied, String> {
+  let preset = THEME_PRESETS
+    .iter()
+    .find(|(n, _, _)| n.eq_ignore_ascii_case(name.trim()))
+    .ok_or_else(|| format!("Unknown theme \"{}\"", name.trim()))?;
+  let config_root = managed_config_root(&app, &state)?;
+  let mut settings = try_read_settings(&config_root)?;
+  settings.ui.get_or_insert_with(Default::default).theme = Some(preset.0.to_string());
+  write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+  if let Some(window) = app.get_window("main") {
+    inject_theme(&window, &settings);
+  }
+  let launched = state.launch_settings.lock().unwrap().clone().unwrap_or_default();
+  Ok(ThemeApplied {
+    theme: theme_info(preset, true),
+    relaunch_required: native_theme(&launched) != native_theme(&settings),
+    native_accent: native_accent_supported(),
+  })
+}
+
+pub(crate) fn dispatch_web_event(window: &tauri::Window, event_name: &str, detail: Option<serde_json::Value>) {
+  // Fire a CustomEvent in the webview so the Next.js UI can react.
+  // `detail` (JSON) lets the host fully specify the action instead of relying on UI state.
+  // Note: this runs after the webview has navigated to http://127.0.0.1:<port>/.
+  let detail = detail
+    .and_then(|d| serde_json::to_string(&d).ok())
+    .map(|d| format!(", {{ detail: {} }}", d))
+    .unwrap_or_default();
+  let js = format!(
+    "window.dispatchEvent(new CustomEvent({:?}{}));",
+    event_name, detail
+  );
+  let _ = window.eval(&js);
+}
+
+pub(crate) fn validate_layout_name(name: &str) -> Result<String, String> {
+  let name = name.trim();
+  if name.is_empty() {
+    return Err("Layout name is empty".to_string());
+  }
+  if name.chars().count() > 64 {
+    return Err("Layout name is too long".to_string());
+  }
+  Ok(name.to_string())
+}
+
+// (x, y, width, height) in physical pixels.
+pub(crate) type Rect = (i32, i32, u32, u32);
+
+// Keeps a restored window on a connected monitor: unchanged if it fits entirely on the monitor
+// holding its center; otherwise shrunk/moved onto that monitor (or the first one if it's off-screen).
+pub(crate) fn fit_to_monitors(rect: Rect, monitors: &[Rect]) -> Rect {
+  let (x, y, w, h) = rect;
+  let (cx, cy) = (x as i64 + w as i64 / 2, y as i64 + h as i64 / 2);
+  let contains = |m: &Rect| {
+    cx >= m.0 as i64 && cx < m.0 as i64 + m.2 as i64 && cy >= m.1 as i64 && cy < m.1 as i64 + m.3 as i64
+  };
+  let Some(m) = monitors.iter().find(|m| contains(m)).or_else(|| monitors.first()) else {
+    return rect;
+  };
+  let w = w.min(m.2);
+  let h = h.min(m.3);
+  let x = x.clamp(m.0, m.0 + (m.2 - w) as i32);
+  let y = y.clamp(m.1, m.1 + (m.3 - h) as i32);
+  (x, y, w, h)
+}
+
+#[tauri::command]
+pub(crate) fn save_layout(window: tauri::Window, name: String, zoom: Option<f64>) -> Result<(), String> {
+  let name = validate_layout_name(&name)?;
+  let pos = window.outer_position().map_err(|e| e.to_string())?;
+  let size = window.outer_size().map_err(|e| e.to_string())?;
+  let config_root = app_config_root(&window.app_handle())
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let mut settings = try_read_settings(&config_root)?;
+  settings.layouts.get_or_insert_with(Default::default).insert(
+    name,
+    LayoutPreset {
+      x: pos.x,
+      y: pos.y,
+      width: size.width,
+      height: size.height,
+      zoom,
+    },
+  );
+  write_settings(&config_root, &settings).map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+// The web UI applies the zoom: listen for `moondream:layout:apply` (detail: { name, zoom }).
+#[tauri::command]
+pub(crate) fn apply_layout(window: tauri::Window, name: String) -> Result<(), String> {
+  let name = validate_layout_name(&name)?;
+  let config_root = app_config_root(&window.app_handle())
+    .ok_or_else(|| "Missing app_data_dir".to_string())?;
+  let preset = read_settings(&config_root)
+    .layouts
+    .and_then(|l| l.get(&name).cloned())
+    .ok_or_else(|| format!("No layout named \"{}\"", name))?;
+
+  let monitors: Vec<Rect> = window
+    .available_monitors()
+    .unwrap_or_default()
+    .iter()
+    .map(|m| (m.position().x, m.position().y, m.size().width, m.size().height))
+    .collect();
+  let (x, y, w, h) = fit_to_monitors((preset.x, preset.y, preset.width, preset.height), &monitors);
+  window
+    .set_size(tauri::PhysicalSize::new(w, h))
+    .map_err(|e| e.to_string())?;
+  window
+    .set_position(tauri::PhysicalPosition::new(x, y))
+    .map_err(|e| e.to_string())?;
+
+  // A non-finite zoom serializes as null, same as none saved.
+  dispatch_web_event(
+    &window,
+    "moondream:layout:apply",
+    Some(serde_json::json!({ "name": name, "zoom": preset.zoom.filter(|z| z.is_finite()) })),
+  );
+  Ok(())
+}
+
+#[tauri::command]
+pub(crate) fn list_layouts(app: tauri::AppHandle) -> Vec<String> {
+  app_config_root(&app)
+    .and_then(|c| read_settings(&c).layouts)
+    .map(|l| l.keys().cloned().collect())
+    .unwrap_or_default()
+}
+
+// Physical pixels, like `LayoutPreset`.
+#[derive(Clone, Serialize)]
+pub(crate) struct MonitorInfo {
+  pub(crate) name: Option<String>,
+  pub(crate) x: i32,
+  pub(crate) y: i32,
+  pub(crate) width: u32,
+  pub(crate) height: u32,
+  pub(crate) scale_factor: f64,
+  pub(crate) primary: bool,
+  // The monitor the main window is (mostly) on.
+  pub(crate) current: bool,
+}
+
+#[tauri::command]
+pub(crate) fn monitors(window: tauri::Window) -> Vec<MonitorInfo> {
+  // Monitor has no id; name + origin is unique enough to match it up.
+  let key = |m: &tauri::Monitor| (m.name().cloned(), m.position().x, m.position().y);
+  let primary = window.primary_monitor().ok().flatten().map(|m| key(&m));
+  let current = window.current_monitor().ok().flatten().map(|m| key(&m));
+  window
+    .available_monitors()
+    .unwrap_or_default()
+    .iter()
+    .map(|m| MonitorInfo {
+      name: m.name().cloned(),
+      x: m.position().x,
+      y: m.position().y,
+      width: m.size().width,
+      height: m.size().height,
+      scale_factor: m.scale_factor(),
+      primary: primary.as_ref() == Some(&key(m)),
+      current: current.as_ref() == Some(&key(m)),
+    })
+    .collect()
+}
+
+// Mirrors the `/projects/:id` match used by the Settings menu handlers.
+pub(crate) fn project_id_from_path(path: &str) -> Option<String> {
+  let rest = path.strip_prefix("/projects/")?;
+  let id = rest.split(['/', '?', '#']).next().unwrap_or("");
+  if id.is_empty() {
+    None
+  } else {
+    Some(id.to_string())
+  }
+}
+
+#[tauri::command]
+pub(crate) fn copy_project_link(window: tauri::Window) -> Option<String> {
+  let id = project_id_from_path(window.url().path())?;
+  let link = format!("moondream://projects/{}", id);
+  let mut clipboard = window.app_handle().clipboard_manager();
+  if clipboard.write_text(link.clone()).is_err() {
+    return None;
+  }
+  dispatch_web_event(&window, "moondream:project-link:copied", None);
+  Some(link)
+}
+
+pub(crate) const WEBKITGTK_HELP_URL: &str = "https://tauri.app/v1/guides/getting-started/prerequisites#setting-up-linux";
+
+pub(crate) const WEBVIEW2_HELP_URL: &str = "https://developer.microsoft.com/microsoft-edge/webview2/";
+
+// (message, help link) for a failure to bring up the window, tailored to the platform's webview.
+pub(crate) fn webview_failure_message(error: &str) -> (String, Option<&'static str>) {
+  if cfg!(target_os = "linux") {
+    (
+      format!(
+        "Reference couldn't open its window. This usually means WebKitGTK is missing or broken.\n\n\
+         Install it with your package manager (e.g. `sudo apt install libwebkit2gtk-4.0-37` on Debian/Ubuntu, \
+         `sudo dnf install webkit2gtk4.0` on Fedora) and try again.\n\nDetails: {}",
+        error
+      ),
+      Some(WEBKITGTK_HELP_URL),
+    )
+  } else if cfg!(target_os = "windows") {
+    (
+      format!(
+        "Reference couldn't open its window. This usually means the Microsoft Edge WebView2 Runtime \
+         is missing or damaged.\n\nInstall the Evergreen runtime from Microsoft and try again.\n\nDetails: {}",
+        error
+      ),
+      Some(WEBVIEW2_HELP_URL),
+    )
+  } else {
+    (
+      format!("Reference couldn't open its window.\n\nDetails: {}", error),
+      None,
+    )
+  }
+}
+
+// The event loop never started, so Tauri's dialogs aren't usable: use the platform's own tools.
+// Returns whether the user asked to open the help link.
+// An AppleScript string literal; only `\` and `"` are special (anything else, newlines and
+// non-ASCII included, goes in as is).
+pub(crate) fn applescript_string(s: &str) -> String {
+  format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub(crate) fn native_error_dialog(title: &str, message: &str, has_link: bool) -> bool {
+  if cfg!(target_os = "macos") {
+    let buttons = if has_link { "{\"Quit\", \"Open Help\"}" } else { "{\"Quit\"}" };
+    let script = format!(
+      "display alert {} message {} as critical buttons {} default button 1",
+      applescript_string(title),
+      applescript_string(message),
+      buttons
+    );
+    return command_stdout("osascript", &["-e", &script])
+      .map(|out| out.contains("Open Help"))
+      .unwrap_or(false);
+  }
+  if cfg!(target_os = "windows") {
+    let buttons = if has_link { "YesNo" } else { "OK" };
+    let text = if has_link {
+      format!("{}\n\nOpen the download page?", message)
+    } else {
+      message.to_string()
+    };
+    let script = format!(
+      "Add-Type -AssemblyName System.Windows.Forms; \
+       [System.Windows.Forms.MessageBox]::Show('{}', '{}', '{}', 'Error')",
+      text.replace('\'', "''"),
+      title.replace('\'', "''"),
+      buttons
+    );
+    return command_stdout("powershell", &["-NoProfile", "-Command", &script])
+      .map(|out| out.trim() == "Yes")
+      .unwrap_or(false);
+  }
+  // Linux: whichever dialog tool the desktop ships; none of these need a webview.
+  let mut zenity = Command::new("zenity");
+  if has_link {
+    zenity.args(["--question", "--ok-label=Open Help", "--cancel-label=Quit"]);
+  } else {
+    zenity.arg("--error");
+  }
+  if let Ok(status) = zenity.arg(format!("--title={}", title)).arg(format!("--text={}", message)).status() {
+    return has_link && status.success();
+  }
+  if let Ok(status) = Command::new("kdialog")
+    .args(["--title", title])
+    .arg(if has_link { "--warningyesno" } else { "--error" })
+    .arg(message)
+    .status()
+  {
+    return has_link && status.success();
+  }
+  let _ = Command::new("xmessage").args(["-center", message]).status();
+  false
+}
+
+pub(crate) fn report_webview_failure(error: &str) -> ! {
+  host_log(LogLevel::Error, &format!("failed to start the webview: {}", error));
+  let (message, link) = webview_failure_message(error);
+  if native_error_dialog("Reference can't start", &message, link.is_some()) {
+    if let Some(link) = link {
+      let _ = open_in_file_manager(&PathBuf::from(link));
+    }
+  }
+  std::process::exit(1);
+}
+
+pub(crate) fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+  if let Some(s) = panic.downcast_ref::<&str>() {
+    s.to_string()
+  } else if let Some(s) = panic.downcast_ref::<String>() {
+    s.clone()
+  } else {
+    "unknown panic".to_string()
+  }
+}
+
+pub(crate) const DEFER_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub(crate) fn defer_until_unlock(settings: &AppSettings) -> bool {
+  settings.security.as_ref().and_then(|s| s.defer_until_unlock).unwrap_or(false)
+}
+
+// Some(true) = the screen is locked, None = couldn't tell (treated as unlocked).
+pub(crate) fn session_locked() -> Option<bool> {
+  if cfg!(target_os = "macos") {
+    // The window server only lists this key while the screen is locked.
+    let out = Command::new("ioreg").args(["-n", "Root", "-d1"]).stderr(Stdio::null()).output().ok()?;
+    let text = String::from_utf8_lossy(&out.stdout).to_string();
+    Some(text.contains("\"CGSSessionScreenIsLocked\"=Yes"))
+  } else if cfg!(windows) {
+    // LogonUI runs while the lock screen (or sign-in screen) is up.
+    let out = Command::new("tasklist")
+      .args(["/FI", "IMAGENAME eq LogonUI.exe", "/NH"])
+      .stderr(Stdio::null())
+      .output()
+      .ok()?;
+    Some(String::from_utf8_lossy(&out.stdout).contains("LogonUI.exe"))
+  } else {
+    let session = std::env::var("XDG_SESSION_ID").ok()?;
+    let out = Command::new("loginctl")
+      .args(["show-session", &session, "-p", "LockedHint", "--value"])
+      .stderr(Stdio::null())
+      .output()
+      .ok()?;
+    match String::from_utf8_lossy(&out.stdout).trim() {
+      "yes" => Some(true),
+      "no" => Some(false),
+      _ => None,
+    }
+  }
+}
+
+// An overlay on the bootstrap page (which keeps polling underneath and takes over once the port is
+// set) or on the app while its services are stopped. `window.__MOONDREAM_HOLD__` names the reason.
+pub(crate) fn show_hold_overlay(window: &tauri::Window, reason: &str, title: &str, message: &str) {
+  let lit = |s: &str| serde_json::to_string(s).unwrap_or_else(|_| "\"\"".to_string());
+  let (reason, title, message) = (lit(reason), lit(title), lit(message));
+  let js = format!(
+    r#"(function () {{
+      window.__MOONDREAM_HOLD__ = {reason};
+      var el = document.getElementById("md-hold");
+      if (!el) {{
+        el = document.createElement("div");
+        el.id = "md-hold";
+        el.style.cssText = "position:fixed;inset:0;z-index:2147483647;display:flex;align-items:center;justify-content:center;background:#111;color:#eee;font-family:-apple-system,BlinkMacSystemFont,system-ui,sans-serif";
+        (document.body || document.documentElement).appendChild(el);
+      }}
+      el.innerHTML =
+        '<div style="max-width:560px;padding:24px">' +
+        '<h2 style="margin:0 0 12px;font-weight:600"></h2>' +
+        '<p style="opacity:.7;font-size:13px"></p>' +
+        '</div>';
+      el.querySelector("h2").textContent = {title};
+      el.querySelector("p").textContent = {message};
+    }})();"#
+  );
+  let _ = window.eval(&js);
+}
+
+pub(crate) const CLEAR_HOLD_JS: &str = r#"
+  (function () {
+    window.__MOONDREAM_HOLD__ = null;
+    var el = document.getElementById("md-hold");
+    if (el) el.remove();
+  })();
+"#;
+
+pub(crate) enum StartupHold {
+  Unlock,
+  Maintenance(String),
+}
+
+// Why startup has to wait, if it does (re-evaluated while waiting).
+pub(crate) fn startup_hold(app: &tauri::AppHandle, settings: &AppSettings) -> Option<StartupHold> {
+  if defer_until_unlock(settings) && session_locked() == Some(true) {
+    return Some(StartupHold::Unlock);
+  }
+  active_maintenance_window(&app.state::<ServerState>(), settings).map(StartupHold::Maintenance)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MAIN: Rect = (0, 0, 1920, 1080);
+
+  #[test]
+  fn layout_that_fits_is_unchanged() {
+    assert_eq!(fit_to_monitors((100, 100, 800, 600), &[MAIN]), (100, 100, 800, 600));
+    let left: Rect = (-1280, 0, 1280, 1024);
+    assert_eq!(fit_to_monitors((-1000, 100, 800, 600), &[left, MAIN]), (-1000, 100, 800, 600));
+  }
+
+  #[test]
+  fn layout_off_screen_moves_to_first_monitor() {
+    assert_eq!(fit_to_monitors((5000, 5000, 800, 600), &[MAIN]), (1120, 480, 800, 600));
+  }
+
+  #[test]
+  fn layout_is_clamped_to_its_monitor() {
+    assert_eq!(fit_to_monitors((0, 0, 3000, 2000), &[MAIN]), (0, 0, 1920, 1080));
+    let right: Rect = (1920, 0, 1280, 1024);
+    assert_eq!(fit_to_monitors((2000, 100, 1600, 900), &[MAIN, right]), (1920, 100, 1280, 900));
+  }
+
+  #[test]
+  fn layout_without_monitors_is_kept() {
+    assert_eq!(fit_to_monitors((10, 20, 300, 400), &[]), (10, 20, 300, 400));
+  }
+
+  #[test]
+  fn applescript_literals() {
+    assert_eq!(applescript_string("plain"), "\"plain\"");
+    assert_eq!(applescript_string(r#"say "hi" \ bye"#), r#""say \"hi\" \\ bye""#);
+    // Passed through as is, where `{:?}` would have produced `\u{2019}` and `\n`.
+    assert_eq!(applescript_string("can’t\nstart"), "\"can’t\nstart\"");
+  }
+}
@@ -0,0 +1,1198 @@
+use crate::*;
+
+pub(crate) const WORKER_CONCURRENCY_RANGE: std::ops::RangeInclusive<u8> = 1..=16;
+
+pub(crate) fn worker_concurrency(settings: &AppSettings) -> u8 {
+  let requested = settings.worker.as_ref().and_then(|w| w.concurrency).unwrap_or(1);
+  requested.clamp(*WORKER_CONCURRENCY_RANGE.start(), *WORKER_CONCURRENCY_RANGE.end())
+}
+
+// Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's days_from_civil).
+pub(crate) fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+  let y = if m <= 2 { y - 1 } else { y };
+  let era = y.div_euclid(400);
+  let yoe = y - era * 400;
+  let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  era * 146097 + doe - 719468
+}
+
+// Formats like SQLite's `datetime('now')` (UTC), which is what `assets.created_at` holds,
+// so the worker can compare the cutoff as a plain string.
+pub(crate) fn sqlite_datetime(unix: i64) -> String {
+  let (days, secs) = (unix.div_euclid(86400), unix.rem_euclid(86400));
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let d = doy - (153 * mp + 2) / 5 + 1;
+  let m = if mp < 10 { mp + 3 } else { mp - 9 };
+  let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+  format!(
+    "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+    y,
+    m,
+    d,
+    secs / 3600,
+    secs % 3600 / 60,
+    secs % 60
+  )
+}
+
+// Accepts `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM[:SS[.fff]]` with an optional `Z` / `±HH:MM` offset
+// (no offset = UTC). Returns unix seconds.
+pub(crate) fn parse_iso_timestamp(s: &str) -> Option<i64> {
+  let s = s.trim();
+  let num = |t: &str| -> Option<i64> {
+    if t.is_empty() || !t.bytes().all(|b| b.is_ascii_digit()) {
+      return None;
+    }
+    t.parse().ok()
+  };
+  let (date, rest) = match s.find(['T', ' ']) {
+    Some(i) => (&s[..i], &s[i + 1..]),
+    None => (s, ""),
+  };
+  let mut dp = date.split('-');
+  let (y, m, d) = (num(dp.next()?)?, num(dp.next()?)?, num(dp.next()?)?);
+  if dp.next().is_some() || !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+    return None;
+  }
+  let (time, offset) = if let Some(t) = rest.strip_suffix('Z') {
+    (t, 0)
+  } else if let Some(i) = rest.rfind(['+', '-']) {
+    let (sign, tz) = (if &rest[i..i + 1] == "-" { -1 } else { 1 }, &rest[i + 1..]);
+    let (oh, om) = tz.split_once(':').unwrap_or((tz, "0"));
+    (&rest[..i], sign * (num(oh)? * 3600 + num(om)? * 60))
+  } else {
+    (rest, 0)
+  };
+  let mut secs = 0;
+  if !time.is_empty() {
+    let time = time.split('.').next()?;
+    let mut tp = time.split(':');
+    let (h, mi) = (num(tp.next()?)?, num(tp.next()?)?);
+    let sec = tp.next().map(num).unwrap_or(Some(0))?;
+    if tp.next().is_some() || h > 23 || mi > 59 || sec > 60 {
+      return None;
+    }
+    secs = h * 3600 + mi * 60 + sec;
+  }
+  Some(days_from_civil(y, m, d) * 86400 + secs - offset)
+}
+
+// Cutoff passed to the worker as MOONDREAM_PROCESS_SINCE ("YYYY-MM-DD HH:MM:SS" UTC). Contract:
+// when set, the worker only claims jobs whose asset `created_at` >= the cutoff; older pending
+// jobs are left as they are.
+pub(crate) fn process_since_cutoff(config_root: &PathBuf, settings: &AppSettings) -> Option<String> {
+  let raw = settings.worker.as_ref().and_then(|w| w.process_since.clone())?;
+  let raw = raw.trim();
+  if raw.is_empty() || raw.eq_ignore_ascii_case("all") {
+    return None;
+  }
+  let unix = if raw.eq_ignore_ascii_case("first_launch") {
+    read_runtime(config_root).first_launch_at.map(|t| t as i64)
+  } else {
+    parse_iso_timestamp(raw)
+  };
+  if unix.is_none() {
+    host_log(
+      LogLevel::Warn,
+      &format!("Ignoring worker.process_since {:?}; processing all assets", raw),
+    );
+  }
+  unix.map(sqlite_datetime)
+}
+
+pub(crate) const WORKER_INFO_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Serialize)]
+pub(crate) struct WorkerRuntimeInfo {
+  pub(crate) path: String,
+  pub(crate) runs: bool, // false = the binary couldn't execute at all (see `error`)
+  pub(crate) version: Option<String>,
+  pub(crate) python: Option<String>,
+  pub(crate) torch: Option<String>,
+  pub(crate) device: Option<String>, // what the worker would select, e.g. "mps" | "cuda" | "cpu"
+  pub(crate) error: Option<String>,
+}
+
+// Worker contract: `moondream-worker --info` prints one JSON object and exits;
+// builds that predate it only understand `--version`.
+#[derive(Deserialize)]
+pub(crate) struct WorkerInfoOutput {
+  pub(crate) version: Option<String>,
+  pub(crate) python: Option<String>,
+  pub(crate) torch: Option<String>,
+  pub(crate) device: Option<String>,
+}
+
+// Ok(None) = still running after `timeout` (killed).
+pub(crate) fn output_with_timeout(program: &PathBuf, arg: &str, timeout: Duration) -> io::Result<Option<std::process::Output>> {
+  let mut child = Command::new(program)
+    .arg(arg)
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()?;
+  let start = Instant::now();
+  while start.elapsed() < timeout {
+    if child.try_wait()?.is_some() {
+      return child.wait_with_output().map(Some);
+    }
+    std::thread::sleep(Duration::from_millis(50));
+  }
+  let _ = child.kill();
+  let _ = child.wait();
+  Ok(None)
+}
+
+pub(crate) fn probe_worker_runtime(worker: &PathBuf) -> WorkerRuntimeInfo {
+  let mut info = WorkerRuntimeInfo {
+    path: worker.to_string_lossy().to_string(),
+    runs: false,
+    version: None,
+    python: None,
+    torch: None,
+    device: None,
+    error: None,
+  };
+  let out = match output_with_timeout(worker, "--info", WORKER_INFO_TIMEOUT) {
+    Ok(Some(out)) => out,
+    Ok(None) => {
+      // It started, so it can run; it just didn't answer in time.
+      info.runs = true;
+      info.error = Some(format!("No answer to --info within {:?}", WORKER_INFO_TIMEOUT));
+      return info;
+    }
+    Err(e) => {
+      info.error = Some(format!("Can't execute {}: {}", worker.display(), e));
+      return info;
+    }
+  };
+  if out.status.success() {
+    if let Ok(parsed) = serde_json::from_slice::<WorkerInfoOutput>(&out.stdout) {
+      info.runs = true;
+      info.version = parsed.version;
+      info.python = parsed.python;
+      info.torch = parsed.torch;
+      info.device = parsed.device;
+      return info;
+    }
+  }
+  match output_with_timeout(worker, "--version", WORKER_INFO_TIMEOUT) {
+    Ok(Some(v)) if v.status.success() => {
+      info.runs = true;
+      info.version = Some(String::from_utf8_lossy(&v.stdout).trim().to_string()).filter(|s| !s.is_empty());
+    }
+    Ok(None) => info.runs = true,
+    _ => {
+      // Both flags failed: report what the binary itself said (e.g. a missing library).
+      let stderr = String::from_utf8_lossy(&out.stderr);
+      let last = stderr.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("").trim().to_string();
+      info.error = Some(if last.is_empty() {
+        format!("{} exited with {}", worker.display(), out.status)
+      } else {
+        last
+      });
+    }
+  }
+  info
+}
+
+// Probed once per session; the bundled binary doesn't change while we run.
+pub(crate) fn worker_runtime(app: &tauri::AppHandle, worker: &PathBuf) -> WorkerRuntimeInfo {
+  let state = app.state::<ServerState>();
+  if let Some(info) = state.worker_runtime.lock().unwrap().clone() {
+    return info;
+  }
+  let info = probe_worker_runtime(worker);
+  host_log(
+    if info.runs { LogLevel::Info } else { LogLevel::Error },
+    &format!(
+      "worker runtime: version={} python={} torch={} device={} error={}",
+      info.version.as_deref().unwrap_or("?"),
+      info.python.as_deref().unwrap_or("?"),
+      info.torch.as_deref().unwrap_or("?"),
+      info.device.as_deref().unwrap_or("?"),
+      info.error.as_deref().unwrap_or("none")
+    ),
+  );
+  *state.worker_runtime.lock().unwrap() = Some(info.clone());
+  info
+}
+
+#[tauri::command(async)]
+pub(crate) fn worker_runtime_info(app: tauri::AppHandle) -> Result<WorkerRuntimeInfo, String> {
+  let worker = resource_path(&app, "bin/moondream-worker")
+    .ok_or_else(|| "Missing resource_dir (bin/moondream-worker)".to_string())?;
+  if !worker.exists() {
+    return Err(format!("Missing bundled worker at {}", worker.display()));
+  }
+  Ok(worker_runtime(&app, &worker))
+}
+
+// Mach-O CPU types (mach/machine.h).
+pub(crate) const CPU_TYPE_X86: u32 = 7;
+
+pub(crate) const CPU_TYPE_ARM: u32 = 12;
+
+pub(crate) const CPU_ARCH_ABI64: u32 = 0x0100_0000;
+
+pub(crate) fn macho_cpu_name(cpu: u32) -> String {
+  match cpu {
+    c if c == CPU_TYPE_X86 | CPU_ARCH_ABI64 => "x86_64".to_string(),
+    c if c == CPU_TYPE_ARM | CPU_ARCH_ABI64 => "arm64".to_string(),
+    CPU_TYPE_X86 => "i386".to_string(),
+    CPU_TYPE_ARM => "arm".to_string(),
+    other => format!("cpu {:#x}", other),
+  }
+}
+
+// Architectures in a Mach-O binary (several for a universal one); None if it isn't Mach-O.
+pub(crate) fn macho_archs(path: &PathBuf) -> Option<Vec<String>> {
+  let mut head = [0u8; 4096];
+  let n = std::fs::File::open(path).and_then(|mut f| f.read(&mut head)).ok()?;
+  let head = &head[..n];
+  let u32_at = |off: usize, big: bool| -> Option<u32> {
+    let b: [u8; 4] = head.get(off..off + 4)?.try_into().ok()?;
+    Some(if big { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+  };
+  match u32_at(0, true)? {
+    // FAT_MAGIC / FAT_MAGIC_64: big-endian header, then fat_arch (20 bytes) / fat_arch_64 (32 bytes).
+    magic @ (0xCAFE_BABE | 0xCAFE_BABF) => {
+      let entry = if magic == 0xCAFE_BABE { 20 } else { 32 };
+      let count = u32_at(4, true)? as usize;
+      // 0xCAFEBABE is also a Java class file; those have a huge "count" (the version).
+      if count == 0 || count > 16 {
+        return None;
+      }
+      (0..count).map(|i| u32_at(8 + i * entry, true).map(macho_cpu_name)).collect()
+    }
+    // MH_MAGIC / MH_MAGIC_64 as stored on little-endian machines.
+    0xCEFA_EDFE | 0xCFFA_EDFE => Some(vec![macho_cpu_name(u32_at(4, false)?)]),
+    _ => None,
+  }
+}
+
+pub(crate) fn app_arch() -> &'static str {
+  match std::env::consts::ARCH {
+    "aarch64" => "arm64",
+    other => other,
+  }
+}
+
+// An x86_64 build translated on Apple Silicon.
+pub(crate) fn running_under_rosetta() -> bool {
+  cfg!(target_os = "macos") && command_stdout("sysctl", &["-n", "sysctl.proc_translated"]).map(|s| s.trim() == "1").unwrap_or(false)
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct ArchInfo {
+  pub(crate) app_arch: String,
+  pub(crate) running_under_rosetta: bool,
+  // e.g. "arm64", or "arm64, x86_64" for a universal binary; None if unknown / not Mach-O.
+  pub(crate) worker_arch: Option<String>,
+  pub(crate) warning: Option<String>,
+}
+
+pub(crate) fn arch_info_for(app: &tauri::AppHandle) -> ArchInfo {
+  let rosetta = running_under_rosetta();
+  let worker_archs = resource_path(app, "bin/moondream-worker").and_then(|w| macho_archs(&w));
+  let apple_silicon = cfg!(target_os = "macos") && (app_arch() == "arm64" || rosetta);
+  let warning = if rosetta {
+    Some("Reference is running under Rosetta on Apple Silicon; install the Apple Silicon build for full speed.".to_string())
+  } else if apple_silicon && worker_archs.as_ref().map(|a| !a.iter().any(|a| a == "arm64")).unwrap_or(false) {
+    Some("The bundled AI worker is built for Intel and runs under Rosetta, which makes it much slower. Reinstall Reference to get a matching worker.".to_string())
+  } else {
+    None
+  };
+  ArchInfo {
+    app_arch: app_arch().to_string(),
+    running_under_rosetta: rosetta,
+    worker_arch: worker_archs.map(|a| a.join(", ")),
+    warning,
+  }
+}
+
+#[tauri::command(async)]
+pub(crate) fn arch_info(app: tauri::AppHandle) -> ArchInfo {
+  arch_info_for(&app)
+}
+
+pub(crate) fn warn_arch_mismatch(app: &tauri::AppHandle) {
+  if !cfg!(target_os = "macos") {
+    return;
+  }
+  let app = app.clone();
+  std::thread::spawn(move || {
+    let info = arch_info_for(&app);
+    let Some(warning) = info.warning.as_ref() else {
+      return;
+    };
+    host_log(LogLevel::Warn, warning);
+    if let Some(window) = app.get_window("main") {
+      dispatch_web_event(&window, "moondream:arch:mismatch", serde_json::to_value(&info).ok());
+    }
+  });
+}
+
+pub(crate) fn spawn_worker(
+  app: &tauri::AppHandle,
+  db_path: &PathBuf,
+  config_root: &PathBuf,
+  settings: &AppSettings,
+) -> io::Result<Child> {
+  // A worker on a different DB file than the server would write results the UI never sees.
+  if let Some(server_db) = app.state::<ServerState>().db_path.lock().unwrap().clone() {
+    if &server_db != db_path {
+      let msg = format!(
+        "Refusing to start the worker on {} while the server uses {}",
+        db_path.display(),
+        server_db.display()
+      );
+      host_log(LogLevel::Error, &msg);
+      return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+    }
+  }
+
+  let (child, concurrency, process_since) = spawn_worker_process(app, db_path, config_root, settings)?;
+  warn_ai_endpoint_conflict(app, settings);
+  warn_arch_mismatch(app);
+  let state = app.state::<ServerState>();
+  *state.worker_concurrency.lock().unwrap() = Some(concurrency);
+  *state.worker_process_since.lock().unwrap() = process_since;
+  *state.worker_settings.lock().unwrap() = Some(settings.clone());
+  *state.worker_db_path.lock().unwrap() = Some(db_path.clone());
+  *state.worker_started_at.lock().unwrap() = Some(unix_now_secs());
+  Ok(child)
+}
+
+// Starts a worker without recording it as the app's own (see `self_test`).
+// Returns it with the concurrency and `process_since` cutoff it was given.
+pub(crate) fn spawn_worker_process(
+  app: &tauri::AppHandle,
+  db_path: &PathBuf,
+  config_root: &PathBuf,
+  settings: &AppSettings,
+) -> io::Result<(Child, u8, Option<String>)> {
+  let worker = resource_path(app, "bin/moondream-worker")
+    .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Missing resource_dir (bin/moondream-worker)"))?;
+  if !worker.exists() {
+    return Err(io::Error::new(
+      ErrorKind::NotFound,
+      format!("Missing bundled worker at {}", worker.display()),
+    ));
+  }
+  verify_bundled_binary(&worker, settings)?;
+  let runtime = worker_runtime(app, &worker);
+  if !runtime.runs {
+    return Err(io::Error::other(format!(
+      "The bundled worker can't run ({}). Please reinstall Reference.",
+      runtime.error.unwrap_or_default()
+    )));
+  }
+
+  // Log worker output
+  let log_dir = logs_dir(config_root);
+  std::fs::create_dir_all(&log_dir)?;
+  let log_path = log_dir.join("moondream-worker.log");
+  rotate_log(&log_path, compress_rotated_logs(settings));
+  let out = OpenOptions::new().create(true).append(true).open(&log_path)?;
+  let err = out.try_clone()?;
+
+  let control_dir = worker_control_dir(config_root);
+  std::fs::create_dir_all(&control_dir)?;
+  // A previous worker's heartbeat would make this one look like it follows the contract.
+  let _ = std::fs::remove_file(control_dir.join("heartbeat"));
+  let concurrency = worker_concurrency(settings);
+
+  let ai = ai_config(settings);
+
+  let mut cmd = Command::new(worker);
+  cmd
+    .env("PYTHONUNBUFFERED", "1")
+    .env("MOONDREAM_DB_PATH", db_path)
+    .env("MOONDREAM_PROVIDER", ai.provider)
+    .env("MOONDREAM_ENDPOINT", ai.endpoint)
+    // HF provider expects these env vars (safe to set even when provider != huggingface).
+    .env("HF_ENDPOINT_URL", settings.ai.as_ref().and_then(|a| a.endpoint.as_ref()).cloned().unwrap_or_default())
+    .env("HF_TOKEN", ai.hf_token)
+    .env("MOONDREAM_POLL_SECONDS", std::env::var("MOONDREAM_POLL_SECONDS").unwrap_or_else(|_| "1.0".to_string()))
+    // Default: do NOT auto-retry "failed" forever (prevents tight loops if a file is missing on disk).
+    // Transient Station/network errors are already re-queued to "pending" by the worker.
+    .env("MOONDREAM_RETRY_FAILED", std::env::var("MOONDREAM_RETRY_FAILED").unwrap_or_else(|_| "0".to_string()))
+    .env("MOONDREAM_APP_CONFIG_DIR", config_root)
+    .env("MOONDREAM_CONTROL_DIR", &control_dir)
+    // Max jobs the worker processes at once (always 1..=16; see `worker_concurrency`).
+    .env("MOONDREAM_CONCURRENCY", concurrency.to_string())
+    .stdin(Stdio::null())
+    .stdout(Stdio::from(out))
+    .stderr(Stdio::from(err));
+
+  let process_since = process_since_cutoff(config_root, settings);
+  match process_since.as_ref() {
+    Some(cutoff) => cmd.env("MOONDREAM_PROCESS_SINCE", cutoff),
+    None => cmd.env_remove("MOONDREAM_PROCESS_SINCE"),
+  };
+
+  let child = cmd.spawn()?;
+  Ok((child, concurrency, process_since))
+}
+
+// Worker control contract: the host drops files into `MOONDREAM_CONTROL_DIR` and the worker
+// checks for them between polls (at least every ~250ms while sleeping).
+// - `sweep`: delete the file and poll for jobs immediately instead of waiting out
+//   MOONDREAM_POLL_SECONDS. Contents (a timestamp) are informational only.
+// - `pause`: while present, don't claim new jobs (an in-flight job may finish). The host
+//   creates/removes it; the worker only reads it. Contents are the reason ("user" | "compacting" | "storage_read_only" | "storage_offline").
+// - `test`: JSON `{"id": "..."}`. Delete it, caption the worker's built-in sample image through the
+//   configured provider (even while paused; no job is claimed) and write `test-result-<id>.json`:
+//   `{"ok": bool, "caption": str?, "model": str?, "error": str?}`.
+// - `heartbeat`: written by the worker (current unix seconds) every loop iteration, including
+//   while paused, so a paused worker isn't mistaken for a stalled one.
+// - `warm`: JSON `{"id": "..."}`. Delete it, make sure the model is loaded (the cheapest request the
+//   provider accepts; no job is claimed) and write `warm-result-<id>.json`: `{"ok": bool, "model": str?, "error": str?}`.
+// - `status.json`: written by the worker at startup: `{"pid": n, "db_path": "<MOONDREAM_DB_PATH>"}`.
+pub(crate) fn worker_control_dir(config_root: &PathBuf) -> PathBuf {
+  config_root.join("worker")
+}
+
+// A new worker's first heartbeat is due well within this.
+pub(crate) const WORKER_CONTROL_GRACE_SECS: u64 = 15;
+
+// Whether the running worker follows the control contract above (and honors MOONDREAM_CONCURRENCY
+// and MOONDREAM_PROCESS_SINCE). Workers from before it ignore MOONDREAM_CONTROL_DIR and never write
+// a heartbeat. None = no worker, or it started too recently to tell.
+pub(crate) fn worker_supports_control(state: &ServerState, config_root: &PathBuf) -> Option<bool> {
+  if !worker_running(state) {
+    return None;
+  }
+  let started = (*state.worker_started_at.lock().unwrap())?;
+  let beat = std::fs::read_to_string(worker_control_dir(config_root).join("heartbeat"))
+    .ok()
+    .and_then(|s| s.trim().parse::<u64>().ok());
+  if beat.is_some() {
+    Some(true)
+  } else if unix_now_secs().saturating_sub(started) < WORKER_CONTROL_GRACE_SECS {
+    None
+  } else {
+    Some(false)
+  }
+}
+
+// Err for a worker known not to read the control dir, so a request isn't reported as applied.
+pub(crate) fn require_worker_control(state: &ServerState, config_root: &PathBuf, what: &str) -> Result<(), String> {
+  match worker_supports_control(state, config_root) {
+    Some(false) => Err(format!(
+      "The running worker doesn't support {} (it never wrote a heartbeat to {}); update the worker.",
+      what,
+      worker_control_dir(config_root).display()
+    )),
+    _ => Ok(()),
+  }
+}
+
+pub(crate) fn worker_running(state: &ServerState) -> bool {
+  match state.worker.lock().unwrap().as_mut() {
+    Some(w) => matches!(w.try_wait(), Ok(None)),
+    None => false,
+  }
+}
+
+#[tauri::command]
+pub(crate) fn trigger_worker_sweep(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<bool, String> {
+  if !worker_running(&state) {
+    return Ok(false);
+  }
+  let config_root = match state.config_root.lock().unwrap().clone() {
+    Some(p) => p,
+    None => app_config_root(&app)
+      .ok_or_else(|| "Missing app_data_dir".to_string())?,
+  };
+  require_worker_control(&state, &config_root, "sweep requests")?;
+  request_worker_sweep(&config_root).map_err(|e| e.to_string())?;
+  Ok(true)
+}
+
+pub(crate) fn request_worker_sweep(config_root: &PathBuf) -> io::Result<()> {
+  let dir = worker_control_dir(config_root);
+  std::fs::create_dir_all(&dir)?;
+  std::fs::write(dir.join("sweep"), unix_now_secs().to_string())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ProjectsResponse {
+  pub(crate) projects: Vec<ProjectRef>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ProjectRef {
+  pub(crate) id: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RetryResponse {
+  pub(crate) changes: u32,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct RetryReport {
+  pub(crate) requeued: u32,
+  // One line per project whose retry failed; the other projects are still requeued.
+  pub(crate) errors: Vec<String>,
+}
+
+// Uses the server's per-project POST /api/projects/<id>/ai/retry (empty body), which only flips
+// `failed` rows back to `pending`; done jobs are untouched.
+#[tauri::command(async)]
+pub(crate) fn retry_failed_jobs(app: tauri::AppHandle) -> Result<RetryReport, String> {
+  let state = app.state::<ServerState>();
+  // Requeued jobs would just fail again.
+  if let Some(msg) = managed_config_root(&app, &state)
+    .ok()
+    .and_then(|c| remote_provider_unreachable(&effective_settings(&state, &c)))
+  {
+    return Err(msg);
+  }
+  let addr = current_server_addr(&state).ok_or_else(|| "The local server is not running".to_string())?;
+  let token = state.session_token.lock().unwrap().clone();
+  let timeout = Duration::from_secs(10);
+
+  let projects = match server_request(&addr, "GET", "/api/projects", None, token.as_deref(), timeout) {
+    Some((200, body)) => serde_json::from_str::<ProjectsResponse>(&body).map_err(|e| e.to_string())?.projects,
+    Some((status, _)) => return Err(format!("Listing projects failed ({})", status)),
+    None => return Err("The local server did not respond".to_string()),
+  };
+  let mut requeued = 0;
+  let mut errors = Vec::new();
+  for p in projects {
+    // Project ids are generated (uuid-like); anything else isn't safe to put in a path.
+    if !p.id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+      continue;
+    }
+    let path = format!("/api/projects/{}/ai/retry", p.id);
+    match server_request(&addr, "POST", &path, Some("{}"), token.as_deref(), timeout) {
+      Some((200, body)) => requeued += serde_json::from_str::<RetryResponse>(&body).map(|r| r.changes).unwrap_or(0),
+      Some((status, body)) => errors.push(format!("Retry failed for project {} ({}): {}", p.id, status, body.trim())),
+      None => errors.push(format!("Retry failed for project {}: the local server did not respond", p.id)),
+    }
+  }
+
+  if requeued > 0 && worker_running(&state) {
+    if let Some(config_root) = state.config_root.lock().unwrap().clone() {
+      let _ = request_worker_sweep(&config_root);
+    }
+  }
+  Ok(RetryReport { requeued, errors })
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct QueueStatus {
+  pub(crate) queued: u64,
+  pub(crate) processing: u64,
+  pub(crate) failed: u64,
+  // Only available from the direct DB read (the server's progress API has no per-day counts).
+  pub(crate) completed_today: Option<u64>,
+  pub(crate) source: String, // "sqlite" | "server"
+}
+
+#[derive(Clone, Deserialize)]
+pub(crate) struct ProgressCounts {
+  pub(crate) pending: u64,
+  pub(crate) processing: u64,
+  pub(crate) failed: u64,
+}
+
+#[derive(Clone, Deserialize)]
+pub(crate) struct ProgressResponse {
+  pub(crate) counts: ProgressCounts,
+}
+
+// `asset_ai.updated_at` is UTC (`datetime('now')`); "today" is the user's local day.
+pub(crate) const QUEUE_SQL: &str = "SELECT \
+  COALESCE(SUM(status = 'pending'), 0), \
+  COALESCE(SUM(status = 'processing'), 0), \
+  COALESCE(SUM(status = 'failed'), 0), \
+  COALESCE(SUM(status = 'done' AND updated_at >= datetime('now', 'localtime', 'start of day', 'utc')), 0) \
+  FROM asset_ai;";
+
+// Read-only connection via the sqlite3 CLI: WAL readers never block the worker's writes.
+pub(crate) fn queue_from_sqlite(db_path: &PathBuf) -> Option<QueueStatus> {
+  let out = Command::new("sqlite3")
+    .arg("-readonly")
+    .arg("-separator")
+    .arg("|")
+    .arg("-cmd")
+    .arg(".timeout 2000")
+    .arg(db_path)
+    .arg(QUEUE_SQL)
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output()
+    .ok()?;
+  if !out.status.success() {
+    return None;
+  }
+  let text = String::from_utf8_lossy(&out.stdout);
+  let nums: Vec<u64> = text
+    .trim()
+    .split('|')
+    .map(|n| n.trim().parse::<u64>())
+    .collect::<Result<_, _>>()
+    .ok()?;
+  match nums.as_slice() {
+    [queued, processing, failed, done_today] => Some(QueueStatus {
+      queued: *queued,
+      processing: *processing,
+      failed: *failed,
+      completed_today: Some(*done_today),
+      source: "sqlite".to_string(),
+    }),
+    _ => None,
+  }
+}
+
+pub(crate) fn queue_from_server(state: &ServerState) -> Option<QueueStatus> {
+  let addr = current_server_addr(state)?;
+  let token = state.session_token.lock().unwrap().clone();
+  let (status, body) = server_request(&addr, "GET", "/api/ai/progress", None, token.as_deref(), STATUS_PROBE_TIMEOUT)?;
+  if status != 200 {
+    return None;
+  }
+  let counts = serde_json::from_str::<ProgressResponse>(&body).ok()?.counts;
+  Some(QueueStatus {
+    queued: counts.pending,
+    processing: counts.processing,
+    failed: counts.failed,
+    completed_today: None,
+    source: "server".to_string(),
+  })
+}
+
+// Falls back to the server's /api/ai/progress where the sqlite3 CLI isn't installed (Windows).
+#[tauri::command(async)]
+pub(crate) fn worker_queue(state: tauri::State<ServerState>) -> Result<QueueStatus, String> {
+  let db_path = state.db_path.lock().unwrap().clone();
+  db_path
+    .as_ref()
+    .and_then(queue_from_sqlite)
+    .or_else(|| queue_from_server(&state))
+    .ok_or_else(|| "Couldn't read the job queue (database or server unavailable)".to_string())
+}
+
+pub(crate) const AI_TEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Serialize)]
+pub(crate) struct AiTestRequest {
+  pub(crate) id: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub(crate) struct AiTestReport {
+  pub(crate) ok: bool,
+  pub(crate) caption: Option<String>,
+  pub(crate) model: Option<String>,
+  pub(crate) error: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct TestResult {
+  pub(crate) ok: bool,
+  pub(crate) provider: String,
+  // Round trip as seen by the app (includes the worker noticing the request).
+  pub(crate) latency_ms: u64,
+  pub(crate) caption: Option<String>,
+  pub(crate) model: Option<String>,
+  pub(crate) error: Option<String>,
+}
+
+// Sends a `test`/`warm` control request and waits for its `<kind>-result-<id>.json`. The request
+// file has a fixed name, so a second request while the worker hasn't picked up the first is
+// refused rather than overwriting it (a request older than `timeout` is abandoned and replaced).
+pub(crate) fn worker_ai_request(
+  state: &ServerState,
+  config_root: &PathBuf,
+  kind: &str,
+  timeout: Duration,
+) -> Result<(AiTestReport, Duration), String> {
+  require_worker_control(state, config_root, &format!("{} requests", kind))?;
+  let dir = worker_control_dir(config_root);
+  std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+  let request = dir.join(kind);
+  let stale = std::fs::metadata(&request)
+    .and_then(|m| m.modified())
+    .ok()
+    .and_then(|t| t.elapsed().ok())
+    .map(|age| age >= timeout);
+  if stale == Some(true) {
+    let _ = std::fs::remove_file(&request);
+  }
+  let id = std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_millis()
+    .to_string();
+  let result_path = dir.join(format!("{}-result-{}.json", kind, id));
+  let body = serde_json::to_string(&AiTestRequest { id: id.clone() }).map_err(|e| e.to_string())?;
+
+  // Written aside and hard-linked into place: the link fails if a request is already there, and
+  // the worker never sees a half-written file.
+  let tmp = dir.join(format!("{}-{}.tmp", kind, id));
+  std::fs::write(&tmp, body).map_err(|e| e.to_string())?;
+  let linked = std::fs::hard_link(&tmp, &request);
+  let _ = std::fs::remove_file(&tmp);
+  match linked {
+    Ok(()) => {}
+    Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+      return Err(format!("A {} request is already waiting for the worker; try again when it finishes.", kind));
+    }
+    Err(e) => return Err(e.to_string()),
+  }
+
+  let start = Instant::now();
+  while start.elapsed() < timeout {
+    if let Ok(raw) = std::fs::read_to_string(&result_path) {
+      // The worker may still be writing; retry until it parses.
+      if let Ok(report) = serde_json::from_str::<AiTestReport>(&raw) {
+        let _ = std::fs::remove_file(&result_path);
+        return Ok((report, start.elapsed()));
+      }
+    }
+    std::thread::sleep(Duration::from_millis(200));
+  }
+  // Only ours to remove if the worker never picked it up (its body still has our id).
+  if std::fs::read_to_string(&request).map(|b| b.contains(&id)).unwrap_or(false) {
+    let _ = std::fs::remove_file(&request);
+  }
+  Err(format!(
+    "No answer from the worker within {}s. See {}",
+    timeout.as_secs(),
+    logs_dir(config_root).join("moondream-worker.log").display()
+  ))
+}
+
+// Runs a real inference through the worker (same provider/auth path as jobs), not just a TCP check.
+// `async` so the wait happens off the main thread.
+#[tauri::command(async)]
+pub(crate) fn test_ai_pipeline(app: tauri::AppHandle) -> Result<TestResult, String> {
+  let state = app.state::<ServerState>();
+  if !worker_running(&state) {
+    return Err("The worker isn't running, so there is nothing to test.".to_string());
+  }
+  let config_root = managed_config_root(&app, &state)?;
+  let settings = effective_settings(&state, &config_root);
+  if let Some(msg) = remote_provider_unreachable(&settings) {
+    return Err(msg);
+  }
+  let provider = ai_config(&settings).provider;
+
+  let (report, elapsed) = worker_ai_request(&state, &config_root, "test", AI_TEST_TIMEOUT)?;
+  Ok(TestResult {
+    ok: report.ok && report.caption.as_deref().map(|c| !c.trim().is_empty()).unwrap_or(false),
+    provider,
+    latency_ms: elapsed.as_millis() as u64,
+    caption: report.caption,
+    model: report.model,
+    error: report.error,
+  })
+}
+
+// A cold Station can take a while to load weights.
+pub(crate) const AI_WARM_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Clone, Serialize)]
+pub(crate) struct AiWarmup {
+  // false = nothing was sent (see `detail`), e.g. a hosted provider that's always warm.
+  pub(crate) warmed: bool,
+  pub(crate) provider: String,
+  pub(crate) latency_ms: u64,
+  pub(crate) model: Option<String>,
+  pub(crate) detail: Option<String>,
+}
+
+// Asks the worker to load the model ahead of the first job (see the `warm` control file).
+#[tauri::command(async)]
+pub(crate) fn warm_ai(app: tauri::AppHandle) -> Result<AiWarmup, String> {
+  let state = app.state::<ServerState>();
+  let config_root = managed_config_root(&app, &state)?;
+  let ai = ai_config(&effective_settings(&state, &config_root));
+  let provider = ai.provider.clone();
+  if !ai.is_local() {
+    return Ok(AiWarmup {
+      warmed: false,
+      provider,
+      latency_ms: 0,
+      model: None,
+      detail: Some("Hosted providers keep their own models loaded".to_string()),
+    });
+  }
+  if !worker_running(&state) {
+    return Err("The worker isn't running, so there is nothing to warm.".to_string());
+  }
+
+  let (report, elapsed) = worker_ai_request(&state, &config_root, "warm", AI_WARM_TIMEOUT)?;
+  if !report.ok {
+    return Err(report.error.unwrap_or_else(|| "The worker couldn't load the model".to_string()));
+  }
+  Ok(AiWarmup {
+    warmed: true,
+    provider,
+    latency_ms: elapsed.as_millis() as u64,
+    model: report.model,
+    detail: None,
+  })
+}
+
+pub(crate) fn set_worker_paused(config_root: &PathBuf, reason: Option<&str>) {
+  let flag = worker_control_dir(config_root).join("pause");
+  match reason {
+    Some(reason) => {
+      let _ = std::fs::create_dir_all(worker_control_dir(config_root));
+      let _ = std::fs::write(&flag, reason);
+    }
+    None => {
+      let _ = std::fs::remove_file(&flag);
+    }
+  }
+}
+
+pub(crate) fn pause_worker_when_offline(settings: &AppSettings) -> bool {
+  settings
+    .storage
+    .as_ref()
+    .and_then(|s| s.pause_worker_when_offline)
+    .unwrap_or(true)
+}
+
+// The `pause` flag is shared by the user toggle and the storage watchdog; it stays while either wants it.
+pub(crate) fn sync_worker_pause(state: &ServerState, config_root: &PathBuf, pause_when_offline: bool) {
+  let reason = if *state.worker_paused.lock().unwrap() {
+    Some("user")
+  } else if *state.db_compacting.lock().unwrap() {
+    Some("compacting")
+  } else if *state.storage_read_only.lock().unwrap() {
+    // Every job would fail to write its results; not subject to `pause_worker_when_offline`.
+    Some("storage_read_only")
+  } else if pause_when_offline && *state.storage_offline.lock().unwrap() {
+    Some("storage_offline")
+  } else {
+    None
+  };
+  set_worker_paused(config_root, reason);
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct WorkerStatus {
+  pub(crate) running: bool,
+  // Only true when the worker reads the `pause` flag; an older one keeps working regardless.
+  pub(crate) paused: bool,
+  // See `worker_supports_control`; None = no worker, or too soon after it started to tell.
+  pub(crate) control_supported: Option<bool>,
+  pub(crate) pause_reason: Option<String>,
+  // Seconds since the worker last wrote its heartbeat (None if it never has).
+  pub(crate) heartbeat_age_secs: Option<u64>,
+  // MOONDREAM_CONCURRENCY it was started with; None when it's a worker that ignores it (runs 1 job).
+  pub(crate) concurrency: Option<u8>,
+  // Only assets created at/after this (UTC, "YYYY-MM-DD HH:MM:SS") are processed; None = all,
+  // including when the worker is an older one that ignores MOONDREAM_PROCESS_SINCE.
+  pub(crate) process_since: Option<String>,
+  // Waiting for AC power before starting (see `defer_worker_until_ac`).
+  pub(crate) deferred: bool,
+}
+
+pub(crate) fn worker_status_for(state: &ServerState, config_root: &PathBuf) -> WorkerStatus {
+  let dir = worker_control_dir(config_root);
+  let pause_reason = std::fs::read_to_string(dir.join("pause"))
+    .ok()
+    .map(|s| s.trim().to_string());
+  let heartbeat_age_secs = std::fs::read_to_string(dir.join("heartbeat"))
+    .ok()
+    .and_then(|s| s.trim().parse::<u64>().ok())
+    .map(|t| unix_now_secs().saturating_sub(t));
+  let control_supported = worker_supports_control(state, config_root);
+  WorkerStatus {
+    running: worker_running(state),
+    paused: pause_reason.is_some() && control_supported != Some(false),
+    control_supported,
+    pause_reason,
+    heartbeat_age_secs,
+    concurrency: state.worker_concurrency.lock().unwrap().filter(|_| control_supported != Some(false)),
+    process_since: state.worker_process_since.lock().unwrap().clone().filter(|_| control_supported != Some(false)),
+    deferred: *state.worker_deferred.lock().unwrap(),
+  }
+}
+
+#[tauri::command]
+pub(crate) fn worker_status(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<WorkerStatus, String> {
+  let config_root = managed_config_root(&app, &state)?;
+  Ok(worker_status_for(&state, &config_root))
+}
+
+pub(crate) fn set_user_worker_pause(app: &tauri::AppHandle, state: &ServerState, paused: bool) -> Result<WorkerStatus, String> {
+  let config_root = managed_config_root(app, state)?;
+  if paused {
+    require_worker_control(state, &config_root, "pausing")?;
+  }
+  *state.worker_paused.lock().unwrap() = paused;
+  let settings = read_settings(&config_root);
+  sync_worker_pause(state, &config_root, pause_worker_when_offline(&settings));
+  Ok(worker_status_for(state, &config_root))
+}
+
+// Not persisted: a restart always comes back unpaused.
+#[tauri::command]
+pub(crate) fn pause_worker(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<WorkerStatus, String> {
+  set_user_worker_pause(&app, &state, true)
+}
+
+#[tauri::command]
+pub(crate) fn resume_worker(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<WorkerStatus, String> {
+  set_user_worker_pause(&app, &state, false)
+}
+
+pub(crate) const POWER_WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+pub(crate) fn defer_worker_on_battery(settings: &AppSettings) -> bool {
+  settings.worker.as_ref().and_then(|w| w.defer_on_battery).unwrap_or(false)
+}
+
+// Some(true) = running on battery, Some(false) = on AC (or no battery), None = couldn't tell.
+pub(crate) fn on_battery_power() -> Option<bool> {
+  if cfg!(target_os = "macos") {
+    // pmset reads the IOKit power source: "Now drawing from 'Battery Power'" / "'AC Power'".
+    let out = Command::new("pmset").args(["-g", "batt"]).stderr(Stdio::null()).output().ok()?;
+    let text = String::from_utf8_lossy(&out.stdout).to_string();
+    let first = text.lines().next()?;
+    if first.contains("'Battery Power'") {
+      Some(true)
+    } else if first.contains("'AC Power'") || first.contains("'UPS Power'") {
+      Some(false)
+    } else {
+      None
+    }
+  } else if cfg!(windows) {
+    // BatteryStatus 1 = discharging; no Win32_Battery instance = desktop on mains.
+    let out = Command::new("powershell")
+      .args([
+        "-NoProfile",
+        "-NonInteractive",
+        "-Command",
+        "(Get-CimInstance Win32_Battery | Select-Object -First 1).BatteryStatus",
+      ])
+      .stderr(Stdio::null())
+      .output()
+      .ok()?;
+    if !out.status.success() {
+      return None;
+    }
+    match String::from_utf8_lossy(&out.stdout).trim() {
+      "" => Some(false),
+      status => Some(status == "1"),
+    }
+  } else {
+    // Any online mains/USB supply means AC; a battery with none online means battery.
+    let entries = std::fs::read_dir("/sys/class/power_supply").ok()?;
+    let mut has_battery = false;
+    for entry in entries.flatten() {
+      let read = |f: &str| std::fs::read_to_string(entry.path().join(f)).map(|s| s.trim().to_string()).ok();
+      match read("type").as_deref() {
+        Some("Battery") => has_battery = true,
+        Some(_) if read("online").as_deref() == Some("1") => return Some(false),
+        _ => {}
+      }
+    }
+    Some(has_battery)
+  }
+}
+
+// Polls until the machine is on AC, then starts the worker unless it was started (or the
+// deferral cleared) in the meantime.
+pub(crate) fn defer_worker_until_ac(app: tauri::AppHandle, db_path: PathBuf, config_root: PathBuf) {
+  let state = app.state::<ServerState>();
+  *state.worker_deferred.lock().unwrap() = true;
+  host_log(LogLevel::Info, "On battery: deferring the worker until the machine is plugged in");
+  if let Some(window) = app.get_window("main") {
+    dispatch_web_event(&window, "moondream:worker:deferred", Some(serde_json::json!({ "reason": "battery" })));
+  }
+  std::thread::spawn(move || loop {
+    std::thread::sleep(POWER_WATCH_INTERVAL);
+    let state = app.state::<ServerState>();
+    if !*state.worker_deferred.lock().unwrap() {
+      return;
+    }
+    if on_battery_power() == Some(true) {
+      continue;
+    }
+    *state.worker_deferred.lock().unwrap() = false;
+    if worker_running(&state) {
+      return;
+    }
+    host_log(LogLevel::Info, "On AC power: starting the deferred worker");
+    let settings = read_settings(&config_root);
+    if let Ok(w) = spawn_worker(&app, &db_path, &config_root, &settings) {
+      *state.worker.lock().unwrap() = Some(w);
+    }
+    return;
+  });
+}
+
+// Starts the worker unless it's already up or held back: safe mode, a battery deferral (or
+// `worker.defer_on_battery` while on battery), or a maintenance window (`watch_maintenance`
+// brings it back when the window ends). Anything that restarts the worker after stopping it
+// goes through here so those holds aren't bypassed.
+pub(crate) fn start_worker_if_allowed(app: &tauri::AppHandle, db_path: &PathBuf, config_root: &PathBuf, settings: &AppSettings) {
+  let state = app.state::<ServerState>();
+  if worker_running(&state) || *state.safe_mode.lock().unwrap() || *state.worker_deferred.lock().unwrap() {
+    return;
+  }
+  let maintenance = state.maintenance_pause.lock().unwrap().clone().or_else(|| active_maintenance_window(&state, settings));
+  if let Some(w) = maintenance {
+    host_log(LogLevel::Info, &format!("Not starting the worker during maintenance window {}", w));
+    return;
+  }
+  if defer_worker_on_battery(settings) && on_battery_power() == Some(true) {
+    defer_worker_until_ac(app.clone(), db_path.clone(), config_root.clone());
+  } else if let Ok(w) = spawn_worker(app, db_path, config_root, settings) {
+    *state.worker.lock().unwrap() = Some(w);
+  }
+}
+
+// Stops the current worker (if any) and starts a fresh one with the current settings; also the
+// manual way to start a worker deferred by `worker.defer_on_battery`.
+#[tauri::command(async)]
+pub(crate) fn restart_worker(app: tauri::AppHandle, state: tauri::State<ServerState>) -> Result<WorkerStatus, String> {
+  if *state.safe_mode.lock().unwrap() {
+    return Err("The worker doesn't run in safe mode".to_string());
+  }
+  let config_root = managed_config_root(&app, &state)?;
+  let db_path = state
+    .db_path
+    .lock()
+    .unwrap()
+    .clone()
+    .ok_or_else(|| "The server isn't running".to_string())?;
+  *state.worker_deferred.lock().unwrap() = false;
+  respawn_worker(&app, &state, &config_root, &db_path)?;
+  Ok(worker_status_for(&state, &config_root))
+}
+
+pub(crate) fn respawn_worker(app: &tauri::AppHandle, state: &ServerState, config_root: &PathBuf, db_path: &PathBuf) -> Result<(), String> {
+  let settings = read_settings(config_root);
+  let previous = state.worker.lock().unwrap().take();
+  if let Some(w) = previous {
+    stop_child_gracefully("worker", w, shutdown_grace(&settings));
+  }
+  let w = spawn_worker(app, db_path, config_root, &settings).map_err(|e| format!("Failed to start the worker: {}", e))?;
+  *state.worker.lock().unwrap() = Some(w);
+  Ok(())
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct WorkerDbMismatch {
+  pub(crate) worker_db: String,
+  pub(crate) server_db: String,
+  // "worker" = from its status.json, "host" = what it was spawned with (older workers don't report).
+  pub(crate) source: String,
+}
+
+// The running worker's DB vs the server's. A status.json from another pid is a previous worker's.
+pub(crate) fn worker_db_mismatch(state: &ServerState, config_root: &PathBuf) -> Option<WorkerDbMismatch> {
+  let pid = state.worker.lock().unwrap().as_mut().and_then(|w| match w.try_wait() {
+    Ok(None) => Some(w.id()),
+    _ => None,
+  })?;
+  let server_db = state.db_path.lock().unwrap().clone()?;
+  let reported = std::fs::read_to_string(worker_control_dir(config_root).join("status.json"))
+    .ok()
+    .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+    .filter(|v| v.get("pid").and_then(|p| p.as_u64()) == Some(pid as u64))
+    .and_then(|v| v.get("db_path").and_then(|p| p.as_str()).map(PathBuf::from));
+  let (worker_db, source) = match reported {
+    Some(p) => (p, "worker"),
+    None => (state.worker_db_path.lock().unwrap().clone()?, "host"),
+  };
+  if worker_db == server_db {
+    return None;
+  }
+  Some(WorkerDbMismatch {
+    worker_db: worker_db.to_string_lossy().to_string(),
+    server_db: server_db.to_string_lossy().to_string(),
+    source: source.to_string(),
+  })
+}
+
+// Restarts the worker on the server's DB if it's on another one. Returns the mismatch that was
+// repaired (None = nothing to do).
+#[tauri::command(async)]
+pub(crate) fn repair_worker(app: tauri::AppHandle) -> Result<Option<WorkerDbMismatch>, String> {
+  let state = app.state::<ServerState>();
+  let config_root = managed_config_root(&app, &state)?;
+  let Some(mismatch) = worker_db_mismatch(&state, &config_root) else {
+    return Ok(None);
+  };
+  host_log(
+    LogLevel::Warn,
+    &format!(
+      "Worker is on {} but the server uses {}; restarting it",
+      mismatch.worker_db, mismatch.server_db
+    ),
+  );
+  respawn_worker(&app, &state, &config_root, &PathBuf::from(&mismatch.server_db))?;
+  Ok(Some(mismatch))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn process_since_timestamps() {
+    assert_eq!(parse_iso_timestamp("1970-01-01"), Some(0));
+    assert_eq!(parse_iso_timestamp("2024-01-01"), Some(1_704_067_200));
+    assert_eq!(parse_iso_timestamp("2024-01-01T12:30:00Z"), Some(1_704_112_200));
+    assert_eq!(parse_iso_timestamp("2024-01-01 12:30"), Some(1_704_112_200));
+    assert_eq!(parse_iso_timestamp("2024-01-01T12:30:00.250Z"), Some(1_704_112_200));
+    assert_eq!(parse_iso_timestamp("2024-01-01T12:30:00+02:00"), Some(1_704_105_000));
+    assert_eq!(parse_iso_timestamp("2024-01-01T12:30:00-05:00"), Some(1_704_130_200));
+    assert_eq!(parse_iso_timestamp("2024-13-01"), None);
+    assert_eq!(parse_iso_timestamp("2024-01-01T25:00"), None);
+    assert_eq!(parse_iso_timestamp("yesterday"), None);
+    assert_eq!(parse_iso_timestamp(""), None);
+  }
+
+  #[test]
+  fn process_since_cutoff_format() {
+    assert_eq!(sqlite_datetime(0), "1970-01-01 00:00:00");
+    assert_eq!(sqlite_datetime(1_704_112_200), "2024-01-01 12:30:00");
+    let leap = parse_iso_timestamp("2000-02-29T23:59:59Z").unwrap();
+    assert_eq!(sqlite_datetime(leap), "2000-02-29 23:59:59");
+  }
+
+  #[test]
+  fn macho_headers() {
+    let dir = scratch_dir("macho");
+    let write = |name: &str, bytes: &[u8]| {
+      let path = dir.join(name);
+      std::fs::write(&path, bytes).unwrap();
+      path
+    };
+    let mut thin = vec![0xCF, 0xFA, 0xED, 0xFE];
+    thin.extend_from_slice(&0x0100_000Cu32.to_le_bytes());
+    assert_eq!(macho_archs(&write("thin", &thin)), Some(vec!["arm64".to_string()]));
+    let mut fat = vec![0xCA, 0xFE, 0xBA, 0xBE];
+    fat.extend_from_slice(&2u32.to_be_bytes());
+    for cpu in [0x0100_0007u32, 0x0100_000C] {
+      fat.extend_from_slice(&cpu.to_be_bytes());
+      fat.extend_from_slice(&[0; 16]);
+    }
+    assert_eq!(macho_archs(&write("fat", &fat)), Some(vec!["x86_64".to_string(), "arm64".to_string()]));
+    // Java class files share the fat magic; their "count" is the class version.
+    assert_eq!(macho_archs(&write("class", &[0xCA, 0xFE, 0xBA, 0xBE, 0, 0, 0, 52])), None);
+    assert_eq!(macho_archs(&write("elf", b"\x7FELF\x02\x01\x01\x00")), None);
+    assert_eq!(macho_archs(&dir.join("missing")), None);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+}